@@ -0,0 +1,35 @@
+-- Unifica los tres ledgers de transacciones en portfolio_transactions.
+--
+-- * Tipos de transacción siempre en mayúsculas (BUY, SELL, DEPOSIT, WITHDRAWAL, DIVIDEND).
+-- * El efectivo se deriva de los propios movimientos: BUY/WITHDRAWAL restan, SELL/DEPOSIT/DIVIDEND suman.
+--   Las patas CASH que escribía handle_cash_flow guardaban el saldo resultante y no el
+--   movimiento, así que se eliminan; los "Auto-deposit" sí eran dinero nuevo y se conservan.
+-- * Los depósitos, retiros y dividendos de la tabla cashflow pasan al ledger y la tabla
+--   queda renombrada como cashflow_legacy. buy_cost/sell_proceeds ya están implícitos en
+--   las compras y ventas.
+
+BEGIN;
+
+UPDATE portfolio_transactions
+SET transaction_type = UPPER(transaction_type), updated_at = now()
+WHERE transaction_type <> UPPER(transaction_type);
+
+DELETE FROM portfolio_transactions
+WHERE ticker = 'CASH'
+  AND (notes LIKE 'Cash withdrawal for % purchase' OR notes LIKE 'Cash deposit from % sale');
+
+DO $$
+BEGIN
+    IF to_regclass('public.cashflow') IS NOT NULL THEN
+        INSERT INTO portfolio_transactions (portfolio_id, user_id, ticker, transaction_type, quantity, price, total_amount, currency, notes, transaction_date)
+        SELECT c.portafolio_id, p.usuario_id, 'CASH', UPPER(c.tipo), 1, c.monto, c.monto, 'MXN', c.descripcion, c.fecha
+        FROM public.cashflow c
+        JOIN public.portafolios p ON p.id = c.portafolio_id
+        WHERE LOWER(c.tipo) IN ('deposit', 'withdrawal', 'dividend')
+          AND p.usuario_id IS NOT NULL;
+
+        ALTER TABLE public.cashflow RENAME TO cashflow_legacy;
+    END IF;
+END $$;
+
+COMMIT;
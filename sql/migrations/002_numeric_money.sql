@@ -0,0 +1,22 @@
+-- Montos, precios y cantidades del ledger pasan de double precision a NUMERIC.
+--
+-- Reglas de redondeo (ver src-tauri/src/money.rs):
+-- * quantity y price: 6 decimales.
+-- * total_amount: centavos, redondeo comercial (mitad hacia arriba).
+-- Las compras y ventas recalculan total_amount = quantity * price para que el efectivo
+-- y el costo de las posiciones cuadren al peso.
+
+BEGIN;
+
+ALTER TABLE portfolio_transactions
+    ALTER COLUMN quantity TYPE numeric(18,6) USING ROUND(quantity::numeric, 6),
+    ALTER COLUMN price TYPE numeric(18,6) USING ROUND(price::numeric, 6),
+    ALTER COLUMN total_amount TYPE numeric(18,2) USING ROUND(total_amount::numeric, 2);
+
+UPDATE portfolio_transactions
+SET total_amount = ROUND(quantity * price, 2), updated_at = now()
+WHERE transaction_type IN ('BUY', 'SELL')
+  AND price IS NOT NULL
+  AND total_amount <> ROUND(quantity * price, 2);
+
+COMMIT;
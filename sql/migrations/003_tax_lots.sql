@@ -0,0 +1,25 @@
+-- Lotes fiscales: cada compra es un lote y cada venta consume lotes según el
+-- método de costeo del portafolio (ver src-tauri/src/lots.rs).
+--
+-- * cost_basis_method: FIFO, LIFO, AVERAGE (costo promedio, el comportamiento anterior) o SPECIFIC.
+-- * lot_selections: lotes elegidos para cada venta cuando el método es SPECIFIC.
+--   Lo que no se elija se consume en orden FIFO.
+
+BEGIN;
+
+ALTER TABLE portafolios
+    ADD COLUMN IF NOT EXISTS cost_basis_method varchar(12) NOT NULL DEFAULT 'AVERAGE'
+        CONSTRAINT portafolios_cost_basis_method_check
+        CHECK (cost_basis_method IN ('FIFO', 'LIFO', 'AVERAGE', 'SPECIFIC'));
+
+CREATE TABLE IF NOT EXISTS public.lot_selections
+(
+    sell_transaction_id integer NOT NULL
+        REFERENCES public.portfolio_transactions (transaction_id) ON DELETE CASCADE,
+    lot_id integer NOT NULL
+        REFERENCES public.portfolio_transactions (transaction_id) ON DELETE CASCADE,
+    quantity numeric(18,6) NOT NULL CHECK (quantity > 0),
+    CONSTRAINT lot_selections_pkey PRIMARY KEY (sell_transaction_id, lot_id)
+);
+
+COMMIT;
@@ -0,0 +1,15 @@
+-- Índice Nacional de Precios al Consumidor (INEGI), usado para actualizar el costo
+-- fiscal de las acciones vendidas en el reporte anual de ISR (ver src-tauri/src/isr.rs).
+-- Los valores se cargan con el comando save_inpc_values o directamente en esta tabla.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.inpc
+(
+    anio integer NOT NULL,
+    mes integer NOT NULL CHECK (mes BETWEEN 1 AND 12),
+    valor numeric(12,6) NOT NULL CHECK (valor > 0),
+    CONSTRAINT inpc_pkey PRIMARY KEY (anio, mes)
+);
+
+COMMIT;
@@ -0,0 +1,26 @@
+-- Detalle fiscal de los dividendos. El ledger guarda el monto neto como un movimiento
+-- DIVIDEND con el ticker de la emisora; aquí queda el bruto, la retención y las fechas
+-- (ver src-tauri/src/dividends.rs).
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.dividend_details
+(
+    transaction_id integer NOT NULL
+        REFERENCES public.portfolio_transactions (transaction_id) ON DELETE CASCADE,
+    portfolio_id integer NOT NULL
+        REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    ticker character varying(20) NOT NULL,
+    ex_date date NOT NULL,
+    pay_date date NOT NULL,
+    shares numeric(18,6) NOT NULL CHECK (shares > 0),
+    amount_per_share numeric(18,6) NOT NULL CHECK (amount_per_share > 0),
+    gross_amount numeric(18,2) NOT NULL,
+    withholding_tax numeric(18,2) NOT NULL DEFAULT 0,
+    net_amount numeric(18,2) NOT NULL,
+    CONSTRAINT dividend_details_pkey PRIMARY KEY (transaction_id),
+    CONSTRAINT dividend_details_unique_event UNIQUE (portfolio_id, ticker, ex_date),
+    CONSTRAINT dividend_details_net_check CHECK (net_amount = gross_amount - withholding_tax)
+);
+
+COMMIT;
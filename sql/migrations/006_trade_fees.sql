@@ -0,0 +1,25 @@
+-- Comisiones de la casa de bolsa más IVA (ver src-tauri/src/fees.rs).
+--
+-- * fee_schedules: esquema por portafolio; comisión = max(importe * commission_rate, minimum_commission) + fixed_fee.
+-- * portfolio_transactions.commission / commission_iva: lo cobrado en cada operación.
+--   total_amount ya los incluye: en compras se suman al costo y en ventas se restan de lo obtenido.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.fee_schedules
+(
+    portfolio_id integer NOT NULL
+        REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    commission_rate numeric(9,6) NOT NULL DEFAULT 0 CHECK (commission_rate >= 0 AND commission_rate < 1),
+    minimum_commission numeric(18,2) NOT NULL DEFAULT 0 CHECK (minimum_commission >= 0),
+    fixed_fee numeric(18,2) NOT NULL DEFAULT 0 CHECK (fixed_fee >= 0),
+    iva_rate numeric(5,4) NOT NULL DEFAULT 0.16 CHECK (iva_rate >= 0 AND iva_rate < 1),
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT fee_schedules_pkey PRIMARY KEY (portfolio_id)
+);
+
+ALTER TABLE portfolio_transactions
+    ADD COLUMN IF NOT EXISTS commission numeric(18,2) NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS commission_iva numeric(18,2) NOT NULL DEFAULT 0;
+
+COMMIT;
@@ -0,0 +1,26 @@
+-- Portafolios multimoneda (ver src-tauri/src/fx.rs y valuation.rs).
+--
+-- * fx_rates: pesos por unidad de moneda extranjera, un registro por día.
+-- * portfolio_transactions.fx_rate: tipo de cambio a pesos en la fecha del movimiento;
+--   con él se separa la ganancia cambiaria de la ganancia por precio.
+-- Los movimientos existentes están en pesos, así que quedan con fx_rate = 1.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.fx_rates
+(
+    rate_date date NOT NULL,
+    currency character varying(3) NOT NULL CHECK (currency <> 'MXN'),
+    rate_to_mxn numeric(18,6) NOT NULL CHECK (rate_to_mxn > 0),
+    CONSTRAINT fx_rates_pkey PRIMARY KEY (currency, rate_date)
+);
+
+UPDATE portfolio_transactions SET currency = UPPER(currency) WHERE currency <> UPPER(currency);
+
+ALTER TABLE portfolio_transactions
+    ADD COLUMN IF NOT EXISTS fx_rate numeric(18,6) NOT NULL DEFAULT 1 CHECK (fx_rate > 0);
+
+CREATE INDEX IF NOT EXISTS idx_transactions_portfolio_currency
+    ON public.portfolio_transactions (portfolio_id, currency);
+
+COMMIT;
@@ -0,0 +1,14 @@
+-- Cierres diarios por ticker, en pesos. Se llenan bajo demanda desde DataBursátil
+-- (ver src-tauri/src/prices.rs) y alimentan el historial de valuación y los rendimientos.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.price_history
+(
+    ticker character varying(20) NOT NULL,
+    price_date date NOT NULL,
+    close numeric(18,6) NOT NULL CHECK (close > 0),
+    CONSTRAINT price_history_pkey PRIMARY KEY (ticker, price_date)
+);
+
+COMMIT;
@@ -0,0 +1,20 @@
+-- Valor diario de cada portafolio (curva de capital), en pesos. Se calcula de forma
+-- incremental a partir del ledger (ver src-tauri/src/snapshots.rs); al borrar o modificar
+-- un movimiento se recalcula desde su fecha.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.portfolio_snapshots
+(
+    portfolio_id integer NOT NULL REFERENCES public.portafolios(id) ON DELETE CASCADE,
+    snapshot_date date NOT NULL,
+    market_value numeric(18,2) NOT NULL,
+    cash numeric(18,2) NOT NULL,
+    total_value numeric(18,2) NOT NULL,
+    net_flow numeric(18,2) NOT NULL DEFAULT 0,
+    contributions numeric(18,2) NOT NULL DEFAULT 0,
+    computed_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT portfolio_snapshots_pkey PRIMARY KEY (portfolio_id, snapshot_date)
+);
+
+COMMIT;
@@ -0,0 +1,15 @@
+-- Histórico de benchmarks (ver src-tauri/src/benchmarks.rs).
+-- Para los índices (IPC, FTSEBIVA, SP500, DJIA) `value` es el nivel de cierre;
+-- para los CETES es la tasa anual en porcentaje.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.benchmark_levels
+(
+    symbol character varying(20) NOT NULL,
+    value_date date NOT NULL,
+    value numeric(18,6) NOT NULL CHECK (value >= 0),
+    CONSTRAINT benchmark_levels_pkey PRIMARY KEY (symbol, value_date)
+);
+
+COMMIT;
@@ -0,0 +1,28 @@
+-- Asignación objetivo y rebalanceo (ver src-tauri/src/allocation.rs).
+--
+-- * ticker_classifications: sector, clase de activo (reemplaza a la derivada de
+--   emisoras.tipo_valor) y lote mínimo de operación por ticker.
+-- * allocation_targets: pesos objetivo en porcentaje por portafolio y dimensión
+--   (TICKER, SECTOR o TIPO_VALOR). El renglón EFECTIVO es la reserva de efectivo.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.ticker_classifications
+(
+    ticker character varying(20) NOT NULL,
+    sector character varying(100),
+    asset_class character varying(50),
+    lot_size numeric(18,6) NOT NULL DEFAULT 1 CHECK (lot_size > 0),
+    CONSTRAINT ticker_classifications_pkey PRIMARY KEY (ticker)
+);
+
+CREATE TABLE IF NOT EXISTS public.allocation_targets
+(
+    portfolio_id integer NOT NULL REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    dimension character varying(20) NOT NULL CHECK (dimension IN ('TICKER', 'SECTOR', 'TIPO_VALOR')),
+    bucket character varying(100) NOT NULL,
+    target_weight numeric(7,4) NOT NULL CHECK (target_weight >= 0 AND target_weight <= 100),
+    CONSTRAINT allocation_targets_pkey PRIMARY KEY (portfolio_id, dimension, bucket)
+);
+
+COMMIT;
@@ -0,0 +1,38 @@
+-- Escenarios de estrés (ver src-tauri/src/stress.rs).
+--
+-- * HISTORICAL: se repiten sobre las posiciones actuales los movimientos de precios y
+--   tipos de cambio observados entre start_date y end_date.
+-- * HYPOTHETICAL: lista de choques en porcentaje, [{"factor": "IPC", "change": -20}, ...],
+--   donde el factor es un índice, USDMXN/EURMXN, SECTOR:<sector>, TIPO_VALOR:<clase> o
+--   TICKER:<ticker>.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.stress_scenarios
+(
+    scenario_id serial PRIMARY KEY,
+    name character varying(100) NOT NULL UNIQUE,
+    kind character varying(20) NOT NULL CHECK (kind IN ('HISTORICAL', 'HYPOTHETICAL')),
+    description text,
+    start_date date,
+    end_date date,
+    shocks jsonb NOT NULL DEFAULT '[]'::jsonb,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    CHECK (kind <> 'HISTORICAL' OR (start_date IS NOT NULL AND end_date IS NOT NULL AND start_date < end_date))
+);
+
+INSERT INTO public.stress_scenarios (name, kind, description, start_date, end_date, shocks) VALUES
+    ('Covid marzo 2020', 'HISTORICAL', 'Caída de los mercados por la pandemia, del máximo de febrero al mínimo de marzo',
+     '2020-02-19', '2020-03-23', '[]'),
+    ('Choque del peso 2016', 'HISTORICAL', 'Depreciación del peso tras la elección en Estados Unidos',
+     '2016-11-08', '2017-01-19', '[]'),
+    ('Crisis 2008', 'HISTORICAL', 'Quiebra de Lehman Brothers y contagio a mercados emergentes',
+     '2008-09-12', '2008-10-27', '[]'),
+    ('IPC -20%', 'HYPOTHETICAL', 'Caída del índice de 20%, transmitida con la beta de cada emisora',
+     NULL, NULL, '[{"factor": "IPC", "change": -20}]'),
+    ('Peso -15%', 'HYPOTHETICAL', 'El dólar sube 15% frente al peso',
+     NULL, NULL, '[{"factor": "USDMXN", "change": 15}]')
+ON CONFLICT (name) DO NOTHING;
+
+COMMIT;
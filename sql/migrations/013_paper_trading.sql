@@ -0,0 +1,36 @@
+-- Portafolios de práctica (ver src-tauri/src/orders.rs).
+--
+-- * portafolios.is_paper: el portafolio es simulado. Solo estos aceptan órdenes.
+-- * orders: órdenes a mercado (MARKET), limitadas (LIMIT) y stop (STOP). Quedan en OPEN
+--   hasta que una cotización de DataBursátil en horario de mercado las ejecuta; la
+--   ejecución se registra en portfolio_transactions como cualquier otra operación.
+
+BEGIN;
+
+ALTER TABLE portafolios
+    ADD COLUMN IF NOT EXISTS is_paper boolean NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS public.orders
+(
+    order_id serial PRIMARY KEY,
+    portfolio_id integer NOT NULL REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    ticker character varying(20) NOT NULL,
+    side character varying(10) NOT NULL CHECK (side IN ('BUY', 'SELL')),
+    order_type character varying(10) NOT NULL CHECK (order_type IN ('MARKET', 'LIMIT', 'STOP')),
+    quantity numeric(18,6) NOT NULL CHECK (quantity > 0),
+    limit_price numeric(18,6) CHECK (limit_price > 0),
+    stop_price numeric(18,6) CHECK (stop_price > 0),
+    status character varying(10) NOT NULL DEFAULT 'OPEN'
+        CHECK (status IN ('OPEN', 'FILLED', 'CANCELLED', 'REJECTED')),
+    fill_price numeric(18,6),
+    filled_at timestamp with time zone,
+    transaction_id integer REFERENCES public.portfolio_transactions (transaction_id) ON DELETE SET NULL,
+    reject_reason text,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    CHECK (order_type <> 'LIMIT' OR limit_price IS NOT NULL),
+    CHECK (order_type <> 'STOP' OR stop_price IS NOT NULL)
+);
+
+CREATE INDEX IF NOT EXISTS orders_open_idx ON public.orders (ticker) WHERE status = 'OPEN';
+
+COMMIT;
@@ -0,0 +1,44 @@
+-- Planes de aportaciones periódicas (ver src-tauri/src/contributions.rs).
+--
+-- * contribution_plans: monto en pesos a invertir en un ticker cada mes (día del mes) o
+--   cada semana (día de la semana, 1 = lunes). last_scheduled_date es la última fecha
+--   programada ya generada; al arrancar la aplicación se generan las que falten.
+-- * pending_contributions: una compra por fecha programada, con el cierre de ese día como
+--   precio sugerido. Queda PENDING hasta que se confirma (CONFIRMED) o se omite (SKIPPED).
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.contribution_plans
+(
+    plan_id serial PRIMARY KEY,
+    portfolio_id integer NOT NULL REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    ticker character varying(20) NOT NULL,
+    amount numeric(18,2) NOT NULL CHECK (amount > 0),
+    frequency character varying(10) NOT NULL CHECK (frequency IN ('MONTHLY', 'WEEKLY')),
+    schedule_day smallint NOT NULL,
+    start_date date NOT NULL,
+    end_date date,
+    status character varying(10) NOT NULL DEFAULT 'ACTIVE' CHECK (status IN ('ACTIVE', 'PAUSED', 'ENDED')),
+    -- false: cada aportación entra como depósito nuevo; true: se paga con el efectivo del portafolio
+    use_portfolio_cash boolean NOT NULL DEFAULT false,
+    -- true: se registra sola al cierre del día programado
+    auto_confirm boolean NOT NULL DEFAULT false,
+    last_scheduled_date date,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    CHECK ((frequency = 'MONTHLY' AND schedule_day BETWEEN 1 AND 31)
+        OR (frequency = 'WEEKLY' AND schedule_day BETWEEN 1 AND 5))
+);
+
+CREATE TABLE IF NOT EXISTS public.pending_contributions
+(
+    pending_id serial PRIMARY KEY,
+    plan_id integer NOT NULL REFERENCES public.contribution_plans (plan_id) ON DELETE CASCADE,
+    scheduled_date date NOT NULL,
+    suggested_price numeric(18,6),
+    status character varying(10) NOT NULL DEFAULT 'PENDING' CHECK (status IN ('PENDING', 'CONFIRMED', 'SKIPPED')),
+    transaction_id integer REFERENCES public.portfolio_transactions (transaction_id) ON DELETE SET NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT pending_contributions_plan_date_key UNIQUE (plan_id, scheduled_date)
+);
+
+COMMIT;
@@ -0,0 +1,55 @@
+-- Instrumentos de deuda (ver src-tauri/src/fixed_income.rs).
+--
+-- * fixed_income_instruments: CETES (a descuento, valor nominal 10) y bonos con cupón
+--   (valor nominal 100). purchase_yield es el rendimiento anual de la primera compra; con
+--   él se calculan los precios diarios que se guardan en price_history como los de
+--   cualquier emisora. Con MARKED se valúa con la tasa guardada en benchmark_levels
+--   (rate_symbol, o el CETE de plazo más cercano) más spread.
+-- * fixed_income_lots: rendimiento de cada compra. A costo amortizado una posición se valúa
+--   lote por lote con el rendimiento de su compra.
+-- * fixed_income_flows: cupones y amortizaciones ya registrados en el ledger por portafolio.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.fixed_income_instruments
+(
+    ticker character varying(20) NOT NULL,
+    kind character varying(10) NOT NULL CHECK (kind IN ('CETE', 'BOND')),
+    face_value numeric(18,6) NOT NULL CHECK (face_value > 0),
+    maturity_date date NOT NULL,
+    coupon_rate numeric(9,6) NOT NULL DEFAULT 0 CHECK (coupon_rate >= 0),
+    coupons_per_year smallint NOT NULL DEFAULT 0 CHECK (coupons_per_year IN (0, 1, 2, 4, 12)),
+    purchase_yield numeric(9,6) NOT NULL,
+    rate_symbol character varying(20),
+    spread numeric(9,6) NOT NULL DEFAULT 0,
+    valuation_method character varying(10) NOT NULL DEFAULT 'AMORTIZED'
+        CHECK (valuation_method IN ('AMORTIZED', 'MARKED')),
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT fixed_income_instruments_pkey PRIMARY KEY (ticker),
+    CHECK (kind = 'BOND' OR (coupon_rate = 0 AND coupons_per_year = 0))
+);
+
+CREATE TABLE IF NOT EXISTS public.fixed_income_lots
+(
+    transaction_id integer NOT NULL
+        REFERENCES public.portfolio_transactions (transaction_id) ON DELETE CASCADE,
+    ticker character varying(20) NOT NULL REFERENCES public.fixed_income_instruments (ticker) ON DELETE CASCADE,
+    purchase_yield numeric(9,6) NOT NULL,
+    CONSTRAINT fixed_income_lots_pkey PRIMARY KEY (transaction_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_fixed_income_lots_ticker ON public.fixed_income_lots (ticker);
+
+CREATE TABLE IF NOT EXISTS public.fixed_income_flows
+(
+    portfolio_id integer NOT NULL REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    ticker character varying(20) NOT NULL REFERENCES public.fixed_income_instruments (ticker) ON DELETE CASCADE,
+    flow_date date NOT NULL,
+    kind character varying(10) NOT NULL CHECK (kind IN ('COUPON', 'REDEMPTION')),
+    quantity numeric(18,6) NOT NULL,
+    amount numeric(18,2) NOT NULL,
+    transaction_id integer REFERENCES public.portfolio_transactions (transaction_id) ON DELETE SET NULL,
+    CONSTRAINT fixed_income_flows_pkey PRIMARY KEY (portfolio_id, ticker, flow_date, kind)
+);
+
+COMMIT;
@@ -0,0 +1,29 @@
+-- Curva de rendimiento diaria de CETES (ver src-tauri/src/yield_curve.rs).
+--
+-- * yield_curve_points: tasa anual en porcentaje (base 360) por plazo en días, tomada
+--   de benchmark_levels (CETE28, CETE91, CETE182 y CETE364) al cierre de curve_date.
+-- * yield_curves: parámetros del ajuste Nelson-Siegel de esa fecha (NULL si hubo
+--   menos de tres plazos).
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.yield_curves
+(
+    curve_date date NOT NULL,
+    ns_beta0 double precision,
+    ns_beta1 double precision,
+    ns_beta2 double precision,
+    ns_tau double precision,
+    computed_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT yield_curves_pkey PRIMARY KEY (curve_date)
+);
+
+CREATE TABLE IF NOT EXISTS public.yield_curve_points
+(
+    curve_date date NOT NULL REFERENCES public.yield_curves (curve_date) ON DELETE CASCADE,
+    tenor_days integer NOT NULL CHECK (tenor_days > 0),
+    rate numeric(9,6) NOT NULL,
+    CONSTRAINT yield_curve_points_pkey PRIMARY KEY (curve_date, tenor_days)
+);
+
+COMMIT;
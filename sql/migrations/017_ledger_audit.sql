@@ -0,0 +1,87 @@
+-- Corrección y anulación de movimientos con bitácora (ver src-tauri/src/ledger_audit.rs).
+--
+-- * portfolio_transactions.linked_transaction_id: las patas de efectivo que registra una
+--   compra o venta (fondeo externo, auto-depósito, retiro de lo obtenido) apuntan a su
+--   operación, y se corrigen, anulan y restauran junto con ella.
+-- * portfolio_transactions.voided_at: los movimientos ya no se borran; un movimiento
+--   anulado se conserva pero deja de contar para posiciones, efectivo y rendimientos.
+-- * ledger_audit: bitácora de solo inserción con el estado del movimiento antes y
+--   después de cada corrección, anulación o deshacer. transaction_id no es llave foránea
+--   para que la bitácora sobreviva aunque se borre el movimiento o su portafolio.
+-- Las patas existentes se enlazan por portafolio, fecha, moneda, monto y nota. Un
+-- 'Auto-deposit for insufficient cash' solo se enlaza si hay exactamente una compra
+-- candidata; los ambiguos quedan sueltos y se corrigen o anulan por separado.
+
+BEGIN;
+
+ALTER TABLE portfolio_transactions
+    ADD COLUMN IF NOT EXISTS linked_transaction_id integer
+        REFERENCES public.portfolio_transactions (transaction_id) ON DELETE CASCADE,
+    ADD COLUMN IF NOT EXISTS voided_at timestamp with time zone;
+
+CREATE INDEX IF NOT EXISTS idx_transactions_linked
+    ON public.portfolio_transactions (linked_transaction_id)
+    WHERE linked_transaction_id IS NOT NULL;
+
+WITH candidates AS (
+    SELECT c.transaction_id AS leg_id, t.transaction_id AS trade_id,
+           c.notes = 'Auto-deposit for insufficient cash' AS auto_deposit
+    FROM portfolio_transactions c
+    JOIN portfolio_transactions t
+      ON t.portfolio_id = c.portfolio_id
+     AND t.transaction_date = c.transaction_date
+     AND t.currency = c.currency
+     AND t.ticker <> 'CASH'
+    WHERE c.ticker = 'CASH'
+      AND c.linked_transaction_id IS NULL
+      AND (
+        (c.transaction_type = 'DEPOSIT' AND t.transaction_type = 'BUY'
+            AND c.notes = 'Fondeo externo para compra de ' || t.ticker AND c.total_amount = t.total_amount)
+        OR (c.transaction_type = 'WITHDRAWAL' AND t.transaction_type = 'SELL'
+            AND c.notes = 'Retiro de lo obtenido por la venta de ' || t.ticker AND c.total_amount = t.total_amount)
+        OR (c.transaction_type = 'DEPOSIT' AND t.transaction_type = 'BUY'
+            AND c.notes = 'Auto-deposit for insufficient cash' AND c.total_amount <= t.total_amount)
+      )
+),
+legs AS (
+    SELECT DISTINCT ON (leg_id) leg_id, trade_id
+    FROM candidates
+    WHERE NOT auto_deposit
+       OR leg_id IN (SELECT leg_id FROM candidates GROUP BY leg_id HAVING count(*) = 1)
+    ORDER BY leg_id, abs(trade_id - leg_id)
+)
+UPDATE portfolio_transactions p
+SET linked_transaction_id = legs.trade_id
+FROM legs
+WHERE p.transaction_id = legs.leg_id;
+
+CREATE TABLE IF NOT EXISTS public.ledger_audit
+(
+    audit_id serial NOT NULL,
+    transaction_id integer NOT NULL,
+    portfolio_id integer NOT NULL,
+    action character varying(10) NOT NULL CHECK (action IN ('UPDATE', 'VOID', 'UNDO')),
+    changed_by character varying(100) NOT NULL,
+    changed_at timestamp with time zone NOT NULL DEFAULT now(),
+    reason text,
+    before_state jsonb NOT NULL,
+    after_state jsonb NOT NULL,
+    CONSTRAINT ledger_audit_pkey PRIMARY KEY (audit_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_ledger_audit_transaction
+    ON public.ledger_audit (transaction_id, audit_id);
+
+CREATE OR REPLACE FUNCTION ledger_audit_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'ledger_audit es de solo inserción';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS ledger_audit_no_update ON public.ledger_audit;
+DROP TRIGGER IF EXISTS ledger_audit_no_change ON public.ledger_audit;
+CREATE TRIGGER ledger_audit_no_change
+    BEFORE UPDATE OR DELETE ON public.ledger_audit
+    FOR EACH ROW EXECUTE FUNCTION ledger_audit_append_only();
+
+COMMIT;
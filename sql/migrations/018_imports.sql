@@ -0,0 +1,37 @@
+-- Importación de historiales CSV y estados de cuenta de casas de bolsa (ver
+-- src-tauri/src/importer.rs).
+--
+-- * import_batches: cada importación confirmada, con su origen y conteos.
+-- * imported_rows: hash (md5) del contenido normalizado de cada renglón importado por
+--   portafolio; un renglón con el mismo hash se omite al reimportar. transaction_id
+--   apunta al movimiento que generó.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.import_batches
+(
+    batch_id serial NOT NULL,
+    portfolio_id integer NOT NULL
+        REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    broker character varying(20) NOT NULL,
+    file_name text,
+    imported_rows integer NOT NULL DEFAULT 0,
+    duplicate_rows integer NOT NULL DEFAULT 0,
+    invalid_rows integer NOT NULL DEFAULT 0,
+    imported_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT import_batches_pkey PRIMARY KEY (batch_id)
+);
+
+CREATE TABLE IF NOT EXISTS public.imported_rows
+(
+    portfolio_id integer NOT NULL
+        REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    content_hash character(32) NOT NULL,
+    batch_id integer NOT NULL
+        REFERENCES public.import_batches (batch_id) ON DELETE CASCADE,
+    line_number integer NOT NULL,
+    transaction_id integer REFERENCES public.portfolio_transactions (transaction_id) ON DELETE SET NULL,
+    CONSTRAINT imported_rows_pkey PRIMARY KEY (portfolio_id, content_hash)
+);
+
+COMMIT;
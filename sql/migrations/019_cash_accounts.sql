@@ -0,0 +1,35 @@
+-- Cuentas de efectivo por portafolio y moneda (ver src-tauri/src/cash.rs).
+--
+-- * cash_accounts: política de cada cuenta. overdraft_limit es el sobregiro o margen que se
+--   permite al comprar o retirar (0 = el saldo no puede quedar en negativo); interest_source
+--   indica si el efectivo ocioso gana intereses a una tasa fija (interest_rate) o a la tasa
+--   de CETES a 28 días menos interest_spread. interest_accrued_through es el último cierre
+--   de mes con intereses ya registrados.
+-- * Los intereses se registran en portfolio_transactions como movimientos INTEREST de CASH;
+--   el CHECK de transaction_type se amplía para aceptarlos.
+-- Sin renglón en cash_accounts una cuenta no tiene sobregiro ni intereses. Las compras ya no
+-- depositan el faltante en automático; los "Auto-deposit" existentes se conservan.
+
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS public.cash_accounts
+(
+    portfolio_id integer NOT NULL
+        REFERENCES public.portafolios (id) ON DELETE CASCADE,
+    currency character varying(3) NOT NULL,
+    overdraft_limit numeric(18,2) NOT NULL DEFAULT 0 CHECK (overdraft_limit >= 0),
+    interest_source character varying(10) NOT NULL DEFAULT 'NONE'
+        CHECK (interest_source IN ('NONE', 'FIXED', 'CETE')),
+    interest_rate numeric(9,6) NOT NULL DEFAULT 0 CHECK (interest_rate >= 0 AND interest_rate < 1),
+    interest_spread numeric(9,6) NOT NULL DEFAULT 0 CHECK (interest_spread >= 0 AND interest_spread < 1),
+    interest_accrued_through date,
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    CONSTRAINT cash_accounts_pkey PRIMARY KEY (portfolio_id, currency)
+);
+
+ALTER TABLE public.portfolio_transactions
+    DROP CONSTRAINT IF EXISTS portfolio_transactions_transaction_type_check,
+    ADD CONSTRAINT portfolio_transactions_transaction_type_check
+        CHECK (transaction_type IN ('BUY', 'SELL', 'DIVIDEND', 'DEPOSIT', 'WITHDRAWAL', 'INTEREST'));
+
+COMMIT;
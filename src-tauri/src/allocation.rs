@@ -0,0 +1,548 @@
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use tokio_postgres::GenericClient;
+
+use crate::fees;
+use crate::fx;
+use crate::ledger::{self, TradeFees, TransactionKind};
+use crate::money;
+use crate::valuation;
+
+// El efectivo es un renglón más de la asignación en cualquier dimensión
+pub const CASH_BUCKET: &str = "EFECTIVO";
+pub const UNCLASSIFIED: &str = "SIN CLASIFICAR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AllocationDimension {
+    Ticker,
+    Sector,
+    // Acción, FIBRA, ETF/TRAC, a partir de emisoras.tipo_valor
+    TipoValor,
+}
+
+impl AllocationDimension {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AllocationDimension::Ticker => "TICKER",
+            AllocationDimension::Sector => "SECTOR",
+            AllocationDimension::TipoValor => "TIPO_VALOR",
+        }
+    }
+}
+
+impl fmt::Display for AllocationDimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AllocationDimension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "TICKER" | "EMISORA" => Ok(AllocationDimension::Ticker),
+            "SECTOR" => Ok(AllocationDimension::Sector),
+            "TIPO_VALOR" | "TIPO" | "ASSET_CLASS" => Ok(AllocationDimension::TipoValor),
+            _ => Err(format!("Dimensión de asignación desconocida: {} (usar TICKER, SECTOR o TIPO_VALOR)", s)),
+        }
+    }
+}
+
+/// Datos de clasificación que no vienen de DataBursátil. `asset_class` reemplaza al
+/// derivado de emisoras.tipo_valor; `lot_size` es el múltiplo mínimo de operación.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickerClassification {
+    pub ticker: String,
+    pub sector: Option<String>,
+    pub asset_class: Option<String>,
+    pub lot_size: Decimal,
+}
+
+/// Peso objetivo de un renglón, en porcentaje.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationTarget {
+    pub bucket: String,
+    pub weight: Decimal,
+}
+
+/// Renglón de la asignación. Pesos y desviación en porcentaje del valor total;
+/// `drift_value` es lo que sobra (positivo) o falta (negativo) contra el objetivo, en pesos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationBucket {
+    pub bucket: String,
+    pub market_value: Decimal,
+    pub weight: Decimal,
+    pub target_weight: Option<Decimal>,
+    pub drift: Option<Decimal>,
+    pub drift_value: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllocationReport {
+    pub portfolio_id: i32,
+    pub dimension: AllocationDimension,
+    pub total_value: Decimal,
+    pub buckets: Vec<AllocationBucket>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebalanceOptions {
+    // Solo compras con el efectivo disponible
+    #[serde(default)]
+    pub no_sell: bool,
+    // Operaciones más chicas que esto (en pesos) no se proponen
+    #[serde(default)]
+    pub min_trade_value: Option<Decimal>,
+}
+
+/// Operación propuesta; precio, importe y comisiones en la moneda del ticker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RebalanceTrade {
+    pub ticker: String,
+    pub transaction_type: TransactionKind,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub currency: String,
+    pub gross_amount: Decimal,
+    pub fees: TradeFees,
+    pub total_amount: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RebalancePlan {
+    pub portfolio_id: i32,
+    pub dimension: AllocationDimension,
+    pub trades: Vec<RebalanceTrade>,
+    pub before: AllocationReport,
+    pub after: AllocationReport,
+    pub warnings: Vec<String>,
+}
+
+// Posición con lo necesario para asignarla y operarla
+#[derive(Debug, Clone)]
+struct Holding {
+    ticker: String,
+    bucket: String,
+    currency: String,
+    quantity: Decimal,
+    price: Decimal,
+    // Pesos por unidad de `currency`
+    fx_rate: Decimal,
+    lot_size: Decimal,
+}
+
+impl Holding {
+    fn price_mxn(&self) -> Decimal {
+        self.price * self.fx_rate
+    }
+
+    fn value_mxn(&self) -> Decimal {
+        money::round_mxn(self.quantity * self.price_mxn())
+    }
+}
+
+fn normalize_bucket(bucket: &str) -> String {
+    bucket.trim().to_uppercase()
+}
+
+/// Clase de activo a partir de la descripción de tipo de valor de DataBursátil.
+pub fn asset_class_of(tipo_valor: Option<&str>) -> String {
+    let Some(tipo) = tipo_valor.map(normalize_bucket).filter(|t| !t.is_empty()) else {
+        return UNCLASSIFIED.to_string();
+    };
+    if tipo.contains("FIBRA") || tipo.contains("INMOBILIARI") {
+        "FIBRA".to_string()
+    } else if tipo.contains("TRAC") || tipo.contains("ETF") || tipo.contains("INDIZAD") {
+        "ETF/TRAC".to_string()
+    } else if tipo.contains("ACCI") {
+        "ACCION".to_string()
+    } else {
+        tipo
+    }
+}
+
+/// Clasificación efectiva de un ticker: la capturada en ticker_classifications o la derivada
+/// de emisoras.tipo_valor.
+#[derive(Debug, Clone)]
+pub struct Classification {
+    pub sector: Option<String>,
+    pub asset_class: String,
+    pub lot_size: Decimal,
+}
+
+pub async fn classifications<C: GenericClient>(
+    client: &C,
+    tickers: &[String],
+) -> Result<HashMap<String, Classification>, String> {
+    let rows = client.query(
+        "SELECT t.ticker, c.sector, c.asset_class, c.lot_size, e.tipo_valor
+         FROM unnest($1::text[]) AS t(ticker)
+         LEFT JOIN ticker_classifications c ON c.ticker = t.ticker
+         LEFT JOIN LATERAL (
+             SELECT tipo_valor FROM emisoras WHERE (emisoras || serie) = t.ticker OR emisoras = t.ticker LIMIT 1
+         ) e ON true",
+        &[&tickers],
+    ).await.map_err(|e| format!("Error al consultar la clasificación de las emisoras: {}", e))?;
+    Ok(rows.iter()
+        .map(|row| {
+            let sector: Option<String> = row.get("sector");
+            let asset_class: Option<String> = row.get("asset_class");
+            let tipo_valor: Option<String> = row.get("tipo_valor");
+            let lot_size: Option<Decimal> = row.get("lot_size");
+            (
+                row.get("ticker"),
+                Classification {
+                    sector: sector.map(|s| normalize_bucket(&s)),
+                    asset_class: asset_class.map(|c| normalize_bucket(&c)).unwrap_or_else(|| asset_class_of(tipo_valor.as_deref())),
+                    lot_size: lot_size.unwrap_or(Decimal::ONE),
+                },
+            )
+        })
+        .collect())
+}
+
+async fn load_targets<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    dimension: AllocationDimension,
+) -> Result<BTreeMap<String, Decimal>, String> {
+    let rows = client.query(
+        "SELECT bucket, target_weight FROM allocation_targets WHERE portfolio_id = $1 AND dimension = $2",
+        &[&portfolio_id, &dimension.as_str()],
+    ).await.map_err(|e| format!("Error al consultar la asignación objetivo: {}", e))?;
+    Ok(rows.iter().map(|r| (r.get("bucket"), r.get("target_weight"))).collect())
+}
+
+// Posiciones con precio y el efectivo por moneda. Las posiciones sin cotización se reportan
+// aparte: no se pueden valuar ni operar.
+async fn load_holdings<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    dimension: AllocationDimension,
+    extra_tickers: &[String],
+    warnings: &mut Vec<String>,
+) -> Result<(Vec<Holding>, BTreeMap<String, (Decimal, Decimal)>), String> {
+    let valuations = valuation::value_holdings(client, portfolio_id, fx::MXN).await?;
+    let mut tickers: Vec<String> = valuations.iter().map(|h| h.ticker.clone()).collect();
+    for ticker in extra_tickers {
+        if !tickers.contains(ticker) {
+            tickers.push(ticker.clone());
+        }
+    }
+    let classes = classifications(client, &tickers).await?;
+    let bucket_of = |ticker: &str| -> String {
+        let class = classes.get(ticker);
+        match dimension {
+            AllocationDimension::Ticker => ticker.to_string(),
+            AllocationDimension::Sector => class.and_then(|c| c.sector.clone()).unwrap_or_else(|| UNCLASSIFIED.to_string()),
+            AllocationDimension::TipoValor => class.map(|c| c.asset_class.clone()).unwrap_or_else(|| UNCLASSIFIED.to_string()),
+        }
+    };
+    let lot_of = |ticker: &str| classes.get(ticker).map(|c| c.lot_size).unwrap_or(Decimal::ONE);
+
+    let mut holdings = Vec::new();
+    for valuation in valuations {
+        // Sin precio positivo no se puede valuar ni calcular títulos a operar
+        let Some(price) = valuation.current_price.filter(|p| *p > Decimal::ZERO) else {
+            warnings.push(format!("{} no tiene cotización; no se incluye en la asignación", valuation.ticker));
+            continue;
+        };
+        holdings.push(Holding {
+            bucket: bucket_of(&valuation.ticker),
+            lot_size: lot_of(&valuation.ticker),
+            ticker: valuation.ticker,
+            currency: valuation.currency,
+            quantity: valuation.quantity,
+            price,
+            fx_rate: valuation.fx_rate,
+        });
+    }
+    // Tickers con objetivo que aún no se tienen; cotizan en pesos
+    for ticker in extra_tickers {
+        if holdings.iter().any(|h| &h.ticker == ticker) {
+            continue;
+        }
+        match valuation::market_price_mxn(ticker).await {
+            Some(price) => holdings.push(Holding {
+                ticker: ticker.clone(),
+                bucket: bucket_of(ticker),
+                currency: fx::MXN.to_string(),
+                quantity: Decimal::ZERO,
+                price,
+                fx_rate: Decimal::ONE,
+                lot_size: lot_of(ticker),
+            }),
+            None => warnings.push(format!("{} no tiene cotización; no se puede comprar", ticker)),
+        }
+    }
+
+    let mut cash = BTreeMap::new();
+    for cash_valuation in valuation::value_cash(client, portfolio_id, fx::MXN).await? {
+        cash.insert(cash_valuation.currency, (cash_valuation.amount, cash_valuation.amount_base));
+    }
+    Ok((holdings, cash))
+}
+
+fn build_report(
+    portfolio_id: i32,
+    dimension: AllocationDimension,
+    holdings: &[Holding],
+    cash_mxn: Decimal,
+    targets: &BTreeMap<String, Decimal>,
+) -> AllocationReport {
+    let mut values: BTreeMap<String, Decimal> = BTreeMap::new();
+    for holding in holdings.iter().filter(|h| h.quantity > Decimal::ZERO) {
+        *values.entry(holding.bucket.clone()).or_default() += holding.value_mxn();
+    }
+    if !cash_mxn.is_zero() {
+        *values.entry(CASH_BUCKET.to_string()).or_default() += cash_mxn;
+    }
+    for bucket in targets.keys() {
+        values.entry(bucket.clone()).or_default();
+    }
+    let total_value: Decimal = values.values().copied().sum();
+
+    let buckets = values.into_iter()
+        .map(|(bucket, market_value)| {
+            let target_weight = if targets.is_empty() { None } else { Some(targets.get(&bucket).copied().unwrap_or_default()) };
+            let weight = money::percent(market_value, total_value);
+            AllocationBucket {
+                drift: target_weight.map(|t| weight - t),
+                drift_value: target_weight.map(|t| market_value - money::round_mxn(total_value * t / Decimal::ONE_HUNDRED)),
+                bucket,
+                market_value,
+                weight,
+                target_weight,
+            }
+        })
+        .collect();
+    AllocationReport { portfolio_id, dimension, total_value, buckets }
+}
+
+fn floor_to_lot(quantity: Decimal, lot_size: Decimal) -> Decimal {
+    if quantity <= Decimal::ZERO {
+        return Decimal::ZERO;
+    }
+    (quantity / lot_size).floor() * lot_size
+}
+
+pub async fn get_allocation_logic(
+    portfolio_id: i32,
+    dimension: &str,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<AllocationReport, String> {
+    let dimension: AllocationDimension = dimension.parse()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let targets = load_targets(&**client, portfolio_id, dimension).await?;
+    let (holdings, cash) = load_holdings(&**client, portfolio_id, dimension, &[], &mut Vec::new()).await?;
+    let cash_mxn = cash.values().map(|(_, mxn)| *mxn).sum();
+    Ok(build_report(portfolio_id, dimension, &holdings, cash_mxn, &targets))
+}
+
+pub async fn set_allocation_targets_logic(
+    portfolio_id: i32,
+    dimension: &str,
+    targets: Vec<AllocationTarget>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<AllocationTarget>, String> {
+    let dimension: AllocationDimension = dimension.parse()?;
+    let mut normalized: BTreeMap<String, Decimal> = BTreeMap::new();
+    for target in &targets {
+        if target.weight < Decimal::ZERO {
+            return Err(format!("El peso objetivo de {} no puede ser negativo", target.bucket));
+        }
+        let mut bucket = normalize_bucket(&target.bucket);
+        if bucket == ledger::CASH_TICKER {
+            bucket = CASH_BUCKET.to_string();
+        }
+        if bucket.is_empty() || normalized.insert(bucket.clone(), target.weight).is_some() {
+            return Err(format!("Renglón de asignación inválido o repetido: {}", target.bucket));
+        }
+    }
+    let total: Decimal = normalized.values().copied().sum();
+    if !normalized.is_empty() && total != Decimal::ONE_HUNDRED {
+        return Err(format!("Los pesos objetivo deben sumar 100% (suman {}%)", total));
+    }
+
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    tx.execute(
+        "DELETE FROM allocation_targets WHERE portfolio_id = $1 AND dimension = $2",
+        &[&portfolio_id, &dimension.as_str()],
+    ).await.map_err(|e| format!("No se pudo actualizar la asignación objetivo: {}", e))?;
+    for (bucket, weight) in &normalized {
+        tx.execute(
+            "INSERT INTO allocation_targets (portfolio_id, dimension, bucket, target_weight) VALUES ($1, $2, $3, $4)",
+            &[&portfolio_id, &dimension.as_str(), bucket, weight],
+        ).await.map_err(|e| format!("No se pudo guardar el objetivo de {}: {}", bucket, e))?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(normalized.into_iter().map(|(bucket, weight)| AllocationTarget { bucket, weight }).collect())
+}
+
+pub async fn set_ticker_classification_logic(
+    classification: TickerClassification,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<TickerClassification, String> {
+    if classification.lot_size <= Decimal::ZERO {
+        return Err("El lote mínimo debe ser positivo".to_string());
+    }
+    let classification = TickerClassification {
+        ticker: normalize_bucket(&classification.ticker),
+        sector: classification.sector.map(|s| normalize_bucket(&s)).filter(|s| !s.is_empty()),
+        asset_class: classification.asset_class.map(|c| normalize_bucket(&c)).filter(|c| !c.is_empty()),
+        lot_size: money::round_quantity(classification.lot_size),
+    };
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    client.execute(
+        "INSERT INTO ticker_classifications (ticker, sector, asset_class, lot_size) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (ticker) DO UPDATE SET sector = EXCLUDED.sector, asset_class = EXCLUDED.asset_class, lot_size = EXCLUDED.lot_size",
+        &[&classification.ticker, &classification.sector, &classification.asset_class, &classification.lot_size],
+    ).await.map_err(|e| format!("No se pudo guardar la clasificación de {}: {}", classification.ticker, e))?;
+    Ok(classification)
+}
+
+/// Lista de operaciones para acercar el portafolio a su asignación objetivo.
+/// El objetivo de cada renglón se reparte entre sus emisoras en proporción a su valor actual;
+/// primero se vende lo sobreponderado (salvo `no_sell`) y luego se compra lo más rezagado con
+/// el efectivo disponible en la moneda del ticker, en múltiplos del lote y con comisiones.
+pub async fn get_rebalance_plan_logic(
+    portfolio_id: i32,
+    dimension: &str,
+    options: RebalanceOptions,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<RebalancePlan, String> {
+    let dimension: AllocationDimension = dimension.parse()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let targets = load_targets(&**client, portfolio_id, dimension).await?;
+    if targets.is_empty() {
+        return Err(format!("El portafolio no tiene asignación objetivo por {}", dimension));
+    }
+    let schedule = fees::fee_schedule(&**client, portfolio_id).await?;
+    let min_trade_value = options.min_trade_value.unwrap_or_default();
+
+    let mut warnings = Vec::new();
+    let new_tickers: Vec<String> = match dimension {
+        AllocationDimension::Ticker => targets.keys().filter(|t| t.as_str() != CASH_BUCKET).cloned().collect(),
+        _ => Vec::new(),
+    };
+    let (mut holdings, cash) = load_holdings(&**client, portfolio_id, dimension, &new_tickers, &mut warnings).await?;
+    let cash_mxn: Decimal = cash.values().map(|(_, mxn)| *mxn).sum();
+    let before = build_report(portfolio_id, dimension, &holdings, cash_mxn, &targets);
+    let total_value = before.total_value;
+
+    // Valor objetivo por emisora
+    let mut bucket_values: HashMap<String, (Decimal, usize)> = HashMap::new();
+    for holding in &holdings {
+        let entry = bucket_values.entry(holding.bucket.clone()).or_default();
+        entry.0 += holding.value_mxn();
+        entry.1 += 1;
+    }
+    for bucket in targets.keys().filter(|b| b.as_str() != CASH_BUCKET) {
+        if !bucket_values.contains_key(bucket) {
+            warnings.push(format!("No hay emisoras en {} para comprar; agregue una posición o un objetivo por ticker", bucket));
+        }
+    }
+    let desired: Vec<Decimal> = holdings.iter()
+        .map(|holding| {
+            let bucket_target = total_value * targets.get(&holding.bucket).copied().unwrap_or_default() / Decimal::ONE_HUNDRED;
+            let (bucket_value, count) = bucket_values[&holding.bucket];
+            let share = if bucket_value > Decimal::ZERO {
+                holding.value_mxn() / bucket_value
+            } else {
+                Decimal::ONE / Decimal::from(count)
+            };
+            bucket_target * share
+        })
+        .collect();
+
+    let mut available: HashMap<String, Decimal> = cash.iter().map(|(currency, (amount, _))| (currency.clone(), *amount)).collect();
+    let mut trades = Vec::new();
+
+    if !options.no_sell {
+        for (holding, target) in holdings.iter_mut().zip(&desired) {
+            let excess = holding.value_mxn() - *target;
+            let quantity = floor_to_lot(excess / holding.price_mxn(), holding.lot_size).min(holding.quantity);
+            let gross = money::round_mxn(quantity * holding.price);
+            if quantity <= Decimal::ZERO || gross * holding.fx_rate < min_trade_value {
+                continue;
+            }
+            let fees = schedule.fees_for(gross);
+            let proceeds = gross - fees.total();
+            if proceeds <= Decimal::ZERO {
+                continue;
+            }
+            *available.entry(holding.currency.clone()).or_default() += proceeds;
+            holding.quantity -= quantity;
+            trades.push(RebalanceTrade {
+                ticker: holding.ticker.clone(),
+                transaction_type: TransactionKind::Sell,
+                quantity,
+                price: holding.price,
+                currency: holding.currency.clone(),
+                gross_amount: gross,
+                fees,
+                total_amount: proceeds,
+            });
+        }
+    }
+
+    // Compras: primero lo que más falta para llegar al objetivo
+    let mut order: Vec<usize> = (0..holdings.len()).collect();
+    order.sort_by(|a, b| {
+        let shortfall = |i: usize| desired[i] - holdings[i].value_mxn();
+        shortfall(*b).cmp(&shortfall(*a))
+    });
+    for i in order {
+        let holding = &mut holdings[i];
+        let shortfall = desired[i] - holding.value_mxn();
+        if shortfall <= Decimal::ZERO {
+            continue;
+        }
+        let cash_left = available.get(&holding.currency).copied().unwrap_or_default();
+        let budget_mxn = shortfall.min(cash_left * holding.fx_rate);
+        let mut quantity = floor_to_lot(budget_mxn / holding.price_mxn(), holding.lot_size);
+        let mut gross = money::round_mxn(quantity * holding.price);
+        let mut fees = schedule.fees_for(gross);
+        while quantity > Decimal::ZERO && gross + fees.total() > cash_left {
+            quantity -= holding.lot_size;
+            gross = money::round_mxn(quantity * holding.price);
+            fees = schedule.fees_for(gross);
+        }
+        if quantity <= Decimal::ZERO || gross * holding.fx_rate < min_trade_value {
+            continue;
+        }
+        let total = gross + fees.total();
+        *available.entry(holding.currency.clone()).or_default() -= total;
+        holding.quantity += quantity;
+        trades.push(RebalanceTrade {
+            ticker: holding.ticker.clone(),
+            transaction_type: TransactionKind::Buy,
+            quantity,
+            price: holding.price,
+            currency: holding.currency.clone(),
+            gross_amount: gross,
+            fees,
+            total_amount: total,
+        });
+    }
+
+    let mut cash_after = Decimal::ZERO;
+    for (currency, amount) in &available {
+        let rate = fx::rate_to_mxn(&**client, currency, Utc::now().date_naive()).await?;
+        cash_after += money::round_mxn(*amount * rate);
+    }
+    let after = build_report(portfolio_id, dimension, &holdings, cash_after, &targets);
+    if trades.is_empty() {
+        warnings.push("No hay operaciones que acerquen el portafolio a su objetivo con el efectivo y lotes disponibles".to_string());
+    }
+
+    Ok(RebalancePlan { portfolio_id, dimension, trades, before, after, warnings })
+}
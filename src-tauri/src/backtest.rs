@@ -0,0 +1,664 @@
+use chrono::{Duration, NaiveDate, Utc};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use tokio_postgres::GenericClient;
+
+use crate::allocation;
+use crate::benchmarks;
+use crate::fees::{self, FeeSchedule};
+use crate::ledger::TransactionKind;
+use crate::money;
+use crate::optimizer::TickerWeight;
+use crate::performance::{to_f64, to_percent};
+use crate::prices::{self, PriceSeries};
+use crate::stress;
+use crate::yield_curve::{self, Interpolation};
+
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+const RISK_FREE_TENOR_DAYS: i64 = 28;
+const DEFAULT_SLIPPAGE_BPS: i64 = 10;
+const DEFAULT_REBALANCE_DAYS: usize = 21;
+const DEFAULT_MOMENTUM_LOOKBACK: usize = 126;
+const DEFAULT_MOMENTUM_TOP: usize = 5;
+const MAX_TICKERS: usize = 60;
+// Desviación (fracción del portafolio) a partir de la cual se rebalancea
+const REBALANCE_TOLERANCE: f64 = 0.01;
+// Emisoras de la muestra del IPC, universo por omisión de la rotación por momentum
+const IPC_CONSTITUENTS: &str = include_str!("ipc.json");
+
+/// Cierres alineados a un mismo calendario. Cada serie se rellena hacia adelante desde su
+/// primer cierre; antes de él el ticker no cotiza.
+pub struct Bars {
+    dates: Vec<NaiveDate>,
+    closes: HashMap<String, Vec<Option<Decimal>>>,
+}
+
+impl Bars {
+    fn new(series: &HashMap<String, PriceSeries>, from: NaiveDate, to: NaiveDate) -> Self {
+        let dates: Vec<NaiveDate> = series.values()
+            .flat_map(|s| s.range(from..=to).map(|(date, _)| *date))
+            .filter(|date| prices::is_business_day(*date))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let closes = series.iter()
+            .map(|(ticker, s)| (ticker.clone(), dates.iter().map(|date| prices::close_on(s, *date)).collect()))
+            .collect();
+        Bars { dates, closes }
+    }
+
+    fn close(&self, ticker: &str, day: usize) -> Option<Decimal> {
+        self.closes.get(ticker).and_then(|c| c[day])
+    }
+}
+
+/// Lo que ve una estrategia al cierre de cada día: precios hasta ese día y pesos actuales.
+pub struct StrategyContext<'a> {
+    // Días hábiles transcurridos desde el inicio del backtest
+    pub step: usize,
+    pub weights: &'a HashMap<String, f64>,
+    day: usize,
+    bars: &'a Bars,
+}
+
+impl StrategyContext<'_> {
+    /// Los últimos `len` cierres hasta hoy, o None si el ticker no cotizaba en todo el tramo.
+    pub fn history(&self, ticker: &str, len: usize) -> Option<Vec<f64>> {
+        if len == 0 || len > self.day + 1 {
+            return None;
+        }
+        let closes = self.bars.closes.get(ticker)?;
+        closes[self.day + 1 - len..=self.day].iter().map(|c| c.map(to_f64)).collect()
+    }
+}
+
+/// Estrategia basada en reglas. Se consulta al cierre de cada día; si devuelve pesos
+/// (fracciones del valor total, el resto queda en efectivo) se opera al cierre siguiente.
+pub trait Strategy: Send {
+    fn name(&self) -> String;
+    fn universe(&self) -> Vec<String>;
+    /// Días hábiles de histórico que necesita antes del inicio.
+    fn warmup_days(&self) -> usize {
+        0
+    }
+    fn rebalance(&mut self, ctx: &StrategyContext) -> Option<HashMap<String, f64>>;
+}
+
+fn equal_weights(tickers: &[String], slots: usize) -> HashMap<String, f64> {
+    tickers.iter().map(|t| (t.clone(), 1.0 / slots as f64)).collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Cruce de medias móviles: cada ticker ocupa 1/N del portafolio mientras su media rápida
+/// está por encima de la lenta.
+pub struct MovingAverageCrossover {
+    tickers: Vec<String>,
+    fast: usize,
+    slow: usize,
+    invested: Option<Vec<String>>,
+}
+
+impl Strategy for MovingAverageCrossover {
+    fn name(&self) -> String {
+        format!("Cruce de medias {}/{}", self.fast, self.slow)
+    }
+
+    fn universe(&self) -> Vec<String> {
+        self.tickers.clone()
+    }
+
+    fn warmup_days(&self) -> usize {
+        self.slow
+    }
+
+    fn rebalance(&mut self, ctx: &StrategyContext) -> Option<HashMap<String, f64>> {
+        let invested: Vec<String> = self.tickers.iter()
+            .filter(|ticker| {
+                ctx.history(ticker, self.slow)
+                    .is_some_and(|closes| mean(&closes[self.slow - self.fast..]) > mean(&closes))
+            })
+            .cloned()
+            .collect();
+        if self.invested.as_ref() == Some(&invested) {
+            return None;
+        }
+        let weights = equal_weights(&invested, self.tickers.len());
+        self.invested = Some(invested);
+        Some(weights)
+    }
+}
+
+/// Rotación por momentum: cada `rebalance_days` se invierte a partes iguales en los `top`
+/// tickers con mayor rendimiento en los últimos `lookback` días. Los de rendimiento
+/// negativo se quedan fuera y su parte queda en efectivo.
+pub struct MomentumRotation {
+    tickers: Vec<String>,
+    lookback: usize,
+    top: usize,
+    rebalance_days: usize,
+}
+
+impl Strategy for MomentumRotation {
+    fn name(&self) -> String {
+        format!("Momentum {} días, {} emisoras", self.lookback, self.top)
+    }
+
+    fn universe(&self) -> Vec<String> {
+        self.tickers.clone()
+    }
+
+    fn warmup_days(&self) -> usize {
+        self.lookback + 1
+    }
+
+    fn rebalance(&mut self, ctx: &StrategyContext) -> Option<HashMap<String, f64>> {
+        if !ctx.step.is_multiple_of(self.rebalance_days) {
+            return None;
+        }
+        let mut ranked: Vec<(String, f64)> = self.tickers.iter()
+            .filter_map(|ticker| {
+                let closes = ctx.history(ticker, self.lookback + 1)?;
+                (closes[0] > 0.0).then(|| (ticker.clone(), closes[self.lookback] / closes[0] - 1.0))
+            })
+            .filter(|(_, momentum)| *momentum > 0.0)
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let winners: Vec<String> = ranked.into_iter().take(self.top).map(|(ticker, _)| ticker).collect();
+        Some(equal_weights(&winners, self.top))
+    }
+}
+
+/// Rebalanceo periódico a pesos fijos. Se omite si ningún peso se desvió más de
+/// `REBALANCE_TOLERANCE` del objetivo, para no pagar comisiones de más.
+pub struct PeriodicRebalance {
+    targets: HashMap<String, f64>,
+    rebalance_days: usize,
+}
+
+impl Strategy for PeriodicRebalance {
+    fn name(&self) -> String {
+        format!("Rebalanceo cada {} días", self.rebalance_days)
+    }
+
+    fn universe(&self) -> Vec<String> {
+        self.targets.keys().cloned().collect()
+    }
+
+    fn rebalance(&mut self, ctx: &StrategyContext) -> Option<HashMap<String, f64>> {
+        if !ctx.step.is_multiple_of(self.rebalance_days) {
+            return None;
+        }
+        let drifted = ctx.step == 0 || self.targets.iter()
+            .map(|(ticker, target)| (target - ctx.weights.get(ticker).copied().unwrap_or_default()).abs())
+            .any(|drift| drift > REBALANCE_TOLERANCE);
+        drifted.then(|| self.targets.clone())
+    }
+}
+
+fn default_rebalance_days() -> usize {
+    DEFAULT_REBALANCE_DAYS
+}
+
+fn default_momentum_lookback() -> usize {
+    DEFAULT_MOMENTUM_LOOKBACK
+}
+
+fn default_momentum_top() -> usize {
+    DEFAULT_MOMENTUM_TOP
+}
+
+/// Estrategias disponibles desde la interfaz. `targets` en porcentaje; sin `tickers` la
+/// rotación por momentum usa las emisoras del IPC.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StrategyConfig {
+    MovingAverageCrossover {
+        tickers: Vec<String>,
+        fast: usize,
+        slow: usize,
+    },
+    MomentumRotation {
+        #[serde(default)]
+        tickers: Vec<String>,
+        #[serde(default = "default_momentum_lookback")]
+        lookback: usize,
+        #[serde(default = "default_momentum_top")]
+        top: usize,
+        #[serde(default = "default_rebalance_days")]
+        rebalance_days: usize,
+    },
+    PeriodicRebalance {
+        targets: Vec<TickerWeight>,
+        #[serde(default = "default_rebalance_days")]
+        rebalance_days: usize,
+    },
+}
+
+fn normalize_tickers(tickers: &[String]) -> Result<Vec<String>, String> {
+    let tickers: Vec<String> = tickers.iter()
+        .map(|t| t.trim().to_uppercase())
+        .filter(|t| !t.is_empty())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if tickers.is_empty() {
+        return Err("La estrategia necesita al menos un ticker".to_string());
+    }
+    if tickers.len() > MAX_TICKERS {
+        return Err(format!("La estrategia admite hasta {} tickers", MAX_TICKERS));
+    }
+    Ok(tickers)
+}
+
+impl StrategyConfig {
+    pub fn build(&self) -> Result<Box<dyn Strategy>, String> {
+        match self {
+            StrategyConfig::MovingAverageCrossover { tickers, fast, slow } => {
+                if *fast == 0 || fast >= slow {
+                    return Err("La media rápida debe ser positiva y menor que la lenta".to_string());
+                }
+                Ok(Box::new(MovingAverageCrossover {
+                    tickers: normalize_tickers(tickers)?,
+                    fast: *fast,
+                    slow: *slow,
+                    invested: None,
+                }))
+            }
+            StrategyConfig::MomentumRotation { tickers, lookback, top, rebalance_days } => {
+                let tickers = if tickers.is_empty() {
+                    serde_json::from_str::<Vec<String>>(IPC_CONSTITUENTS)
+                        .map_err(|e| format!("No se pudo leer la muestra del IPC: {}", e))?
+                } else {
+                    tickers.clone()
+                };
+                if *lookback == 0 || *top == 0 || *rebalance_days == 0 {
+                    return Err("El periodo, el número de emisoras y la frecuencia deben ser positivos".to_string());
+                }
+                Ok(Box::new(MomentumRotation {
+                    tickers: normalize_tickers(&tickers)?,
+                    lookback: *lookback,
+                    top: *top,
+                    rebalance_days: *rebalance_days,
+                }))
+            }
+            StrategyConfig::PeriodicRebalance { targets, rebalance_days } => {
+                if *rebalance_days == 0 {
+                    return Err("La frecuencia de rebalanceo debe ser positiva".to_string());
+                }
+                if targets.iter().any(|t| t.weight < Decimal::ZERO) {
+                    return Err("Los pesos no pueden ser negativos".to_string());
+                }
+                let total: Decimal = targets.iter().map(|t| t.weight).sum();
+                if total > Decimal::ONE_HUNDRED {
+                    return Err(format!("Los pesos suman {}%, más de 100%", total));
+                }
+                let tickers: Vec<String> = targets.iter().map(|t| t.ticker.clone()).collect();
+                normalize_tickers(&tickers)?;
+                Ok(Box::new(PeriodicRebalance {
+                    targets: targets.iter()
+                        .map(|t| (t.ticker.trim().to_uppercase(), to_f64(t.weight) / 100.0))
+                        .collect(),
+                    rebalance_days: *rebalance_days,
+                }))
+            }
+        }
+    }
+}
+
+/// Con `portfolio_id` se aplican su esquema de comisiones; sin él, operaciones sin comisión.
+/// `slippage_bps` en puntos base sobre el cierre (10 por omisión), en contra de cada operación.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestParams {
+    pub strategy: StrategyConfig,
+    pub start_date: NaiveDate,
+    #[serde(default)]
+    pub end_date: Option<NaiveDate>,
+    pub initial_capital: Decimal,
+    #[serde(default)]
+    pub portfolio_id: Option<i32>,
+    #[serde(default)]
+    pub slippage_bps: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestTrade {
+    pub date: NaiveDate,
+    pub ticker: String,
+    pub side: TransactionKind,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub gross_amount: Decimal,
+    pub fees: Decimal,
+    pub slippage: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EquityPoint {
+    pub date: NaiveDate,
+    pub equity: Decimal,
+    pub cash: Decimal,
+    pub drawdown: Decimal,
+}
+
+/// Rendimientos, volatilidad y caída máxima en porcentaje; Sharpe sobre el exceso diario
+/// contra la curva de CETES a 28 días vigente cada día.
+/// `turnover` es el volumen operado entre el valor promedio, anualizado.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestStats {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub initial_capital: Decimal,
+    pub final_value: Decimal,
+    pub total_return: Option<Decimal>,
+    pub annualized_return: Option<Decimal>,
+    pub annualized_volatility: Option<Decimal>,
+    pub sharpe_ratio: Option<Decimal>,
+    pub max_drawdown: Option<Decimal>,
+    pub benchmark_return: Option<Decimal>,
+    pub trades: usize,
+    pub total_fees: Decimal,
+    pub total_slippage: Decimal,
+    pub turnover: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestResult {
+    pub strategy: String,
+    pub stats: BacktestStats,
+    pub equity_curve: Vec<EquityPoint>,
+    pub trades: Vec<BacktestTrade>,
+    pub warnings: Vec<String>,
+}
+
+struct Account<'a> {
+    cash: Decimal,
+    positions: HashMap<String, Decimal>,
+    fees: &'a FeeSchedule,
+    lots: &'a HashMap<String, Decimal>,
+    slippage: Decimal,
+    trades: Vec<BacktestTrade>,
+}
+
+impl Account<'_> {
+    fn equity(&self, bars: &Bars, day: usize) -> Decimal {
+        self.cash + self.positions.iter()
+            .map(|(ticker, quantity)| *quantity * bars.close(ticker, day).unwrap_or_default())
+            .sum::<Decimal>()
+    }
+
+    fn weights(&self, bars: &Bars, day: usize) -> HashMap<String, f64> {
+        let equity = to_f64(self.equity(bars, day));
+        self.positions.iter()
+            .filter(|(_, quantity)| **quantity > Decimal::ZERO)
+            .map(|(ticker, quantity)| {
+                let value = to_f64(*quantity * bars.close(ticker, day).unwrap_or_default());
+                (ticker.clone(), if equity > 0.0 { value / equity } else { 0.0 })
+            })
+            .collect()
+    }
+
+    fn fill(&mut self, date: NaiveDate, ticker: &str, side: TransactionKind, quantity: Decimal, close: Decimal) {
+        let price = match side {
+            TransactionKind::Sell => money::round_price(close * (Decimal::ONE - self.slippage)),
+            _ => money::round_price(close * (Decimal::ONE + self.slippage)),
+        };
+        let gross_amount = money::round_mxn(quantity * price);
+        let fees = self.fees.fees_for(gross_amount).total();
+        let position = self.positions.entry(ticker.to_string()).or_default();
+        match side {
+            TransactionKind::Sell => {
+                *position -= quantity;
+                self.cash += gross_amount - fees;
+            }
+            _ => {
+                *position += quantity;
+                self.cash -= gross_amount + fees;
+            }
+        }
+        self.trades.push(BacktestTrade {
+            date,
+            ticker: ticker.to_string(),
+            side,
+            quantity,
+            price,
+            gross_amount,
+            fees,
+            slippage: money::round_mxn(quantity * (price - close).abs()),
+        });
+    }
+
+    // Lleva las posiciones a los pesos objetivo al cierre del día; primero ventas, luego compras
+    fn rebalance(&mut self, bars: &Bars, day: usize, targets: &HashMap<String, f64>, warnings: &mut Vec<String>) {
+        let date = bars.dates[day];
+        let total: f64 = targets.values().map(|w| w.max(0.0)).sum();
+        let scale = if total > 1.0 { 1.0 / total } else { 1.0 };
+        let equity = self.equity(bars, day);
+
+        let tickers: BTreeSet<String> = targets.keys().chain(self.positions.keys()).cloned().collect();
+        let mut buys = Vec::new();
+        for ticker in tickers {
+            let Some(close) = bars.close(&ticker, day) else {
+                if targets.get(&ticker).is_some_and(|w| *w > 0.0) {
+                    warnings.push(format!("{} no cotizaba el {}; no se compró", ticker, date));
+                }
+                continue;
+            };
+            let weight = targets.get(&ticker).map(|w| w.max(0.0) * scale).unwrap_or_default();
+            let lot = self.lots.get(&ticker).copied().unwrap_or(Decimal::ONE);
+            let buy_price = close * (Decimal::ONE + self.slippage);
+            let target_value = equity * Decimal::from_f64(weight).unwrap_or_default();
+            let desired = (target_value / buy_price / lot).floor() * lot;
+            let current = self.positions.get(&ticker).copied().unwrap_or_default();
+            if desired < current {
+                self.fill(date, &ticker, TransactionKind::Sell, current - desired, close);
+            } else if desired > current {
+                buys.push((ticker, desired - current, close, lot));
+            }
+        }
+
+        for (ticker, wanted, close, lot) in buys {
+            let buy_price = close * (Decimal::ONE + self.slippage);
+            let mut quantity = wanted.min((self.cash.max(Decimal::ZERO) / buy_price / lot).floor() * lot);
+            // Se recorta de lote en lote hasta que alcance para la comisión
+            while quantity > Decimal::ZERO {
+                let gross = money::round_mxn(quantity * money::round_price(buy_price));
+                if gross + self.fees.fees_for(gross).total() <= self.cash {
+                    break;
+                }
+                quantity -= lot;
+            }
+            if quantity > Decimal::ZERO {
+                self.fill(date, &ticker, TransactionKind::Buy, quantity, close);
+            }
+        }
+        self.positions.retain(|_, quantity| *quantity > Decimal::ZERO);
+    }
+}
+
+fn simulate(
+    strategy: &mut dyn Strategy,
+    bars: &Bars,
+    start: usize,
+    params: &BacktestParams,
+    account: &mut Account,
+    warnings: &mut Vec<String>,
+) -> Vec<EquityPoint> {
+    let mut curve = Vec::new();
+    let mut pending: Option<HashMap<String, f64>> = None;
+    let mut peak = Decimal::ZERO;
+    for day in start..bars.dates.len() {
+        // Las señales del cierre anterior se ejecutan al cierre de hoy
+        if let Some(targets) = pending.take() {
+            account.rebalance(bars, day, &targets, warnings);
+        }
+        let equity = money::round_mxn(account.equity(bars, day));
+        peak = peak.max(equity).max(params.initial_capital);
+        curve.push(EquityPoint {
+            date: bars.dates[day],
+            equity,
+            cash: money::round_mxn(account.cash),
+            drawdown: money::percent(equity - peak, peak),
+        });
+        let weights = account.weights(bars, day);
+        let ctx = StrategyContext { step: day - start, weights: &weights, day, bars };
+        pending = strategy.rebalance(&ctx);
+    }
+    curve
+}
+
+fn statistics(
+    params: &BacktestParams,
+    curve: &[EquityPoint],
+    trades: &[BacktestTrade],
+    benchmark_return: Option<f64>,
+    risk_free: &[f64],
+) -> BacktestStats {
+    let first = curve.first().unwrap();
+    let last = curve.last().unwrap();
+    let initial = to_f64(params.initial_capital);
+    let equities: Vec<f64> = std::iter::once(initial).chain(curve.iter().map(|p| to_f64(p.equity))).collect();
+    let returns: Vec<f64> = equities.windows(2)
+        .map(|pair| if pair[0] > 0.0 { pair[1] / pair[0] - 1.0 } else { 0.0 })
+        .collect();
+
+    let total_return = equities[equities.len() - 1] / initial - 1.0;
+    let years = returns.len() as f64 / TRADING_DAYS_PER_YEAR;
+    let annualized_return = (years >= 1.0).then(|| (1.0 + total_return).powf(1.0 / years) - 1.0);
+    let volatility = (returns.len() > 1).then(|| {
+        let m = mean(&returns);
+        let variance = returns.iter().map(|r| (r - m).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+        (variance * TRADING_DAYS_PER_YEAR).sqrt()
+    });
+    let excess: Vec<f64> = returns.iter().zip(risk_free).map(|(r, rf)| r - rf).collect();
+    let sharpe = volatility
+        .filter(|v| *v > 0.0)
+        .map(|v| mean(&excess) * TRADING_DAYS_PER_YEAR / v);
+    let max_drawdown = curve.iter().map(|p| p.drawdown).min();
+    let traded: f64 = trades.iter().map(|t| to_f64(t.gross_amount)).sum();
+    let turnover = (years > 0.0).then(|| traded / mean(&equities[1..]) / years);
+
+    BacktestStats {
+        start_date: first.date,
+        end_date: last.date,
+        initial_capital: params.initial_capital,
+        final_value: last.equity,
+        total_return: to_percent(total_return),
+        annualized_return: annualized_return.and_then(to_percent),
+        annualized_volatility: volatility.and_then(to_percent),
+        sharpe_ratio: sharpe.and_then(|s| Decimal::from_f64(s).map(|d| d.round_dp(4))),
+        max_drawdown,
+        benchmark_return: benchmark_return.and_then(to_percent),
+        trades: trades.len(),
+        total_fees: trades.iter().map(|t| t.fees).sum(),
+        total_slippage: trades.iter().map(|t| t.slippage).sum(),
+        turnover: turnover.and_then(to_percent),
+    }
+}
+
+/// Corre una estrategia sobre los cierres diarios guardados (se descargan los que falten).
+/// Las señales se calculan al cierre y se ejecutan al cierre del día hábil siguiente, en
+/// lotes completos, con deslizamiento y comisiones.
+pub async fn backtest_strategy<C: GenericClient>(
+    client: &C,
+    strategy: &mut dyn Strategy,
+    params: &BacktestParams,
+) -> Result<BacktestResult, String> {
+    if params.initial_capital <= Decimal::ZERO {
+        return Err("El capital inicial debe ser mayor a cero".to_string());
+    }
+    let end_date = params.end_date.unwrap_or_else(|| Utc::now().date_naive());
+    if params.start_date >= end_date {
+        return Err("La fecha de inicio debe ser anterior a la de fin".to_string());
+    }
+    let slippage_bps = params.slippage_bps.unwrap_or(Decimal::from(DEFAULT_SLIPPAGE_BPS));
+    if slippage_bps < Decimal::ZERO || slippage_bps >= Decimal::from(10_000) {
+        return Err("El deslizamiento debe estar entre 0 y 10,000 puntos base".to_string());
+    }
+
+    let mut warnings = Vec::new();
+    // Días naturales suficientes para cubrir el calentamiento en días hábiles
+    let load_from = params.start_date - Duration::days(strategy.warmup_days() as i64 * 7 / 5 + 10);
+    let mut series = HashMap::new();
+    for ticker in strategy.universe() {
+        let closes = prices::daily_closes(client, &ticker, load_from).await?;
+        if closes.range(..=end_date).next().is_none() {
+            warnings.push(format!("{} no tiene cierres en el periodo; se omite", ticker));
+            continue;
+        }
+        series.insert(ticker, closes);
+    }
+    if series.is_empty() {
+        return Err("Ningún ticker de la estrategia tiene cierres en el periodo".to_string());
+    }
+
+    let bars = Bars::new(&series, load_from, end_date);
+    let start = bars.dates.iter().position(|date| *date >= params.start_date)
+        .ok_or_else(|| "No hay cierres a partir de la fecha de inicio".to_string())?;
+    if start < strategy.warmup_days() {
+        warnings.push(format!(
+            "Solo hay {} días de histórico antes del inicio y la estrategia necesita {}",
+            start, strategy.warmup_days()
+        ));
+    }
+
+    let tickers: Vec<String> = series.keys().cloned().collect();
+    let lots: HashMap<String, Decimal> = allocation::classifications(client, &tickers).await?
+        .into_iter()
+        .map(|(ticker, class)| (ticker, class.lot_size))
+        .collect();
+    let fee_schedule = match params.portfolio_id {
+        Some(portfolio_id) => fees::fee_schedule(client, portfolio_id).await?,
+        None => FeeSchedule::none(0),
+    };
+    let benchmark = benchmarks::stored_levels(client, stress::MARKET_INDEX).await?;
+    let benchmark_return = match (prices::close_on(&benchmark, bars.dates[start]), prices::close_on(&benchmark, end_date)) {
+        (Some(first), Some(last)) if first > Decimal::ZERO => Some(to_f64(last / first) - 1.0),
+        _ => None,
+    };
+
+    let mut account = Account {
+        cash: params.initial_capital,
+        positions: HashMap::new(),
+        fees: &fee_schedule,
+        lots: &lots,
+        slippage: slippage_bps / Decimal::from(10_000),
+        trades: Vec::new(),
+    };
+    let curve = simulate(strategy, &bars, start, params, &mut account, &mut warnings);
+    // Tasa libre de riesgo diaria fechada
+    let curves = yield_curve::curves_between(client, bars.dates[start] - Duration::days(7), end_date).await?;
+    if curves.is_empty() {
+        warnings.push("No hay curvas de rendimiento en el periodo; el Sharpe no descuenta tasa libre de riesgo".to_string());
+    }
+    let risk_free: Vec<f64> = curve.iter()
+        .map(|point| {
+            curves.range(..=point.date).next_back()
+                .map(|(_, c)| c.rate(RISK_FREE_TENOR_DAYS, Interpolation::default()) / 100.0 / TRADING_DAYS_PER_YEAR)
+                .unwrap_or(0.0)
+        })
+        .collect();
+    let stats = statistics(params, &curve, &account.trades, benchmark_return, &risk_free);
+    warnings.dedup();
+
+    Ok(BacktestResult {
+        strategy: strategy.name(),
+        stats,
+        equity_curve: curve,
+        trades: account.trades,
+        warnings,
+    })
+}
+
+pub async fn run_backtest_logic(
+    params: BacktestParams,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<BacktestResult, String> {
+    let mut strategy = params.strategy.build()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    backtest_strategy(&**client, strategy.as_mut(), &params).await
+}
@@ -0,0 +1,372 @@
+use chrono::{NaiveDate, Utc};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio_postgres::GenericClient;
+
+use crate::data_bursatil_client::{self, IndiceItem, TasaItem};
+use crate::money;
+use crate::performance::{self, to_percent};
+use crate::prices::{self, PriceSeries};
+use crate::snapshots;
+use crate::yield_curve;
+
+// Índices que publica DataBursátil; se guarda su nivel de cierre
+pub const INDEX_BENCHMARKS: [&str; 4] = ["IPC", "FTSEBIVA", "SP500", "DJIA"];
+// Tasas anuales en porcentaje (CETES, TIIE y objetivo de Banxico); rinden a diario con base 360
+pub const RATE_BENCHMARKS: [&str; 8] = ["CETE28", "CETE91", "CETE182", "CETE364", "TIIE28", "TIIE91", "TIIE182", "TASA_OBJETIVO"];
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+const RATE_DAY_COUNT: f64 = 360.0;
+// Beta, correlación y razón de información
+const RATIO_SCALE: u32 = 4;
+
+/// Nivel de un índice o tasa de un CETE al cierre de `value_date`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkLevel {
+    pub symbol: String,
+    pub value_date: NaiveDate,
+    pub value: Decimal,
+}
+
+/// Un componente del benchmark. `symbol` puede ser un índice, un CETE o cualquier emisora
+/// con histórico de precios; `weight` en porcentaje.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkComponent {
+    pub symbol: String,
+    pub weight: Decimal,
+}
+
+/// Ambas curvas en base 100 al inicio del periodo, para graficar.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkPoint {
+    pub date: NaiveDate,
+    pub portfolio: Decimal,
+    pub benchmark: Decimal,
+}
+
+/// Comparación contra el benchmark con rendimientos diarios (TWR del portafolio).
+/// Rendimientos, alpha y tracking error en porcentaje, anualizados con 252 días;
+/// las capturas son el rendimiento promedio del portafolio en días de alza (o baja)
+/// del benchmark como porcentaje del rendimiento promedio del benchmark esos días.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkComparison {
+    pub portfolio_id: i32,
+    pub components: Vec<BenchmarkComponent>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub observations: usize,
+    pub portfolio_return: Option<Decimal>,
+    pub benchmark_return: Option<Decimal>,
+    pub excess_return: Option<Decimal>,
+    pub alpha: Option<Decimal>,
+    pub beta: Option<Decimal>,
+    pub correlation: Option<Decimal>,
+    pub tracking_error: Option<Decimal>,
+    pub information_ratio: Option<Decimal>,
+    pub up_capture: Option<Decimal>,
+    pub down_capture: Option<Decimal>,
+    pub series: Vec<BenchmarkPoint>,
+}
+
+enum BenchmarkSeries {
+    Level(PriceSeries),
+    Rate(PriceSeries),
+}
+
+impl BenchmarkSeries {
+    fn period_return(&self, from: NaiveDate, to: NaiveDate) -> Option<f64> {
+        match self {
+            BenchmarkSeries::Level(series) => {
+                let start = prices::close_on(series, from)?;
+                let end = prices::close_on(series, to)?;
+                (!start.is_zero()).then(|| performance::to_f64(end / start) - 1.0)
+            }
+            BenchmarkSeries::Rate(series) => {
+                let rate = performance::to_f64(prices::close_on(series, from)?) / 100.0;
+                Some(rate * (to - from).num_days() as f64 / RATE_DAY_COUNT)
+            }
+        }
+    }
+}
+
+fn normalize_symbol(symbol: &str) -> String {
+    symbol.trim().to_uppercase().replace(' ', "")
+}
+
+fn validate_components(components: &[BenchmarkComponent]) -> Result<Vec<BenchmarkComponent>, String> {
+    if components.is_empty() {
+        return Err("Indique al menos un componente del benchmark".to_string());
+    }
+    let mut total = Decimal::ZERO;
+    let mut normalized = Vec::new();
+    for component in components {
+        if component.weight <= Decimal::ZERO {
+            return Err(format!("El peso de {} debe ser positivo", component.symbol));
+        }
+        total += component.weight;
+        normalized.push(BenchmarkComponent { symbol: normalize_symbol(&component.symbol), weight: component.weight });
+    }
+    if total != Decimal::ONE_HUNDRED {
+        return Err(format!("Los pesos del benchmark deben sumar 100% (suman {}%)", total));
+    }
+    Ok(normalized)
+}
+
+/// Histórico guardado de un índice o tasa, por fecha.
+pub async fn stored_levels<C: GenericClient>(client: &C, symbol: &str) -> Result<PriceSeries, String> {
+    let rows = client.query(
+        "SELECT value_date, value FROM benchmark_levels WHERE symbol = $1 ORDER BY value_date",
+        &[&symbol],
+    ).await.map_err(|e| format!("Error al consultar el histórico de {}: {}", symbol, e))?;
+    Ok(rows.iter().map(|r| (r.get("value_date"), r.get("value"))).collect())
+}
+
+async fn load_series<C: GenericClient>(client: &C, symbol: &str, from: NaiveDate) -> Result<BenchmarkSeries, String> {
+    let series = if INDEX_BENCHMARKS.contains(&symbol) || RATE_BENCHMARKS.contains(&symbol) {
+        stored_levels(client, symbol).await?
+    } else {
+        prices::daily_closes(client, symbol, from).await?
+    };
+    if series.is_empty() {
+        return Err(format!("No hay histórico de {}; cárguelo en benchmark_levels", symbol));
+    }
+    Ok(if RATE_BENCHMARKS.contains(&symbol) {
+        BenchmarkSeries::Rate(series)
+    } else {
+        BenchmarkSeries::Level(series)
+    })
+}
+
+pub async fn save_benchmark_levels<C: GenericClient>(client: &C, levels: &[BenchmarkLevel]) -> Result<usize, String> {
+    for level in levels {
+        let symbol = normalize_symbol(&level.symbol);
+        if !INDEX_BENCHMARKS.contains(&symbol.as_str()) && !RATE_BENCHMARKS.contains(&symbol.as_str()) {
+            return Err(format!(
+                "Benchmark desconocido: {} (usar {} o {})",
+                symbol, INDEX_BENCHMARKS.join(", "), RATE_BENCHMARKS.join(", ")
+            ));
+        }
+        if level.value < Decimal::ZERO || (INDEX_BENCHMARKS.contains(&symbol.as_str()) && level.value.is_zero()) {
+            return Err(format!("Valor inválido para {} al {}", symbol, level.value_date));
+        }
+        client.execute(
+            "INSERT INTO benchmark_levels (symbol, value_date, value) VALUES ($1, $2, $3)
+             ON CONFLICT (symbol, value_date) DO UPDATE SET value = EXCLUDED.value",
+            &[&symbol, &level.value_date, &money::round_price(level.value)],
+        ).await.map_err(|e| format!("No se pudo guardar {} al {}: {}", symbol, level.value_date, e))?;
+    }
+    Ok(levels.len())
+}
+
+pub async fn save_benchmark_levels_logic(levels: Vec<BenchmarkLevel>, db_pool: &deadpool_postgres::Pool) -> Result<usize, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let saved = save_benchmark_levels(&*tx, &levels).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(saved)
+}
+
+/// Guarda el cierre del día de los índices y las tasas (CETES, TIIE y objetivo) que publica
+/// DataBursátil, y la curva de CETES del día.
+pub async fn refresh_benchmark_levels_logic(db_pool: &deadpool_postgres::Pool) -> Result<Vec<BenchmarkLevel>, String> {
+    let indices = data_bursatil_client::get_indices_async()
+        .await
+        .map_err(|e| format!("Error obteniendo índices: {}", e))?;
+    let tasas = data_bursatil_client::get_tasas_struct_async()
+        .await
+        .map_err(|e| format!("Error obteniendo tasas: {}", e))?;
+    let today = Utc::now().date_naive();
+
+    let index_items: [(&str, Option<IndiceItem>); 4] = [
+        ("IPC", indices.IPC),
+        ("FTSEBIVA", indices.FTSEBIVA),
+        ("SP500", indices.SP500),
+        ("DJIA", indices.DJIA),
+    ];
+    let rate_items: [(&str, Option<TasaItem>); 8] = [
+        ("CETE28", tasas.CETE28),
+        ("CETE91", tasas.cete_91),
+        ("CETE182", tasas.CETE182),
+        ("CETE364", tasas.CETE364),
+        ("TIIE28", tasas.TIIE28),
+        ("TIIE91", tasas.TIIE91),
+        ("TIIE182", tasas.TIIE182),
+        ("TASA_OBJETIVO", tasas.tasa_objetivo),
+    ];
+    let levels: Vec<BenchmarkLevel> = index_items
+        .into_iter()
+        .filter_map(|(symbol, item)| Some((symbol, money::price_from_f64(item?.u)?)))
+        .filter(|(_, level)| *level > Decimal::ZERO)
+        .chain(rate_items.into_iter().filter_map(|(symbol, item)| Some((symbol, money::price_from_f64(item?.t)?))))
+        .map(|(symbol, value)| BenchmarkLevel { symbol: symbol.to_string(), value_date: today, value })
+        .collect();
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    save_benchmark_levels(&**client, &levels).await?;
+    yield_curve::store_curve(&**client, today).await?;
+    Ok(levels)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn covariance(a: &[f64], b: &[f64]) -> f64 {
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum::<f64>() / (a.len() as f64 - 1.0)
+}
+
+fn compound(returns: &[f64]) -> f64 {
+    returns.iter().fold(1.0, |acc, r| acc * (1.0 + r)) - 1.0
+}
+
+fn capture(portfolio: &[f64], benchmark: &[f64], up: bool) -> Option<f64> {
+    let (p, b): (Vec<f64>, Vec<f64>) = portfolio.iter().zip(benchmark)
+        .filter(|(_, b)| if up { **b > 0.0 } else { **b < 0.0 })
+        .map(|(p, b)| (*p, *b))
+        .unzip();
+    if b.is_empty() {
+        return None;
+    }
+    Some(mean(&p) / mean(&b))
+}
+
+fn to_ratio(value: f64) -> Option<Decimal> {
+    if !value.is_finite() {
+        return None;
+    }
+    Decimal::from_f64(value).map(|r| r.round_dp(RATIO_SCALE))
+}
+
+/// Métricas a partir de rendimientos diarios alineados del portafolio y del benchmark.
+pub fn compare_returns(
+    portfolio_id: i32,
+    components: Vec<BenchmarkComponent>,
+    dates: &[NaiveDate],
+    portfolio: &[f64],
+    benchmark: &[f64],
+) -> BenchmarkComparison {
+    let n = portfolio.len();
+    let mut series = Vec::new();
+    let (mut p_growth, mut b_growth) = (1.0, 1.0);
+    if let Some(first) = dates.first() {
+        series.push(BenchmarkPoint { date: *first, portfolio: Decimal::ONE_HUNDRED, benchmark: Decimal::ONE_HUNDRED });
+    }
+    for (i, date) in dates.iter().skip(1).enumerate() {
+        p_growth *= 1.0 + portfolio[i];
+        b_growth *= 1.0 + benchmark[i];
+        series.push(BenchmarkPoint {
+            date: *date,
+            portfolio: to_percent(p_growth).unwrap_or_default(),
+            benchmark: to_percent(b_growth).unwrap_or_default(),
+        });
+    }
+
+    let mut comparison = BenchmarkComparison {
+        portfolio_id,
+        components,
+        start_date: dates.first().copied(),
+        end_date: dates.last().copied(),
+        observations: n,
+        portfolio_return: None,
+        benchmark_return: None,
+        excess_return: None,
+        alpha: None,
+        beta: None,
+        correlation: None,
+        tracking_error: None,
+        information_ratio: None,
+        up_capture: None,
+        down_capture: None,
+        series,
+    };
+    if n == 0 {
+        return comparison;
+    }
+
+    let portfolio_total = compound(portfolio);
+    let benchmark_total = compound(benchmark);
+    comparison.portfolio_return = to_percent(portfolio_total);
+    comparison.benchmark_return = to_percent(benchmark_total);
+    comparison.excess_return = to_percent(portfolio_total - benchmark_total);
+    comparison.up_capture = capture(portfolio, benchmark, true).and_then(to_percent);
+    comparison.down_capture = capture(portfolio, benchmark, false).and_then(to_percent);
+    if n < 2 {
+        return comparison;
+    }
+
+    let variance_b = covariance(benchmark, benchmark);
+    let variance_p = covariance(portfolio, portfolio);
+    let active: Vec<f64> = portfolio.iter().zip(benchmark).map(|(p, b)| p - b).collect();
+    let tracking_error = covariance(&active, &active).sqrt() * TRADING_DAYS_PER_YEAR.sqrt();
+    if variance_b > 0.0 {
+        let beta = covariance(portfolio, benchmark) / variance_b;
+        comparison.beta = to_ratio(beta);
+        comparison.alpha = to_percent((mean(portfolio) - beta * mean(benchmark)) * TRADING_DAYS_PER_YEAR);
+        if variance_p > 0.0 {
+            comparison.correlation = to_ratio(covariance(portfolio, benchmark) / (variance_b * variance_p).sqrt());
+        }
+    }
+    comparison.tracking_error = to_percent(tracking_error);
+    if tracking_error > 0.0 {
+        comparison.information_ratio = to_ratio(mean(&active) * TRADING_DAYS_PER_YEAR / tracking_error);
+    }
+    comparison
+}
+
+pub async fn compare_to_benchmark_logic(
+    portfolio_id: i32,
+    components: Vec<BenchmarkComponent>,
+    range: String,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<BenchmarkComparison, String> {
+    let components = validate_components(&components)?;
+    let from = snapshots::range_start(&range, Utc::now().date_naive())?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+
+    let history: Vec<_> = snapshots::valuation_series(&**client, portfolio_id).await?
+        .into_iter()
+        .filter(|day| !matches!(from, Some(f) if day.date < f))
+        .collect();
+    let Some(first) = history.first() else {
+        return Ok(compare_returns(portfolio_id, components, &[], &[], &[]));
+    };
+
+    let mut series: HashMap<String, BenchmarkSeries> = HashMap::new();
+    for component in &components {
+        if !series.contains_key(&component.symbol) {
+            series.insert(component.symbol.clone(), load_series(&**client, &component.symbol, first.date).await?);
+        }
+    }
+
+    // Rebalanceo diario a los pesos objetivo: el rendimiento del día es el promedio ponderado
+    let mut dates = vec![first.date];
+    let mut portfolio_returns = Vec::new();
+    let mut benchmark_returns = Vec::new();
+    for (pair, (date, portfolio_return)) in history.windows(2).zip(performance::daily_returns(&history)) {
+        let mut benchmark_return = 0.0;
+        let mut complete = true;
+        for component in &components {
+            match series[&component.symbol].period_return(pair[0].date, date) {
+                Some(r) => benchmark_return += performance::to_f64(component.weight) / 100.0 * r,
+                None => complete = false,
+            }
+        }
+        // Sin dato del benchmark ese día no hay con qué comparar; si aún no empieza, se recorre el inicio
+        if !complete {
+            if portfolio_returns.is_empty() {
+                dates[0] = date;
+            }
+            continue;
+        }
+        dates.push(date);
+        portfolio_returns.push(portfolio_return);
+        benchmark_returns.push(benchmark_return);
+    }
+    if portfolio_returns.is_empty() {
+        dates.truncate(0);
+    }
+
+    Ok(compare_returns(portfolio_id, components, &dates, &portfolio_returns, &benchmark_returns))
+}
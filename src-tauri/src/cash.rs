@@ -0,0 +1,453 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use deadpool_postgres::Pool;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_postgres::{GenericClient, Row};
+
+use crate::fx;
+use crate::ledger::{self, LedgerEntry, NewLedgerEntry, TransactionKind};
+use crate::money;
+use crate::yield_curve::{self, Interpolation, YieldCurve};
+
+// Los intereses sobre efectivo se pagan a la tasa de CETES a 28 días, con base 360
+const CETE_TENOR_DAYS: i64 = 28;
+const DAY_COUNT: Decimal = Decimal::from_parts(360, 0, 0, false, 0);
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+const ACCOUNT_COLUMNS: &str = "portfolio_id, currency, overdraft_limit, interest_source, interest_rate, interest_spread, interest_accrued_through";
+
+/// De dónde sale la tasa que gana el efectivo ocioso de una cuenta.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum InterestSource {
+    #[default]
+    None,
+    // Tasa anual fija (interest_rate)
+    Fixed,
+    // CETES a 28 días de la curva del día menos interest_spread
+    Cete,
+}
+
+impl InterestSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InterestSource::None => "NONE",
+            InterestSource::Fixed => "FIXED",
+            InterestSource::Cete => "CETE",
+        }
+    }
+}
+
+impl fmt::Display for InterestSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for InterestSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "NONE" | "NINGUNA" | "SIN INTERESES" => Ok(InterestSource::None),
+            "FIXED" | "FIJA" => Ok(InterestSource::Fixed),
+            "CETE" | "CETES" | "CETE28" => Ok(InterestSource::Cete),
+            _ => Err(format!("Fuente de intereses desconocida: {}", s)),
+        }
+    }
+}
+
+/// Política de la cuenta de efectivo de un portafolio en una moneda. Las tasas son anuales
+/// en fracción (0.1 = 10%).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashAccount {
+    pub portfolio_id: i32,
+    pub currency: String,
+    // Cuánto puede quedar el saldo en negativo al comprar o retirar
+    pub overdraft_limit: Decimal,
+    pub interest_source: InterestSource,
+    pub interest_rate: Decimal,
+    pub interest_spread: Decimal,
+    // Último cierre de mes con intereses registrados; lo mantiene accrue_interest
+    #[serde(default)]
+    pub interest_accrued_through: Option<NaiveDate>,
+}
+
+impl CashAccount {
+    // Sin política registrada la cuenta no admite sobregiro ni gana intereses
+    pub fn none(portfolio_id: i32, currency: &str) -> Self {
+        CashAccount {
+            portfolio_id,
+            currency: currency.to_string(),
+            overdraft_limit: Decimal::ZERO,
+            interest_source: InterestSource::None,
+            interest_rate: Decimal::ZERO,
+            interest_spread: Decimal::ZERO,
+            interest_accrued_through: None,
+        }
+    }
+
+    fn from_row(row: &Row) -> Result<Self, String> {
+        let interest_source: String = row.get("interest_source");
+        Ok(CashAccount {
+            portfolio_id: row.get("portfolio_id"),
+            currency: row.get("currency"),
+            overdraft_limit: row.get("overdraft_limit"),
+            interest_source: interest_source.parse()?,
+            interest_rate: row.get("interest_rate"),
+            interest_spread: row.get("interest_spread"),
+            interest_accrued_through: row.get("interest_accrued_through"),
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.overdraft_limit < Decimal::ZERO {
+            return Err("El sobregiro permitido no puede ser negativo".to_string());
+        }
+        if self.interest_rate < Decimal::ZERO || self.interest_rate >= Decimal::ONE {
+            return Err("La tasa de interés debe estar entre 0 y 1".to_string());
+        }
+        if self.interest_spread < Decimal::ZERO || self.interest_spread >= Decimal::ONE {
+            return Err("El diferencial sobre CETES debe estar entre 0 y 1".to_string());
+        }
+        if self.interest_source == InterestSource::Cete && self.currency != fx::MXN {
+            return Err("Solo el efectivo en MXN puede ganar la tasa de CETES".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Cuenta de efectivo con su saldo y lo que queda disponible contando el sobregiro.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashAccountStatus {
+    pub account: CashAccount,
+    pub balance: Decimal,
+    pub available: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashStatementLine {
+    pub transaction_id: i32,
+    pub transaction_date: DateTime<Utc>,
+    pub transaction_type: TransactionKind,
+    pub ticker: String,
+    pub description: String,
+    // Positivo si entra efectivo, negativo si sale
+    pub amount: Decimal,
+    pub balance: Decimal,
+}
+
+/// Estado de cuenta del efectivo en una moneda con saldo después de cada movimiento.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashStatement {
+    pub portfolio_id: i32,
+    pub currency: String,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub opening_balance: Decimal,
+    pub total_in: Decimal,
+    pub total_out: Decimal,
+    pub closing_balance: Decimal,
+    pub overdraft_limit: Decimal,
+    pub lines: Vec<CashStatementLine>,
+}
+
+fn month_end(date: NaiveDate) -> NaiveDate {
+    (date.with_day(1).unwrap() + Months::new(1)).pred_opt().unwrap()
+}
+
+fn describe(entry: &LedgerEntry) -> String {
+    if let Some(notes) = entry.notes.as_deref().filter(|n| !n.trim().is_empty()) {
+        return notes.to_string();
+    }
+    match entry.transaction_type {
+        TransactionKind::Buy => format!("Compra de {} {}", entry.quantity.normalize(), entry.ticker),
+        TransactionKind::Sell => format!("Venta de {} {}", entry.quantity.normalize(), entry.ticker),
+        TransactionKind::Dividend => format!("Dividendo de {}", entry.ticker),
+        TransactionKind::Interest => "Intereses sobre efectivo".to_string(),
+        TransactionKind::Deposit => "Depósito".to_string(),
+        TransactionKind::Withdrawal => "Retiro".to_string(),
+    }
+}
+
+pub async fn account<C: GenericClient>(client: &C, portfolio_id: i32, currency: &str) -> Result<CashAccount, String> {
+    let row = client.query_opt(
+        &format!("SELECT {} FROM cash_accounts WHERE portfolio_id = $1 AND currency = $2", ACCOUNT_COLUMNS),
+        &[&portfolio_id, &currency],
+    ).await.map_err(|e| format!("Error al consultar la cuenta de efectivo: {}", e))?;
+    match row {
+        Some(row) => CashAccount::from_row(&row),
+        None => Ok(CashAccount::none(portfolio_id, currency)),
+    }
+}
+
+/// Falla si sacar `amount` de la cuenta la deja por debajo del sobregiro permitido.
+/// `purpose` completa el mensaje: "la compra", "el retiro".
+pub async fn check_funds<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    currency: &str,
+    amount: Decimal,
+    purpose: &str,
+) -> Result<(), String> {
+    let balance = ledger::cash_balance(client, portfolio_id, currency).await?;
+    let overdraft_limit = account(client, portfolio_id, currency).await?.overdraft_limit;
+    let available = balance + overdraft_limit;
+    if available < amount {
+        return Err(format!(
+            "Saldo insuficiente para realizar {}: se requieren {} {} y hay {} disponibles (saldo {} más sobregiro de {})",
+            purpose, amount, currency, available, balance, overdraft_limit
+        ));
+    }
+    Ok(())
+}
+
+/// Cuentas con política registrada o con movimientos, y siempre la de MXN.
+pub async fn list_cash_accounts_logic(portfolio_id: i32, db_pool: &Pool) -> Result<Vec<CashAccountStatus>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM cash_accounts WHERE portfolio_id = $1", ACCOUNT_COLUMNS),
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar las cuentas de efectivo: {}", e))?;
+    let mut accounts: BTreeMap<String, CashAccount> = BTreeMap::new();
+    for row in &rows {
+        let account = CashAccount::from_row(row)?;
+        accounts.insert(account.currency.clone(), account);
+    }
+    let balances = ledger::cash_balances(&**client, portfolio_id).await?;
+    for (currency, _) in &balances {
+        accounts.entry(currency.clone()).or_insert_with(|| CashAccount::none(portfolio_id, currency));
+    }
+    accounts.entry(fx::MXN.to_string()).or_insert_with(|| CashAccount::none(portfolio_id, fx::MXN));
+
+    Ok(accounts.into_values().map(|account| {
+        let balance = balances.iter().find(|(c, _)| *c == account.currency).map(|(_, b)| *b).unwrap_or_default();
+        CashAccountStatus { available: balance + account.overdraft_limit, balance, account }
+    }).collect())
+}
+
+/// Guarda la política de una cuenta. Los intereses corren desde el mes en que se activan;
+/// cambiar la tasa no recalcula los meses ya registrados.
+pub async fn set_cash_account_logic(mut account: CashAccount, db_pool: &Pool) -> Result<CashAccount, String> {
+    account.currency = fx::normalize_currency(&account.currency)?;
+    account.validate()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let previous_month_end = Utc::now().date_naive().with_day(1).unwrap().pred_opt().unwrap();
+    let row = client.query_one(
+        &format!(
+            "INSERT INTO cash_accounts (portfolio_id, currency, overdraft_limit, interest_source, interest_rate, interest_spread, interest_accrued_through, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, now())
+             ON CONFLICT (portfolio_id, currency) DO UPDATE SET
+                overdraft_limit = EXCLUDED.overdraft_limit,
+                interest_source = EXCLUDED.interest_source,
+                interest_rate = EXCLUDED.interest_rate,
+                interest_spread = EXCLUDED.interest_spread,
+                interest_accrued_through = CASE WHEN cash_accounts.interest_source = 'NONE'
+                    THEN EXCLUDED.interest_accrued_through ELSE cash_accounts.interest_accrued_through END,
+                updated_at = now()
+             RETURNING {}",
+            ACCOUNT_COLUMNS
+        ),
+        &[&account.portfolio_id, &account.currency, &account.overdraft_limit, &account.interest_source.as_str(),
+          &account.interest_rate, &account.interest_spread, &previous_month_end],
+    ).await.map_err(|e| format!("No se pudo guardar la cuenta de efectivo: {}", e))?;
+    CashAccount::from_row(&row)
+}
+
+// Tasa anual que gana el efectivo en `day`
+fn daily_rate(account: &CashAccount, curves: &BTreeMap<NaiveDate, YieldCurve>, day: NaiveDate) -> Result<Decimal, String> {
+    match account.interest_source {
+        InterestSource::None => Ok(Decimal::ZERO),
+        InterestSource::Fixed => Ok(account.interest_rate),
+        InterestSource::Cete => {
+            let (_, curve) = curves.range(..=day).next_back()
+                .ok_or_else(|| format!("No hay curva de CETES al {}; construya las curvas de rendimiento primero", day))?;
+            let cete = Decimal::from_f64(curve.rate(CETE_TENOR_DAYS, Interpolation::default()) / 100.0).unwrap_or_default();
+            Ok((cete - account.interest_spread).max(Decimal::ZERO))
+        }
+    }
+}
+
+// Intereses de los meses completos de una cuenta hasta `through`, uno por cierre de mes
+async fn accrue_account<C: GenericClient>(
+    client: &C,
+    account: &CashAccount,
+    through: NaiveDate,
+) -> Result<Vec<LedgerEntry>, String> {
+    let start = match account.interest_accrued_through {
+        Some(date) => date.succ_opt().unwrap(),
+        None => Utc::now().date_naive().with_day(1).unwrap(),
+    };
+    if month_end(start) > through {
+        return Ok(Vec::new());
+    }
+
+    let mut curves = BTreeMap::new();
+    if account.interest_source == InterestSource::Cete {
+        if let Some(curve) = yield_curve::curve_on(client, start).await? {
+            curves.insert(curve.curve_date, curve);
+        }
+        curves.extend(yield_curve::curves_between(client, start, through).await?);
+    }
+
+    let entries: Vec<LedgerEntry> = ledger::list_entries(client, account.portfolio_id, None).await?
+        .into_iter()
+        .filter(|e| e.currency == account.currency)
+        .collect();
+    let mut balance = Decimal::ZERO;
+    let mut next_entry = 0;
+    let mut recorded = Vec::new();
+    let mut month_start = start;
+
+    while month_end(month_start) <= through {
+        let close = month_end(month_start);
+        let mut accrued = Decimal::ZERO;
+        let mut day = month_start;
+        while day <= close {
+            while next_entry < entries.len() && entries[next_entry].transaction_date.date_naive() <= day {
+                balance += entries[next_entry].cash_effect();
+                next_entry += 1;
+            }
+            // Solo el saldo a favor gana intereses
+            if balance > Decimal::ZERO {
+                accrued += balance * daily_rate(account, &curves, day)? / DAY_COUNT;
+            }
+            day = day.succ_opt().unwrap();
+        }
+
+        let amount = money::round_mxn(accrued);
+        if amount > Decimal::ZERO {
+            let mut entry = NewLedgerEntry::cash(account.portfolio_id, TransactionKind::Interest, amount);
+            entry.currency = account.currency.clone();
+            entry.transaction_date = Some(ledger::local_timestamp(close));
+            entry.notes = Some(format!("Intereses sobre efectivo de {}", close.format("%m/%Y")));
+            recorded.push(ledger::record_cash_movement(client, entry).await?);
+            balance += amount;
+        }
+        client.execute(
+            "UPDATE cash_accounts SET interest_accrued_through = $3, updated_at = now() WHERE portfolio_id = $1 AND currency = $2",
+            &[&account.portfolio_id, &account.currency, &close],
+        ).await.map_err(|e| format!("Error al actualizar la cuenta de efectivo: {}", e))?;
+        month_start = close.succ_opt().unwrap();
+    }
+    Ok(recorded)
+}
+
+/// Registra los intereses del efectivo ocioso de cada mes cerrado hasta `through` (por
+/// omisión, ayer) como movimientos INTEREST al cierre del mes. Sin portafolio procesa todas
+/// las cuentas con intereses; cada cuenta va en su propia transacción y una que falla se
+/// reporta en el log sin detener a las demás.
+pub async fn accrue_interest_logic(
+    portfolio_id: Option<i32>,
+    through: Option<NaiveDate>,
+    db_pool: &Pool,
+) -> Result<Vec<LedgerEntry>, String> {
+    let through = through.unwrap_or_else(|| Utc::now().date_naive().pred_opt().unwrap());
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!(
+            "SELECT {} FROM cash_accounts
+             WHERE interest_source <> 'NONE' AND ($1::integer IS NULL OR portfolio_id = $1)
+             ORDER BY portfolio_id, currency",
+            ACCOUNT_COLUMNS
+        ),
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar las cuentas de efectivo: {}", e))?;
+    let accounts: Vec<CashAccount> = rows.iter().map(CashAccount::from_row).collect::<Result<_, _>>()?;
+
+    let mut recorded = Vec::new();
+    for account in &accounts {
+        let tx = client.transaction().await.map_err(|e| e.to_string())?;
+        let accrued = match accrue_account(&*tx, account, through).await {
+            Ok(entries) => tx.commit().await.map_err(|e| e.to_string()).map(|_| entries),
+            Err(e) => Err(e),
+        };
+        match accrued {
+            Ok(entries) => recorded.extend(entries),
+            Err(e) => println!(
+                "[CASH] No se calcularon los intereses del portafolio {} en {}: {}",
+                account.portfolio_id, account.currency, e
+            ),
+        }
+    }
+    Ok(recorded)
+}
+
+/// Se pone al corriente al arrancar la aplicación y después revisa cada hora.
+pub async fn run_interest_scheduler(db_pool: Arc<Pool>) {
+    let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+    loop {
+        interval.tick().await;
+        match accrue_interest_logic(None, None, &db_pool).await {
+            Ok(entries) if !entries.is_empty() => {
+                println!("[CASH] {} pagos de intereses sobre efectivo registrados", entries.len());
+            }
+            Ok(_) => {}
+            Err(e) => println!("[CASH] Error al calcular intereses sobre efectivo: {}", e),
+        }
+    }
+}
+
+/// Movimientos de efectivo en una moneda entre dos fechas (inclusive) con el saldo corrido.
+pub async fn cash_statement_logic(
+    portfolio_id: i32,
+    currency: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    db_pool: &Pool,
+) -> Result<CashStatement, String> {
+    let currency = fx::normalize_currency(currency.as_deref().unwrap_or(fx::MXN))?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let entries = ledger::list_entries(&**client, portfolio_id, None).await?;
+    let overdraft_limit = account(&**client, portfolio_id, &currency).await?.overdraft_limit;
+
+    let mut opening_balance = Decimal::ZERO;
+    let mut balance = Decimal::ZERO;
+    let mut total_in = Decimal::ZERO;
+    let mut total_out = Decimal::ZERO;
+    let mut lines = Vec::new();
+    for entry in entries.iter().filter(|e| e.currency == currency) {
+        let date = entry.transaction_date.date_naive();
+        if matches!(to, Some(to) if date > to) {
+            break;
+        }
+        let amount = entry.cash_effect();
+        balance += amount;
+        if matches!(from, Some(from) if date < from) {
+            opening_balance = balance;
+            continue;
+        }
+        if amount > Decimal::ZERO {
+            total_in += amount;
+        } else {
+            total_out -= amount;
+        }
+        lines.push(CashStatementLine {
+            transaction_id: entry.transaction_id,
+            transaction_date: entry.transaction_date,
+            transaction_type: entry.transaction_type,
+            ticker: entry.ticker.clone(),
+            description: describe(entry),
+            amount,
+            balance,
+        });
+    }
+
+    Ok(CashStatement {
+        portfolio_id,
+        currency,
+        from,
+        to,
+        opening_balance,
+        total_in,
+        total_out,
+        closing_balance: balance,
+        overdraft_limit,
+        lines,
+    })
+}
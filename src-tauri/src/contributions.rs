@@ -0,0 +1,535 @@
+use chrono::{Datelike, Duration, Months, NaiveDate, Utc};
+use deadpool_postgres::Pool;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_postgres::GenericClient;
+
+use crate::allocation;
+use crate::fees;
+use crate::ledger::{self, CashSource, NewLedgerEntry, TransactionKind};
+use crate::money;
+use crate::orders;
+use crate::prices;
+
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PlanFrequency {
+    // schedule_day = día del mes; si el mes es más corto, el último día
+    Monthly,
+    // schedule_day = día de la semana, 1 = lunes a 5 = viernes
+    Weekly,
+}
+
+impl PlanFrequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanFrequency::Monthly => "MONTHLY",
+            PlanFrequency::Weekly => "WEEKLY",
+        }
+    }
+}
+
+impl FromStr for PlanFrequency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "MONTHLY" | "MENSUAL" => Ok(PlanFrequency::Monthly),
+            "WEEKLY" | "SEMANAL" => Ok(PlanFrequency::Weekly),
+            _ => Err(format!("Frecuencia desconocida: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PlanStatus {
+    Active,
+    Paused,
+    Ended,
+}
+
+impl PlanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanStatus::Active => "ACTIVE",
+            PlanStatus::Paused => "PAUSED",
+            PlanStatus::Ended => "ENDED",
+        }
+    }
+}
+
+impl fmt::Display for PlanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PlanStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "ACTIVE" => Ok(PlanStatus::Active),
+            "PAUSED" => Ok(PlanStatus::Paused),
+            "ENDED" => Ok(PlanStatus::Ended),
+            _ => Err(format!("Estado de plan desconocido: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContributionPlan {
+    pub plan_id: i32,
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub amount: Decimal,
+    pub frequency: PlanFrequency,
+    pub schedule_day: i16,
+    pub start_date: NaiveDate,
+    pub end_date: Option<NaiveDate>,
+    pub status: PlanStatus,
+    pub use_portfolio_cash: bool,
+    pub auto_confirm: bool,
+    pub last_scheduled_date: Option<NaiveDate>,
+}
+
+impl ContributionPlan {
+    /// Días hábiles programados entre `from` y `to`. Una fecha que cae en fin de semana se
+    /// recorre al lunes siguiente.
+    pub fn scheduled_dates(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        match self.frequency {
+            PlanFrequency::Monthly => {
+                // Desde el mes anterior por si su fecha se recorrió al mes de `from`
+                let mut month = NaiveDate::from_ymd_opt(from.year(), from.month(), 1).unwrap() - Months::new(1);
+                while month <= to {
+                    let day = (1..=self.schedule_day.clamp(1, 31) as u32)
+                        .rev()
+                        .find_map(|d| NaiveDate::from_ymd_opt(month.year(), month.month(), d))
+                        .unwrap();
+                    dates.push(next_business_day(day));
+                    month = month + Months::new(1);
+                }
+            }
+            PlanFrequency::Weekly => {
+                let weekday = self.schedule_day as u32 - 1;
+                let mut day = from + Duration::days((7 + weekday as i64 - from.weekday().num_days_from_monday() as i64) % 7);
+                while day <= to {
+                    dates.push(day);
+                    day += Duration::days(7);
+                }
+            }
+        }
+        dates.retain(|d| *d >= from && *d <= to && *d >= self.start_date);
+        dates
+    }
+}
+
+fn next_business_day(date: NaiveDate) -> NaiveDate {
+    let mut day = date;
+    while !prices::is_business_day(day) {
+        day += Duration::days(1);
+    }
+    day
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewContributionPlan {
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub amount: Decimal,
+    pub frequency: String,
+    pub schedule_day: i16,
+    #[serde(default)]
+    pub start_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub end_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub use_portfolio_cash: bool,
+    #[serde(default)]
+    pub auto_confirm: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PendingStatus {
+    Pending,
+    Confirmed,
+    Skipped,
+}
+
+impl PendingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PendingStatus::Pending => "PENDING",
+            PendingStatus::Confirmed => "CONFIRMED",
+            PendingStatus::Skipped => "SKIPPED",
+        }
+    }
+}
+
+impl FromStr for PendingStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "PENDING" => Ok(PendingStatus::Pending),
+            "CONFIRMED" => Ok(PendingStatus::Confirmed),
+            "SKIPPED" => Ok(PendingStatus::Skipped),
+            _ => Err(format!("Estado de aportación desconocido: {}", s)),
+        }
+    }
+}
+
+/// Aportación generada por un plan. `suggested_price` es el cierre del día programado,
+/// o None mientras no se conozca.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingContribution {
+    pub pending_id: i32,
+    pub plan_id: i32,
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub amount: Decimal,
+    pub scheduled_date: NaiveDate,
+    pub suggested_price: Option<Decimal>,
+    pub status: PendingStatus,
+    pub transaction_id: Option<i32>,
+}
+
+const PLAN_COLUMNS: &str = "plan_id, portfolio_id, ticker, amount, frequency, schedule_day, start_date, end_date, status, use_portfolio_cash, auto_confirm, last_scheduled_date";
+
+const PENDING_QUERY: &str = "SELECT c.pending_id, c.plan_id, p.portfolio_id, p.ticker, p.amount, c.scheduled_date,
+        c.suggested_price, c.status, c.transaction_id
+ FROM pending_contributions c JOIN contribution_plans p ON p.plan_id = c.plan_id";
+
+fn plan_from_row(row: &tokio_postgres::Row) -> Result<ContributionPlan, String> {
+    let frequency: String = row.get("frequency");
+    let status: String = row.get("status");
+    Ok(ContributionPlan {
+        plan_id: row.get("plan_id"),
+        portfolio_id: row.get("portfolio_id"),
+        ticker: row.get("ticker"),
+        amount: row.get("amount"),
+        frequency: frequency.parse()?,
+        schedule_day: row.get("schedule_day"),
+        start_date: row.get("start_date"),
+        end_date: row.get("end_date"),
+        status: status.parse()?,
+        use_portfolio_cash: row.get("use_portfolio_cash"),
+        auto_confirm: row.get("auto_confirm"),
+        last_scheduled_date: row.get("last_scheduled_date"),
+    })
+}
+
+fn pending_from_row(row: &tokio_postgres::Row) -> Result<PendingContribution, String> {
+    let status: String = row.get("status");
+    Ok(PendingContribution {
+        pending_id: row.get("pending_id"),
+        plan_id: row.get("plan_id"),
+        portfolio_id: row.get("portfolio_id"),
+        ticker: row.get("ticker"),
+        amount: row.get("amount"),
+        scheduled_date: row.get("scheduled_date"),
+        suggested_price: row.get("suggested_price"),
+        status: status.parse()?,
+        transaction_id: row.get("transaction_id"),
+    })
+}
+
+pub async fn create_contribution_plan_logic(plan: NewContributionPlan, db_pool: &Pool) -> Result<ContributionPlan, String> {
+    let frequency: PlanFrequency = plan.frequency.parse()?;
+    let ticker = plan.ticker.trim().to_uppercase();
+    if ticker.is_empty() {
+        return Err("El plan necesita un ticker".to_string());
+    }
+    let amount = money::round_mxn(plan.amount);
+    if amount <= Decimal::ZERO {
+        return Err("El monto de la aportación debe ser mayor a cero".to_string());
+    }
+    match frequency {
+        PlanFrequency::Monthly if !(1..=31).contains(&plan.schedule_day) => {
+            return Err("El día del mes debe estar entre 1 y 31".to_string());
+        }
+        PlanFrequency::Weekly if !(1..=5).contains(&plan.schedule_day) => {
+            return Err("El día de la semana debe estar entre 1 (lunes) y 5 (viernes)".to_string());
+        }
+        _ => {}
+    }
+    let start_date = plan.start_date.unwrap_or_else(|| Utc::now().date_naive());
+    if plan.end_date.is_some_and(|end| end < start_date) {
+        return Err("La fecha de fin no puede ser anterior a la de inicio".to_string());
+    }
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    orders::ensure_real_portfolio(&**client, plan.portfolio_id).await?;
+    let row = client.query_one(
+        &format!(
+            "INSERT INTO contribution_plans
+                (portfolio_id, ticker, amount, frequency, schedule_day, start_date, end_date, use_portfolio_cash, auto_confirm)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING {}",
+            PLAN_COLUMNS
+        ),
+        &[&plan.portfolio_id, &ticker, &amount, &frequency.as_str(), &plan.schedule_day, &start_date, &plan.end_date,
+          &plan.use_portfolio_cash, &plan.auto_confirm],
+    ).await.map_err(|e| format!("No se pudo crear el plan de aportaciones: {}", e))?;
+    plan_from_row(&row)
+}
+
+pub async fn list_contribution_plans_logic(portfolio_id: i32, db_pool: &Pool) -> Result<Vec<ContributionPlan>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM contribution_plans WHERE portfolio_id = $1 ORDER BY plan_id", PLAN_COLUMNS),
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar los planes de aportaciones: {}", e))?;
+    rows.iter().map(plan_from_row).collect()
+}
+
+/// Pausa, reanuda o termina un plan. Al reanudarlo no se generan las fechas que cayeron
+/// mientras estuvo en pausa.
+pub async fn set_contribution_plan_status_logic(plan_id: i32, status: String, db_pool: &Pool) -> Result<ContributionPlan, String> {
+    let status: PlanStatus = status.parse()?;
+    let yesterday = Utc::now().date_naive() - Duration::days(1);
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    if status == PlanStatus::Active {
+        let portfolio_id: i32 = client.query_opt("SELECT portfolio_id FROM contribution_plans WHERE plan_id = $1", &[&plan_id])
+            .await.map_err(|e| format!("Error al consultar el plan: {}", e))?
+            .ok_or_else(|| format!("No se encontró el plan {}", plan_id))?
+            .get("portfolio_id");
+        orders::ensure_real_portfolio(&**client, portfolio_id).await?;
+    }
+    let row = client.query_opt(
+        &format!(
+            "UPDATE contribution_plans SET
+                last_scheduled_date = CASE WHEN status = 'PAUSED' AND $2 = 'ACTIVE'
+                                           THEN GREATEST(last_scheduled_date, $3) ELSE last_scheduled_date END,
+                status = $2
+             WHERE plan_id = $1 RETURNING {}",
+            PLAN_COLUMNS
+        ),
+        &[&plan_id, &status.as_str(), &yesterday],
+    ).await.map_err(|e| format!("No se pudo actualizar el plan: {}", e))?
+        .ok_or_else(|| format!("No se encontró el plan {}", plan_id))?;
+    plan_from_row(&row)
+}
+
+pub async fn delete_contribution_plan_logic(plan_id: i32, db_pool: &Pool) -> Result<String, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    // Las aportaciones ya confirmadas permanecen en el ledger
+    let rows_affected = client.execute("DELETE FROM contribution_plans WHERE plan_id = $1", &[&plan_id])
+        .await.map_err(|e| format!("Error al eliminar el plan: {}", e))?;
+    if rows_affected == 1 {
+        Ok("Plan de aportaciones eliminado correctamente.".to_string())
+    } else {
+        Err("No se encontró el plan para eliminar.".to_string())
+    }
+}
+
+pub async fn list_pending_contributions_logic(
+    portfolio_id: i32,
+    status: Option<String>,
+    db_pool: &Pool,
+) -> Result<Vec<PendingContribution>, String> {
+    let status = status.map(|s| s.parse::<PendingStatus>()).transpose()?.map(|s| s.as_str());
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!(
+            "{} WHERE p.portfolio_id = $1 AND ($2::text IS NULL OR c.status = $2) ORDER BY c.scheduled_date DESC, c.pending_id",
+            PENDING_QUERY
+        ),
+        &[&portfolio_id, &status],
+    ).await.map_err(|e| format!("Error al consultar las aportaciones: {}", e))?;
+    rows.iter().map(pending_from_row).collect()
+}
+
+pub async fn skip_contribution_logic(pending_id: i32, db_pool: &Pool) -> Result<String, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows_affected = client.execute(
+        "UPDATE pending_contributions SET status = 'SKIPPED' WHERE pending_id = $1 AND status = 'PENDING'",
+        &[&pending_id],
+    ).await.map_err(|e| format!("No se pudo omitir la aportación: {}", e))?;
+    if rows_affected == 1 {
+        Ok("Aportación omitida.".to_string())
+    } else {
+        Err("No se encontró una aportación pendiente con ese número.".to_string())
+    }
+}
+
+// Títulos que alcanza a comprar el monto, en lotes completos y con la comisión incluida
+async fn contribution_quantity<C: GenericClient>(
+    client: &C,
+    pending: &PendingContribution,
+    price: Decimal,
+) -> Result<Decimal, String> {
+    let lot = allocation::classifications(client, std::slice::from_ref(&pending.ticker)).await?
+        .get(&pending.ticker)
+        .map(|c| c.lot_size)
+        .unwrap_or(Decimal::ONE);
+    let schedule = fees::fee_schedule(client, pending.portfolio_id).await?;
+    let mut quantity = (pending.amount / price / lot).floor() * lot;
+    while quantity > Decimal::ZERO {
+        let gross = money::round_mxn(quantity * price);
+        if gross + schedule.fees_for(gross).total() <= pending.amount {
+            break;
+        }
+        quantity -= lot;
+    }
+    Ok(quantity)
+}
+
+/// Registra la compra de una aportación pendiente al precio indicado o, sin él, al cierre
+/// del día programado.
+pub async fn confirm_contribution(
+    client: &mut deadpool_postgres::Client,
+    pending_id: i32,
+    price: Option<Decimal>,
+) -> Result<PendingContribution, String> {
+    let row = client.query_opt(
+        &format!("{} WHERE c.pending_id = $1", PENDING_QUERY),
+        &[&pending_id],
+    ).await.map_err(|e| format!("Error al consultar la aportación: {}", e))?
+        .ok_or_else(|| format!("No se encontró la aportación {}", pending_id))?;
+    let pending = pending_from_row(&row)?;
+    if pending.status != PendingStatus::Pending {
+        return Err("La aportación ya fue confirmada u omitida".to_string());
+    }
+    orders::ensure_real_portfolio(&***client, pending.portfolio_id).await?;
+    let price = price.or(pending.suggested_price)
+        .ok_or_else(|| format!("Aún no hay cierre de {} para el {}; indique el precio", pending.ticker, pending.scheduled_date))?;
+    if price <= Decimal::ZERO {
+        return Err("El precio debe ser mayor a cero".to_string());
+    }
+    let quantity = contribution_quantity(&***client, &pending, price).await?;
+    if quantity <= Decimal::ZERO {
+        return Err(format!("{} MXN no alcanzan para un lote de {} a {}", pending.amount, pending.ticker, price));
+    }
+    let use_portfolio_cash: bool = client.query_one(
+        "SELECT use_portfolio_cash FROM contribution_plans WHERE plan_id = $1",
+        &[&pending.plan_id],
+    ).await.map_err(|e| format!("Error al consultar el plan: {}", e))?.get("use_portfolio_cash");
+
+    // Apartar, comprar y enlazar van en una sola transacción: el apartado bloquea la
+    // aportación para que dos confirmaciones no compren dos veces, y si la compra falla
+    // sigue pendiente
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let claimed = tx.execute(
+        "UPDATE pending_contributions SET status = 'CONFIRMED' WHERE pending_id = $1 AND status = 'PENDING'",
+        &[&pending_id],
+    ).await.map_err(|e| format!("No se pudo actualizar la aportación: {}", e))?;
+    if claimed == 0 {
+        return Err("La aportación ya fue confirmada u omitida".to_string());
+    }
+    let mut entry = NewLedgerEntry::trade(pending.portfolio_id, &pending.ticker, TransactionKind::Buy, quantity, price);
+    entry.transaction_date = Some(ledger::local_timestamp(pending.scheduled_date));
+    entry.notes = Some(format!("Aportación programada (plan #{})", pending.plan_id));
+    let source = if use_portfolio_cash { CashSource::Portfolio } else { CashSource::External };
+    let recorded = ledger::record_trade_in(&*tx, entry, source).await?;
+    tx.execute(
+        "UPDATE pending_contributions SET transaction_id = $2, suggested_price = COALESCE(suggested_price, $3) WHERE pending_id = $1",
+        &[&pending_id, &recorded.transaction_id, &price],
+    ).await.map_err(|e| format!("No se pudo actualizar la aportación: {}", e))?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(PendingContribution { status: PendingStatus::Confirmed, transaction_id: Some(recorded.transaction_id), ..pending })
+}
+
+pub async fn confirm_contribution_logic(
+    pending_id: i32,
+    price: Option<Decimal>,
+    db_pool: &Pool,
+) -> Result<PendingContribution, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    confirm_contribution(&mut client, pending_id, price).await
+}
+
+// Genera las fechas que faltan de un plan activo hasta hoy
+async fn schedule_plan<C: GenericClient>(client: &C, plan: &ContributionPlan, today: NaiveDate) -> Result<usize, String> {
+    let from = plan.last_scheduled_date.map(|d| d + Duration::days(1)).unwrap_or(plan.start_date);
+    let to = plan.end_date.map(|end| end.min(today)).unwrap_or(today);
+    let mut generated = 0;
+    if from <= to {
+        for date in plan.scheduled_dates(from, to) {
+            generated += client.execute(
+                "INSERT INTO pending_contributions (plan_id, scheduled_date) VALUES ($1, $2)
+                 ON CONFLICT (plan_id, scheduled_date) DO NOTHING",
+                &[&plan.plan_id, &date],
+            ).await.map_err(|e| format!("No se pudo generar la aportación: {}", e))? as usize;
+        }
+    }
+    let ended = plan.end_date.is_some_and(|end| end <= today);
+    client.execute(
+        "UPDATE contribution_plans SET last_scheduled_date = GREATEST(last_scheduled_date, $2),
+                status = CASE WHEN $3 THEN 'ENDED' ELSE status END
+         WHERE plan_id = $1",
+        &[&plan.plan_id, &to.max(from - Duration::days(1)), &ended],
+    ).await.map_err(|e| format!("No se pudo actualizar el plan: {}", e))?;
+    Ok(generated)
+}
+
+/// Genera las aportaciones que faltan (incluidas las de días en que la aplicación no
+/// estuvo abierta), completa los cierres conocidos y confirma las de planes automáticos.
+pub async fn run_contribution_scheduler_logic(db_pool: &Pool) -> Result<Vec<PendingContribution>, String> {
+    let today = Utc::now().date_naive();
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM contribution_plans WHERE status = 'ACTIVE' AND start_date <= $1", PLAN_COLUMNS),
+        &[&today],
+    ).await.map_err(|e| format!("Error al consultar los planes de aportaciones: {}", e))?;
+    for row in &rows {
+        schedule_plan(&**client, &plan_from_row(row)?, today).await?;
+    }
+
+    // Precio sugerido: el cierre del día programado, o el siguiente si ese día no hubo sesión
+    let rows = client.query(
+        &format!("{} WHERE c.status = 'PENDING' AND c.suggested_price IS NULL AND c.scheduled_date < $1", PENDING_QUERY),
+        &[&today],
+    ).await.map_err(|e| format!("Error al consultar las aportaciones: {}", e))?;
+    for row in &rows {
+        let pending = pending_from_row(row)?;
+        let closes = prices::daily_closes(&**client, &pending.ticker, pending.scheduled_date).await?;
+        if let Some((_, close)) = closes.range(pending.scheduled_date..).next() {
+            client.execute(
+                "UPDATE pending_contributions SET suggested_price = $2 WHERE pending_id = $1",
+                &[&pending.pending_id, close],
+            ).await.map_err(|e| format!("No se pudo actualizar la aportación: {}", e))?;
+        }
+    }
+
+    let rows = client.query(
+        &format!(
+            "{} WHERE c.status = 'PENDING' AND c.suggested_price IS NOT NULL AND p.auto_confirm
+             ORDER BY c.scheduled_date, c.pending_id",
+            PENDING_QUERY
+        ),
+        &[],
+    ).await.map_err(|e| format!("Error al consultar las aportaciones: {}", e))?;
+    let mut confirmed = Vec::new();
+    for row in &rows {
+        let pending_id: i32 = row.get("pending_id");
+        match confirm_contribution(&mut client, pending_id, None).await {
+            Ok(pending) => confirmed.push(pending),
+            // Queda pendiente para que el usuario la revise
+            Err(e) => println!("[CONTRIBUTIONS] No se pudo confirmar la aportación {}: {}", pending_id, e),
+        }
+    }
+    Ok(confirmed)
+}
+
+/// Se pone al corriente al arrancar la aplicación y después revisa cada hora.
+pub async fn run_contribution_scheduler(db_pool: Arc<Pool>) {
+    let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+    loop {
+        interval.tick().await;
+        match run_contribution_scheduler_logic(&db_pool).await {
+            Ok(confirmed) if !confirmed.is_empty() => {
+                println!("[CONTRIBUTIONS] {} aportaciones programadas registradas", confirmed.len());
+            }
+            Ok(_) => {}
+            Err(e) => println!("[CONTRIBUTIONS] Error al generar aportaciones: {}", e),
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use tokio_postgres::{GenericClient, Row};
+
+use crate::ledger::{self, NewLedgerEntry, TransactionKind};
+use crate::lots;
+use crate::money;
+
+// Retención de ISR sobre dividendos de emisoras mexicanas (LISR art. 140)
+pub const DIVIDEND_WITHHOLDING_RATE: Decimal = Decimal::from_parts(10, 0, 0, false, 2);
+
+const DIVIDEND_COLUMNS: &str = "d.transaction_id, d.portfolio_id, d.ticker, d.ex_date, d.pay_date, d.shares, d.amount_per_share, d.gross_amount, d.withholding_tax, d.net_amount, t.currency, t.notes";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DividendRecord {
+    pub transaction_id: i32,
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub ex_date: NaiveDate,
+    pub pay_date: NaiveDate,
+    pub shares: Decimal,
+    pub amount_per_share: Decimal,
+    pub gross_amount: Decimal,
+    pub withholding_tax: Decimal,
+    pub net_amount: Decimal,
+    pub currency: String,
+    pub notes: Option<String>,
+}
+
+impl DividendRecord {
+    fn from_row(row: &Row) -> Self {
+        DividendRecord {
+            transaction_id: row.get("transaction_id"),
+            portfolio_id: row.get("portfolio_id"),
+            ticker: row.get("ticker"),
+            ex_date: row.get("ex_date"),
+            pay_date: row.get("pay_date"),
+            shares: row.get("shares"),
+            amount_per_share: row.get("amount_per_share"),
+            gross_amount: row.get("gross_amount"),
+            withholding_tax: row.get("withholding_tax"),
+            net_amount: row.get("net_amount"),
+            currency: row.get("currency"),
+            notes: row.get("notes"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewDividend {
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub ex_date: NaiveDate,
+    pub pay_date: NaiveDate,
+    pub amount_per_share: Decimal,
+    // None = títulos en posición el día anterior a la fecha ex-derecho
+    pub shares: Option<Decimal>,
+    // None = 10%; las emisoras extranjeras del SIC pueden traer otra retención
+    pub withholding_rate: Option<Decimal>,
+    // None = MXN
+    pub currency: Option<String>,
+    pub notes: Option<String>,
+}
+
+/// Dividendo decretado por una emisora en cartera que todavía no se registra.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DividendSuggestion {
+    pub ticker: String,
+    pub ex_date: NaiveDate,
+    pub pay_date: NaiveDate,
+    pub shares: Decimal,
+    pub amount_per_share: Decimal,
+    pub gross_amount: Decimal,
+    pub withholding_tax: Decimal,
+    pub net_amount: Decimal,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DividendTotals {
+    pub key: String,
+    pub payments: usize,
+    pub gross_amount: Decimal,
+    pub withholding_tax: Decimal,
+    pub net_amount: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DividendIncomeReport {
+    pub dividends: Vec<DividendRecord>,
+    pub by_ticker: Vec<DividendTotals>,
+    pub by_year: Vec<DividendTotals>,
+    pub total_gross: Decimal,
+    pub total_withholding: Decimal,
+    pub total_net: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YieldOnCost {
+    pub ticker: String,
+    pub shares: Decimal,
+    pub cost_basis: Decimal,
+    // Dividendos por acción con fecha ex-derecho en los últimos 12 meses
+    pub dividends_per_share_ttm: Decimal,
+    pub annual_income: Decimal,
+    pub yield_on_cost: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DividendEvent {
+    ex_date: NaiveDate,
+    pay_date: NaiveDate,
+    amount_per_share: Decimal,
+}
+
+struct DividendAmounts {
+    gross_amount: Decimal,
+    withholding_tax: Decimal,
+    net_amount: Decimal,
+}
+
+fn dividend_amounts(shares: Decimal, amount_per_share: Decimal, withholding_rate: Decimal) -> DividendAmounts {
+    let gross_amount = money::round_mxn(shares * amount_per_share);
+    let withholding_tax = money::round_mxn(gross_amount * withholding_rate);
+    DividendAmounts {
+        gross_amount,
+        withholding_tax,
+        net_amount: gross_amount - withholding_tax,
+    }
+}
+
+fn ex_date_start(ex_date: NaiveDate) -> DateTime<Utc> {
+    ex_date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn parse_date(value: &Value) -> Option<NaiveDate> {
+    let text = value.as_str()?;
+    let text = text.get(..10).unwrap_or(text);
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(text, "%d/%m/%Y"))
+        .ok()
+}
+
+fn parse_amount(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Number(n) => n.as_f64().and_then(Decimal::from_f64),
+        Value::String(s) => s.trim().replace(',', "").parse().ok(),
+        _ => None,
+    }
+}
+
+fn field<'a>(obj: &'a serde_json::Map<String, Value>, names: &[&str]) -> Option<&'a Value> {
+    names.iter().find_map(|name| obj.get(*name))
+}
+
+// emisoras.dividendos guarda lo que regresa DataBursátil: una lista de decretos o un objeto
+// con un decreto por llave. Se toma lo que tenga fecha ex-derecho y monto por acción.
+fn parse_dividend_events(raw: &str) -> Vec<DividendEvent> {
+    let Ok(value) = serde_json::from_str::<Value>(raw) else { return Vec::new() };
+    let items: Vec<&Value> = match &value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(map) => map.values().collect(),
+        _ => Vec::new(),
+    };
+    items.into_iter()
+        .filter_map(|item| {
+            let obj = item.as_object()?;
+            let ex_date = field(obj, &["fecha_ex", "fecha_exderecho", "fecha_ex_derecho", "ex_date", "fecha"]).and_then(parse_date)?;
+            let pay_date = field(obj, &["fecha_pago", "pago", "pay_date"]).and_then(parse_date).unwrap_or(ex_date);
+            let amount_per_share = field(obj, &["monto", "importe", "dividendo", "valor", "amount"]).and_then(parse_amount)?;
+            (amount_per_share > Decimal::ZERO).then_some(DividendEvent { ex_date, pay_date, amount_per_share: money::round_price(amount_per_share) })
+        })
+        .collect()
+}
+
+async fn load_dividend_events<C: GenericClient>(client: &C, ticker: &str) -> Result<Vec<DividendEvent>, String> {
+    let row = client.query_opt(
+        "SELECT dividendos FROM emisoras WHERE (emisoras || serie) = $1 OR emisoras = $1 LIMIT 1",
+        &[&ticker],
+    ).await.map_err(|e| format!("Error al consultar los dividendos de {}: {}", ticker, e))?;
+    Ok(row
+        .and_then(|r| r.get::<_, Option<String>>("dividendos"))
+        .map(|raw| parse_dividend_events(&raw))
+        .unwrap_or_default())
+}
+
+pub async fn list_dividends<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<Vec<DividendRecord>, String> {
+    let query = format!(
+        "SELECT {} FROM dividend_details d
+         JOIN portfolio_transactions t ON t.transaction_id = d.transaction_id
+         WHERE d.portfolio_id = $1 AND t.voided_at IS NULL
+         ORDER BY d.pay_date ASC, d.transaction_id ASC",
+        DIVIDEND_COLUMNS
+    );
+    let rows = client.query(&query, &[&portfolio_id])
+        .await.map_err(|e| format!("Error al listar dividendos: {}", e))?;
+    Ok(rows.iter().map(DividendRecord::from_row).collect())
+}
+
+/// Dividendo con sus importes ya calculados, o tomados tal cual de un estado de cuenta.
+#[derive(Debug, Clone)]
+pub struct DividendPayment {
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub ex_date: NaiveDate,
+    pub pay_date: NaiveDate,
+    pub shares: Decimal,
+    pub amount_per_share: Decimal,
+    pub gross_amount: Decimal,
+    pub withholding_tax: Decimal,
+    pub net_amount: Decimal,
+    // None = MXN
+    pub currency: Option<String>,
+    pub notes: Option<String>,
+}
+
+/// Títulos con derecho al dividendo: los que se tenían antes de la fecha ex-derecho.
+pub async fn entitled_shares<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    ticker: &str,
+    ex_date: NaiveDate,
+) -> Result<Decimal, String> {
+    ledger::held_quantity_before(client, portfolio_id, ticker, ex_date_start(ex_date)).await
+}
+
+/// Registra el neto en el ledger y el detalle fiscal en dividend_details, dentro de la
+/// transacción de quien llama.
+pub async fn insert_dividend<C: GenericClient>(client: &C, payment: DividendPayment) -> Result<DividendRecord, String> {
+    let mut entry = NewLedgerEntry::dividend(payment.portfolio_id, &payment.ticker, payment.net_amount);
+    entry.transaction_date = Some(ledger::local_timestamp(payment.pay_date));
+    if let Some(currency) = payment.currency {
+        entry.currency = currency;
+    }
+    entry.notes = payment.notes.or_else(|| Some(format!("Dividendo {} ex-derecho {}", payment.ticker, payment.ex_date)));
+    let recorded = ledger::insert_entry(client, &entry).await?;
+
+    client.execute(
+        "INSERT INTO dividend_details (transaction_id, portfolio_id, ticker, ex_date, pay_date, shares, amount_per_share, gross_amount, withholding_tax, net_amount)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        &[&recorded.transaction_id, &payment.portfolio_id, &payment.ticker, &payment.ex_date, &payment.pay_date,
+          &payment.shares, &payment.amount_per_share, &payment.gross_amount, &payment.withholding_tax, &payment.net_amount],
+    ).await.map_err(|e| {
+        if e.code() == Some(&tokio_postgres::error::SqlState::UNIQUE_VIOLATION) {
+            format!("El dividendo de {} con ex-derecho {} ya está registrado", payment.ticker, payment.ex_date)
+        } else {
+            format!("No se pudo guardar el detalle del dividendo: {}", e)
+        }
+    })?;
+
+    Ok(DividendRecord {
+        transaction_id: recorded.transaction_id,
+        portfolio_id: payment.portfolio_id,
+        ticker: payment.ticker,
+        ex_date: payment.ex_date,
+        pay_date: payment.pay_date,
+        shares: payment.shares,
+        amount_per_share: payment.amount_per_share,
+        gross_amount: payment.gross_amount,
+        withholding_tax: payment.withholding_tax,
+        net_amount: payment.net_amount,
+        currency: recorded.currency,
+        notes: recorded.notes,
+    })
+}
+
+pub async fn record_dividend_logic(
+    dividend: NewDividend,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<DividendRecord, String> {
+    if dividend.amount_per_share <= Decimal::ZERO {
+        return Err("El dividendo por acción debe ser mayor a cero".to_string());
+    }
+    if dividend.pay_date < dividend.ex_date {
+        return Err("La fecha de pago no puede ser anterior a la fecha ex-derecho".to_string());
+    }
+    let withholding_rate = dividend.withholding_rate.unwrap_or(DIVIDEND_WITHHOLDING_RATE);
+    if withholding_rate < Decimal::ZERO || withholding_rate >= Decimal::ONE {
+        return Err("La tasa de retención debe estar entre 0 y 1".to_string());
+    }
+
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+    let shares = match dividend.shares {
+        Some(shares) => money::round_quantity(shares),
+        None => entitled_shares(&*tx, dividend.portfolio_id, &dividend.ticker, dividend.ex_date).await?,
+    };
+    if shares <= Decimal::ZERO {
+        return Err(format!("No había títulos de {} antes de la fecha ex-derecho {}", dividend.ticker, dividend.ex_date));
+    }
+    let amount_per_share = money::round_price(dividend.amount_per_share);
+    let amounts = dividend_amounts(shares, amount_per_share, withholding_rate);
+    let record = insert_dividend(&*tx, DividendPayment {
+        portfolio_id: dividend.portfolio_id,
+        ticker: dividend.ticker,
+        ex_date: dividend.ex_date,
+        pay_date: dividend.pay_date,
+        shares,
+        amount_per_share,
+        gross_amount: amounts.gross_amount,
+        withholding_tax: amounts.withholding_tax,
+        net_amount: amounts.net_amount,
+        currency: dividend.currency,
+        notes: dividend.notes,
+    }).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(record)
+}
+
+/// Decretos de `emisoras.dividendos` para los tickers que se tenían en la fecha ex-derecho
+/// y que aún no están registrados.
+pub async fn suggest_dividends_logic(
+    portfolio_id: i32,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<DividendSuggestion>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let trades = ledger::list_entries(&**client, portfolio_id, Some(&[TransactionKind::Buy, TransactionKind::Sell])).await?;
+    let recorded: HashSet<(String, NaiveDate)> = list_dividends(&**client, portfolio_id).await?
+        .into_iter()
+        .map(|d| (d.ticker, d.ex_date))
+        .collect();
+    let Some(first_trade) = trades.first().map(|t| t.transaction_date.date_naive()) else {
+        return Ok(Vec::new());
+    };
+
+    let mut tickers: Vec<String> = trades.iter().map(|t| t.ticker.clone()).collect();
+    tickers.sort();
+    tickers.dedup();
+
+    let mut suggestions = Vec::new();
+    for ticker in tickers {
+        for event in load_dividend_events(&**client, &ticker).await? {
+            if event.ex_date <= first_trade || recorded.contains(&(ticker.clone(), event.ex_date)) {
+                continue;
+            }
+            let cutoff = ex_date_start(event.ex_date);
+            let shares: Decimal = trades.iter()
+                .filter(|t| t.ticker == ticker && t.transaction_date < cutoff)
+                .map(|t| if t.transaction_type == TransactionKind::Buy { t.quantity } else { -t.quantity })
+                .sum();
+            if shares <= Decimal::ZERO {
+                continue;
+            }
+            let amounts = dividend_amounts(shares, event.amount_per_share, DIVIDEND_WITHHOLDING_RATE);
+            suggestions.push(DividendSuggestion {
+                ticker: ticker.clone(),
+                ex_date: event.ex_date,
+                pay_date: event.pay_date,
+                shares,
+                amount_per_share: event.amount_per_share,
+                gross_amount: amounts.gross_amount,
+                withholding_tax: amounts.withholding_tax,
+                net_amount: amounts.net_amount,
+            });
+        }
+    }
+    suggestions.sort_by(|a, b| a.ex_date.cmp(&b.ex_date).then(a.ticker.cmp(&b.ticker)));
+    Ok(suggestions)
+}
+
+fn add_to_totals(totals: &mut BTreeMap<String, DividendTotals>, key: String, dividend: &DividendRecord) {
+    let entry = totals.entry(key.clone()).or_insert_with(|| DividendTotals { key, ..Default::default() });
+    entry.payments += 1;
+    entry.gross_amount += dividend.gross_amount;
+    entry.withholding_tax += dividend.withholding_tax;
+    entry.net_amount += dividend.net_amount;
+}
+
+pub async fn get_dividend_income_logic(
+    portfolio_id: i32,
+    year: Option<i32>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<DividendIncomeReport, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let dividends: Vec<DividendRecord> = list_dividends(&**client, portfolio_id).await?
+        .into_iter()
+        .filter(|d| !matches!(year, Some(y) if d.pay_date.year() != y))
+        .collect();
+
+    let mut by_ticker = BTreeMap::new();
+    let mut by_year = BTreeMap::new();
+    for dividend in &dividends {
+        add_to_totals(&mut by_ticker, dividend.ticker.clone(), dividend);
+        add_to_totals(&mut by_year, dividend.pay_date.year().to_string(), dividend);
+    }
+
+    Ok(DividendIncomeReport {
+        total_gross: dividends.iter().map(|d| d.gross_amount).sum(),
+        total_withholding: dividends.iter().map(|d| d.withholding_tax).sum(),
+        total_net: dividends.iter().map(|d| d.net_amount).sum(),
+        by_ticker: by_ticker.into_values().collect(),
+        by_year: by_year.into_values().collect(),
+        dividends,
+    })
+}
+
+/// Rendimiento sobre costo de las posiciones abiertas: dividendos brutos por acción de los
+/// últimos 12 meses por los títulos actuales, entre el costo de esos títulos.
+pub async fn get_dividend_yield_on_cost_logic(
+    portfolio_id: i32,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<YieldOnCost>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let positions = lots::positions(&**client, portfolio_id).await?;
+    let dividends = list_dividends(&**client, portfolio_id).await?;
+    let since = Utc::now().date_naive() - Duration::days(365);
+
+    let mut result: Vec<YieldOnCost> = positions.into_iter()
+        .map(|(ticker, position)| {
+            let dividends_per_share_ttm: Decimal = dividends.iter()
+                .filter(|d| d.ticker == ticker && d.ex_date > since)
+                .map(|d| d.amount_per_share)
+                .sum();
+            let annual_income = money::round_mxn(dividends_per_share_ttm * position.quantity);
+            YieldOnCost {
+                yield_on_cost: money::percent(annual_income, position.total_cost),
+                ticker,
+                shares: position.quantity,
+                cost_basis: position.total_cost,
+                dividends_per_share_ttm,
+                annual_income,
+            }
+        })
+        .collect();
+    result.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+    Ok(result)
+}
@@ -0,0 +1,375 @@
+use chrono::{NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::str::FromStr;
+
+use crate::dividends::{self, DividendRecord};
+use crate::fx;
+use crate::isr::csv_field;
+use crate::ledger::{self, LedgerEntry, TransactionKind, CASH_TICKER};
+use crate::snapshots::{self, PortfolioSnapshot};
+
+// Cuentas del diario contable (ledger/hledger)
+const CASH_ACCOUNT: &str = "Activos:Efectivo";
+const SECURITIES_ACCOUNT: &str = "Activos:Inversiones";
+const CONTRIBUTIONS_ACCOUNT: &str = "Patrimonio:Aportaciones";
+const DIVIDENDS_ACCOUNT: &str = "Ingresos:Dividendos";
+const INTEREST_ACCOUNT: &str = "Ingresos:Intereses";
+const COMMISSION_ACCOUNT: &str = "Gastos:Comisiones";
+const COMMISSION_IVA_ACCOUNT: &str = "Gastos:IVA de comisiones";
+const WITHHOLDING_ACCOUNT: &str = "Gastos:ISR retenido";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    // Estado de cuenta de inversiones OFX 2.2
+    Ofx,
+    // Diario en texto plano que leen ledger y hledger
+    Ledger,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "ofx" => Ok(ExportFormat::Ofx),
+            "ledger" | "hledger" | "ledger-cli" | "journal" => Ok(ExportFormat::Ledger),
+            _ => Err(format!("Formato de exportación no soportado: {} (usar csv, json, ofx o ledger)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FeeTotals {
+    pub commission: Decimal,
+    pub commission_iva: Decimal,
+    pub total: Decimal,
+}
+
+/// Movimientos del periodo tal como los devuelve `list_portfolio_transactions` (sin los
+/// anulados), con el detalle fiscal de los dividendos y, opcionalmente, la valuación diaria.
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerExport {
+    pub portfolio_id: i32,
+    pub portfolio_name: String,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub generated_at: chrono::DateTime<Utc>,
+    pub transactions: Vec<LedgerEntry>,
+    pub dividends: Vec<DividendRecord>,
+    pub fees: FeeTotals,
+    pub snapshots: Option<Vec<PortfolioSnapshot>>,
+}
+
+impl LedgerExport {
+    fn dividend(&self, transaction_id: i32) -> Option<&DividendRecord> {
+        self.dividends.iter().find(|d| d.transaction_id == transaction_id)
+    }
+}
+
+fn gross_amount(entry: &LedgerEntry) -> Decimal {
+    (entry.quantity * entry.price).round_dp(2)
+}
+
+fn to_csv(data: &LedgerExport) -> String {
+    let mut csv = String::new();
+    csv.push_str("Fecha,Id,Tipo,Ticker,Titulos,Precio,Importe bruto,Comision,IVA comision,Importe total,Moneda,Tipo de cambio,Importe MXN,Efecto en efectivo,ISR retenido,Operacion enlazada,Notas\n");
+    for entry in &data.transactions {
+        let withholding = data.dividend(entry.transaction_id).map(|d| d.withholding_tax.to_string()).unwrap_or_default();
+        let row = [
+            entry.transaction_date.format("%Y-%m-%d").to_string(),
+            entry.transaction_id.to_string(),
+            entry.transaction_type.to_string(),
+            csv_field(&entry.ticker),
+            entry.quantity.normalize().to_string(),
+            entry.price.normalize().to_string(),
+            format!("{:.2}", gross_amount(entry)),
+            format!("{:.2}", entry.commission),
+            format!("{:.2}", entry.commission_iva),
+            format!("{:.2}", entry.total_amount),
+            entry.currency.clone(),
+            entry.fx_rate.normalize().to_string(),
+            format!("{:.2}", entry.total_amount * entry.fx_rate),
+            format!("{:.2}", entry.cash_effect()),
+            withholding,
+            entry.linked_transaction_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(entry.notes.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv.push('\n');
+    csv.push_str("Concepto,Importe MXN\n");
+    csv.push_str(&format!("Comisiones,{:.2}\nIVA de comisiones,{:.2}\nTotal de comisiones,{:.2}\n", data.fees.commission, data.fees.commission_iva, data.fees.total));
+    if let Some(snapshots) = &data.snapshots {
+        csv.push('\n');
+        csv.push_str("Fecha,Valor de mercado,Efectivo,Valor total,Flujo neto,Aportaciones acumuladas\n");
+        for s in snapshots {
+            csv.push_str(&format!(
+                "{},{:.2},{:.2},{:.2},{:.2},{:.2}\n",
+                s.snapshot_date, s.market_value, s.cash, s.total_value, s.net_flow, s.contributions
+            ));
+        }
+    }
+    csv
+}
+
+fn ofx_text(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn ofx_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+fn ofx_currency(entry: &LedgerEntry) -> String {
+    if entry.currency == fx::MXN {
+        String::new()
+    } else {
+        format!("<CURRENCY><CURRATE>{}</CURRATE><CURSYM>{}</CURSYM></CURRENCY>", entry.fx_rate.normalize(), entry.currency)
+    }
+}
+
+fn ofx_security(ticker: &str) -> String {
+    format!("<SECID><UNIQUEID>{}</UNIQUEID><UNIQUEIDTYPE>TICKER</UNIQUEIDTYPE></SECID>", ofx_text(ticker))
+}
+
+fn ofx_invtran(entry: &LedgerEntry) -> String {
+    let memo = entry.notes.as_deref().map(|n| format!("<MEMO>{}</MEMO>", ofx_text(n))).unwrap_or_default();
+    format!(
+        "<INVTRAN><FITID>{}</FITID><DTTRADE>{}</DTTRADE>{}</INVTRAN>",
+        entry.transaction_id, ofx_date(entry.transaction_date.date_naive()), memo
+    )
+}
+
+fn to_ofx(data: &LedgerExport) -> String {
+    let today = Utc::now().date_naive();
+    let first = data.transactions.first().map(|e| e.transaction_date.date_naive());
+    let start = data.from.or(first).unwrap_or(today);
+    let end = data.to.unwrap_or(today);
+
+    let mut transactions = String::new();
+    for entry in &data.transactions {
+        let fees = entry.commission + entry.commission_iva;
+        let line = match entry.transaction_type {
+            TransactionKind::Buy => format!(
+                "<BUYSTOCK><INVBUY>{}{}<UNITS>{}</UNITS><UNITPRICE>{}</UNITPRICE><COMMISSION>{}</COMMISSION><TOTAL>{}</TOTAL>{}<SUBACCTSEC>CASH</SUBACCTSEC><SUBACCTFUND>CASH</SUBACCTFUND></INVBUY><BUYTYPE>BUY</BUYTYPE></BUYSTOCK>",
+                ofx_invtran(entry), ofx_security(&entry.ticker), entry.quantity.normalize(), entry.price.normalize(),
+                fees, -entry.total_amount, ofx_currency(entry)
+            ),
+            TransactionKind::Sell => format!(
+                "<SELLSTOCK><INVSELL>{}{}<UNITS>{}</UNITS><UNITPRICE>{}</UNITPRICE><COMMISSION>{}</COMMISSION><TOTAL>{}</TOTAL>{}<SUBACCTSEC>CASH</SUBACCTSEC><SUBACCTFUND>CASH</SUBACCTFUND></INVSELL><SELLTYPE>SELL</SELLTYPE></SELLSTOCK>",
+                ofx_invtran(entry), ofx_security(&entry.ticker), -entry.quantity.normalize(), entry.price.normalize(),
+                fees, entry.total_amount, ofx_currency(entry)
+            ),
+            TransactionKind::Dividend => {
+                let withholding = data.dividend(entry.transaction_id)
+                    .map(|d| format!("<WITHHOLDING>{}</WITHHOLDING>", d.withholding_tax))
+                    .unwrap_or_default();
+                format!(
+                    "<INCOME>{}{}<INCOMETYPE>DIV</INCOMETYPE><TOTAL>{}</TOTAL><SUBACCTSEC>CASH</SUBACCTSEC><SUBACCTFUND>CASH</SUBACCTFUND>{}{}</INCOME>",
+                    ofx_invtran(entry), ofx_security(&entry.ticker), entry.total_amount, withholding, ofx_currency(entry)
+                )
+            }
+            TransactionKind::Deposit | TransactionKind::Withdrawal | TransactionKind::Interest => {
+                let (kind, amount) = match entry.transaction_type {
+                    TransactionKind::Deposit => ("CREDIT", entry.total_amount),
+                    TransactionKind::Interest => ("INT", entry.total_amount),
+                    _ => ("DEBIT", -entry.total_amount),
+                };
+                let memo = entry.notes.as_deref().map(|n| format!("<MEMO>{}</MEMO>", ofx_text(n))).unwrap_or_default();
+                format!(
+                    "<INVBANKTRAN><STMTTRN><TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}</TRNAMT><FITID>{}</FITID>{}{}</STMTTRN><SUBACCTFUND>CASH</SUBACCTFUND></INVBANKTRAN>",
+                    kind, ofx_date(entry.transaction_date.date_naive()), amount, entry.transaction_id, memo, ofx_currency(entry)
+                )
+            }
+        };
+        transactions.push_str(&line);
+        transactions.push('\n');
+    }
+
+    // Con valuación se informa el efectivo y los valores del último día del periodo
+    let balance = data.snapshots.as_ref().and_then(|s| s.last()).map(|last| {
+        let bal = |name: &str, value: Decimal| format!(
+            "<BAL><NAME>{}</NAME><DESC>{}</DESC><BALTYPE>DOLLAR</BALTYPE><VALUE>{}</VALUE><DTASOF>{}</DTASOF></BAL>",
+            name, name, value, ofx_date(last.snapshot_date)
+        );
+        format!(
+            "<INVBAL><AVAILCASH>{}</AVAILCASH><MARGINBALANCE>0</MARGINBALANCE><SHORTBALANCE>0</SHORTBALANCE><BALLIST>{}{}</BALLIST></INVBAL>\n",
+            last.cash, bal("Valor de mercado", last.market_value), bal("Valor total", last.total_value)
+        )
+    }).unwrap_or_default();
+
+    let tickers: BTreeSet<&str> = data.transactions.iter()
+        .filter(|e| e.ticker != CASH_TICKER)
+        .map(|e| e.ticker.as_str())
+        .collect();
+    let securities: String = tickers.iter()
+        .map(|t| format!(
+            "<STOCKINFO><SECINFO>{}<SECNAME>{}</SECNAME><TICKER>{}</TICKER></SECINFO></STOCKINFO>\n",
+            ofx_security(t), ofx_text(t), ofx_text(t)
+        ))
+        .collect();
+
+    let status = "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>";
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+         <?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n\
+         <OFX>\n\
+         <SIGNONMSGSRSV1><SONRS>{status}<DTSERVER>{server}</DTSERVER><LANGUAGE>SPA</LANGUAGE></SONRS></SIGNONMSGSRSV1>\n\
+         <INVSTMTMSGSRSV1><INVSTMTTRNRS><TRNUID>{id}</TRNUID>{status}\n\
+         <INVSTMTRS><DTASOF>{end}</DTASOF><CURDEF>MXN</CURDEF>\
+         <INVACCTFROM><BROKERID>daliatrac</BROKERID><ACCTID>{id}</ACCTID></INVACCTFROM>\n\
+         <INVTRANLIST><DTSTART>{start}</DTSTART><DTEND>{end}</DTEND>\n\
+         {transactions}</INVTRANLIST>\n\
+         {balance}</INVSTMTRS></INVSTMTTRNRS></INVSTMTMSGSRSV1>\n\
+         <SECLISTMSGSRSV1><SECLIST>\n\
+         {securities}</SECLIST></SECLISTMSGSRSV1>\n\
+         </OFX>\n",
+        status = status,
+        server = ofx_date(today),
+        id = data.portfolio_id,
+        start = ofx_date(start),
+        end = ofx_date(end),
+        transactions = transactions,
+        balance = balance,
+        securities = securities,
+    )
+}
+
+fn ledger_posting(account: &str, amount: &str) -> String {
+    format!("    {:<40}  {}\n", account, amount)
+}
+
+fn to_ledger(data: &LedgerExport) -> String {
+    let mut journal = format!(
+        "; Portafolio {} ({}), exportado el {}\n\n",
+        data.portfolio_name, data.portfolio_id, data.generated_at.format("%Y-%m-%d")
+    );
+    for entry in &data.transactions {
+        let date = entry.transaction_date.format("%Y-%m-%d");
+        let cash = format!("{}:{}", CASH_ACCOUNT, entry.currency);
+        let money = |amount: Decimal| format!("{:.2} {}", amount, entry.currency);
+        let (payee, postings) = match entry.transaction_type {
+            TransactionKind::Buy | TransactionKind::Sell => {
+                let sign = if entry.transaction_type == TransactionKind::Buy { Decimal::ONE } else { -Decimal::ONE };
+                // Los tickers pueden traer '*' o dígitos, así que el commodity va entre comillas
+                let mut postings = ledger_posting(
+                    &format!("{}:{}", SECURITIES_ACCOUNT, entry.ticker),
+                    &format!("{} \"{}\" @ {} {}", sign * entry.quantity.normalize(), entry.ticker, entry.price.normalize(), entry.currency),
+                );
+                if entry.commission > Decimal::ZERO {
+                    postings.push_str(&ledger_posting(COMMISSION_ACCOUNT, &money(entry.commission)));
+                }
+                if entry.commission_iva > Decimal::ZERO {
+                    postings.push_str(&ledger_posting(COMMISSION_IVA_ACCOUNT, &money(entry.commission_iva)));
+                }
+                postings.push_str(&ledger_posting(&cash, &money(entry.cash_effect())));
+                let verb = if entry.transaction_type == TransactionKind::Buy { "Compra" } else { "Venta" };
+                (format!("{} {}", verb, entry.ticker), postings)
+            }
+            TransactionKind::Dividend => {
+                let detail = data.dividend(entry.transaction_id);
+                let gross = detail.map(|d| d.gross_amount).unwrap_or(entry.total_amount);
+                let withholding = detail.map(|d| d.withholding_tax).unwrap_or_default();
+                let mut postings = ledger_posting(&cash, &money(entry.total_amount));
+                if withholding > Decimal::ZERO {
+                    postings.push_str(&ledger_posting(WITHHOLDING_ACCOUNT, &money(withholding)));
+                }
+                postings.push_str(&ledger_posting(&format!("{}:{}", DIVIDENDS_ACCOUNT, entry.ticker), &money(-gross)));
+                (format!("Dividendo {}", entry.ticker), postings)
+            }
+            TransactionKind::Interest => {
+                let postings = ledger_posting(&cash, &money(entry.total_amount)) + &ledger_posting(INTEREST_ACCOUNT, &money(-entry.total_amount));
+                ("Intereses sobre efectivo".to_string(), postings)
+            }
+            TransactionKind::Deposit | TransactionKind::Withdrawal => {
+                let amount = entry.cash_effect();
+                let postings = ledger_posting(&cash, &money(amount)) + &ledger_posting(CONTRIBUTIONS_ACCOUNT, &money(-amount));
+                let payee = if amount > Decimal::ZERO { "Depósito" } else { "Retiro" };
+                (payee.to_string(), postings)
+            }
+        };
+        journal.push_str(&format!("{} * {}  ; id:{}\n", date, payee, entry.transaction_id));
+        if let Some(notes) = entry.notes.as_deref().filter(|n| !n.is_empty()) {
+            journal.push_str(&format!("    ; {}\n", notes.replace('\n', " ")));
+        }
+        journal.push_str(&postings);
+        journal.push('\n');
+    }
+    if let Some(snapshots) = &data.snapshots {
+        journal.push_str("; Valuación diaria: fecha, valor de mercado, efectivo, valor total (MXN)\n");
+        for s in snapshots {
+            journal.push_str(&format!("; {} {:.2} {:.2} {:.2}\n", s.snapshot_date, s.market_value, s.cash, s.total_value));
+        }
+    }
+    journal
+}
+
+/// Exporta los movimientos, flujos de efectivo, dividendos y comisiones del portafolio
+/// entre `from` y `to` (inclusive) en csv, json, ofx o ledger.
+pub async fn export_ledger_logic(
+    portfolio_id: i32,
+    format: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    include_snapshots: bool,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<String, String> {
+    let format: ExportFormat = format.parse()?;
+    if matches!((from, to), (Some(from), Some(to)) if from > to) {
+        return Err("La fecha inicial debe ser anterior a la final".to_string());
+    }
+    let in_range = |date: NaiveDate| {
+        !matches!(from, Some(from) if date < from) && !matches!(to, Some(to) if date > to)
+    };
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let portfolio_name: String = client.query_opt("SELECT nombre FROM portafolios WHERE id = $1", &[&portfolio_id])
+        .await.map_err(|e| format!("Error al consultar el portafolio: {}", e))?
+        .ok_or_else(|| format!("No se encontró el portafolio {}", portfolio_id))?
+        .get("nombre");
+    let transactions: Vec<LedgerEntry> = ledger::list_entries(&**client, portfolio_id, None).await?
+        .into_iter()
+        .filter(|e| in_range(e.transaction_date.date_naive()))
+        .collect();
+    let ids: HashSet<i32> = transactions.iter().map(|e| e.transaction_id).collect();
+    let dividends = dividends::list_dividends(&**client, portfolio_id).await?
+        .into_iter()
+        .filter(|d| ids.contains(&d.transaction_id))
+        .collect();
+    let snapshots = if include_snapshots {
+        snapshots::update_snapshots(&**client, portfolio_id).await?;
+        Some(snapshots::load_snapshots(&**client, portfolio_id, from).await?
+            .into_iter()
+            .filter(|s| in_range(s.snapshot_date))
+            .collect())
+    } else {
+        None
+    };
+    let commission: Decimal = transactions.iter().map(|e| e.commission * e.fx_rate).sum::<Decimal>().round_dp(2);
+    let commission_iva: Decimal = transactions.iter().map(|e| e.commission_iva * e.fx_rate).sum::<Decimal>().round_dp(2);
+
+    let data = LedgerExport {
+        portfolio_id,
+        portfolio_name,
+        from,
+        to,
+        generated_at: Utc::now(),
+        transactions,
+        dividends,
+        fees: FeeTotals { commission, commission_iva, total: commission + commission_iva },
+        snapshots,
+    };
+    match format {
+        ExportFormat::Csv => Ok(to_csv(&data)),
+        ExportFormat::Json => serde_json::to_string_pretty(&data).map_err(|e| e.to_string()),
+        ExportFormat::Ofx => Ok(to_ofx(&data)),
+        ExportFormat::Ledger => Ok(to_ledger(&data)),
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::Datelike;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio_postgres::GenericClient;
+
+use crate::ledger::{self, TradeFees, TransactionKind};
+use crate::money;
+
+pub const IVA_RATE: Decimal = Decimal::from_parts(16, 0, 0, false, 2);
+
+/// Esquema de comisiones de la casa de bolsa para un portafolio.
+/// comisión = max(importe * commission_rate, minimum_commission) + fixed_fee, más IVA.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub portfolio_id: i32,
+    pub commission_rate: Decimal,
+    pub minimum_commission: Decimal,
+    pub fixed_fee: Decimal,
+    pub iva_rate: Decimal,
+}
+
+impl FeeSchedule {
+    // Sin esquema registrado las operaciones no llevan comisión
+    pub fn none(portfolio_id: i32) -> Self {
+        FeeSchedule {
+            portfolio_id,
+            commission_rate: Decimal::ZERO,
+            minimum_commission: Decimal::ZERO,
+            fixed_fee: Decimal::ZERO,
+            iva_rate: IVA_RATE,
+        }
+    }
+
+    pub fn fees_for(&self, gross_amount: Decimal) -> TradeFees {
+        let variable = (gross_amount * self.commission_rate).max(self.minimum_commission);
+        let commission = money::round_mxn(variable + self.fixed_fee);
+        TradeFees {
+            commission,
+            commission_iva: money::round_mxn(commission * self.iva_rate),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.commission_rate < Decimal::ZERO || self.commission_rate >= Decimal::ONE {
+            return Err("La comisión porcentual debe estar entre 0 y 1".to_string());
+        }
+        if self.minimum_commission < Decimal::ZERO || self.fixed_fee < Decimal::ZERO {
+            return Err("La comisión mínima y la cuota fija no pueden ser negativas".to_string());
+        }
+        if self.iva_rate < Decimal::ZERO || self.iva_rate >= Decimal::ONE {
+            return Err("La tasa de IVA debe estar entre 0 y 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeeTotals {
+    pub key: String,
+    pub trades: usize,
+    pub traded_volume: Decimal,
+    pub commission: Decimal,
+    pub commission_iva: Decimal,
+    pub total_fees: Decimal,
+    pub fees_percent_of_volume: Decimal,
+}
+
+impl FeeTotals {
+    fn add(&mut self, volume: Decimal, fees: TradeFees) {
+        self.trades += 1;
+        self.traded_volume += volume;
+        self.commission += fees.commission;
+        self.commission_iva += fees.commission_iva;
+        self.total_fees += fees.total();
+    }
+
+    fn finish(mut self) -> Self {
+        self.fees_percent_of_volume = money::percent(self.total_fees, self.traded_volume);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeReport {
+    pub total: FeeTotals,
+    pub by_ticker: Vec<FeeTotals>,
+    pub by_year: Vec<FeeTotals>,
+}
+
+pub async fn fee_schedule<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<FeeSchedule, String> {
+    let row = client.query_opt(
+        "SELECT commission_rate, minimum_commission, fixed_fee, iva_rate FROM fee_schedules WHERE portfolio_id = $1",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar el esquema de comisiones: {}", e))?;
+    Ok(match row {
+        Some(row) => FeeSchedule {
+            portfolio_id,
+            commission_rate: row.get("commission_rate"),
+            minimum_commission: row.get("minimum_commission"),
+            fixed_fee: row.get("fixed_fee"),
+            iva_rate: row.get("iva_rate"),
+        },
+        None => FeeSchedule::none(portfolio_id),
+    })
+}
+
+pub async fn get_fee_schedule_logic(portfolio_id: i32, db_pool: &deadpool_postgres::Pool) -> Result<FeeSchedule, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    fee_schedule(&**client, portfolio_id).await
+}
+
+pub async fn set_fee_schedule_logic(schedule: FeeSchedule, db_pool: &deadpool_postgres::Pool) -> Result<FeeSchedule, String> {
+    schedule.validate()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    client.execute(
+        "INSERT INTO fee_schedules (portfolio_id, commission_rate, minimum_commission, fixed_fee, iva_rate, updated_at)
+         VALUES ($1, $2, $3, $4, $5, now())
+         ON CONFLICT (portfolio_id) DO UPDATE SET
+            commission_rate = EXCLUDED.commission_rate,
+            minimum_commission = EXCLUDED.minimum_commission,
+            fixed_fee = EXCLUDED.fixed_fee,
+            iva_rate = EXCLUDED.iva_rate,
+            updated_at = now()",
+        &[&schedule.portfolio_id, &schedule.commission_rate, &schedule.minimum_commission, &schedule.fixed_fee, &schedule.iva_rate],
+    ).await.map_err(|e| format!("No se pudo guardar el esquema de comisiones: {}", e))?;
+    Ok(schedule)
+}
+
+/// Comisiones e IVA pagados en compras y ventas, en pesos, en total, por ticker y por año.
+pub async fn get_fee_report_logic(
+    portfolio_id: i32,
+    year: Option<i32>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<FeeReport, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let trades = ledger::list_entries(&**client, portfolio_id, Some(&[TransactionKind::Buy, TransactionKind::Sell])).await?;
+
+    let mut total = FeeTotals { key: "TOTAL".to_string(), ..Default::default() };
+    let mut by_ticker: BTreeMap<String, FeeTotals> = BTreeMap::new();
+    let mut by_year: BTreeMap<String, FeeTotals> = BTreeMap::new();
+    for trade in trades.iter().filter(|t| !matches!(year, Some(y) if t.transaction_date.year() != y)) {
+        // Las operaciones en otra moneda se suman en pesos al tipo de cambio de cada una
+        let volume = money::round_mxn(trade.quantity * trade.price * trade.fx_rate);
+        let fees = TradeFees {
+            commission: money::round_mxn(trade.commission * trade.fx_rate),
+            commission_iva: money::round_mxn(trade.commission_iva * trade.fx_rate),
+        };
+        total.add(volume, fees);
+        by_ticker.entry(trade.ticker.clone())
+            .or_insert_with(|| FeeTotals { key: trade.ticker.clone(), ..Default::default() })
+            .add(volume, fees);
+        let year_key = trade.transaction_date.year().to_string();
+        by_year.entry(year_key.clone())
+            .or_insert_with(|| FeeTotals { key: year_key, ..Default::default() })
+            .add(volume, fees);
+    }
+
+    Ok(FeeReport {
+        total: total.finish(),
+        by_ticker: by_ticker.into_values().map(FeeTotals::finish).collect(),
+        by_year: by_year.into_values().map(FeeTotals::finish).collect(),
+    })
+}
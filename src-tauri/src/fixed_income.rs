@@ -0,0 +1,797 @@
+use chrono::{DateTime, Duration, Months, NaiveDate, Utc};
+use deadpool_postgres::Pool;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_postgres::GenericClient;
+
+use crate::benchmarks;
+use crate::ledger::{self, CashSource, LedgerEntry, NewLedgerEntry, TradeFees, TransactionKind};
+use crate::lots::{self, OpenLot};
+use crate::money;
+use crate::orders;
+use crate::performance::to_f64;
+use crate::prices::{self, PriceSeries};
+use crate::snapshots;
+use crate::yield_curve::{self, Interpolation, YieldCurve};
+
+pub const CETE_FACE_VALUE: Decimal = Decimal::TEN;
+pub const BOND_FACE_VALUE: Decimal = Decimal::ONE_HUNDRED;
+// Convención del mercado de dinero mexicano: año de 360 días
+const DAY_COUNT: f64 = 360.0;
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum InstrumentKind {
+    // A descuento, sin cupones
+    Cete,
+    // Cupón fijo y amortización al vencimiento
+    Bond,
+}
+
+impl InstrumentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstrumentKind::Cete => "CETE",
+            InstrumentKind::Bond => "BOND",
+        }
+    }
+}
+
+impl FromStr for InstrumentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "CETE" | "CETES" => Ok(InstrumentKind::Cete),
+            "BOND" | "BONO" => Ok(InstrumentKind::Bond),
+            _ => Err(format!("Tipo de instrumento desconocido: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ValuationMethod {
+    // Con el rendimiento de compra
+    Amortized,
+    // Con la tasa de mercado guardada
+    Marked,
+}
+
+impl ValuationMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValuationMethod::Amortized => "AMORTIZED",
+            ValuationMethod::Marked => "MARKED",
+        }
+    }
+}
+
+impl FromStr for ValuationMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "AMORTIZED" | "COSTO_AMORTIZADO" => Ok(ValuationMethod::Amortized),
+            "MARKED" | "MERCADO" => Ok(ValuationMethod::Marked),
+            _ => Err(format!("Método de valuación desconocido: {}", s)),
+        }
+    }
+}
+
+/// Rendimientos, cupón y spread en porcentaje anual.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixedIncomeInstrument {
+    pub ticker: String,
+    pub kind: InstrumentKind,
+    pub face_value: Decimal,
+    pub maturity_date: NaiveDate,
+    pub coupon_rate: Decimal,
+    pub coupons_per_year: i16,
+    // Rendimiento de la primera compra; cada lote guarda el suyo en fixed_income_lots
+    pub purchase_yield: Decimal,
+    pub rate_symbol: Option<String>,
+    pub spread: Decimal,
+    pub valuation_method: ValuationMethod,
+}
+
+impl FixedIncomeInstrument {
+    fn coupon(&self) -> f64 {
+        to_f64(self.face_value * self.coupon_rate / Decimal::ONE_HUNDRED) / self.coupons_per_year.max(1) as f64
+    }
+
+    /// Fechas de cupón en (`after`, `until`], contadas hacia atrás desde el vencimiento.
+    pub fn coupon_dates(&self, after: NaiveDate, until: NaiveDate) -> Vec<NaiveDate> {
+        if self.kind != InstrumentKind::Bond || self.coupons_per_year <= 0 {
+            return Vec::new();
+        }
+        let step = 12 / self.coupons_per_year as u32;
+        let mut dates = Vec::new();
+        let mut periods = 0;
+        loop {
+            let date = self.maturity_date - Months::new(step * periods);
+            if date <= after {
+                break;
+            }
+            if date <= until {
+                dates.push(date);
+            }
+            periods += 1;
+        }
+        dates.reverse();
+        dates
+    }
+
+    /// Precio sucio por título con un rendimiento anual `yield_rate` (fracción) al día `date`.
+    pub fn price_at_yield(&self, yield_rate: f64, date: NaiveDate) -> f64 {
+        let face = to_f64(self.face_value);
+        let days = (self.maturity_date - date).num_days().max(0) as f64;
+        if self.kind == InstrumentKind::Cete || self.coupons_per_year <= 0 {
+            return face / (1.0 + yield_rate * days / DAY_COUNT);
+        }
+        let per_year = self.coupons_per_year as f64;
+        let period_days = DAY_COUNT / per_year;
+        let discount = |flow_date: NaiveDate| {
+            let periods = (flow_date - date).num_days() as f64 / period_days;
+            (1.0 + yield_rate / per_year).powf(periods)
+        };
+        let coupons: f64 = self.coupon_dates(date, self.maturity_date).into_iter()
+            .map(|d| self.coupon() / discount(d))
+            .sum();
+        coupons + face / discount(self.maturity_date)
+    }
+
+    /// Rendimiento anual (fracción) que corresponde a `price` al día `date`.
+    pub fn yield_at_price(&self, price: f64, date: NaiveDate) -> Option<f64> {
+        let days = (self.maturity_date - date).num_days();
+        if price <= 0.0 || days <= 0 {
+            return None;
+        }
+        if self.kind == InstrumentKind::Cete || self.coupons_per_year <= 0 {
+            return Some((to_f64(self.face_value) / price - 1.0) * DAY_COUNT / days as f64);
+        }
+        // El precio baja conforme sube el rendimiento: bisección
+        let (mut low, mut high) = (-0.5, 5.0);
+        for _ in 0..100 {
+            let mid = (low + high) / 2.0;
+            if self.price_at_yield(mid, date) > price {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Some((low + high) / 2.0)
+    }
+}
+
+// Tasas para valuar a mercado: la serie de `rate_symbol` o, sin ella, la curva de CETES
+// al plazo por vencer. Si no hay curvas construidas para el periodo se usa la tasa del
+// plazo de CETES más cercano guardada en benchmark_levels.
+enum MarkingRates {
+    Symbol(PriceSeries),
+    Curves(BTreeMap<NaiveDate, YieldCurve>),
+    Tenors(Vec<(i64, PriceSeries)>),
+}
+
+impl MarkingRates {
+    async fn load<C: GenericClient>(
+        client: &C,
+        instrument: &FixedIncomeInstrument,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<MarkingRates, String> {
+        if let Some(symbol) = &instrument.rate_symbol {
+            return Ok(MarkingRates::Symbol(benchmarks::stored_levels(client, symbol).await?));
+        }
+        let curves = yield_curve::curves_between(client, from - Duration::days(7), to).await?;
+        if curves.range(..=from).next_back().is_some() {
+            return Ok(MarkingRates::Curves(curves));
+        }
+        println!(
+            "[FIXED_INCOME] Sin curvas de CETES desde el {}; {} se valúa con la tasa del plazo más cercano en benchmark_levels",
+            from, instrument.ticker
+        );
+        let mut tenors = Vec::new();
+        for (tenor_days, symbol) in yield_curve::CETE_TENORS {
+            tenors.push((tenor_days, benchmarks::stored_levels(client, symbol).await?));
+        }
+        Ok(MarkingRates::Tenors(tenors))
+    }
+
+    // Tasa de mercado más spread, en porcentaje
+    fn rate_on(&self, instrument: &FixedIncomeInstrument, date: NaiveDate) -> Option<Decimal> {
+        let rate = match self {
+            MarkingRates::Symbol(series) => prices::close_on(series, date)?,
+            MarkingRates::Curves(curves) => {
+                let (_, curve) = curves.range(..=date).next_back()?;
+                let days = (instrument.maturity_date - date).num_days();
+                Decimal::from_f64(curve.rate(days, Interpolation::default()))?.round_dp(6)
+            }
+            MarkingRates::Tenors(tenors) => {
+                let days = (instrument.maturity_date - date).num_days();
+                let (_, series) = tenors.iter().min_by_key(|(tenor_days, _)| (tenor_days - days).abs())?;
+                prices::close_on(series, date)?
+            }
+        };
+        Some(rate + instrument.spread)
+    }
+}
+
+const INSTRUMENT_COLUMNS: &str = "ticker, kind, face_value, maturity_date, coupon_rate, coupons_per_year, purchase_yield, rate_symbol, spread, valuation_method";
+
+fn instrument_from_row(row: &tokio_postgres::Row) -> Result<FixedIncomeInstrument, String> {
+    let kind: String = row.get("kind");
+    let method: String = row.get("valuation_method");
+    Ok(FixedIncomeInstrument {
+        ticker: row.get("ticker"),
+        kind: kind.parse()?,
+        face_value: row.get("face_value"),
+        maturity_date: row.get("maturity_date"),
+        coupon_rate: row.get("coupon_rate"),
+        coupons_per_year: row.get("coupons_per_year"),
+        purchase_yield: row.get("purchase_yield"),
+        rate_symbol: row.get("rate_symbol"),
+        spread: row.get("spread"),
+        valuation_method: method.parse()?,
+    })
+}
+
+pub async fn instrument<C: GenericClient>(client: &C, ticker: &str) -> Result<Option<FixedIncomeInstrument>, String> {
+    let row = client.query_opt(
+        &format!("SELECT {} FROM fixed_income_instruments WHERE ticker = $1", INSTRUMENT_COLUMNS),
+        &[&ticker],
+    ).await.map_err(|e| format!("Error al consultar el instrumento {}: {}", ticker, e))?;
+    row.as_ref().map(instrument_from_row).transpose()
+}
+
+pub async fn list_instruments_logic(db_pool: &Pool) -> Result<Vec<FixedIncomeInstrument>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM fixed_income_instruments ORDER BY maturity_date, ticker", INSTRUMENT_COLUMNS),
+        &[],
+    ).await.map_err(|e| format!("Error al consultar los instrumentos de deuda: {}", e))?;
+    rows.iter().map(instrument_from_row).collect()
+}
+
+fn price_at(instrument: &FixedIncomeInstrument, yield_rate: Decimal, date: NaiveDate) -> Decimal {
+    Decimal::from_f64(instrument.price_at_yield(to_f64(yield_rate) / 100.0, date)).map(money::round_price).unwrap_or_default()
+}
+
+// Precio por título al día `date` según el método del instrumento. A mercado, si no hay
+// tasa guardada para esa fecha, se usa el costo amortizado de la primera compra.
+fn price_on(instrument: &FixedIncomeInstrument, date: NaiveDate, marked_rates: Option<&MarkingRates>) -> Decimal {
+    let yield_rate = match (instrument.valuation_method, marked_rates) {
+        (ValuationMethod::Marked, Some(rates)) => rates.rate_on(instrument, date).unwrap_or(instrument.purchase_yield),
+        _ => instrument.purchase_yield,
+    };
+    price_at(instrument, yield_rate, date)
+}
+
+// Cantidad y rendimiento de compra de cada lote abierto del instrumento. Un lote sin
+// renglón en fixed_income_lots usa el rendimiento de la primera compra.
+async fn lot_yields<C: GenericClient>(
+    client: &C,
+    instrument: &FixedIncomeInstrument,
+    open_lots: &[OpenLot],
+) -> Result<Vec<(Decimal, Decimal)>, String> {
+    let rows = client.query(
+        "SELECT transaction_id, purchase_yield FROM fixed_income_lots WHERE ticker = $1",
+        &[&instrument.ticker],
+    ).await.map_err(|e| format!("Error al consultar los lotes de {}: {}", instrument.ticker, e))?;
+    let stored: HashMap<i32, Decimal> = rows.iter().map(|row| (row.get("transaction_id"), row.get("purchase_yield"))).collect();
+    Ok(open_lots.iter()
+        .filter(|lot| lot.ticker == instrument.ticker)
+        .map(|lot| (lot.quantity, stored.get(&lot.lot_id).copied().unwrap_or(instrument.purchase_yield)))
+        .collect())
+}
+
+// Costo amortizado por título: cada lote con el rendimiento de su compra, ponderado por cantidad
+fn amortized_price(instrument: &FixedIncomeInstrument, date: NaiveDate, lot_yields: &[(Decimal, Decimal)]) -> Decimal {
+    let quantity: Decimal = lot_yields.iter().map(|(quantity, _)| *quantity).sum();
+    if quantity <= Decimal::ZERO {
+        return price_at(instrument, instrument.purchase_yield, date);
+    }
+    let value: Decimal = lot_yields.iter()
+        .map(|(quantity, yield_rate)| *quantity * price_at(instrument, *yield_rate, date))
+        .sum();
+    money::round_price(value / quantity)
+}
+
+/// Precio del día para la valuación de una posición con sus lotes abiertos.
+pub async fn position_price<C: GenericClient>(
+    client: &C,
+    instrument: &FixedIncomeInstrument,
+    open_lots: &[OpenLot],
+    date: NaiveDate,
+) -> Result<Decimal, String> {
+    let date = date.min(instrument.maturity_date);
+    if instrument.valuation_method == ValuationMethod::Marked {
+        let rates = MarkingRates::load(client, instrument, date, date).await?;
+        if let Some(rate) = rates.rate_on(instrument, date) {
+            return Ok(price_at(instrument, rate, date));
+        }
+    }
+    let yields = lot_yields(client, instrument, open_lots).await?;
+    Ok(amortized_price(instrument, date, &yields))
+}
+
+/// Escribe en price_history el precio de cada día hábil entre `from` y `to` (o el
+/// vencimiento), para que el histórico y los análisis traten al instrumento como a
+/// cualquier emisora.
+pub async fn update_price_history<C: GenericClient>(
+    client: &C,
+    instrument: &FixedIncomeInstrument,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<usize, String> {
+    let to = to.min(instrument.maturity_date);
+    let rates = match instrument.valuation_method {
+        ValuationMethod::Marked => Some(MarkingRates::load(client, instrument, from, to).await?),
+        ValuationMethod::Amortized => None,
+    };
+    let mut written = 0;
+    let mut day = from;
+    while day <= to {
+        if prices::is_business_day(day) {
+            let price = price_on(instrument, day, rates.as_ref());
+            client.execute(
+                "INSERT INTO price_history (ticker, price_date, close) VALUES ($1, $2, $3)
+                 ON CONFLICT (ticker, price_date) DO UPDATE SET close = EXCLUDED.close",
+                &[&instrument.ticker, &day, &price],
+            ).await.map_err(|e| format!("No se pudo guardar el precio de {}: {}", instrument.ticker, e))?;
+            written += 1;
+        }
+        day += Duration::days(1);
+    }
+    Ok(written)
+}
+
+/// Compra de un CETE o bono. Se indica el rendimiento o el precio por título; el otro se
+/// calcula. Sin `ticker` se usa CETES<vencimiento> o BONO<vencimiento>.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixedIncomePurchase {
+    pub portfolio_id: i32,
+    pub kind: String,
+    #[serde(default)]
+    pub ticker: Option<String>,
+    pub maturity_date: NaiveDate,
+    pub quantity: Decimal,
+    #[serde(default)]
+    pub purchase_yield: Option<Decimal>,
+    #[serde(default)]
+    pub price: Option<Decimal>,
+    #[serde(default)]
+    pub face_value: Option<Decimal>,
+    #[serde(default)]
+    pub coupon_rate: Option<Decimal>,
+    #[serde(default)]
+    pub coupons_per_year: Option<i16>,
+    #[serde(default)]
+    pub rate_symbol: Option<String>,
+    #[serde(default)]
+    pub spread: Option<Decimal>,
+    #[serde(default)]
+    pub valuation_method: Option<String>,
+    #[serde(default)]
+    pub trade_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub use_cash_from_portfolio: bool,
+}
+
+pub async fn buy_fixed_income_logic(purchase: FixedIncomePurchase, db_pool: &Pool) -> Result<LedgerEntry, String> {
+    let kind: InstrumentKind = purchase.kind.parse()?;
+    let trade_date = purchase.trade_date.unwrap_or_else(|| Utc::now().date_naive());
+    if purchase.maturity_date <= trade_date {
+        return Err("El vencimiento debe ser posterior a la fecha de compra".to_string());
+    }
+    if purchase.quantity <= Decimal::ZERO || !purchase.quantity.fract().is_zero() {
+        return Err("La cantidad debe ser un número entero de títulos".to_string());
+    }
+    let (coupon_rate, coupons_per_year) = match kind {
+        InstrumentKind::Cete => (Decimal::ZERO, 0),
+        InstrumentKind::Bond => {
+            let coupons_per_year = purchase.coupons_per_year.unwrap_or(2);
+            if ![1, 2, 4, 12].contains(&coupons_per_year) {
+                return Err("Los cupones por año deben ser 1, 2, 4 o 12".to_string());
+            }
+            (purchase.coupon_rate.unwrap_or_default(), coupons_per_year)
+        }
+    };
+    if coupon_rate < Decimal::ZERO {
+        return Err("La tasa del cupón no puede ser negativa".to_string());
+    }
+    let rate_symbol = purchase.rate_symbol.map(|s| s.trim().to_uppercase().replace(' ', ""));
+    if let Some(symbol) = &rate_symbol {
+        if !benchmarks::RATE_BENCHMARKS.contains(&symbol.as_str()) {
+            return Err(format!("Tasa de referencia desconocida: {} (usar {})", symbol, benchmarks::RATE_BENCHMARKS.join(", ")));
+        }
+    }
+    let ticker = purchase.ticker
+        .map(|t| t.trim().to_uppercase())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| match kind {
+            InstrumentKind::Cete => format!("CETES{}", purchase.maturity_date.format("%y%m%d")),
+            InstrumentKind::Bond => format!("BONO{}", purchase.maturity_date.format("%y%m%d")),
+        });
+
+    // El alta del instrumento y la compra van en la misma transacción: si la compra falla no
+    // queda un instrumento con el rendimiento de un intento fallido
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    orders::ensure_real_portfolio(&**client, purchase.portfolio_id).await?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let (mut instrument, is_new) = match instrument(&*tx, &ticker).await? {
+        Some(existing) => {
+            if existing.kind != kind || existing.maturity_date != purchase.maturity_date || existing.coupon_rate != coupon_rate {
+                return Err(format!("{} ya existe con otras condiciones; use otro ticker", ticker));
+            }
+            (existing, false)
+        }
+        None => {
+            let instrument = FixedIncomeInstrument {
+                ticker: ticker.clone(),
+                kind,
+                face_value: purchase.face_value.unwrap_or(match kind {
+                    InstrumentKind::Cete => CETE_FACE_VALUE,
+                    InstrumentKind::Bond => BOND_FACE_VALUE,
+                }),
+                maturity_date: purchase.maturity_date,
+                coupon_rate,
+                coupons_per_year,
+                purchase_yield: Decimal::ZERO,
+                rate_symbol,
+                spread: purchase.spread.unwrap_or_default(),
+                valuation_method: match purchase.valuation_method {
+                    Some(method) => method.parse()?,
+                    None => ValuationMethod::Amortized,
+                },
+            };
+            if instrument.face_value <= Decimal::ZERO {
+                return Err("El valor nominal debe ser mayor a cero".to_string());
+            }
+            (instrument, true)
+        }
+    };
+
+    let price = match (purchase.price, purchase.purchase_yield) {
+        (Some(price), _) => price,
+        (None, Some(yield_rate)) => Decimal::from_f64(instrument.price_at_yield(to_f64(yield_rate) / 100.0, trade_date))
+            .unwrap_or_default(),
+        (None, None) => return Err("Indique el rendimiento o el precio de compra".to_string()),
+    };
+    if price <= Decimal::ZERO {
+        return Err("El precio debe ser mayor a cero".to_string());
+    }
+    let purchase_yield = match purchase.purchase_yield {
+        Some(yield_rate) => yield_rate,
+        None => instrument.yield_at_price(to_f64(price), trade_date)
+            .and_then(|y| Decimal::from_f64(y * 100.0))
+            .map(|y| y.round_dp(6))
+            .ok_or_else(|| "No se pudo calcular el rendimiento con ese precio".to_string())?,
+    };
+    if is_new {
+        instrument.purchase_yield = purchase_yield;
+        tx.execute(
+            "INSERT INTO fixed_income_instruments
+                (ticker, kind, face_value, maturity_date, coupon_rate, coupons_per_year, purchase_yield, rate_symbol, spread, valuation_method)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            &[&instrument.ticker, &instrument.kind.as_str(), &instrument.face_value, &instrument.maturity_date,
+              &instrument.coupon_rate, &instrument.coupons_per_year, &instrument.purchase_yield, &instrument.rate_symbol,
+              &instrument.spread, &instrument.valuation_method.as_str()],
+        ).await.map_err(|e| format!("No se pudo registrar el instrumento: {}", e))?;
+    }
+    let mut entry = NewLedgerEntry::trade(purchase.portfolio_id, &ticker, TransactionKind::Buy, purchase.quantity, price);
+    entry.transaction_date = Some(ledger::local_timestamp(trade_date));
+    entry.notes = Some(format!("Compra de {} con vencimiento {}", ticker, instrument.maturity_date));
+    let source = if purchase.use_cash_from_portfolio { CashSource::Portfolio } else { CashSource::External };
+    let recorded = ledger::record_trade_in(&*tx, entry, source).await?;
+    tx.execute(
+        "INSERT INTO fixed_income_lots (transaction_id, ticker, purchase_yield) VALUES ($1, $2, $3)",
+        &[&recorded.transaction_id, &instrument.ticker, &purchase_yield],
+    ).await.map_err(|e| format!("No se pudo registrar el rendimiento del lote: {}", e))?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    update_price_history(&**client, &instrument, trade_date, Utc::now().date_naive()).await?;
+    Ok(recorded)
+}
+
+pub async fn set_fixed_income_valuation_logic(
+    ticker: String,
+    valuation_method: String,
+    spread: Option<Decimal>,
+    db_pool: &Pool,
+) -> Result<FixedIncomeInstrument, String> {
+    let method: ValuationMethod = valuation_method.parse()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let row = client.query_opt(
+        &format!(
+            "UPDATE fixed_income_instruments SET valuation_method = $2, spread = COALESCE($3, spread)
+             WHERE ticker = $1 RETURNING {}",
+            INSTRUMENT_COLUMNS
+        ),
+        &[&ticker.trim().to_uppercase(), &method.as_str(), &spread],
+    ).await.map_err(|e| format!("No se pudo actualizar el instrumento: {}", e))?
+        .ok_or_else(|| format!("No se encontró el instrumento {}", ticker))?;
+    let instrument = instrument_from_row(&row)?;
+
+    // Se reescribe el histórico de precios con el nuevo método
+    let holders = client.query(
+        "SELECT portfolio_id, MIN(transaction_date) AS first_trade FROM portfolio_transactions
+         WHERE ticker = $1 AND voided_at IS NULL GROUP BY portfolio_id",
+        &[&instrument.ticker],
+    ).await.map_err(|e| format!("Error al consultar las operaciones: {}", e))?;
+    let first_trade = holders.iter().map(|row| row.get::<_, DateTime<Utc>>("first_trade").date_naive()).min();
+    if let Some(first_trade) = first_trade {
+        update_price_history(&**client, &instrument, first_trade, Utc::now().date_naive()).await?;
+    }
+    for row in &holders {
+        let first_trade: DateTime<Utc> = row.get("first_trade");
+        snapshots::invalidate_from(&**client, row.get("portfolio_id"), first_trade.date_naive()).await?;
+    }
+    Ok(instrument)
+}
+
+/// Posición en un instrumento de deuda con su rendimiento y precio del día.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixedIncomePosition {
+    pub instrument: FixedIncomeInstrument,
+    pub quantity: Decimal,
+    pub days_to_maturity: i64,
+    pub amortized_price: Decimal,
+    pub marked_rate: Option<Decimal>,
+    pub marked_price: Option<Decimal>,
+    pub price: Decimal,
+    pub market_value: Decimal,
+    pub next_coupon: Option<NaiveDate>,
+}
+
+pub async fn get_fixed_income_positions_logic(portfolio_id: i32, db_pool: &Pool) -> Result<Vec<FixedIncomePosition>, String> {
+    let today = Utc::now().date_naive();
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let book = lots::load_lot_book(&**client, portfolio_id).await?;
+    let rows = client.query(
+        &format!(
+            "SELECT {} FROM fixed_income_instruments WHERE ticker IN
+                (SELECT ticker FROM portfolio_transactions WHERE portfolio_id = $1 AND voided_at IS NULL)
+             ORDER BY maturity_date, ticker",
+            INSTRUMENT_COLUMNS
+        ),
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar los instrumentos de deuda: {}", e))?;
+
+    let mut positions = Vec::new();
+    for row in &rows {
+        let instrument = instrument_from_row(row)?;
+        let quantity = ledger::held_quantity(&**client, portfolio_id, &instrument.ticker).await?;
+        if quantity <= Decimal::ZERO {
+            continue;
+        }
+        let yields = lot_yields(&**client, &instrument, &book.open_lots).await?;
+        let amortized_price = amortized_price(&instrument, today, &yields);
+        let marked_rate = MarkingRates::load(&**client, &instrument, today, today).await?.rate_on(&instrument, today);
+        let marked_price = marked_rate.map(|rate| price_at(&instrument, rate, today));
+        let price = match (instrument.valuation_method, marked_price) {
+            (ValuationMethod::Marked, Some(marked_price)) => marked_price,
+            _ => amortized_price,
+        };
+        positions.push(FixedIncomePosition {
+            quantity,
+            days_to_maturity: (instrument.maturity_date - today).num_days(),
+            amortized_price,
+            marked_rate,
+            marked_price,
+            price,
+            market_value: money::round_mxn(price * quantity),
+            next_coupon: instrument.coupon_dates(today, instrument.maturity_date).first().copied(),
+            instrument,
+        });
+    }
+    Ok(positions)
+}
+
+/// Cupón o amortización registrada en el ledger. `ticker` es el instrumento aunque el cupón
+/// entre al ledger como interés de CASH.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixedIncomeFlow {
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub flow_date: NaiveDate,
+    pub kind: String,
+    pub quantity: Decimal,
+    pub amount: Decimal,
+    pub transaction_id: i32,
+}
+
+// Registra en una sola transacción el movimiento del ledger y su renglón en fixed_income_flows
+async fn record_flow(
+    client: &mut deadpool_postgres::Client,
+    instrument: &FixedIncomeInstrument,
+    entry: NewLedgerEntry,
+    flow_date: NaiveDate,
+    kind: &str,
+) -> Result<Option<FixedIncomeFlow>, String> {
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let amount = entry.total_amount();
+    let claimed = tx.execute(
+        "INSERT INTO fixed_income_flows (portfolio_id, ticker, flow_date, kind, quantity, amount)
+         VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
+        &[&entry.portfolio_id, &instrument.ticker, &flow_date, &kind, &entry.quantity, &amount],
+    ).await.map_err(|e| format!("No se pudo registrar el flujo: {}", e))?;
+    if claimed == 0 {
+        return Ok(None);
+    }
+    let recorded = ledger::insert_entry(&*tx, &entry).await?;
+    tx.execute(
+        "UPDATE fixed_income_flows SET transaction_id = $5 WHERE portfolio_id = $1 AND ticker = $2 AND flow_date = $3 AND kind = $4",
+        &[&entry.portfolio_id, &instrument.ticker, &flow_date, &kind, &recorded.transaction_id],
+    ).await.map_err(|e| format!("No se pudo registrar el flujo: {}", e))?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(Some(FixedIncomeFlow {
+        portfolio_id: entry.portfolio_id,
+        ticker: instrument.ticker.clone(),
+        flow_date,
+        kind: kind.to_string(),
+        quantity: entry.quantity,
+        amount,
+        transaction_id: recorded.transaction_id,
+    }))
+}
+
+/// Registra los cupones y amortizaciones vencidos (también los de días en que la
+/// aplicación no estuvo abierta) y actualiza los precios diarios de cada instrumento.
+/// Los cupones entran como INTEREST de CASH, fuera del ingreso por dividendos; la
+/// amortización, como venta de todos los títulos al valor nominal sin comisión.
+pub async fn process_fixed_income_logic(db_pool: &Pool) -> Result<Vec<FixedIncomeFlow>, String> {
+    let today = Utc::now().date_naive();
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM fixed_income_instruments ORDER BY ticker", INSTRUMENT_COLUMNS),
+        &[],
+    ).await.map_err(|e| format!("Error al consultar los instrumentos de deuda: {}", e))?;
+    let instruments: Vec<FixedIncomeInstrument> = rows.iter().map(instrument_from_row).collect::<Result<_, _>>()?;
+
+    let mut flows = Vec::new();
+    for instrument in &instruments {
+        let last_price: Option<NaiveDate> = client.query_one(
+            "SELECT MAX(price_date) AS last_price FROM price_history WHERE ticker = $1",
+            &[&instrument.ticker],
+        ).await.map_err(|e| format!("Error al consultar los precios de {}: {}", instrument.ticker, e))?.get("last_price");
+        if let Some(last_price) = last_price.filter(|d| *d < today.min(instrument.maturity_date)) {
+            update_price_history(&**client, instrument, last_price + Duration::days(1), today).await?;
+        }
+
+        let holders = client.query(
+            "SELECT portfolio_id, MIN(transaction_date) AS first_trade FROM portfolio_transactions
+             WHERE ticker = $1 AND transaction_type = 'BUY' AND voided_at IS NULL GROUP BY portfolio_id",
+            &[&instrument.ticker],
+        ).await.map_err(|e| format!("Error al consultar las posiciones de {}: {}", instrument.ticker, e))?;
+        for holder in &holders {
+            let portfolio_id: i32 = holder.get("portfolio_id");
+            let first_trade: DateTime<Utc> = holder.get("first_trade");
+
+            for coupon_date in instrument.coupon_dates(first_trade.date_naive(), today.min(instrument.maturity_date)) {
+                let as_of = ledger::local_day_start(coupon_date);
+                let quantity = ledger::held_quantity_before(&**client, portfolio_id, &instrument.ticker, as_of).await?;
+                if quantity <= Decimal::ZERO {
+                    continue;
+                }
+                let amount = money::round_mxn(quantity * Decimal::from_f64(instrument.coupon()).unwrap_or_default());
+                let mut entry = NewLedgerEntry::cash(portfolio_id, TransactionKind::Interest, amount);
+                entry.transaction_date = Some(ledger::local_timestamp(coupon_date));
+                entry.notes = Some(format!("Cupón de {} por {} títulos", instrument.ticker, quantity));
+                if let Some(flow) = record_flow(&mut client, instrument, entry, coupon_date, "COUPON").await? {
+                    flows.push(flow);
+                }
+            }
+
+            if instrument.maturity_date <= today {
+                let quantity = ledger::held_quantity(&**client, portfolio_id, &instrument.ticker).await?;
+                if quantity <= Decimal::ZERO {
+                    continue;
+                }
+                let mut entry = NewLedgerEntry::trade(portfolio_id, &instrument.ticker, TransactionKind::Sell, quantity, instrument.face_value);
+                entry.transaction_date = Some(ledger::local_timestamp(instrument.maturity_date));
+                entry.fees = Some(TradeFees::default());
+                entry.notes = Some(format!("Amortización de {} al vencimiento", instrument.ticker));
+                if let Some(flow) = record_flow(&mut client, instrument, entry, instrument.maturity_date, "REDEMPTION").await? {
+                    flows.push(flow);
+                }
+            }
+        }
+    }
+    Ok(flows)
+}
+
+/// Se pone al corriente al arrancar la aplicación y después revisa cada hora.
+pub async fn run_fixed_income_scheduler(db_pool: Arc<Pool>) {
+    let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+    loop {
+        interval.tick().await;
+        match process_fixed_income_logic(&db_pool).await {
+            Ok(flows) if !flows.is_empty() => {
+                println!("[FIXED_INCOME] {} cupones y amortizaciones registrados", flows.len());
+            }
+            Ok(_) => {}
+            Err(e) => println!("[FIXED_INCOME] Error al procesar instrumentos de deuda: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn instrument(kind: InstrumentKind, coupon_rate: i64, coupons_per_year: i16) -> FixedIncomeInstrument {
+        FixedIncomeInstrument {
+            ticker: "TEST".to_string(),
+            kind,
+            face_value: match kind {
+                InstrumentKind::Cete => CETE_FACE_VALUE,
+                InstrumentKind::Bond => BOND_FACE_VALUE,
+            },
+            maturity_date: date(2026, 6, 30),
+            coupon_rate: Decimal::from(coupon_rate),
+            coupons_per_year,
+            purchase_yield: Decimal::ZERO,
+            rate_symbol: None,
+            spread: Decimal::ZERO,
+            valuation_method: ValuationMethod::Amortized,
+        }
+    }
+
+    #[test]
+    fn cete_price_discounts_face_value_on_360_days() {
+        let cete = instrument(InstrumentKind::Cete, 0, 0);
+        let today = date(2026, 6, 30) - Duration::days(180);
+        // 10 / (1 + 0.10 * 180 / 360)
+        assert!((cete.price_at_yield(0.10, today) - 10.0 / 1.05).abs() < 1e-12);
+        assert!((cete.price_at_yield(0.10, date(2026, 6, 30)) - 10.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cete_yield_round_trips_through_price() {
+        let cete = instrument(InstrumentKind::Cete, 0, 0);
+        let today = date(2026, 1, 15);
+        let price = cete.price_at_yield(0.1125, today);
+        assert!((cete.yield_at_price(price, today).unwrap() - 0.1125).abs() < 1e-10);
+    }
+
+    #[test]
+    fn bond_yield_round_trips_through_price() {
+        let bond = instrument(InstrumentKind::Bond, 8, 2);
+        let today = date(2024, 3, 1);
+        for yield_rate in [0.05, 0.08, 0.115] {
+            let price = bond.price_at_yield(yield_rate, today);
+            assert!((bond.yield_at_price(price, today).unwrap() - yield_rate).abs() < 1e-8);
+        }
+        // Con el rendimiento igual al cupón en fecha de cupón, el bono vale la par
+        let at_coupon = bond.price_at_yield(0.08, date(2025, 12, 30));
+        assert!((at_coupon - 100.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn yield_needs_positive_price_and_time_to_maturity() {
+        let cete = instrument(InstrumentKind::Cete, 0, 0);
+        assert!(cete.yield_at_price(0.0, date(2026, 1, 15)).is_none());
+        assert!(cete.yield_at_price(9.5, date(2026, 6, 30)).is_none());
+    }
+
+    #[test]
+    fn coupon_dates_count_back_from_maturity() {
+        let bond = instrument(InstrumentKind::Bond, 8, 2);
+        assert_eq!(
+            bond.coupon_dates(date(2024, 12, 30), date(2026, 6, 30)),
+            vec![date(2025, 6, 30), date(2025, 12, 30), date(2026, 6, 30)]
+        );
+        // El intervalo es (after, until]
+        assert_eq!(bond.coupon_dates(date(2025, 6, 30), date(2025, 12, 30)), vec![date(2025, 12, 30)]);
+        assert!(instrument(InstrumentKind::Cete, 0, 0).coupon_dates(date(2024, 1, 1), date(2026, 6, 30)).is_empty());
+    }
+}
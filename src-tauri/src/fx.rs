@@ -0,0 +1,126 @@
+use chrono::{NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio_postgres::GenericClient;
+
+use crate::data_bursatil_client;
+use crate::money;
+
+// Moneda en la que se llevan los tipos de cambio y en la que cotiza la BMV, incluido el SIC
+pub const MXN: &str = "MXN";
+pub const SUPPORTED_CURRENCIES: [&str; 3] = ["MXN", "USD", "EUR"];
+
+/// Pesos por una unidad de `currency` al cierre de `rate_date`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FxRate {
+    pub rate_date: NaiveDate,
+    pub currency: String,
+    pub rate_to_mxn: Decimal,
+}
+
+pub fn normalize_currency(currency: &str) -> Result<String, String> {
+    let currency = currency.trim().to_uppercase();
+    if SUPPORTED_CURRENCIES.contains(&currency.as_str()) {
+        Ok(currency)
+    } else {
+        Err(format!("Moneda no soportada: {} (usar {})", currency, SUPPORTED_CURRENCIES.join(", ")))
+    }
+}
+
+/// Último tipo de cambio publicado en o antes de `date`.
+pub async fn rate_to_mxn<C: GenericClient>(client: &C, currency: &str, date: NaiveDate) -> Result<Decimal, String> {
+    if currency == MXN {
+        return Ok(Decimal::ONE);
+    }
+    let row = client.query_opt(
+        "SELECT rate_to_mxn FROM fx_rates WHERE currency = $1 AND rate_date <= $2 ORDER BY rate_date DESC LIMIT 1",
+        &[&currency, &date],
+    ).await.map_err(|e| format!("Error al consultar el tipo de cambio: {}", e))?;
+    row.map(|r| r.get("rate_to_mxn"))
+        .ok_or_else(|| format!("No hay tipo de cambio {}MXN al {}; cárguelo en fx_rates", currency, date))
+}
+
+/// Unidades de `to` por una unidad de `from` en `date`.
+pub async fn conversion_rate<C: GenericClient>(client: &C, from: &str, to: &str, date: NaiveDate) -> Result<Decimal, String> {
+    if from == to {
+        return Ok(Decimal::ONE);
+    }
+    let from_mxn = rate_to_mxn(client, from, date).await?;
+    let to_mxn = rate_to_mxn(client, to, date).await?;
+    Ok(money::round_price(from_mxn / to_mxn))
+}
+
+/// Todos los tipos de cambio guardados de `currency`, por fecha.
+pub async fn rate_series<C: GenericClient>(client: &C, currency: &str) -> Result<BTreeMap<NaiveDate, Decimal>, String> {
+    let rows = client.query(
+        "SELECT rate_date, rate_to_mxn FROM fx_rates WHERE currency = $1 ORDER BY rate_date",
+        &[&currency],
+    ).await.map_err(|e| format!("Error al consultar los tipos de cambio: {}", e))?;
+    Ok(rows.iter().map(|r| (r.get("rate_date"), r.get("rate_to_mxn"))).collect())
+}
+
+pub async fn save_fx_rates<C: GenericClient>(client: &C, rates: &[FxRate]) -> Result<usize, String> {
+    for rate in rates {
+        let currency = normalize_currency(&rate.currency)?;
+        if currency == MXN || rate.rate_to_mxn <= Decimal::ZERO {
+            return Err(format!("Tipo de cambio inválido para {} al {}", currency, rate.rate_date));
+        }
+        client.execute(
+            "INSERT INTO fx_rates (rate_date, currency, rate_to_mxn) VALUES ($1, $2, $3)
+             ON CONFLICT (rate_date, currency) DO UPDATE SET rate_to_mxn = EXCLUDED.rate_to_mxn",
+            &[&rate.rate_date, &currency, &money::round_price(rate.rate_to_mxn)],
+        ).await.map_err(|e| format!("No se pudo guardar el tipo de cambio {} al {}: {}", currency, rate.rate_date, e))?;
+    }
+    Ok(rates.len())
+}
+
+pub async fn save_fx_rates_logic(rates: Vec<FxRate>, db_pool: &deadpool_postgres::Pool) -> Result<usize, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let saved = save_fx_rates(&*tx, &rates).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(saved)
+}
+
+/// Guarda el USDMXN y EURMXN del día que publica DataBursátil.
+pub async fn refresh_fx_rates_logic(db_pool: &deadpool_postgres::Pool) -> Result<Vec<FxRate>, String> {
+    let forex = data_bursatil_client::get_forex_async()
+        .await
+        .map_err(|e| format!("Error obteniendo forex: {}", e))?;
+    let today = Utc::now().date_naive();
+    let rates: Vec<FxRate> = [("USD", forex.USDMXN), ("EUR", forex.EURMXN)]
+        .into_iter()
+        .filter_map(|(currency, item)| {
+            let rate_to_mxn = money::price_from_f64(item?.u)?;
+            Some(FxRate { rate_date: today, currency: currency.to_string(), rate_to_mxn })
+        })
+        .collect();
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    save_fx_rates(&**client, &rates).await?;
+    Ok(rates)
+}
+
+pub async fn list_fx_rates_logic(
+    currency: String,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<FxRate>, String> {
+    let currency = normalize_currency(&currency)?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        "SELECT rate_date, currency, rate_to_mxn FROM fx_rates
+         WHERE currency = $1 AND ($2::date IS NULL OR rate_date >= $2) AND ($3::date IS NULL OR rate_date <= $3)
+         ORDER BY rate_date ASC",
+        &[&currency, &from, &to],
+    ).await.map_err(|e| format!("Error al consultar los tipos de cambio: {}", e))?;
+    Ok(rows.iter()
+        .map(|row| FxRate {
+            rate_date: row.get("rate_date"),
+            currency: row.get("currency"),
+            rate_to_mxn: row.get("rate_to_mxn"),
+        })
+        .collect())
+}
@@ -0,0 +1,749 @@
+use chrono::NaiveDate;
+use deadpool_postgres::Pool;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio_postgres::GenericClient;
+
+use crate::dividends::{self, DividendPayment};
+use crate::fx;
+use crate::ledger::{self, CashSource, LedgerEntry, NewLedgerEntry, TradeFees, TransactionKind, CASH_TICKER};
+use crate::money;
+use crate::orders;
+use crate::snapshots;
+use crate::user_management;
+
+// Renglones que se revisan para detectar el separador y el encabezado
+const HEADER_SEARCH_LINES: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Broker {
+    Gbm,
+    Actinver,
+    // CSV propio con encabezados en inglés
+    #[default]
+    Csv,
+}
+
+impl Broker {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Broker::Gbm => "GBM",
+            Broker::Actinver => "ACTINVER",
+            Broker::Csv => "CSV",
+        }
+    }
+
+    /// Columnas del export de movimientos de cada casa de bolsa.
+    pub fn mapping(&self) -> ColumnMapping {
+        let column = |name: &str| Some(name.to_string());
+        match self {
+            Broker::Gbm => ColumnMapping {
+                date: "Fecha".to_string(),
+                ticker: "Emisora".to_string(),
+                series: column("Serie"),
+                transaction_type: "Operación".to_string(),
+                quantity: column("Títulos"),
+                price: column("Precio"),
+                amount: column("Importe"),
+                commission: column("Comisión"),
+                commission_iva: column("IVA"),
+                withholding: column("ISR"),
+                currency: None,
+                notes: None,
+                date_format: "%d/%m/%Y".to_string(),
+                delimiter: None,
+                decimal_comma: false,
+                type_aliases: HashMap::new(),
+            },
+            Broker::Actinver => ColumnMapping {
+                date: "Fecha Operación".to_string(),
+                ticker: "Emisora".to_string(),
+                series: column("Serie"),
+                transaction_type: "Movimiento".to_string(),
+                quantity: column("Títulos"),
+                price: column("Precio"),
+                amount: column("Importe Neto"),
+                commission: column("Comisión"),
+                commission_iva: column("IVA"),
+                withholding: column("ISR Retenido"),
+                currency: None,
+                notes: column("Descripción"),
+                date_format: "%d/%m/%Y".to_string(),
+                delimiter: None,
+                decimal_comma: false,
+                type_aliases: HashMap::new(),
+            },
+            Broker::Csv => ColumnMapping {
+                date: "date".to_string(),
+                ticker: "ticker".to_string(),
+                series: None,
+                transaction_type: "type".to_string(),
+                quantity: column("quantity"),
+                price: column("price"),
+                amount: column("amount"),
+                commission: column("commission"),
+                commission_iva: column("commission_iva"),
+                withholding: column("withholding"),
+                currency: column("currency"),
+                notes: column("notes"),
+                date_format: default_date_format(),
+                delimiter: None,
+                decimal_comma: false,
+                type_aliases: HashMap::new(),
+            },
+        }
+    }
+}
+
+impl FromStr for Broker {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize(s).replace(' ', "").as_str() {
+            "GBM" | "GBM+" | "GBMPLUS" => Ok(Broker::Gbm),
+            "ACTINVER" => Ok(Broker::Actinver),
+            "CSV" => Ok(Broker::Csv),
+            _ => Err(format!("Casa de bolsa no soportada: {} (usar GBM, ACTINVER o CSV)", s)),
+        }
+    }
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+/// Encabezados de las columnas (sin importar mayúsculas ni acentos). Con `series` el ticker
+/// es emisora más serie. `type_aliases` traduce etiquetas propias de la casa de bolsa a
+/// BUY, SELL, DEPOSIT, WITHDRAWAL o DIVIDEND; sin alias se reconocen por palabra clave
+/// (compra, venta, depósito, retiro, dividendo).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    pub date: String,
+    pub ticker: String,
+    #[serde(default)]
+    pub series: Option<String>,
+    pub transaction_type: String,
+    #[serde(default)]
+    pub quantity: Option<String>,
+    #[serde(default)]
+    pub price: Option<String>,
+    // Importe del movimiento; para depósitos, retiros y dividendos, o si falta el precio
+    #[serde(default)]
+    pub amount: Option<String>,
+    #[serde(default)]
+    pub commission: Option<String>,
+    #[serde(default)]
+    pub commission_iva: Option<String>,
+    // ISR retenido de los dividendos; el importe es el neto pagado
+    #[serde(default)]
+    pub withholding: Option<String>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    // None = se detecta entre coma, punto y coma o tabulador
+    #[serde(default)]
+    pub delimiter: Option<char>,
+    // Montos como 1.234,56
+    #[serde(default)]
+    pub decimal_comma: bool,
+    #[serde(default)]
+    pub type_aliases: HashMap<String, String>,
+}
+
+/// Archivo a importar. `mapping` reemplaza las columnas de `broker`. Con
+/// `use_portfolio_cash` las compras se pagan con el efectivo del portafolio, porque el estado
+/// de cuenta trae sus depósitos y retiros; si no, el dinero entra y sale con cada operación.
+/// Sin indicarlo, los estados de GBM y Actinver usan el efectivo del portafolio y el CSV
+/// propio no.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRequest {
+    pub portfolio_id: i32,
+    pub content: String,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    #[serde(default)]
+    pub broker: Option<String>,
+    #[serde(default)]
+    pub mapping: Option<ColumnMapping>,
+    #[serde(default)]
+    pub use_portfolio_cash: Option<bool>,
+    // Importa los renglones válidos aunque otros tengan errores
+    #[serde(default)]
+    pub skip_invalid: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RowStatus {
+    New,
+    // Ya importado en este portafolio
+    Duplicate,
+    Invalid,
+}
+
+/// Movimiento que generaría un renglón. Sin comisión en el archivo se usa el esquema del
+/// portafolio al registrar. Un dividendo lleva el neto en `price`; sin `shares` se toman
+/// los títulos en posición antes de la fecha.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedEntry {
+    pub transaction_date: NaiveDate,
+    pub ticker: String,
+    pub transaction_type: TransactionKind,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub commission: Option<Decimal>,
+    pub commission_iva: Option<Decimal>,
+    pub shares: Option<Decimal>,
+    pub withholding_tax: Option<Decimal>,
+    pub currency: String,
+    pub notes: Option<String>,
+}
+
+impl ImportedEntry {
+    fn ledger_entry(&self, portfolio_id: i32) -> NewLedgerEntry {
+        let mut entry = NewLedgerEntry::trade(portfolio_id, &self.ticker, self.transaction_type, self.quantity, self.price);
+        entry.currency = self.currency.clone();
+        entry.notes = self.notes.clone();
+        entry.transaction_date = Some(ledger::local_timestamp(self.transaction_date));
+        if self.commission.is_some() || self.commission_iva.is_some() {
+            entry.fees = Some(TradeFees {
+                commission: self.commission.unwrap_or_default(),
+                commission_iva: self.commission_iva.unwrap_or_default(),
+            });
+        }
+        entry
+    }
+
+    // Contenido normalizado; el mismo movimiento repetido en el archivo se distingue por su ordinal
+    fn content_key(&self, portfolio_id: i32, occurrence: usize) -> String {
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}|{}#{}",
+            portfolio_id,
+            self.transaction_date,
+            self.ticker,
+            self.transaction_type,
+            self.quantity.normalize(),
+            self.price.normalize(),
+            self.commission.unwrap_or_default().normalize(),
+            self.commission_iva.unwrap_or_default().normalize(),
+            self.currency,
+            occurrence
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRow {
+    pub line_number: usize,
+    pub status: RowStatus,
+    pub entry: Option<ImportedEntry>,
+    pub error: Option<String>,
+    #[serde(skip)]
+    content_key: Option<String>,
+}
+
+/// Vista previa: nada se escribe en el ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportPreview {
+    pub broker: Broker,
+    pub new_rows: usize,
+    pub duplicate_rows: usize,
+    pub invalid_rows: usize,
+    pub rows: Vec<ImportRow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportResult {
+    // None si no había renglones nuevos
+    pub batch_id: Option<i32>,
+    pub imported_rows: usize,
+    pub duplicate_rows: usize,
+    pub invalid_rows: usize,
+    pub entries: Vec<LedgerEntry>,
+}
+
+// Mayúsculas, sin acentos y con espacios simples
+fn normalize(value: &str) -> String {
+    let plain: String = value.trim().to_uppercase().chars()
+        .map(|c| match c {
+            'Á' | 'À' | 'Ä' => 'A',
+            'É' | 'È' | 'Ë' => 'E',
+            'Í' | 'Ì' | 'Ï' => 'I',
+            'Ó' | 'Ò' | 'Ö' => 'O',
+            'Ú' | 'Ù' | 'Ü' => 'U',
+            'Ñ' => 'N',
+            other => other,
+        })
+        .collect();
+    plain.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn detect_delimiter(content: &str) -> char {
+    let sample: String = content.lines().take(HEADER_SEARCH_LINES).collect::<Vec<_>>().join("\n");
+    [',', ';', '\t'].into_iter()
+        .max_by_key(|d| sample.matches(*d).count())
+        .unwrap()
+}
+
+// Registros con su número de renglón; respeta comillas (con "" como comilla escapada)
+fn parse_csv(content: &str, delimiter: char) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = content.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            '\r' if !quoted => {}
+            c if c == delimiter && !quoted => record.push(std::mem::take(&mut field)),
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    records.retain(|(_, cells)| cells.iter().any(|c| !c.trim().is_empty()));
+    records
+}
+
+fn parse_amount(raw: &str, decimal_comma: bool) -> Result<Option<Decimal>, String> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() || trimmed == "-" {
+        return Ok(None);
+    }
+    let negative = trimmed.starts_with('(') && trimmed.ends_with(')');
+    let digits: String = trimmed.chars().filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-')).collect();
+    let digits = if decimal_comma { digits.replace('.', "").replace(',', ".") } else { digits.replace(',', "") };
+    let value = Decimal::from_str(&digits).map_err(|_| format!("Importe inválido: {}", raw))?;
+    Ok(Some(if negative { -value } else { value }))
+}
+
+fn parse_kind(raw: &str, aliases: &HashMap<String, String>) -> Result<TransactionKind, String> {
+    let label = normalize(raw);
+    if let Some(kind) = aliases.iter().find(|(alias, _)| normalize(alias) == label).map(|(_, kind)| kind) {
+        return kind.parse();
+    }
+    let keywords = [
+        ("DIVIDENDO", TransactionKind::Dividend),
+        ("INTERES", TransactionKind::Interest),
+        ("COMPRA", TransactionKind::Buy),
+        ("VENTA", TransactionKind::Sell),
+        ("DEPOSITO", TransactionKind::Deposit),
+        ("RETIRO", TransactionKind::Withdrawal),
+    ];
+    match keywords.iter().find(|(keyword, _)| label.contains(keyword)) {
+        Some((_, kind)) => Ok(*kind),
+        None => raw.parse().map_err(|_| format!("Tipo de movimiento no reconocido: {}", raw.trim())),
+    }
+}
+
+struct Columns {
+    date: usize,
+    ticker: usize,
+    series: Option<usize>,
+    transaction_type: usize,
+    quantity: Option<usize>,
+    price: Option<usize>,
+    amount: Option<usize>,
+    commission: Option<usize>,
+    commission_iva: Option<usize>,
+    withholding: Option<usize>,
+    currency: Option<usize>,
+    notes: Option<usize>,
+}
+
+impl Columns {
+    // El encabezado es el primer registro que trae las columnas de fecha, ticker y tipo;
+    // lo anterior suele ser el membrete del estado de cuenta
+    fn locate(records: &[(usize, Vec<String>)], mapping: &ColumnMapping) -> Result<(usize, Columns), String> {
+        for (index, (_, cells)) in records.iter().enumerate().take(HEADER_SEARCH_LINES) {
+            let headers: Vec<String> = cells.iter().map(|c| normalize(c)).collect();
+            let find = |name: &str| headers.iter().position(|h| *h == normalize(name));
+            let optional = |name: &Option<String>| name.as_deref().and_then(find);
+            let (Some(date), Some(ticker), Some(transaction_type)) =
+                (find(&mapping.date), find(&mapping.ticker), find(&mapping.transaction_type)) else {
+                continue;
+            };
+            return Ok((index, Columns {
+                date,
+                ticker,
+                series: optional(&mapping.series),
+                transaction_type,
+                quantity: optional(&mapping.quantity),
+                price: optional(&mapping.price),
+                amount: optional(&mapping.amount),
+                commission: optional(&mapping.commission),
+                commission_iva: optional(&mapping.commission_iva),
+                withholding: optional(&mapping.withholding),
+                currency: optional(&mapping.currency),
+                notes: optional(&mapping.notes),
+            }));
+        }
+        Err(format!(
+            "No se encontró el encabezado con las columnas '{}', '{}' y '{}'",
+            mapping.date, mapping.ticker, mapping.transaction_type
+        ))
+    }
+}
+
+fn parse_row(cells: &[String], columns: &Columns, mapping: &ColumnMapping) -> Result<ImportedEntry, String> {
+    let cell = |index: Option<usize>| index.and_then(|i| cells.get(i)).map(|c| c.trim()).unwrap_or("");
+    let amount = |index: Option<usize>| parse_amount(cell(index), mapping.decimal_comma);
+
+    let raw_date = cell(Some(columns.date));
+    let transaction_date = NaiveDate::parse_from_str(raw_date, &mapping.date_format)
+        .or_else(|_| NaiveDate::parse_from_str(raw_date, "%Y-%m-%d"))
+        .map_err(|_| format!("Fecha inválida: {} (formato {})", raw_date, mapping.date_format))?;
+    let transaction_type = parse_kind(cell(Some(columns.transaction_type)), &mapping.type_aliases)?;
+    let ticker: String = format!("{}{}", cell(Some(columns.ticker)), cell(columns.series))
+        .to_uppercase()
+        .split_whitespace()
+        .collect();
+    let currency = match cell(columns.currency) {
+        "" => fx::MXN.to_string(),
+        raw => fx::normalize_currency(raw)?,
+    };
+    let quantity = amount(columns.quantity)?.map(|q| q.abs());
+    let price = amount(columns.price)?.map(|p| p.abs());
+    let total = amount(columns.amount)?.map(|a| a.abs());
+    // En un dividendo la cantidad son los títulos que lo cobraron
+    let shares = quantity.filter(|q| *q > Decimal::ZERO && transaction_type == TransactionKind::Dividend).map(money::round_quantity);
+
+    let (ticker, quantity, price) = match transaction_type {
+        TransactionKind::Buy | TransactionKind::Sell => {
+            if ticker.is_empty() {
+                return Err("Falta la emisora de la operación".to_string());
+            }
+            let quantity = quantity.filter(|q| *q > Decimal::ZERO)
+                .ok_or_else(|| "Falta la cantidad de títulos".to_string())?;
+            let price = price.or_else(|| total.map(|t| t / quantity))
+                .ok_or_else(|| "Falta el precio o el importe de la operación".to_string())?;
+            (ticker, quantity, price)
+        }
+        TransactionKind::Dividend if ticker.is_empty() => return Err("Falta la emisora del dividendo".to_string()),
+        kind => {
+            let total = total
+                .or_else(|| Some(quantity? * price?))
+                .ok_or_else(|| "Falta el importe del movimiento".to_string())?;
+            let ticker = if kind == TransactionKind::Dividend { ticker } else { CASH_TICKER.to_string() };
+            (ticker, Decimal::ONE, money::round_mxn(total))
+        }
+    };
+    let trade = transaction_type.is_trade();
+    let commission = if trade { amount(columns.commission)?.map(|c| money::round_mxn(c.abs())) } else { None };
+    let commission_iva = if trade { amount(columns.commission_iva)?.map(|c| money::round_mxn(c.abs())) } else { None };
+    let dividend = transaction_type == TransactionKind::Dividend;
+    let withholding_tax = if dividend { amount(columns.withholding)?.map(|w| money::round_mxn(w.abs())) } else { None };
+    let notes = Some(cell(columns.notes)).filter(|n| !n.is_empty()).map(str::to_string);
+
+    let entry = ImportedEntry {
+        transaction_date,
+        ticker,
+        transaction_type,
+        quantity: money::round_quantity(quantity),
+        price: money::round_price(price),
+        commission,
+        commission_iva,
+        shares,
+        withholding_tax,
+        currency,
+        notes,
+    };
+    entry.ledger_entry(0).validate()?;
+    Ok(entry)
+}
+
+// Interpreta el archivo y marca cada renglón como nuevo, duplicado o inválido
+async fn analyze(client: &deadpool_postgres::Client, request: &ImportRequest) -> Result<(Broker, Vec<ImportRow>), String> {
+    let broker: Broker = request.broker.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+    let mapping = request.mapping.clone().unwrap_or_else(|| broker.mapping());
+    let delimiter = mapping.delimiter.unwrap_or_else(|| detect_delimiter(&request.content));
+    let records = parse_csv(&request.content, delimiter);
+    let (header, columns) = Columns::locate(&records, &mapping)?;
+
+    let mut rows = Vec::new();
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    for (line_number, cells) in &records[header + 1..] {
+        // Totales y notas al pie no traen fecha
+        if !matches!(cells.get(columns.date), Some(cell) if !cell.trim().is_empty()) {
+            continue;
+        }
+        let row = match parse_row(cells, &columns, &mapping) {
+            Ok(entry) => {
+                let base = entry.content_key(request.portfolio_id, 0);
+                let occurrence = occurrences.entry(base).or_insert(0);
+                *occurrence += 1;
+                ImportRow {
+                    line_number: *line_number,
+                    status: RowStatus::New,
+                    content_key: Some(entry.content_key(request.portfolio_id, *occurrence)),
+                    entry: Some(entry),
+                    error: None,
+                }
+            }
+            Err(e) => ImportRow { line_number: *line_number, status: RowStatus::Invalid, entry: None, error: Some(e), content_key: None },
+        };
+        rows.push(row);
+    }
+    if rows.is_empty() {
+        return Err("El archivo no tiene movimientos".to_string());
+    }
+
+    let mut tickers: Vec<String> = rows.iter()
+        .filter_map(|r| r.entry.as_ref())
+        .filter(|e| e.ticker != CASH_TICKER)
+        .map(|e| e.ticker.clone())
+        .collect();
+    tickers.sort();
+    tickers.dedup();
+    let mut unknown = HashMap::new();
+    for ticker in tickers {
+        match user_management::ticker_exists_in_emisoras(&ticker, client).await {
+            Ok(true) => {}
+            Ok(false) => { unknown.insert(ticker.clone(), format!("El ticker '{}' no existe en la tabla emisoras", ticker)); }
+            Err(e) => { unknown.insert(ticker, e); }
+        }
+    }
+
+    let keys: Vec<String> = rows.iter().filter_map(|r| r.content_key.clone()).collect();
+    let imported: Vec<String> = client.query(
+        "SELECT key FROM unnest($2::text[]) AS key
+         WHERE md5(key) IN (SELECT content_hash FROM imported_rows WHERE portfolio_id = $1)",
+        &[&request.portfolio_id, &keys],
+    ).await.map_err(|e| format!("Error al consultar los renglones importados: {}", e))?
+        .iter()
+        .map(|r| r.get("key"))
+        .collect();
+
+    for row in rows.iter_mut() {
+        let Some(entry) = &row.entry else { continue };
+        if let Some(error) = unknown.get(&entry.ticker) {
+            row.status = RowStatus::Invalid;
+            row.error = Some(error.clone());
+        } else if matches!(&row.content_key, Some(key) if imported.contains(key)) {
+            row.status = RowStatus::Duplicate;
+        }
+    }
+    Ok((broker, rows))
+}
+
+fn count(rows: &[ImportRow], status: RowStatus) -> usize {
+    rows.iter().filter(|r| r.status == status).count()
+}
+
+pub async fn preview_import_logic(request: ImportRequest, db_pool: &Pool) -> Result<ImportPreview, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let (broker, rows) = analyze(&client, &request).await?;
+    Ok(ImportPreview {
+        broker,
+        new_rows: count(&rows, RowStatus::New),
+        duplicate_rows: count(&rows, RowStatus::Duplicate),
+        invalid_rows: count(&rows, RowStatus::Invalid),
+        rows,
+    })
+}
+
+/// Registra los renglones nuevos en orden cronológico, en una sola transacción de BD.
+/// Volver a importar el mismo archivo no duplica movimientos.
+pub async fn import_transactions_logic(request: ImportRequest, db_pool: &Pool) -> Result<ImportResult, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    orders::ensure_real_portfolio(&**client, request.portfolio_id).await?;
+    let (broker, rows) = analyze(&client, &request).await?;
+    let invalid_rows = count(&rows, RowStatus::Invalid);
+    if invalid_rows > 0 && !request.skip_invalid {
+        let first = rows.iter().find(|r| r.status == RowStatus::Invalid).unwrap();
+        return Err(format!(
+            "{} renglones tienen errores (renglón {}: {}); corríjalos o importe solo los válidos",
+            invalid_rows, first.line_number, first.error.as_deref().unwrap_or_default()
+        ));
+    }
+    let mut new_rows: Vec<&ImportRow> = rows.iter().filter(|r| r.status == RowStatus::New).collect();
+    let duplicate_rows = count(&rows, RowStatus::Duplicate);
+    if new_rows.is_empty() {
+        return Ok(ImportResult { batch_id: None, imported_rows: 0, duplicate_rows, invalid_rows, entries: Vec::new() });
+    }
+    new_rows.sort_by_key(|r| (r.entry.as_ref().unwrap().transaction_date, r.line_number));
+
+    let use_portfolio_cash = request.use_portfolio_cash.unwrap_or(broker != Broker::Csv);
+    let source = if use_portfolio_cash { CashSource::Portfolio } else { CashSource::External };
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let batch_id: i32 = tx.query_one(
+        "INSERT INTO import_batches (portfolio_id, broker, file_name, imported_rows, duplicate_rows, invalid_rows)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING batch_id",
+        &[&request.portfolio_id, &broker.as_str(), &request.file_name,
+          &(new_rows.len() as i32), &(duplicate_rows as i32), &(invalid_rows as i32)],
+    ).await.map_err(|e| format!("No se pudo registrar la importación: {}", e))?.get("batch_id");
+
+    let mut entries = Vec::new();
+    for row in &new_rows {
+        let imported = row.entry.as_ref().unwrap();
+        let mut entry = imported.ledger_entry(request.portfolio_id);
+        if entry.notes.is_none() {
+            entry.notes = Some(match &request.file_name {
+                Some(file) => format!("Importado de {} ({}, renglón {})", broker.as_str(), file, row.line_number),
+                None => format!("Importado de {} (renglón {})", broker.as_str(), row.line_number),
+            });
+        }
+        let recorded = match imported.transaction_type {
+            kind if kind.is_trade() => ledger::record_trade_in(&*tx, entry, source).await,
+            TransactionKind::Dividend => record_dividend(&*tx, imported, entry).await,
+            _ => ledger::record_cash_movement(&*tx, entry).await,
+        }.map_err(|e| format!("Renglón {}: {}", row.line_number, e))?;
+        tx.execute(
+            "INSERT INTO imported_rows (portfolio_id, content_hash, batch_id, line_number, transaction_id)
+             VALUES ($1, md5($2), $3, $4, $5)",
+            &[&request.portfolio_id, &row.content_key, &batch_id, &(row.line_number as i32), &recorded.transaction_id],
+        ).await.map_err(|e| format!("No se pudo registrar el renglón {}: {}", row.line_number, e))?;
+        entries.push(recorded);
+    }
+
+    let from = new_rows[0].entry.as_ref().unwrap().transaction_date;
+    snapshots::invalidate_from(&*tx, request.portfolio_id, from).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(ImportResult { batch_id: Some(batch_id), imported_rows: entries.len(), duplicate_rows, invalid_rows, entries })
+}
+
+// El importe del estado de cuenta es el neto; el bruto suma el ISR retenido. Queda en
+// dividend_details igual que un dividendo capturado a mano.
+async fn record_dividend<C: GenericClient>(client: &C, imported: &ImportedEntry, entry: NewLedgerEntry) -> Result<LedgerEntry, String> {
+    let shares = match imported.shares {
+        Some(shares) => shares,
+        None => dividends::entitled_shares(client, entry.portfolio_id, &entry.ticker, imported.transaction_date).await?,
+    };
+    if shares <= Decimal::ZERO {
+        return Err(format!("No había títulos de {} antes del {}", entry.ticker, imported.transaction_date));
+    }
+    let net_amount = imported.price;
+    let withholding_tax = imported.withholding_tax.unwrap_or_default();
+    let gross_amount = net_amount + withholding_tax;
+    let record = dividends::insert_dividend(client, DividendPayment {
+        portfolio_id: entry.portfolio_id,
+        ticker: entry.ticker,
+        ex_date: imported.transaction_date,
+        pay_date: imported.transaction_date,
+        shares,
+        amount_per_share: money::round_price(gross_amount / shares),
+        gross_amount,
+        withholding_tax,
+        net_amount,
+        currency: Some(entry.currency),
+        notes: entry.notes,
+    }).await?;
+    ledger::entry_by_id(client, record.transaction_id).await?
+        .ok_or_else(|| format!("No se encontró el movimiento {}", record.transaction_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(records: &[(usize, Vec<String>)], index: usize) -> Vec<&str> {
+        records[index].1.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn parse_csv_handles_quotes_and_escaped_quotes() {
+        let content = "\u{feff}Fecha,Emisora,Notas\r\n2024-01-02,WALMEX,\"Compra, lote \"\"A\"\"\"\r\n\r\n2024-01-03,\"FEMSA\nUBD\",sin nota\n";
+        let records = parse_csv(content, ',');
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(cells(&records, 0), vec!["Fecha", "Emisora", "Notas"]);
+        assert_eq!(cells(&records, 1), vec!["2024-01-02", "WALMEX", "Compra, lote \"A\""]);
+        // El salto de línea dentro de comillas es parte del campo y el renglón vacío se omite
+        assert_eq!(cells(&records, 2), vec!["2024-01-03", "FEMSA\nUBD", "sin nota"]);
+        assert_eq!(records.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn parse_csv_keeps_trailing_record_and_empty_fields() {
+        let records = parse_csv("a;;c\nd;e;", ';');
+        assert_eq!(cells(&records, 0), vec!["a", "", "c"]);
+        assert_eq!(cells(&records, 1), vec!["d", "e", ""]);
+    }
+
+    #[test]
+    fn detect_delimiter_prefers_the_most_frequent() {
+        assert_eq!(detect_delimiter("Fecha;Emisora;Importe\n02/01/2024;WALMEX;1.234,56"), ';');
+        assert_eq!(detect_delimiter("Fecha\tEmisora\tImporte\n2024-01-02\tWALMEX\t1234.56"), '\t');
+    }
+
+    #[test]
+    fn parse_amount_reads_parentheses_as_negative() {
+        assert_eq!(parse_amount("(1,234.56)", false).unwrap(), Some(Decimal::new(-123456, 2)));
+        assert_eq!(parse_amount(" $1,234.56 ", false).unwrap(), Some(Decimal::new(123456, 2)));
+        assert_eq!(parse_amount("-15.5", false).unwrap(), Some(Decimal::new(-155, 1)));
+    }
+
+    #[test]
+    fn parse_amount_with_decimal_comma() {
+        assert_eq!(parse_amount("1.234,56", true).unwrap(), Some(Decimal::new(123456, 2)));
+        assert_eq!(parse_amount("(1.234,56)", true).unwrap(), Some(Decimal::new(-123456, 2)));
+        assert_eq!(parse_amount("0,5", true).unwrap(), Some(Decimal::new(5, 1)));
+    }
+
+    #[test]
+    fn parse_amount_blank_and_invalid() {
+        assert_eq!(parse_amount("  ", false).unwrap(), None);
+        assert_eq!(parse_amount("-", false).unwrap(), None);
+        assert!(parse_amount("n/d", false).is_err());
+    }
+
+    #[test]
+    fn parse_kind_keyword_precedence() {
+        let aliases = HashMap::new();
+        assert_eq!(parse_kind("Venta de dividendo", &aliases).unwrap(), TransactionKind::Dividend);
+        assert_eq!(parse_kind("Intereses por venta en reporto", &aliases).unwrap(), TransactionKind::Interest);
+        assert_eq!(parse_kind("Depósito por venta", &aliases).unwrap(), TransactionKind::Sell);
+        assert_eq!(parse_kind("Compra CPO", &aliases).unwrap(), TransactionKind::Buy);
+        assert_eq!(parse_kind("  retiro  de efectivo ", &aliases).unwrap(), TransactionKind::Withdrawal);
+        assert_eq!(parse_kind("BUY", &aliases).unwrap(), TransactionKind::Buy);
+        assert!(parse_kind("Traspaso", &aliases).is_err());
+    }
+
+    #[test]
+    fn parse_kind_aliases_win_over_keywords() {
+        let aliases = HashMap::from([("Venta de derechos".to_string(), "DIVIDEND".to_string())]);
+        assert_eq!(parse_kind("VENTA DE DERECHOS", &aliases).unwrap(), TransactionKind::Dividend);
+        assert_eq!(parse_kind("Venta", &aliases).unwrap(), TransactionKind::Sell);
+    }
+
+    #[test]
+    fn parse_row_keeps_dividend_shares_and_withholding() {
+        let mapping = Broker::Gbm.mapping();
+        let records = parse_csv("Fecha,Emisora,Serie,Operación,Títulos,Importe,ISR\n15/03/2024,WALMEX,*,Dividendo,100,\"1,080.00\",120.00\n", ',');
+        let (header, columns) = Columns::locate(&records, &mapping).unwrap();
+        let entry = parse_row(&records[header + 1].1, &columns, &mapping).unwrap();
+
+        assert_eq!(entry.transaction_type, TransactionKind::Dividend);
+        assert_eq!(entry.ticker, "WALMEX*");
+        assert_eq!(entry.quantity, Decimal::ONE);
+        assert_eq!(entry.price, Decimal::from(1080));
+        assert_eq!(entry.shares, Some(Decimal::from(100)));
+        assert_eq!(entry.withholding_tax, Some(Decimal::from(120)));
+    }
+}
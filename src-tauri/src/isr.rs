@@ -0,0 +1,434 @@
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tokio_postgres::GenericClient;
+
+use crate::ledger::{self, TransactionKind};
+use crate::lots::{self, ClosedLot, CostBasisMethod};
+use crate::money;
+
+// ISR definitivo sobre la ganancia neta por enajenación de acciones en bolsa (LISR art. 129)
+pub const ISR_RATE: Decimal = Decimal::from_parts(10, 0, 0, false, 2);
+// Las pérdidas pueden disminuirse de las ganancias de los diez ejercicios siguientes
+const LOSS_CARRYFORWARD_YEARS: i32 = 10;
+const BMV_MARKETS: [&str; 2] = ["BMV", "BIVA"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InpcValue {
+    pub anio: i32,
+    pub mes: i32,
+    pub valor: Decimal,
+}
+
+/// Un renglón de la constancia: una venta de un lote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IsrSaleLine {
+    pub emisora: String,
+    pub serie: String,
+    pub ticker: String,
+    pub sell_transaction_id: i32,
+    pub fecha_adquisicion: NaiveDate,
+    pub fecha_enajenacion: NaiveDate,
+    pub titulos: Decimal,
+    pub precio_venta: Decimal,
+    pub importe_venta: Decimal,
+    pub costo_fiscal: Decimal,
+    pub factor_actualizacion: Decimal,
+    pub costo_actualizado: Decimal,
+    pub ganancia: Decimal,
+    pub perdida: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IsrAnnualReport {
+    pub portfolio_id: i32,
+    pub ejercicio: i32,
+    pub ventas: Vec<IsrSaleLine>,
+    pub importe_total_ventas: Decimal,
+    pub costo_actualizado_total: Decimal,
+    pub ganancias: Decimal,
+    pub perdidas: Decimal,
+    pub resultado_neto: Decimal,
+    pub perdidas_anteriores_aplicadas: Decimal,
+    pub perdidas_pendientes: Decimal,
+    pub ganancia_gravable: Decimal,
+    pub tasa_isr: Decimal,
+    pub isr_causado: Decimal,
+    // Tickers vendidos en el ejercicio que no cotizan en BMV/BIVA
+    pub tickers_excluidos: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Listing {
+    emisora: String,
+    serie: String,
+}
+
+fn previous_month(date: NaiveDate) -> (i32, u32) {
+    if date.month() == 1 { (date.year() - 1, 12) } else { (date.year(), date.month() - 1) }
+}
+
+// Factor = INPC del mes anterior a la venta / INPC del mes de adquisición, a 4 decimales y nunca menor a 1
+fn inflation_factor(
+    inpc: &HashMap<(i32, u32), Decimal>,
+    acquired: NaiveDate,
+    disposed: NaiveDate,
+) -> Result<Decimal, String> {
+    let sale_month = previous_month(disposed);
+    let acquisition_month = (acquired.year(), acquired.month());
+    if sale_month <= acquisition_month {
+        return Ok(Decimal::ONE);
+    }
+    let lookup = |(anio, mes): (i32, u32)| {
+        inpc.get(&(anio, mes)).copied()
+            .ok_or_else(|| format!("Falta el INPC de {:02}/{} en la tabla inpc", mes, anio))
+    };
+    let factor = (lookup(sale_month)? / lookup(acquisition_month)?)
+        .round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero);
+    Ok(factor.max(Decimal::ONE))
+}
+
+fn sale_line(lot: &ClosedLot, listing: &Listing, inpc: &HashMap<(i32, u32), Decimal>) -> Result<IsrSaleLine, String> {
+    let fecha_adquisicion = lot.acquired_at.date_naive();
+    let fecha_enajenacion = lot.disposed_at.date_naive();
+    let factor_actualizacion = inflation_factor(inpc, fecha_adquisicion, fecha_enajenacion)?;
+    // Los títulos del SIC comprados en dólares se llevan a pesos al tipo de cambio de cada fecha
+    let costo_fiscal = lot.cost_basis_mxn();
+    let importe_venta = lot.proceeds_mxn();
+    let costo_actualizado = money::round_mxn(costo_fiscal * factor_actualizacion);
+    let resultado = importe_venta - costo_actualizado;
+    Ok(IsrSaleLine {
+        emisora: listing.emisora.clone(),
+        serie: listing.serie.clone(),
+        ticker: lot.ticker.clone(),
+        sell_transaction_id: lot.sell_transaction_id,
+        fecha_adquisicion,
+        fecha_enajenacion,
+        titulos: lot.quantity,
+        precio_venta: money::round_price(lot.sale_price * lot.disposal_fx_rate),
+        importe_venta,
+        costo_fiscal,
+        factor_actualizacion,
+        costo_actualizado,
+        ganancia: resultado.max(Decimal::ZERO),
+        perdida: (-resultado).max(Decimal::ZERO),
+    })
+}
+
+/// Calcula el ejercicio `year` aplicando las pérdidas pendientes de los ejercicios anteriores.
+fn build_annual_report(
+    portfolio_id: i32,
+    year: i32,
+    closed_lots: &[ClosedLot],
+    listings: &HashMap<String, Listing>,
+    inpc: &HashMap<(i32, u32), Decimal>,
+) -> Result<IsrAnnualReport, String> {
+    let mut lines_by_year: BTreeMap<i32, Vec<IsrSaleLine>> = BTreeMap::new();
+    let mut tickers_excluidos = Vec::new();
+    for lot in closed_lots.iter().filter(|l| l.disposed_at.year() <= year) {
+        match listings.get(&lot.ticker) {
+            Some(listing) => lines_by_year.entry(lot.disposed_at.year()).or_default().push(sale_line(lot, listing, inpc)?),
+            None => {
+                if lot.disposed_at.year() == year && !tickers_excluidos.contains(&lot.ticker) {
+                    tickers_excluidos.push(lot.ticker.clone());
+                }
+            }
+        }
+    }
+
+    // (ejercicio de origen, pérdida aún no aplicada)
+    let mut pending_losses: Vec<(i32, Decimal)> = Vec::new();
+    let mut report = None;
+    for (&ejercicio, ventas) in lines_by_year.iter() {
+        pending_losses.retain(|(origen, _)| ejercicio - origen <= LOSS_CARRYFORWARD_YEARS);
+        let ganancias: Decimal = ventas.iter().map(|v| v.ganancia).sum();
+        let perdidas: Decimal = ventas.iter().map(|v| v.perdida).sum();
+        let resultado_neto = ganancias - perdidas;
+
+        let mut perdidas_anteriores_aplicadas = Decimal::ZERO;
+        let mut ganancia_gravable = resultado_neto.max(Decimal::ZERO);
+        for (_, pendiente) in pending_losses.iter_mut() {
+            let aplicada = (*pendiente).min(ganancia_gravable);
+            *pendiente -= aplicada;
+            ganancia_gravable -= aplicada;
+            perdidas_anteriores_aplicadas += aplicada;
+        }
+        pending_losses.retain(|(_, pendiente)| *pendiente > Decimal::ZERO);
+        if resultado_neto < Decimal::ZERO {
+            pending_losses.push((ejercicio, -resultado_neto));
+        }
+
+        if ejercicio == year {
+            report = Some(IsrAnnualReport {
+                portfolio_id,
+                ejercicio,
+                importe_total_ventas: ventas.iter().map(|v| v.importe_venta).sum(),
+                costo_actualizado_total: ventas.iter().map(|v| v.costo_actualizado).sum(),
+                ventas: ventas.clone(),
+                ganancias,
+                perdidas,
+                resultado_neto,
+                perdidas_anteriores_aplicadas,
+                perdidas_pendientes: pending_losses.iter().map(|(_, p)| *p).sum(),
+                ganancia_gravable,
+                tasa_isr: ISR_RATE,
+                isr_causado: money::round_mxn(ganancia_gravable * ISR_RATE),
+                tickers_excluidos: tickers_excluidos.clone(),
+            });
+        }
+    }
+
+    pending_losses.retain(|(origen, _)| year - origen <= LOSS_CARRYFORWARD_YEARS);
+    Ok(report.unwrap_or_else(|| IsrAnnualReport {
+        portfolio_id,
+        ejercicio: year,
+        ventas: Vec::new(),
+        importe_total_ventas: Decimal::ZERO,
+        costo_actualizado_total: Decimal::ZERO,
+        ganancias: Decimal::ZERO,
+        perdidas: Decimal::ZERO,
+        resultado_neto: Decimal::ZERO,
+        perdidas_anteriores_aplicadas: Decimal::ZERO,
+        perdidas_pendientes: pending_losses.iter().map(|(_, p)| *p).sum(),
+        ganancia_gravable: Decimal::ZERO,
+        tasa_isr: ISR_RATE,
+        isr_causado: Decimal::ZERO,
+        tickers_excluidos,
+    }))
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Formato de la constancia anual de enajenación de acciones que emiten las casas de bolsa.
+pub fn report_to_csv(report: &IsrAnnualReport) -> String {
+    let mut csv = String::new();
+    csv.push_str(&format!("Constancia anual de enajenacion de acciones,Ejercicio {}\n", report.ejercicio));
+    csv.push_str("Emisora,Serie,Fecha de adquisicion,Fecha de enajenacion,Titulos,Precio de venta,Importe de venta,Costo fiscal,Factor de actualizacion,Costo actualizado,Ganancia,Perdida\n");
+    for v in &report.ventas {
+        let row = [
+            csv_field(&v.emisora),
+            csv_field(&v.serie),
+            v.fecha_adquisicion.format("%d/%m/%Y").to_string(),
+            v.fecha_enajenacion.format("%d/%m/%Y").to_string(),
+            v.titulos.normalize().to_string(),
+            v.precio_venta.normalize().to_string(),
+            v.importe_venta.to_string(),
+            v.costo_fiscal.to_string(),
+            v.factor_actualizacion.to_string(),
+            v.costo_actualizado.to_string(),
+            v.ganancia.to_string(),
+            v.perdida.to_string(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv.push('\n');
+    csv.push_str("Concepto,Importe\n");
+    for (concepto, importe) in [
+        ("Importe total de ventas", report.importe_total_ventas),
+        ("Costo actualizado total", report.costo_actualizado_total),
+        ("Ganancias", report.ganancias),
+        ("Perdidas", report.perdidas),
+        ("Resultado neto", report.resultado_neto),
+        ("Perdidas de ejercicios anteriores aplicadas", report.perdidas_anteriores_aplicadas),
+        ("Ganancia gravable", report.ganancia_gravable),
+        ("ISR causado (10%)", report.isr_causado),
+        ("Perdidas pendientes por aplicar", report.perdidas_pendientes),
+    ] {
+        csv.push_str(&format!("{},{}\n", concepto, importe));
+    }
+    csv
+}
+
+async fn load_inpc<C: GenericClient>(client: &C) -> Result<HashMap<(i32, u32), Decimal>, String> {
+    let rows = client.query("SELECT anio, mes, valor FROM inpc", &[])
+        .await.map_err(|e| format!("Error al consultar la tabla inpc: {}", e))?;
+    Ok(rows.iter()
+        .map(|row| ((row.get::<_, i32>("anio"), row.get::<_, i32>("mes") as u32), row.get("valor")))
+        .collect())
+}
+
+// Solo las emisoras listadas en una bolsa mexicana entran al régimen del 10%
+fn listed_in_mexico(bolsa: Option<&str>) -> bool {
+    bolsa.is_some_and(|b| BMV_MARKETS.contains(&b.trim().to_uppercase().as_str()))
+}
+
+async fn load_listings<C: GenericClient>(client: &C, closed_lots: &[ClosedLot]) -> Result<HashMap<String, Listing>, String> {
+    let mut listings = HashMap::new();
+    for lot in closed_lots {
+        if listings.contains_key(&lot.ticker) {
+            continue;
+        }
+        let row = client.query_opt(
+            "SELECT emisoras, serie, bolsa FROM emisoras WHERE (emisoras || serie) = $1 OR emisoras = $1 LIMIT 1",
+            &[&lot.ticker],
+        ).await.map_err(|e| format!("Error al consultar la emisora {}: {}", lot.ticker, e))?;
+        if let Some(row) = row {
+            let bolsa: Option<String> = row.get("bolsa");
+            if listed_in_mexico(bolsa.as_deref()) {
+                listings.insert(lot.ticker.clone(), Listing {
+                    emisora: row.get("emisoras"),
+                    serie: row.get::<_, Option<String>>("serie").unwrap_or_default(),
+                });
+            }
+        }
+    }
+    Ok(listings)
+}
+
+pub async fn get_isr_annual_report_logic(
+    portfolio_id: i32,
+    year: i32,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<IsrAnnualReport, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    // El costo fiscal es el promedio de adquisición (LISR art. 129) sin importar el método
+    // elegido para el portafolio; así cambiarlo no altera ejercicios ya declarados
+    let entries = ledger::list_entries(&**client, portfolio_id, Some(&[TransactionKind::Buy, TransactionKind::Sell])).await?;
+    let book = lots::build_lot_book(&entries, CostBasisMethod::Average, &HashMap::new())?;
+    let listings = load_listings(&**client, &book.closed_lots).await?;
+    let inpc = load_inpc(&**client).await?;
+    build_annual_report(portfolio_id, year, &book.closed_lots, &listings, &inpc)
+}
+
+pub async fn export_isr_annual_report_logic(
+    portfolio_id: i32,
+    year: i32,
+    format: &str,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<String, String> {
+    let report = get_isr_annual_report_logic(portfolio_id, year, db_pool).await?;
+    match format.to_lowercase().as_str() {
+        "csv" => Ok(report_to_csv(&report)),
+        "json" => serde_json::to_string_pretty(&report).map_err(|e| e.to_string()),
+        _ => Err(format!("Formato de exportación no soportado: {}", format)),
+    }
+}
+
+pub async fn save_inpc_values_logic(
+    values: Vec<InpcValue>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<usize, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    for value in &values {
+        if !(1..=12).contains(&value.mes) || value.valor <= Decimal::ZERO {
+            return Err(format!("INPC inválido para {:02}/{}", value.mes, value.anio));
+        }
+        tx.execute(
+            "INSERT INTO inpc (anio, mes, valor) VALUES ($1, $2, $3)
+             ON CONFLICT (anio, mes) DO UPDATE SET valor = EXCLUDED.valor",
+            &[&value.anio, &value.mes, &value.valor],
+        ).await.map_err(|e| format!("No se pudo guardar el INPC de {:02}/{}: {}", value.mes, value.anio, e))?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(values.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn d(value: i64) -> Decimal {
+        Decimal::from(value)
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    // Lote de un título vendido el 15 de junio de `year` con resultado `gain`
+    fn closed_lot(ticker: &str, year: i32, gain: i64) -> ClosedLot {
+        let cost = d(1_000);
+        ClosedLot {
+            lot_id: year,
+            sell_transaction_id: year,
+            ticker: ticker.to_string(),
+            acquired_at: Utc.with_ymd_and_hms(year, 6, 1, 18, 0, 0).unwrap(),
+            disposed_at: Utc.with_ymd_and_hms(year, 6, 15, 18, 0, 0).unwrap(),
+            quantity: Decimal::ONE,
+            unit_cost: cost,
+            sale_price: cost + d(gain),
+            cost_basis: cost,
+            proceeds: cost + d(gain),
+            realized_gain: d(gain),
+            currency: "MXN".to_string(),
+            acquisition_fx_rate: Decimal::ONE,
+            disposal_fx_rate: Decimal::ONE,
+        }
+    }
+
+    fn walmex() -> HashMap<String, Listing> {
+        HashMap::from([("WALMEX*".to_string(), Listing { emisora: "WALMEX".to_string(), serie: "*".to_string() })])
+    }
+
+    #[test]
+    fn inflation_factor_uses_month_before_sale_and_rounds() {
+        let inpc = HashMap::from([((2023, 1), d(300)), ((2024, 2), Decimal::new(3123456, 4))]);
+        // 312.3456 / 300 = 1.041152 → 1.0412
+        let factor = inflation_factor(&inpc, date(2023, 1, 20), date(2024, 3, 10)).unwrap();
+        assert_eq!(factor, Decimal::new(10412, 4));
+    }
+
+    #[test]
+    fn inflation_factor_is_never_below_one() {
+        let inpc = HashMap::from([((2023, 1), d(300)), ((2023, 5), d(290))]);
+        assert_eq!(inflation_factor(&inpc, date(2023, 1, 20), date(2023, 6, 1)).unwrap(), Decimal::ONE);
+        // Vendido en el mes siguiente a la compra: no hay meses que actualizar
+        assert_eq!(inflation_factor(&HashMap::new(), date(2023, 1, 20), date(2023, 2, 3)).unwrap(), Decimal::ONE);
+    }
+
+    #[test]
+    fn inflation_factor_requires_inpc() {
+        let inpc = HashMap::from([((2023, 1), d(300))]);
+        assert!(inflation_factor(&inpc, date(2023, 1, 20), date(2023, 6, 1)).is_err());
+    }
+
+    #[test]
+    fn losses_carry_forward_ten_years() {
+        let lots = [closed_lot("WALMEX*", 2010, -500), closed_lot("WALMEX*", 2020, 800)];
+        let report = build_annual_report(1, 2020, &lots, &walmex(), &HashMap::new()).unwrap();
+        assert_eq!(report.perdidas_anteriores_aplicadas, d(500));
+        assert_eq!(report.ganancia_gravable, d(300));
+        assert_eq!(report.isr_causado, d(30));
+        assert_eq!(report.perdidas_pendientes, Decimal::ZERO);
+    }
+
+    #[test]
+    fn losses_expire_after_ten_years() {
+        let lots = [closed_lot("WALMEX*", 2010, -500), closed_lot("WALMEX*", 2021, 800)];
+        let report = build_annual_report(1, 2021, &lots, &walmex(), &HashMap::new()).unwrap();
+        assert_eq!(report.perdidas_anteriores_aplicadas, Decimal::ZERO);
+        assert_eq!(report.ganancia_gravable, d(800));
+    }
+
+    #[test]
+    fn partially_applied_loss_stays_pending() {
+        let lots = [closed_lot("WALMEX*", 2018, -500), closed_lot("WALMEX*", 2019, 200)];
+        let report = build_annual_report(1, 2019, &lots, &walmex(), &HashMap::new()).unwrap();
+        assert_eq!(report.ganancia_gravable, Decimal::ZERO);
+        assert_eq!(report.perdidas_pendientes, d(300));
+    }
+
+    #[test]
+    fn only_bmv_and_biva_listings_enter_the_report() {
+        assert!(listed_in_mexico(Some("BMV")));
+        assert!(listed_in_mexico(Some(" biva ")));
+        assert!(!listed_in_mexico(Some("NYSE")));
+        assert!(!listed_in_mexico(None));
+
+        let lots = [closed_lot("WALMEX*", 2024, 100), closed_lot("AAPL", 2024, 900)];
+        let report = build_annual_report(1, 2024, &lots, &walmex(), &HashMap::new()).unwrap();
+        assert_eq!(report.ventas.len(), 1);
+        assert_eq!(report.ganancias, d(100));
+        assert_eq!(report.tickers_excluidos, vec!["AAPL".to_string()]);
+    }
+}
@@ -0,0 +1,513 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use tokio_postgres::{GenericClient, Row};
+
+use crate::cash;
+use crate::fees;
+use crate::fx;
+use crate::money;
+
+// Ticker reservado para los movimientos de efectivo dentro del ledger
+pub const CASH_TICKER: &str = "CASH";
+// Hora del centro de México (UTC-6 todo el año)
+const MEXICO_UTC_OFFSET_SECONDS: i32 = -6 * 3600;
+
+const ENTRY_COLUMNS: &str = "transaction_id, portfolio_id, user_id, ticker, transaction_type, quantity, price, transaction_date, total_amount, commission, commission_iva, currency, fx_rate, notes, linked_transaction_id, voided_at, created_at, updated_at";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TransactionKind {
+    Buy,
+    Sell,
+    Deposit,
+    Withdrawal,
+    Dividend,
+    // Intereses que paga la casa de bolsa sobre el efectivo (ver cash.rs)
+    Interest,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Buy => "BUY",
+            TransactionKind::Sell => "SELL",
+            TransactionKind::Deposit => "DEPOSIT",
+            TransactionKind::Withdrawal => "WITHDRAWAL",
+            TransactionKind::Dividend => "DIVIDEND",
+            TransactionKind::Interest => "INTEREST",
+        }
+    }
+
+    pub fn is_trade(&self) -> bool {
+        matches!(self, TransactionKind::Buy | TransactionKind::Sell)
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    // Acepta también las variantes en minúsculas y en español que usaba el frontend
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "BUY" | "COMPRA" => Ok(TransactionKind::Buy),
+            "SELL" | "VENTA" => Ok(TransactionKind::Sell),
+            "DEPOSIT" | "DEPOSITO" => Ok(TransactionKind::Deposit),
+            "WITHDRAWAL" | "RETIRO" => Ok(TransactionKind::Withdrawal),
+            "DIVIDEND" | "DIVIDENDO" => Ok(TransactionKind::Dividend),
+            "INTEREST" | "INTERES" | "INTERESES" => Ok(TransactionKind::Interest),
+            _ => Err(format!("Tipo de transacción desconocido: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub transaction_id: i32,
+    pub portfolio_id: i32,
+    pub user_id: i32,
+    pub ticker: String,
+    pub transaction_type: TransactionKind,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub transaction_date: DateTime<Utc>,
+    pub total_amount: Decimal,
+    pub commission: Decimal,
+    pub commission_iva: Decimal,
+    pub currency: String,
+    // Pesos por unidad de `currency` en la fecha del movimiento
+    pub fx_rate: Decimal,
+    pub notes: Option<String>,
+    // Operación de la que este movimiento es la pata de efectivo
+    pub linked_transaction_id: Option<i32>,
+    // Los movimientos anulados se conservan pero no cuentan (ver ledger_audit.rs)
+    pub voided_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl LedgerEntry {
+    fn from_row(row: &Row) -> Result<Self, String> {
+        let transaction_type: String = row.get("transaction_type");
+        Ok(LedgerEntry {
+            transaction_id: row.get("transaction_id"),
+            portfolio_id: row.get("portfolio_id"),
+            user_id: row.get("user_id"),
+            ticker: row.get("ticker"),
+            transaction_type: transaction_type.parse()?,
+            quantity: row.get("quantity"),
+            price: row.get::<_, Option<Decimal>>("price").unwrap_or(Decimal::ZERO),
+            transaction_date: row.get("transaction_date"),
+            total_amount: row.get("total_amount"),
+            commission: row.get("commission"),
+            commission_iva: row.get("commission_iva"),
+            currency: row.get("currency"),
+            fx_rate: row.get("fx_rate"),
+            notes: row.get("notes"),
+            linked_transaction_id: row.get("linked_transaction_id"),
+            voided_at: row.get("voided_at"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        })
+    }
+
+    /// Efectivo que entra (positivo) o sale (negativo) con el movimiento.
+    pub fn cash_effect(&self) -> Decimal {
+        match self.transaction_type {
+            TransactionKind::Buy | TransactionKind::Withdrawal => -self.total_amount,
+            TransactionKind::Sell | TransactionKind::Deposit | TransactionKind::Dividend | TransactionKind::Interest => self.total_amount,
+        }
+    }
+}
+
+/// Momento de un movimiento que solo trae fecha: mediodía en la Ciudad de México, que es el
+/// mismo día tanto en hora local como en UTC.
+pub fn local_timestamp(date: NaiveDate) -> DateTime<Utc> {
+    let offset = FixedOffset::east_opt(MEXICO_UTC_OFFSET_SECONDS).unwrap();
+    date.and_hms_opt(12, 0, 0).unwrap().and_local_timezone(offset).unwrap().with_timezone(&Utc)
+}
+
+/// Inicio del día `date` en la Ciudad de México; sirve de corte para lo que se tenía antes de esa fecha.
+pub fn local_day_start(date: NaiveDate) -> DateTime<Utc> {
+    let offset = FixedOffset::east_opt(MEXICO_UTC_OFFSET_SECONDS).unwrap();
+    date.and_hms_opt(0, 0, 0).unwrap().and_local_timezone(offset).unwrap().with_timezone(&Utc)
+}
+
+/// Comisión e IVA cobrados en una operación.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TradeFees {
+    pub commission: Decimal,
+    pub commission_iva: Decimal,
+}
+
+impl TradeFees {
+    pub fn total(&self) -> Decimal {
+        self.commission + self.commission_iva
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewLedgerEntry {
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub transaction_type: TransactionKind,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub currency: String,
+    // None = tipo de cambio de fx_rates en la fecha del movimiento
+    pub fx_rate: Option<Decimal>,
+    pub notes: Option<String>,
+    // None = now()
+    pub transaction_date: Option<DateTime<Utc>>,
+    // None = según el esquema de comisiones del portafolio (ver fees.rs) al registrar la operación
+    pub fees: Option<TradeFees>,
+}
+
+impl NewLedgerEntry {
+    pub fn trade(portfolio_id: i32, ticker: &str, transaction_type: TransactionKind, quantity: Decimal, price: Decimal) -> Self {
+        NewLedgerEntry {
+            portfolio_id,
+            ticker: ticker.to_string(),
+            transaction_type,
+            quantity: money::round_quantity(quantity),
+            price: money::round_price(price),
+            currency: fx::MXN.to_string(),
+            fx_rate: None,
+            notes: None,
+            transaction_date: None,
+            fees: None,
+        }
+    }
+
+    // Los movimientos de efectivo se guardan como 1 unidad de CASH al precio del monto
+    pub fn cash(portfolio_id: i32, transaction_type: TransactionKind, amount: Decimal) -> Self {
+        NewLedgerEntry::trade(portfolio_id, CASH_TICKER, transaction_type, Decimal::ONE, money::round_mxn(amount))
+    }
+
+    // Un dividendo entra al ledger por su monto neto; el detalle fiscal vive en dividend_details
+    pub fn dividend(portfolio_id: i32, ticker: &str, net_amount: Decimal) -> Self {
+        NewLedgerEntry::trade(portfolio_id, ticker, TransactionKind::Dividend, Decimal::ONE, money::round_mxn(net_amount))
+    }
+
+    pub fn gross_amount(&self) -> Decimal {
+        money::round_mxn(self.quantity * self.price)
+    }
+
+    // Las comisiones se suman al costo de una compra y se restan de lo obtenido en una venta
+    pub fn total_amount(&self) -> Decimal {
+        let fees = self.fees.unwrap_or_default().total();
+        match self.transaction_type {
+            TransactionKind::Buy => self.gross_amount() + fees,
+            TransactionKind::Sell => self.gross_amount() - fees,
+            _ => self.gross_amount(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.quantity <= Decimal::ZERO {
+            return Err("La cantidad debe ser mayor a cero".to_string());
+        }
+        if self.price < Decimal::ZERO {
+            return Err("El precio no puede ser negativo".to_string());
+        }
+        fx::normalize_currency(&self.currency)?;
+        if matches!(self.fx_rate, Some(rate) if rate <= Decimal::ZERO) {
+            return Err("El tipo de cambio debe ser mayor a cero".to_string());
+        }
+        let fees = self.fees.unwrap_or_default();
+        if fees.commission < Decimal::ZERO || fees.commission_iva < Decimal::ZERO {
+            return Err("La comisión no puede ser negativa".to_string());
+        }
+        if fees.total() > Decimal::ZERO && !self.transaction_type.is_trade() {
+            return Err("Solo las compras y ventas llevan comisión".to_string());
+        }
+        if self.total_amount() < Decimal::ZERO {
+            return Err("La comisión excede el importe de la venta".to_string());
+        }
+        match self.transaction_type {
+            TransactionKind::Buy | TransactionKind::Sell if self.ticker == CASH_TICKER => {
+                Err("No se puede comprar o vender el ticker reservado CASH".to_string())
+            }
+            TransactionKind::Deposit | TransactionKind::Withdrawal | TransactionKind::Interest if self.ticker != CASH_TICKER => {
+                Err("Los depósitos, retiros e intereses deben registrarse con el ticker CASH".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// De dónde sale (o a dónde va) el efectivo de una compra o venta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CashSource {
+    // Se usa el efectivo del portafolio; la compra falla si excede el saldo más el sobregiro
+    // permitido por la cuenta de efectivo (ver cash.rs)
+    Portfolio,
+    // El dinero entra y sale del portafolio junto con la operación
+    External,
+}
+
+async fn portfolio_owner<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<i32, String> {
+    let row = client.query_opt(
+        "SELECT usuario_id FROM portafolios WHERE id = $1",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar el portafolio: {}", e))?;
+    row.and_then(|r| r.get::<_, Option<i32>>("usuario_id"))
+        .ok_or_else(|| format!("El portafolio {} no existe o no tiene usuario asignado", portfolio_id))
+}
+
+/// Inserta un movimiento tal cual, sin tocar el efectivo. Usar `record_trade` para compras y ventas.
+pub async fn insert_entry<C: GenericClient>(client: &C, entry: &NewLedgerEntry) -> Result<LedgerEntry, String> {
+    entry.validate()?;
+    let user_id = portfolio_owner(client, entry.portfolio_id).await?;
+    let total_amount = entry.total_amount();
+    let fees = entry.fees.unwrap_or_default();
+    let currency = fx::normalize_currency(&entry.currency)?;
+    let fx_rate = match entry.fx_rate {
+        Some(rate) => money::round_price(rate),
+        None => {
+            let date = entry.transaction_date.unwrap_or_else(Utc::now).date_naive();
+            fx::rate_to_mxn(client, &currency, date).await?
+        }
+    };
+    let query = format!(
+        "INSERT INTO portfolio_transactions (portfolio_id, user_id, ticker, transaction_type, quantity, price, total_amount, commission, commission_iva, currency, fx_rate, notes, transaction_date) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, COALESCE($13, now())) RETURNING {}",
+        ENTRY_COLUMNS
+    );
+    let row = client.query_one(
+        &query,
+        &[&entry.portfolio_id, &user_id, &entry.ticker, &entry.transaction_type.as_str(), &entry.quantity, &entry.price, &total_amount, &fees.commission, &fees.commission_iva, &currency, &fx_rate, &entry.notes, &entry.transaction_date],
+    ).await.map_err(|e| format!("Error al registrar el movimiento: {}", e))?;
+    LedgerEntry::from_row(&row)
+}
+
+pub async fn list_entries<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    kinds: Option<&[TransactionKind]>,
+) -> Result<Vec<LedgerEntry>, String> {
+    let rows = match kinds {
+        Some(kinds) => {
+            let kinds: Vec<&str> = kinds.iter().map(|k| k.as_str()).collect();
+            let query = format!(
+                "SELECT {} FROM portfolio_transactions WHERE portfolio_id = $1 AND transaction_type = ANY($2) AND voided_at IS NULL ORDER BY transaction_date ASC, transaction_id ASC",
+                ENTRY_COLUMNS
+            );
+            client.query(&query, &[&portfolio_id, &kinds]).await
+        }
+        None => {
+            let query = format!(
+                "SELECT {} FROM portfolio_transactions WHERE portfolio_id = $1 AND voided_at IS NULL ORDER BY transaction_date ASC, transaction_id ASC",
+                ENTRY_COLUMNS
+            );
+            client.query(&query, &[&portfolio_id]).await
+        }
+    }.map_err(|e| format!("Error al listar transacciones: {}", e))?;
+    rows.iter().map(LedgerEntry::from_row).collect()
+}
+
+/// Un movimiento por id, anulado o no.
+pub async fn entry_by_id<C: GenericClient>(client: &C, transaction_id: i32) -> Result<Option<LedgerEntry>, String> {
+    let row = client.query_opt(
+        &format!("SELECT {} FROM portfolio_transactions WHERE transaction_id = $1", ENTRY_COLUMNS),
+        &[&transaction_id],
+    ).await.map_err(|e| format!("Error al consultar la transacción {}: {}", transaction_id, e))?;
+    row.as_ref().map(LedgerEntry::from_row).transpose()
+}
+
+/// Patas de efectivo enlazadas a una compra o venta, anuladas o no.
+pub async fn linked_entries<C: GenericClient>(client: &C, transaction_id: i32) -> Result<Vec<LedgerEntry>, String> {
+    let rows = client.query(
+        &format!("SELECT {} FROM portfolio_transactions WHERE linked_transaction_id = $1 ORDER BY transaction_id", ENTRY_COLUMNS),
+        &[&transaction_id],
+    ).await.map_err(|e| format!("Error al consultar los movimientos de efectivo de {}: {}", transaction_id, e))?;
+    rows.iter().map(LedgerEntry::from_row).collect()
+}
+
+/// Sobrescribe los campos corregibles de un movimiento (importes, fecha, notas y anulación).
+/// El tipo, el ticker y el portafolio no cambian.
+pub async fn write_entry<C: GenericClient>(client: &C, entry: &LedgerEntry) -> Result<LedgerEntry, String> {
+    let row = client.query_opt(
+        &format!(
+            "UPDATE portfolio_transactions
+             SET quantity = $2, price = $3, transaction_date = $4, total_amount = $5, commission = $6,
+                 commission_iva = $7, fx_rate = $8, notes = $9, voided_at = $10, updated_at = now()
+             WHERE transaction_id = $1
+             RETURNING {}",
+            ENTRY_COLUMNS
+        ),
+        &[&entry.transaction_id, &entry.quantity, &entry.price, &entry.transaction_date, &entry.total_amount,
+          &entry.commission, &entry.commission_iva, &entry.fx_rate, &entry.notes, &entry.voided_at],
+    ).await.map_err(|e| format!("Error al actualizar la transacción {}: {}", entry.transaction_id, e))?
+        .ok_or_else(|| format!("No se encontró la transacción {}", entry.transaction_id))?;
+    LedgerEntry::from_row(&row)
+}
+
+/// Efectivo del portafolio en `currency`.
+pub async fn cash_balance<C: GenericClient>(client: &C, portfolio_id: i32, currency: &str) -> Result<Decimal, String> {
+    let row = client.query_one(
+        "SELECT COALESCE(SUM(CASE
+                    WHEN transaction_type IN ('SELL', 'DEPOSIT', 'DIVIDEND', 'INTEREST') THEN total_amount
+                    WHEN transaction_type IN ('BUY', 'WITHDRAWAL') THEN -total_amount
+                    ELSE 0
+                  END), 0) as current_cash
+         FROM portfolio_transactions
+         WHERE portfolio_id = $1 AND currency = $2 AND voided_at IS NULL",
+        &[&portfolio_id, &currency],
+    ).await.map_err(|e| format!("Error al consultar cash: {}", e))?;
+    Ok(row.get("current_cash"))
+}
+
+/// Efectivo del portafolio por moneda, sin convertir.
+pub async fn cash_balances<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<Vec<(String, Decimal)>, String> {
+    let rows = client.query(
+        "SELECT currency, SUM(CASE
+                    WHEN transaction_type IN ('SELL', 'DEPOSIT', 'DIVIDEND', 'INTEREST') THEN total_amount
+                    WHEN transaction_type IN ('BUY', 'WITHDRAWAL') THEN -total_amount
+                    ELSE 0
+                  END) as current_cash
+         FROM portfolio_transactions
+         WHERE portfolio_id = $1 AND voided_at IS NULL
+         GROUP BY currency
+         ORDER BY currency",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar cash: {}", e))?;
+    Ok(rows.iter().map(|r| (r.get("currency"), r.get("current_cash"))).collect())
+}
+
+// Un ticker se opera siempre en la misma moneda dentro de un portafolio para que sus lotes sean comparables
+async fn check_trade_currency<C: GenericClient>(client: &C, entry: &NewLedgerEntry) -> Result<(), String> {
+    let row = client.query_opt(
+        "SELECT currency FROM portfolio_transactions
+         WHERE portfolio_id = $1 AND ticker = $2 AND transaction_type IN ('BUY', 'SELL') AND voided_at IS NULL
+         LIMIT 1",
+        &[&entry.portfolio_id, &entry.ticker],
+    ).await.map_err(|e| format!("Error al consultar la moneda de {}: {}", entry.ticker, e))?;
+    match row.map(|r| r.get::<_, String>("currency")) {
+        Some(currency) if currency != entry.currency => Err(format!(
+            "{} ya se opera en {} en este portafolio; no se puede registrar en {}",
+            entry.ticker, currency, entry.currency
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Títulos de `ticker` en el portafolio, sin importar el método de costeo.
+pub async fn held_quantity<C: GenericClient>(client: &C, portfolio_id: i32, ticker: &str) -> Result<Decimal, String> {
+    let row = client.query_one(
+        "SELECT COALESCE(SUM(CASE WHEN transaction_type = 'BUY' THEN quantity ELSE -quantity END), 0) as held
+         FROM portfolio_transactions
+         WHERE portfolio_id = $1 AND ticker = $2 AND transaction_type IN ('BUY', 'SELL') AND voided_at IS NULL",
+        &[&portfolio_id, &ticker],
+    ).await.map_err(|e| format!("Error al consultar la posición: {}", e))?;
+    Ok(row.get("held"))
+}
+
+/// Títulos de `ticker` comprados antes de `before`; con la fecha ex-derecho da los títulos con derecho a dividendo.
+pub async fn held_quantity_before<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    ticker: &str,
+    before: DateTime<Utc>,
+) -> Result<Decimal, String> {
+    let row = client.query_one(
+        "SELECT COALESCE(SUM(CASE WHEN transaction_type = 'BUY' THEN quantity ELSE -quantity END), 0) as held
+         FROM portfolio_transactions
+         WHERE portfolio_id = $1 AND ticker = $2 AND transaction_type IN ('BUY', 'SELL') AND transaction_date < $3 AND voided_at IS NULL",
+        &[&portfolio_id, &ticker, &before],
+    ).await.map_err(|e| format!("Error al consultar la posición: {}", e))?;
+    Ok(row.get("held"))
+}
+
+/// Registra una compra o venta junto con su efecto en efectivo, en una sola transacción de BD.
+pub async fn record_trade(
+    client: &mut deadpool_postgres::Client,
+    entry: NewLedgerEntry,
+    source: CashSource,
+) -> Result<LedgerEntry, String> {
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let recorded = record_trade_in(&*tx, entry, source).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(recorded)
+}
+
+/// Como `record_trade`, dentro de una transacción de BD abierta por quien llama.
+pub async fn record_trade_in<C: GenericClient>(
+    client: &C,
+    mut entry: NewLedgerEntry,
+    source: CashSource,
+) -> Result<LedgerEntry, String> {
+    if !entry.transaction_type.is_trade() {
+        return Err(format!("{} no es una compra o venta", entry.transaction_type));
+    }
+    entry.currency = fx::normalize_currency(&entry.currency)?;
+    check_trade_currency(client, &entry).await?;
+    if entry.fees.is_none() {
+        let schedule = fees::fee_schedule(client, entry.portfolio_id).await?;
+        entry.fees = Some(schedule.fees_for(entry.gross_amount()));
+    }
+    let total_amount = entry.total_amount();
+    let mut cash_legs = Vec::new();
+    let mut funding = NewLedgerEntry::cash(entry.portfolio_id, TransactionKind::Deposit, total_amount);
+    funding.currency = entry.currency.clone();
+    funding.fx_rate = entry.fx_rate;
+    funding.transaction_date = entry.transaction_date;
+
+    match entry.transaction_type {
+        TransactionKind::Buy => {
+            match source {
+                CashSource::Portfolio => {
+                    cash::check_funds(client, entry.portfolio_id, &entry.currency, total_amount, "la compra").await?;
+                }
+                CashSource::External => {
+                    funding.notes = Some(format!("Fondeo externo para compra de {}", entry.ticker));
+                    cash_legs.push(insert_entry(client, &funding).await?);
+                }
+            }
+        }
+        TransactionKind::Sell => {
+            let held = held_quantity(client, entry.portfolio_id, &entry.ticker).await?;
+            if held < entry.quantity {
+                return Err(format!("No hay suficientes títulos de {} para vender (disponibles: {})", entry.ticker, held));
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    let recorded = insert_entry(client, &entry).await?;
+
+    if entry.transaction_type == TransactionKind::Sell && source == CashSource::External {
+        funding.transaction_type = TransactionKind::Withdrawal;
+        funding.notes = Some(format!("Retiro de lo obtenido por la venta de {}", entry.ticker));
+        cash_legs.push(insert_entry(client, &funding).await?);
+    }
+    for leg in &cash_legs {
+        client.execute(
+            "UPDATE portfolio_transactions SET linked_transaction_id = $1 WHERE transaction_id = $2",
+            &[&recorded.transaction_id, &leg.transaction_id],
+        ).await.map_err(|e| format!("Error al enlazar el movimiento de efectivo: {}", e))?;
+    }
+
+    Ok(recorded)
+}
+
+/// Registra un depósito, retiro, dividendo o interés. Los retiros no pueden exceder el saldo
+/// más el sobregiro permitido.
+pub async fn record_cash_movement<C: GenericClient>(client: &C, entry: NewLedgerEntry) -> Result<LedgerEntry, String> {
+    match entry.transaction_type {
+        TransactionKind::Deposit | TransactionKind::Dividend | TransactionKind::Interest => {}
+        TransactionKind::Withdrawal => {
+            let currency = fx::normalize_currency(&entry.currency)?;
+            cash::check_funds(client, entry.portfolio_id, &currency, entry.total_amount(), "el retiro").await?;
+        }
+        other => return Err(format!("{} no es un movimiento de efectivo", other)),
+    }
+    insert_entry(client, &entry).await
+}
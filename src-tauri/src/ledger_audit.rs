@@ -0,0 +1,431 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use deadpool_postgres::Pool;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tokio_postgres::{GenericClient, Row};
+
+use crate::cash;
+use crate::fees;
+use crate::ledger::{self, LedgerEntry, NewLedgerEntry, TradeFees, TransactionKind};
+use crate::lots;
+use crate::money;
+use crate::snapshots;
+
+const AUDIT_COLUMNS: &str = "audit_id, transaction_id, portfolio_id, action, changed_by, changed_at, reason, before_state::text AS before_state, after_state::text AS after_state";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AuditAction {
+    Update,
+    Void,
+    // Regresa el movimiento al estado previo al último cambio
+    Undo,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Update => "UPDATE",
+            AuditAction::Void => "VOID",
+            AuditAction::Undo => "UNDO",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "UPDATE" | "CORRECCION" => Ok(AuditAction::Update),
+            "VOID" | "ANULACION" => Ok(AuditAction::Void),
+            "UNDO" | "DESHACER" => Ok(AuditAction::Undo),
+            _ => Err(format!("Acción de bitácora desconocida: {}", s)),
+        }
+    }
+}
+
+/// Un renglón de la bitácora. `before` y `after` son el movimiento completo tal como
+/// estaba en portfolio_transactions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub audit_id: i32,
+    pub transaction_id: i32,
+    pub portfolio_id: i32,
+    pub action: AuditAction,
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+impl AuditRecord {
+    fn from_row(row: &Row) -> Result<Self, String> {
+        let action: String = row.get("action");
+        let state = |column: &str| {
+            serde_json::from_str(row.get::<_, &str>(column))
+                .map_err(|e| format!("Estado inválido en la bitácora: {}", e))
+        };
+        Ok(AuditRecord {
+            audit_id: row.get("audit_id"),
+            transaction_id: row.get("transaction_id"),
+            portfolio_id: row.get("portfolio_id"),
+            action: action.parse()?,
+            changed_by: row.get("changed_by"),
+            changed_at: row.get("changed_at"),
+            reason: row.get("reason"),
+            before: state("before_state")?,
+            after: state("after_state")?,
+        })
+    }
+}
+
+/// Corrección de un movimiento; lo que no se indica se conserva. Si cambia la cantidad o el
+/// precio de una compra o venta sin indicar comisión, se recalcula con el esquema del
+/// portafolio. El tipo de cambio se conserva aunque cambie la fecha.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntryUpdate {
+    #[serde(default)]
+    pub quantity: Option<Decimal>,
+    #[serde(default)]
+    pub price: Option<Decimal>,
+    #[serde(default)]
+    pub transaction_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub commission: Option<Decimal>,
+    #[serde(default)]
+    pub commission_iva: Option<Decimal>,
+    #[serde(default)]
+    pub fx_rate: Option<Decimal>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+// Sin usuario explícito se registra al dueño del portafolio
+async fn author<C: GenericClient>(client: &C, portfolio_id: i32, changed_by: Option<String>) -> Result<String, String> {
+    if let Some(name) = changed_by.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()) {
+        return Ok(name);
+    }
+    let row = client.query_opt(
+        "SELECT u.nombre FROM portafolios p JOIN usuarios u ON u.id = p.usuario_id WHERE p.id = $1",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar el dueño del portafolio: {}", e))?;
+    row.map(|r| r.get("nombre"))
+        .ok_or_else(|| format!("El portafolio {} no existe o no tiene usuario asignado", portfolio_id))
+}
+
+async fn entry_state<C: GenericClient>(client: &C, transaction_id: i32) -> Result<String, String> {
+    let row = client.query_one(
+        "SELECT to_jsonb(t)::text AS state FROM portfolio_transactions t WHERE t.transaction_id = $1",
+        &[&transaction_id],
+    ).await.map_err(|e| format!("Error al consultar la transacción {}: {}", transaction_id, e))?;
+    Ok(row.get("state"))
+}
+
+// El estado posterior se toma del renglón ya modificado
+async fn append_audit<C: GenericClient>(
+    client: &C,
+    entry: &LedgerEntry,
+    action: AuditAction,
+    changed_by: &str,
+    reason: Option<&str>,
+    before_state: &str,
+) -> Result<AuditRecord, String> {
+    let row = client.query_one(
+        &format!(
+            "INSERT INTO ledger_audit (transaction_id, portfolio_id, action, changed_by, reason, before_state, after_state)
+             SELECT t.transaction_id, t.portfolio_id, $2, $3, $4, $5::text::jsonb, to_jsonb(t)
+             FROM portfolio_transactions t WHERE t.transaction_id = $1
+             RETURNING {}",
+            AUDIT_COLUMNS
+        ),
+        &[&entry.transaction_id, &action.as_str(), &changed_by, &reason, &before_state],
+    ).await.map_err(|e| format!("No se pudo registrar el cambio en la bitácora: {}", e))?;
+    AuditRecord::from_row(&row)
+}
+
+async fn apply_change<C: GenericClient>(
+    client: &C,
+    changed: &LedgerEntry,
+    action: AuditAction,
+    changed_by: &str,
+    reason: Option<&str>,
+) -> Result<LedgerEntry, String> {
+    let before_state = entry_state(client, changed.transaction_id).await?;
+    let written = ledger::write_entry(client, changed).await?;
+    append_audit(client, &written, action, changed_by, reason, &before_state).await?;
+    Ok(written)
+}
+
+// Un movimiento corregible: existe, no está anulado y no es la pata de efectivo de otra operación
+async fn editable_entry<C: GenericClient>(client: &C, transaction_id: i32) -> Result<LedgerEntry, String> {
+    let entry = ledger::entry_by_id(client, transaction_id).await?
+        .ok_or_else(|| format!("No se encontró la transacción {}", transaction_id))?;
+    if entry.voided_at.is_some() {
+        return Err(format!("La transacción {} está anulada; deshaga la anulación para corregirla", transaction_id));
+    }
+    if let Some(trade_id) = entry.linked_transaction_id {
+        return Err(format!(
+            "La transacción {} es el efectivo de la operación {}; corrija o anule esa operación",
+            transaction_id, trade_id
+        ));
+    }
+    Ok(entry)
+}
+
+// Dividendos con detalle fiscal y cupones de deuda llevan su fecha e importe también en
+// dividend_details o fixed_income_flows.
+async fn has_detail_row<C: GenericClient>(client: &C, transaction_id: i32) -> Result<bool, String> {
+    let row = client.query_one(
+        "SELECT EXISTS (SELECT 1 FROM dividend_details WHERE transaction_id = $1)
+             OR EXISTS (SELECT 1 FROM fixed_income_flows WHERE transaction_id = $1) AS has_detail",
+        &[&transaction_id],
+    ).await.map_err(|e| format!("Error al consultar el detalle del movimiento: {}", e))?;
+    Ok(row.get("has_detail"))
+}
+
+// Tras el cambio ninguna venta puede exceder los títulos disponibles, ni el efectivo de una
+// moneda quedar por debajo del sobregiro permitido (o más abajo de lo que ya estaba).
+async fn check_ledger<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    cash_before: &[(String, Decimal)],
+) -> Result<(), String> {
+    lots::load_lot_book(client, portfolio_id).await?;
+    for (currency, balance) in ledger::cash_balances(client, portfolio_id).await? {
+        let before = cash_before.iter().find(|(c, _)| *c == currency).map(|(_, b)| *b).unwrap_or_default();
+        let overdraft_limit = cash::account(client, portfolio_id, &currency).await?.overdraft_limit;
+        if balance < -overdraft_limit && balance < before {
+            return Err(format!("El cambio deja el efectivo en {} en {}", currency, balance));
+        }
+    }
+    Ok(())
+}
+
+/// Corrige un movimiento y sus patas de efectivo. Las patas siguen la fecha y el tipo de
+/// cambio de la operación; la que la fondeaba completa (fondeo externo o retiro de lo
+/// obtenido) sigue también su importe. Los dividendos y cupones con detalle no cambian de
+/// fecha ni de importe: se anulan y se registran de nuevo.
+pub async fn update_entry_logic(
+    transaction_id: i32,
+    update: EntryUpdate,
+    changed_by: Option<String>,
+    reason: Option<String>,
+    db_pool: &Pool,
+) -> Result<LedgerEntry, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let entry = editable_entry(&*tx, transaction_id).await?;
+    let kind = entry.transaction_type;
+    let changes_amount = update.quantity.is_some() || update.price.is_some();
+    let changes_fees = update.commission.is_some() || update.commission_iva.is_some();
+    if kind == TransactionKind::Dividend && (changes_amount || changes_fees) {
+        return Err("El importe de un dividendo no se corrige; anúlelo y regístrelo de nuevo".to_string());
+    }
+    let changes_date = update.transaction_date.is_some_and(|date| date != entry.transaction_date.date_naive());
+    if matches!(kind, TransactionKind::Dividend | TransactionKind::Interest)
+        && (changes_date || changes_amount)
+        && has_detail_row(&*tx, transaction_id).await?
+    {
+        return Err("La fecha o el importe de un dividendo o cupón no se corrigen; anúlelo y regístrelo de nuevo".to_string());
+    }
+    if !kind.is_trade() && update.quantity.is_some() {
+        return Err("Los movimientos de efectivo se corrigen por su importe (price)".to_string());
+    }
+
+    let mut corrected = NewLedgerEntry::trade(
+        entry.portfolio_id,
+        &entry.ticker,
+        kind,
+        update.quantity.unwrap_or(entry.quantity),
+        update.price.unwrap_or(entry.price),
+    );
+    corrected.currency = entry.currency.clone();
+    corrected.fx_rate = Some(update.fx_rate.unwrap_or(entry.fx_rate));
+    let current_fees = TradeFees { commission: entry.commission, commission_iva: entry.commission_iva };
+    corrected.fees = Some(if changes_fees {
+        TradeFees {
+            commission: money::round_mxn(update.commission.unwrap_or(current_fees.commission)),
+            commission_iva: money::round_mxn(update.commission_iva.unwrap_or(current_fees.commission_iva)),
+        }
+    } else if changes_amount && kind.is_trade() {
+        fees::fee_schedule(&*tx, entry.portfolio_id).await?.fees_for(corrected.gross_amount())
+    } else {
+        current_fees
+    });
+    corrected.validate()?;
+
+    let author = author(&*tx, entry.portfolio_id, changed_by).await?;
+    let cash_before = ledger::cash_balances(&*tx, entry.portfolio_id).await?;
+    let fees = corrected.fees.unwrap_or_default();
+    let changed = LedgerEntry {
+        quantity: corrected.quantity,
+        price: corrected.price,
+        transaction_date: match update.transaction_date {
+            Some(date) => date.and_time(entry.transaction_date.time()).and_utc(),
+            None => entry.transaction_date,
+        },
+        total_amount: corrected.total_amount(),
+        commission: fees.commission,
+        commission_iva: fees.commission_iva,
+        fx_rate: money::round_price(corrected.fx_rate.unwrap_or(entry.fx_rate)),
+        notes: update.notes.or_else(|| entry.notes.clone()),
+        ..entry.clone()
+    };
+    let written = apply_change(&*tx, &changed, AuditAction::Update, &author, reason.as_deref()).await?;
+
+    for leg in ledger::linked_entries(&*tx, transaction_id).await? {
+        if leg.voided_at.is_some() {
+            continue;
+        }
+        let amount = if leg.total_amount == entry.total_amount { written.total_amount } else { leg.total_amount };
+        let leg_changed = LedgerEntry {
+            price: amount,
+            total_amount: amount,
+            transaction_date: written.transaction_date,
+            fx_rate: written.fx_rate,
+            ..leg
+        };
+        apply_change(&*tx, &leg_changed, AuditAction::Update, &author, reason.as_deref()).await?;
+    }
+
+    check_ledger(&*tx, entry.portfolio_id, &cash_before).await?;
+    let from = entry.transaction_date.min(written.transaction_date).date_naive();
+    snapshots::invalidate_from(&*tx, entry.portfolio_id, from).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(written)
+}
+
+/// Anula un movimiento junto con sus patas de efectivo. El renglón se conserva y la
+/// anulación se puede deshacer.
+pub async fn void_entry(
+    client: &mut deadpool_postgres::Client,
+    transaction_id: i32,
+    changed_by: Option<String>,
+    reason: Option<String>,
+) -> Result<LedgerEntry, String> {
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let entry = editable_entry(&*tx, transaction_id).await?;
+    let author = author(&*tx, entry.portfolio_id, changed_by).await?;
+    let cash_before = ledger::cash_balances(&*tx, entry.portfolio_id).await?;
+    let voided_at = Some(Utc::now());
+
+    let voided = apply_change(&*tx, &LedgerEntry { voided_at, ..entry.clone() }, AuditAction::Void, &author, reason.as_deref()).await?;
+    for leg in ledger::linked_entries(&*tx, transaction_id).await? {
+        if leg.voided_at.is_none() {
+            apply_change(&*tx, &LedgerEntry { voided_at, ..leg }, AuditAction::Void, &author, reason.as_deref()).await?;
+        }
+    }
+
+    check_ledger(&*tx, entry.portfolio_id, &cash_before).await?;
+    snapshots::invalidate_from(&*tx, entry.portfolio_id, entry.transaction_date.date_naive()).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(voided)
+}
+
+pub async fn void_entry_logic(
+    transaction_id: i32,
+    changed_by: Option<String>,
+    reason: Option<String>,
+    db_pool: &Pool,
+) -> Result<LedgerEntry, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    void_entry(&mut client, transaction_id, changed_by, reason).await
+}
+
+// Regresa el movimiento al estado previo de un renglón de la bitácora y registra el deshacer
+async fn restore<C: GenericClient>(client: &C, audit: &AuditRecord, changed_by: &str) -> Result<LedgerEntry, String> {
+    let before_state = entry_state(client, audit.transaction_id).await?;
+    client.execute(
+        "UPDATE portfolio_transactions t
+         SET quantity = (a.before_state->>'quantity')::numeric,
+             price = (a.before_state->>'price')::numeric,
+             transaction_date = (a.before_state->>'transaction_date')::timestamptz,
+             total_amount = (a.before_state->>'total_amount')::numeric,
+             commission = (a.before_state->>'commission')::numeric,
+             commission_iva = (a.before_state->>'commission_iva')::numeric,
+             fx_rate = (a.before_state->>'fx_rate')::numeric,
+             notes = a.before_state->>'notes',
+             voided_at = (a.before_state->>'voided_at')::timestamptz,
+             updated_at = now()
+         FROM ledger_audit a
+         WHERE a.audit_id = $1 AND t.transaction_id = a.transaction_id",
+        &[&audit.audit_id],
+    ).await.map_err(|e| format!("No se pudo restaurar la transacción {}: {}", audit.transaction_id, e))?;
+    let restored = ledger::entry_by_id(client, audit.transaction_id).await?
+        .ok_or_else(|| format!("No se encontró la transacción {}", audit.transaction_id))?;
+    append_audit(client, &restored, AuditAction::Undo, changed_by, Some(&format!("Deshace el cambio {}", audit.audit_id)), &before_state).await?;
+    Ok(restored)
+}
+
+/// Deshace el último cambio de un movimiento (corrección, anulación o un deshacer previo)
+/// y los que se hicieron a sus patas de efectivo en la misma operación.
+pub async fn undo_last_change_logic(
+    transaction_id: i32,
+    changed_by: Option<String>,
+    db_pool: &Pool,
+) -> Result<LedgerEntry, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let entry = ledger::entry_by_id(&*tx, transaction_id).await?
+        .ok_or_else(|| format!("No se encontró la transacción {}", transaction_id))?;
+    if let Some(trade_id) = entry.linked_transaction_id {
+        return Err(format!(
+            "La transacción {} es el efectivo de la operación {}; deshaga el cambio de esa operación",
+            transaction_id, trade_id
+        ));
+    }
+    let row = tx.query_opt(
+        &format!("SELECT {} FROM ledger_audit WHERE transaction_id = $1 ORDER BY audit_id DESC LIMIT 1", AUDIT_COLUMNS),
+        &[&transaction_id],
+    ).await.map_err(|e| format!("Error al consultar la bitácora: {}", e))?
+        .ok_or_else(|| format!("La transacción {} no tiene cambios que deshacer", transaction_id))?;
+    let last = AuditRecord::from_row(&row)?;
+    let author = author(&*tx, entry.portfolio_id, changed_by).await?;
+    let cash_before = ledger::cash_balances(&*tx, entry.portfolio_id).await?;
+
+    let restored = restore(&*tx, &last, &author).await?;
+    // Los cambios de una misma operación de BD comparten changed_at
+    let legs = tx.query(
+        &format!(
+            "SELECT DISTINCT ON (transaction_id) {} FROM ledger_audit
+             WHERE transaction_id IN (SELECT transaction_id FROM portfolio_transactions WHERE linked_transaction_id = $1)
+             ORDER BY transaction_id, audit_id DESC",
+            AUDIT_COLUMNS
+        ),
+        &[&transaction_id],
+    ).await.map_err(|e| format!("Error al consultar la bitácora: {}", e))?;
+    for row in &legs {
+        let leg = AuditRecord::from_row(row)?;
+        if leg.changed_at == last.changed_at {
+            restore(&*tx, &leg, &author).await?;
+        }
+    }
+
+    check_ledger(&*tx, entry.portfolio_id, &cash_before).await?;
+    let from = entry.transaction_date.min(restored.transaction_date).date_naive();
+    snapshots::invalidate_from(&*tx, entry.portfolio_id, from).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(restored)
+}
+
+/// Bitácora del portafolio, o de un movimiento, del cambio más reciente al más antiguo.
+pub async fn list_ledger_audit_logic(
+    portfolio_id: i32,
+    transaction_id: Option<i32>,
+    db_pool: &Pool,
+) -> Result<Vec<AuditRecord>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!(
+            "SELECT {} FROM ledger_audit
+             WHERE portfolio_id = $1 AND ($2::integer IS NULL OR transaction_id = $2)
+             ORDER BY audit_id DESC",
+            AUDIT_COLUMNS
+        ),
+        &[&portfolio_id, &transaction_id],
+    ).await.map_err(|e| format!("Error al consultar la bitácora: {}", e))?;
+    rows.iter().map(AuditRecord::from_row).collect()
+}
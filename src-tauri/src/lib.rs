@@ -2,16 +2,46 @@
 
 use deadpool_postgres::Runtime;
 use dotenv::dotenv;
+use rust_decimal::Decimal;
 use std::env;
 use std::sync::Arc;
 use tauri::State;
 
+mod allocation;
 mod asset_services;
 mod assets;
+mod backtest;
+mod benchmarks;
+mod cash;
+mod contributions;
+mod dividends;
+mod export;
+mod fees;
+mod fixed_income;
+mod fx;
+mod importer;
+mod ledger;
+mod ledger_audit;
+mod lots;
+mod money;
+mod montecarlo;
+mod optimizer;
+mod orders;
+mod performance;
+mod pnl;
+mod portfolio;
+mod portfolio_services;
+mod prices;
+mod risk;
+mod snapshots;
+mod stress;
 mod user_management;
 mod ticker_search;
+mod valuation;
+mod yield_curve;
 mod ticker_tape;
 mod heapmap;
+mod isr;
 pub mod data_bursatil_client;
 
 // State para compartir la conexión de base de datos
@@ -70,7 +100,7 @@ async fn run_demo_operations(state: State<'_, AppState>) -> Result<String, Strin
         portfolio_id,
         "AMXB", // Using a Mexican ticker that should exist
         10,
-        16.50,
+        Decimal::new(1650, 2),
         "BUY",
         db_pool,
     )
@@ -85,7 +115,7 @@ async fn run_demo_operations(state: State<'_, AppState>) -> Result<String, Strin
         portfolio_id,
         "WALMEX*", // Using another Mexican ticker that should exist
         5,
-        60.0,
+        Decimal::new(60, 0),
         "SELL",
         db_pool,
     )
@@ -189,26 +219,34 @@ async fn debug_data_dates(
     }
 }
 
+// Montos en la moneda base elegida (MXN por omisión); precios en la moneda del ticker
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct HoldingInfo {
     pub ticker: String,
-    pub total_shares: i32,
-    pub average_price: f64,
-    pub current_price: Option<f64>,
-    pub market_value: Option<f64>,
-    pub total_cost: f64,
-    pub unrealized_pnl: Option<f64>,
-    pub unrealized_pnl_percent: Option<f64>,
+    pub currency: String,
+    pub total_shares: Decimal,
+    pub average_price: Decimal,
+    pub current_price: Option<Decimal>,
+    pub market_value: Option<Decimal>,
+    pub total_cost: Decimal,
+    pub unrealized_pnl: Option<Decimal>,
+    pub unrealized_pnl_percent: Option<Decimal>,
+    pub price_pnl: Option<Decimal>,
+    pub fx_pnl: Decimal,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct PortfolioStats {
-    pub total_invested: f64,
-    pub current_value: f64,
-    pub total_pnl: f64,
-    pub total_pnl_percent: f64,
-    pub cash_balance: f64,
-    pub portfolio_value: f64,
+    pub base_currency: String,
+    pub total_invested: Decimal,
+    pub current_value: Decimal,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    pub fx_pnl: Decimal,
+    pub total_pnl: Decimal,
+    pub total_pnl_percent: Decimal,
+    pub cash_balance: Decimal,
+    pub portfolio_value: Decimal,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -216,60 +254,40 @@ pub struct TransactionInfo {
     pub transaction_id: i32,
     pub ticker: String,
     pub transaction_type: String,
-    pub quantity: i32,
-    pub price: f64,
-    pub total_value: f64,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub total_value: Decimal,
+    pub fees: Decimal,
+    pub currency: String,
     pub date: String,
 }
 
 #[tauri::command]
 async fn get_portfolio_holdings(
     portfolio_id: i32,
+    base_currency: Option<String>,
     state: State<'_, AppState>
 ) -> Result<Vec<HoldingInfo>, String> {
     let db_pool = &state.db_pool;
     let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let base_currency = base_currency.unwrap_or_else(|| fx::MXN.to_string());
     
-    let query = "
-        SELECT 
-            ticker,
-            SUM(CASE WHEN transaction_type = 'BUY' THEN quantity ELSE -quantity END) as total_shares,
-            SUM(CASE WHEN transaction_type = 'BUY' THEN quantity * price ELSE -quantity * price END) as total_cost
-        FROM portfolio_transactions 
-        WHERE portfolio_id = $1 
-        GROUP BY ticker
-        HAVING SUM(CASE WHEN transaction_type = 'BUY' THEN quantity ELSE -quantity END) > 0
-    ";
-    
-    let rows = client.query(query, &[&portfolio_id]).await.map_err(|e| e.to_string())?;
-    let mut holdings = Vec::new();
-    
-    for row in rows {
-        let ticker: String = row.get(0);
-        let total_shares: f64 = row.get(1);
-        let total_cost: f64 = row.get(2);
-        let average_price = if total_shares > 0.0 { total_cost / total_shares } else { 0.0 };
-        
-        let current_price = match data_bursatil_client::get_cotizaciones_async(&ticker).await {
-            Ok(Some(cotizacion)) => cotizacion.ultimo_precio,
-            _ => None,
-        };
-        
-        let market_value = current_price.map(|price| price * total_shares);
-        let unrealized_pnl = market_value.map(|mv| mv - total_cost);
-        let unrealized_pnl_percent = unrealized_pnl.map(|pnl| if total_cost > 0.0 { (pnl / total_cost) * 100.0 } else { 0.0 });
-        
-        holdings.push(HoldingInfo {
-            ticker,
-            total_shares: total_shares as i32,
-            average_price,
-            current_price,
-            market_value,
-            total_cost,
-            unrealized_pnl,
-            unrealized_pnl_percent,
-        });
-    }
+    let valuations = valuation::value_holdings(&**client, portfolio_id, &base_currency).await?;
+    let holdings = valuations.into_iter()
+        .map(|v| HoldingInfo {
+            unrealized_pnl_percent: v.unrealized_pnl_base.map(|pnl| money::percent(pnl, v.cost_base)),
+            ticker: v.ticker,
+            currency: v.currency,
+            total_shares: v.quantity,
+            average_price: v.average_cost,
+            current_price: v.current_price,
+            market_value: v.market_value_base,
+            total_cost: v.cost_base,
+            unrealized_pnl: v.unrealized_pnl_base,
+            price_pnl: v.price_gain_base,
+            fx_pnl: v.fx_gain_base,
+        })
+        .collect();
     
     Ok(holdings)
 }
@@ -277,23 +295,42 @@ async fn get_portfolio_holdings(
 #[tauri::command]
 async fn get_portfolio_stats(
     portfolio_id: i32,
+    base_currency: Option<String>,
     state: State<'_, AppState>
 ) -> Result<PortfolioStats, String> {
-    let holdings = get_portfolio_holdings(portfolio_id, state).await?;
+    let base_currency = fx::normalize_currency(base_currency.as_deref().unwrap_or(fx::MXN))?;
+    let holdings = get_portfolio_holdings(portfolio_id, Some(base_currency.clone()), state.clone()).await?;
     
-    let total_invested: f64 = holdings.iter().map(|h| h.total_cost).sum();
-    let current_value: f64 = holdings.iter()
+    let total_invested: Decimal = holdings.iter().map(|h| h.total_cost).sum();
+    let current_value: Decimal = holdings.iter()
         .filter_map(|h| h.market_value)
         .sum();
-    let total_pnl = current_value - total_invested;
-    let total_pnl_percent = if total_invested > 0.0 { (total_pnl / total_invested) * 100.0 } else { 0.0 };
+    let unrealized_pnl = current_value - total_invested;
+    let fx_pnl: Decimal = holdings.iter().map(|h| h.fx_pnl).sum();
     
-    let cash_balance = 0.0;
+    let client = state.db_pool.get().await.map_err(|e| e.to_string())?;
+    let today = chrono::Utc::now().date_naive();
+    let mxn_to_base = fx::conversion_rate(&**client, fx::MXN, &base_currency, today).await?;
+    // Lo realizado se mide contra el costo de lo vendido; lo no realizado contra lo que sigue invertido
+    let closed_lots = lots::load_lot_book(&**client, portfolio_id).await?.closed_lots;
+    let realized_pnl = money::round_mxn(closed_lots.iter().map(|l| l.realized_gain_mxn()).sum::<Decimal>() * mxn_to_base);
+    let realized_cost = money::round_mxn(closed_lots.iter().map(|l| l.cost_basis_mxn()).sum::<Decimal>() * mxn_to_base);
+    let total_pnl = realized_pnl + unrealized_pnl;
+    let total_pnl_percent = money::percent(total_pnl, total_invested + realized_cost);
+    
+    let cash_balance: Decimal = valuation::value_cash(&**client, portfolio_id, &base_currency).await?
+        .iter()
+        .map(|c| c.amount_base)
+        .sum();
     let portfolio_value = current_value + cash_balance;
     
     Ok(PortfolioStats {
+        base_currency,
         total_invested,
         current_value,
+        realized_pnl,
+        unrealized_pnl,
+        fx_pnl,
         total_pnl,
         total_pnl_percent,
         cash_balance,
@@ -313,10 +350,12 @@ async fn get_portfolio_transactions(
         result.push(TransactionInfo {
             transaction_id: transaction.transaction_id,
             ticker: transaction.ticker,
-            transaction_type: transaction.transaction_type,
-            quantity: transaction.quantity as i32,
+            transaction_type: transaction.transaction_type.to_string(),
+            quantity: transaction.quantity,
             price: transaction.price,
             total_value: transaction.total_amount,
+            fees: transaction.commission + transaction.commission_iva,
+            currency: transaction.currency,
             date: transaction.transaction_date.format("%Y-%m-%d %H:%M:%S").to_string(),
         });
     }
@@ -389,6 +428,15 @@ pub fn run() {
     
     let db_pool = Arc::new(pool);
 
+    // Ejecución de órdenes de los portafolios de práctica
+    tauri::async_runtime::spawn(orders::run_order_matcher(db_pool.clone()));
+    // Aportaciones programadas, incluidas las que se perdieron con la aplicación cerrada
+    tauri::async_runtime::spawn(contributions::run_contribution_scheduler(db_pool.clone()));
+    // Cupones y vencimientos de CETES y bonos
+    tauri::async_runtime::spawn(fixed_income::run_fixed_income_scheduler(db_pool.clone()));
+    // Intereses mensuales sobre el efectivo ocioso
+    tauri::async_runtime::spawn(cash::run_interest_scheduler(db_pool.clone()));
+
     tauri::Builder::default()
         .manage(AppState { db_pool })
         .plugin(tauri_plugin_opener::init())
@@ -403,6 +451,79 @@ pub fn run() {
             search_tickers,
             get_cotizaciones,
             get_heatmap_data,
+            portfolio_services::get_portfolio_summary,
+            portfolio_services::add_cash_movement,
+            portfolio_services::add_asset_transaction,
+            portfolio_services::delete_transaction,
+            portfolio_services::update_transaction,
+            portfolio_services::void_transaction,
+            portfolio_services::undo_transaction_change,
+            portfolio_services::list_ledger_audit,
+            portfolio_services::get_tax_lots,
+            portfolio_services::set_cost_basis_method,
+            portfolio_services::assign_sale_lots,
+            portfolio_services::get_realized_pnl,
+            portfolio_services::get_closed_positions,
+            portfolio_services::get_isr_annual_report,
+            portfolio_services::export_isr_annual_report,
+            portfolio_services::save_inpc_values,
+            portfolio_services::record_dividend,
+            portfolio_services::list_dividends,
+            portfolio_services::suggest_dividends,
+            portfolio_services::get_dividend_income,
+            portfolio_services::get_dividend_yield_on_cost,
+            portfolio_services::get_fee_schedule,
+            portfolio_services::set_fee_schedule,
+            portfolio_services::get_fee_report,
+            portfolio_services::save_fx_rates,
+            portfolio_services::refresh_fx_rates,
+            portfolio_services::list_fx_rates,
+            portfolio_services::get_portfolio_performance,
+            portfolio_services::get_portfolio_history,
+            portfolio_services::compare_to_benchmark,
+            portfolio_services::save_benchmark_levels,
+            portfolio_services::refresh_benchmark_levels,
+            portfolio_services::get_allocation,
+            portfolio_services::set_allocation_targets,
+            portfolio_services::set_ticker_classification,
+            portfolio_services::get_rebalance_plan,
+            portfolio_services::get_portfolio_risk,
+            portfolio_services::run_monte_carlo,
+            portfolio_services::optimize_portfolio,
+            portfolio_services::apply_frontier_point,
+            portfolio_services::list_stress_scenarios,
+            portfolio_services::save_stress_scenario,
+            portfolio_services::delete_stress_scenario,
+            portfolio_services::run_stress_test,
+            portfolio_services::run_all_stress_tests,
+            portfolio_services::run_backtest,
+            portfolio_services::set_paper_portfolio,
+            portfolio_services::place_order,
+            portfolio_services::cancel_order,
+            portfolio_services::list_orders,
+            portfolio_services::process_open_orders,
+            portfolio_services::create_contribution_plan,
+            portfolio_services::list_contribution_plans,
+            portfolio_services::set_contribution_plan_status,
+            portfolio_services::delete_contribution_plan,
+            portfolio_services::list_pending_contributions,
+            portfolio_services::confirm_contribution,
+            portfolio_services::skip_contribution,
+            portfolio_services::run_contribution_scheduler,
+            portfolio_services::buy_fixed_income,
+            portfolio_services::list_fixed_income_instruments,
+            portfolio_services::set_fixed_income_valuation,
+            portfolio_services::get_fixed_income_positions,
+            portfolio_services::process_fixed_income,
+            portfolio_services::get_yield_curve,
+            portfolio_services::build_yield_curves,
+            portfolio_services::preview_import,
+            portfolio_services::import_transactions,
+            portfolio_services::export_portfolio_ledger,
+            portfolio_services::get_cash_accounts,
+            portfolio_services::set_cash_account,
+            portfolio_services::accrue_cash_interest,
+            portfolio_services::get_cash_statement,
             asset_services::get_asset_details,
             user_management::list_users_with_portfolios,
             user_management::create_user,
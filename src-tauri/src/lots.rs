@@ -0,0 +1,509 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use tokio_postgres::GenericClient;
+
+use crate::ledger::{self, LedgerEntry, TransactionKind};
+use crate::money;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CostBasisMethod {
+    Fifo,
+    Lifo,
+    Average,
+    // Lotes elegidos por venta en lot_selections; lo no elegido se consume FIFO
+    Specific,
+}
+
+impl CostBasisMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CostBasisMethod::Fifo => "FIFO",
+            CostBasisMethod::Lifo => "LIFO",
+            CostBasisMethod::Average => "AVERAGE",
+            CostBasisMethod::Specific => "SPECIFIC",
+        }
+    }
+}
+
+impl fmt::Display for CostBasisMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CostBasisMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "FIFO" | "PEPS" => Ok(CostBasisMethod::Fifo),
+            "LIFO" | "UEPS" => Ok(CostBasisMethod::Lifo),
+            "AVERAGE" | "PROMEDIO" => Ok(CostBasisMethod::Average),
+            "SPECIFIC" => Ok(CostBasisMethod::Specific),
+            _ => Err(format!("Método de costeo desconocido: {}", s)),
+        }
+    }
+}
+
+/// Lote abierto: lo que queda de una compra. `lot_id` es el transaction_id de la compra.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenLot {
+    pub lot_id: i32,
+    pub ticker: String,
+    pub acquired_at: DateTime<Utc>,
+    pub original_quantity: Decimal,
+    pub quantity: Decimal,
+    pub unit_cost: Decimal,
+    pub cost_basis: Decimal,
+    // Moneda del lote y pesos por unidad al comprarlo
+    pub currency: String,
+    pub fx_rate: Decimal,
+}
+
+/// Parte de un lote consumida por una venta.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosedLot {
+    pub lot_id: i32,
+    pub sell_transaction_id: i32,
+    pub ticker: String,
+    pub acquired_at: DateTime<Utc>,
+    pub disposed_at: DateTime<Utc>,
+    pub quantity: Decimal,
+    pub unit_cost: Decimal,
+    pub sale_price: Decimal,
+    pub cost_basis: Decimal,
+    pub proceeds: Decimal,
+    pub realized_gain: Decimal,
+    pub currency: String,
+    pub acquisition_fx_rate: Decimal,
+    pub disposal_fx_rate: Decimal,
+}
+
+impl ClosedLot {
+    pub fn cost_basis_mxn(&self) -> Decimal {
+        money::round_mxn(self.cost_basis * self.acquisition_fx_rate)
+    }
+
+    pub fn proceeds_mxn(&self) -> Decimal {
+        money::round_mxn(self.proceeds * self.disposal_fx_rate)
+    }
+
+    // En pesos incluye la ganancia o pérdida cambiaria de los lotes en otra moneda
+    pub fn realized_gain_mxn(&self) -> Decimal {
+        self.proceeds_mxn() - self.cost_basis_mxn()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LotSelection {
+    pub lot_id: i32,
+    pub quantity: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LotBook {
+    pub method: CostBasisMethod,
+    pub open_lots: Vec<OpenLot>,
+    pub closed_lots: Vec<ClosedLot>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Position {
+    pub quantity: Decimal,
+    pub total_cost: Decimal,
+    pub currency: String,
+}
+
+impl Position {
+    pub fn average_cost(&self) -> Decimal {
+        if self.quantity > Decimal::ZERO { money::round_price(self.total_cost / self.quantity) } else { Decimal::ZERO }
+    }
+}
+
+impl OpenLot {
+    fn refresh_unit_cost(&mut self) {
+        self.unit_cost = if self.quantity > Decimal::ZERO {
+            money::round_price(self.cost_basis / self.quantity)
+        } else {
+            Decimal::ZERO
+        };
+    }
+}
+
+impl LotBook {
+    /// Posiciones abiertas por ticker, sumando sus lotes.
+    pub fn positions(&self) -> HashMap<String, Position> {
+        let mut positions: HashMap<String, Position> = HashMap::new();
+        for lot in &self.open_lots {
+            let position = positions.entry(lot.ticker.clone()).or_default();
+            position.quantity += lot.quantity;
+            position.total_cost += lot.cost_basis;
+            position.currency = lot.currency.clone();
+        }
+        positions
+    }
+}
+
+// Con costo promedio todos los lotes del ticker comparten el mismo costo unitario.
+// El residuo del redondeo se asigna al último lote para que el total no cambie.
+fn pool_average_cost(lots: &mut [OpenLot]) {
+    let total_quantity: Decimal = lots.iter().map(|l| l.quantity).sum();
+    let total_cost: Decimal = lots.iter().map(|l| l.cost_basis).sum();
+    if total_quantity <= Decimal::ZERO {
+        return;
+    }
+    // El tipo de cambio también se promedia, ponderado por costo, para que el costo en pesos no cambie
+    let total_cost_mxn: Decimal = lots.iter().map(|l| l.cost_basis * l.fx_rate).sum();
+    let fx_rate = if total_cost > Decimal::ZERO { money::round_price(total_cost_mxn / total_cost) } else { Decimal::ONE };
+    let mut assigned = Decimal::ZERO;
+    let last = lots.len() - 1;
+    for (i, lot) in lots.iter_mut().enumerate() {
+        lot.cost_basis = if i == last {
+            total_cost - assigned
+        } else {
+            money::round_mxn(total_cost * lot.quantity / total_quantity)
+        };
+        assigned += lot.cost_basis;
+        lot.fx_rate = fx_rate;
+        lot.refresh_unit_cost();
+    }
+}
+
+// Orden en que una venta consume los lotes abiertos: (índice, máximo a tomar)
+fn consumption_order(
+    lots: &[OpenLot],
+    method: CostBasisMethod,
+    selected: Option<&Vec<LotSelection>>,
+) -> Vec<(usize, Option<Decimal>)> {
+    let mut order = Vec::new();
+    if let (CostBasisMethod::Specific, Some(selected)) = (method, selected) {
+        for selection in selected {
+            if let Some(idx) = lots.iter().position(|l| l.lot_id == selection.lot_id) {
+                order.push((idx, Some(selection.quantity)));
+            }
+        }
+    }
+    match method {
+        CostBasisMethod::Lifo => order.extend((0..lots.len()).rev().map(|i| (i, None))),
+        _ => order.extend((0..lots.len()).map(|i| (i, None))),
+    }
+    order
+}
+
+/// Reconstruye los lotes a partir del ledger. `entries` debe venir en orden cronológico.
+pub fn build_lot_book(
+    entries: &[LedgerEntry],
+    method: CostBasisMethod,
+    selections: &HashMap<i32, Vec<LotSelection>>,
+) -> Result<LotBook, String> {
+    let mut open: HashMap<String, Vec<OpenLot>> = HashMap::new();
+    let mut closed_lots = Vec::new();
+
+    for entry in entries.iter().filter(|e| e.transaction_type.is_trade()) {
+        let lots = open.entry(entry.ticker.clone()).or_default();
+        match entry.transaction_type {
+            TransactionKind::Buy => {
+                let mut lot = OpenLot {
+                    lot_id: entry.transaction_id,
+                    ticker: entry.ticker.clone(),
+                    acquired_at: entry.transaction_date,
+                    original_quantity: entry.quantity,
+                    quantity: entry.quantity,
+                    unit_cost: Decimal::ZERO,
+                    cost_basis: entry.total_amount,
+                    currency: entry.currency.clone(),
+                    fx_rate: entry.fx_rate,
+                };
+                lot.refresh_unit_cost();
+                lots.push(lot);
+            }
+            TransactionKind::Sell => {
+                if method == CostBasisMethod::Average {
+                    pool_average_cost(lots);
+                }
+                let mut remaining = entry.quantity;
+                let mut proceeds_left = entry.total_amount;
+                for (idx, limit) in consumption_order(lots, method, selections.get(&entry.transaction_id)) {
+                    if remaining <= Decimal::ZERO {
+                        break;
+                    }
+                    let lot = &mut lots[idx];
+                    let mut take = remaining.min(lot.quantity);
+                    if let Some(limit) = limit {
+                        take = take.min(limit);
+                    }
+                    if take <= Decimal::ZERO {
+                        continue;
+                    }
+                    let cost_basis = if take == lot.quantity {
+                        lot.cost_basis
+                    } else {
+                        money::round_mxn(lot.cost_basis * take / lot.quantity)
+                    };
+                    let proceeds = if take == remaining {
+                        proceeds_left
+                    } else {
+                        money::round_mxn(entry.total_amount * take / entry.quantity)
+                    };
+                    closed_lots.push(ClosedLot {
+                        lot_id: lot.lot_id,
+                        sell_transaction_id: entry.transaction_id,
+                        ticker: entry.ticker.clone(),
+                        acquired_at: lot.acquired_at,
+                        disposed_at: entry.transaction_date,
+                        quantity: take,
+                        unit_cost: lot.unit_cost,
+                        sale_price: entry.price,
+                        cost_basis,
+                        proceeds,
+                        realized_gain: proceeds - cost_basis,
+                        currency: lot.currency.clone(),
+                        acquisition_fx_rate: lot.fx_rate,
+                        disposal_fx_rate: entry.fx_rate,
+                    });
+                    lot.quantity -= take;
+                    lot.cost_basis -= cost_basis;
+                    remaining -= take;
+                    proceeds_left -= proceeds;
+                }
+                if remaining > Decimal::ZERO {
+                    return Err(format!(
+                        "La venta {} de {} excede en {} los títulos disponibles",
+                        entry.transaction_id, entry.ticker, remaining
+                    ));
+                }
+                lots.retain(|l| l.quantity > Decimal::ZERO);
+            }
+            _ => {}
+        }
+    }
+
+    let mut open_lots: Vec<OpenLot> = open.into_values().flatten().collect();
+    open_lots.sort_by(|a, b| a.ticker.cmp(&b.ticker).then(a.acquired_at.cmp(&b.acquired_at)).then(a.lot_id.cmp(&b.lot_id)));
+
+    Ok(LotBook {
+        method,
+        open_lots,
+        closed_lots,
+    })
+}
+
+pub async fn cost_basis_method<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<CostBasisMethod, String> {
+    let row = client.query_opt(
+        "SELECT cost_basis_method FROM portafolios WHERE id = $1",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar el método de costeo: {}", e))?
+        .ok_or_else(|| format!("No se encontró el portafolio {}", portfolio_id))?;
+    let method: String = row.get("cost_basis_method");
+    method.parse()
+}
+
+async fn load_selections<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<HashMap<i32, Vec<LotSelection>>, String> {
+    let rows = client.query(
+        "SELECT ls.sell_transaction_id, ls.lot_id, ls.quantity
+         FROM lot_selections ls
+         JOIN portfolio_transactions t ON t.transaction_id = ls.sell_transaction_id
+         WHERE t.portfolio_id = $1 AND t.voided_at IS NULL
+         ORDER BY ls.sell_transaction_id, ls.lot_id",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar los lotes elegidos: {}", e))?;
+    let mut selections: HashMap<i32, Vec<LotSelection>> = HashMap::new();
+    for row in rows {
+        selections.entry(row.get("sell_transaction_id")).or_default().push(LotSelection {
+            lot_id: row.get("lot_id"),
+            quantity: row.get("quantity"),
+        });
+    }
+    Ok(selections)
+}
+
+/// Compras y ventas del portafolio junto con los lotes que se derivan de ellas.
+pub async fn load_trades_with_lots<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<(Vec<LedgerEntry>, LotBook), String> {
+    let method = cost_basis_method(client, portfolio_id).await?;
+    let selections = load_selections(client, portfolio_id).await?;
+    let entries = ledger::list_entries(client, portfolio_id, Some(&[TransactionKind::Buy, TransactionKind::Sell])).await?;
+    let book = build_lot_book(&entries, method, &selections)?;
+    Ok((entries, book))
+}
+
+pub async fn load_lot_book<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<LotBook, String> {
+    Ok(load_trades_with_lots(client, portfolio_id).await?.1)
+}
+
+pub async fn positions<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<HashMap<String, Position>, String> {
+    Ok(load_lot_book(client, portfolio_id).await?.positions())
+}
+
+pub async fn set_cost_basis_method_logic(
+    portfolio_id: i32,
+    method: &str,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<CostBasisMethod, String> {
+    let method: CostBasisMethod = method.parse()?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows_affected = client.execute(
+        "UPDATE portafolios SET cost_basis_method = $1, updated_at = now() WHERE id = $2",
+        &[&method.as_str(), &portfolio_id],
+    ).await.map_err(|e| format!("No se pudo actualizar el método de costeo: {}", e))?;
+    if rows_affected == 0 {
+        return Err(format!("No se encontró el portafolio {}", portfolio_id));
+    }
+    Ok(method)
+}
+
+/// Elige qué lotes consume una venta. Solo aplica a portafolios con método SPECIFIC.
+pub async fn assign_sale_lots_logic(
+    sell_transaction_id: i32,
+    selections: Vec<LotSelection>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<LotBook, String> {
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let tx = client.transaction().await.map_err(|e| e.to_string())?;
+
+    let sale = tx.query_opt(
+        "SELECT portfolio_id, ticker, transaction_type, quantity FROM portfolio_transactions WHERE transaction_id = $1 AND voided_at IS NULL",
+        &[&sell_transaction_id],
+    ).await.map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No se encontró la transacción {}", sell_transaction_id))?;
+    let portfolio_id: i32 = sale.get("portfolio_id");
+    let ticker: String = sale.get("ticker");
+    let transaction_type: String = sale.get("transaction_type");
+    let sale_quantity: Decimal = sale.get("quantity");
+    if transaction_type != TransactionKind::Sell.as_str() {
+        return Err(format!("La transacción {} no es una venta", sell_transaction_id));
+    }
+    if cost_basis_method(&*tx, portfolio_id).await? != CostBasisMethod::Specific {
+        return Err("El portafolio no usa identificación específica de lotes (SPECIFIC)".to_string());
+    }
+    let selected_total: Decimal = selections.iter().map(|s| s.quantity).sum();
+    if selected_total > sale_quantity {
+        return Err(format!("Se eligieron {} títulos pero la venta es de {}", selected_total, sale_quantity));
+    }
+
+    tx.execute("DELETE FROM lot_selections WHERE sell_transaction_id = $1", &[&sell_transaction_id])
+        .await.map_err(|e| e.to_string())?;
+    for selection in &selections {
+        let lot = tx.query_opt(
+            "SELECT 1 FROM portfolio_transactions WHERE transaction_id = $1 AND portfolio_id = $2 AND ticker = $3 AND transaction_type = 'BUY' AND voided_at IS NULL",
+            &[&selection.lot_id, &portfolio_id, &ticker],
+        ).await.map_err(|e| e.to_string())?;
+        if lot.is_none() {
+            return Err(format!("El lote {} no es una compra de {} en este portafolio", selection.lot_id, ticker));
+        }
+        tx.execute(
+            "INSERT INTO lot_selections (sell_transaction_id, lot_id, quantity) VALUES ($1, $2, $3)",
+            &[&sell_transaction_id, &selection.lot_id, &money::round_quantity(selection.quantity)],
+        ).await.map_err(|e| format!("No se pudo guardar el lote elegido: {}", e))?;
+    }
+
+    // Cada lote elegido debe tener títulos suficientes al momento de la venta
+    let book = load_lot_book(&*tx, portfolio_id).await?;
+    for selection in &selections {
+        let consumed: Decimal = book.closed_lots.iter()
+            .filter(|c| c.sell_transaction_id == sell_transaction_id && c.lot_id == selection.lot_id)
+            .map(|c| c.quantity)
+            .sum();
+        if consumed < selection.quantity {
+            return Err(format!(
+                "El lote {} solo tenía {} títulos disponibles al momento de la venta",
+                selection.lot_id, consumed
+            ));
+        }
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(book)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn d(value: i64) -> Decimal {
+        Decimal::from(value)
+    }
+
+    fn trade(transaction_id: i32, kind: TransactionKind, day: u32, quantity: Decimal, price: Decimal) -> LedgerEntry {
+        let date = Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap();
+        LedgerEntry {
+            transaction_id,
+            portfolio_id: 1,
+            user_id: 1,
+            ticker: "WALMEX".to_string(),
+            transaction_type: kind,
+            quantity,
+            price,
+            transaction_date: date,
+            total_amount: quantity * price,
+            commission: Decimal::ZERO,
+            commission_iva: Decimal::ZERO,
+            currency: "MXN".to_string(),
+            fx_rate: Decimal::ONE,
+            notes: None,
+            linked_transaction_id: None,
+            voided_at: None,
+            created_at: date,
+            updated_at: date,
+        }
+    }
+
+    fn ledger() -> Vec<LedgerEntry> {
+        vec![
+            trade(1, TransactionKind::Buy, 2, d(10), d(100)),
+            trade(2, TransactionKind::Buy, 3, d(10), d(120)),
+            trade(3, TransactionKind::Sell, 4, d(15), d(130)),
+        ]
+    }
+
+    #[test]
+    fn fifo_partial_sell_consumes_oldest_lot_first() {
+        let book = build_lot_book(&ledger(), CostBasisMethod::Fifo, &HashMap::new()).unwrap();
+
+        assert_eq!(book.closed_lots.len(), 2);
+        assert_eq!(book.closed_lots[0].lot_id, 1);
+        assert_eq!(book.closed_lots[0].quantity, d(10));
+        assert_eq!(book.closed_lots[0].cost_basis, d(1000));
+        assert_eq!(book.closed_lots[1].lot_id, 2);
+        assert_eq!(book.closed_lots[1].quantity, d(5));
+        assert_eq!(book.closed_lots[1].cost_basis, d(600));
+        let realized: Decimal = book.closed_lots.iter().map(|l| l.realized_gain).sum();
+        assert_eq!(realized, d(1950) - d(1600));
+
+        assert_eq!(book.open_lots.len(), 1);
+        assert_eq!(book.open_lots[0].lot_id, 2);
+        assert_eq!(book.open_lots[0].quantity, d(5));
+        assert_eq!(book.open_lots[0].cost_basis, d(600));
+    }
+
+    #[test]
+    fn specific_lots_are_consumed_before_the_default_order() {
+        let selections = HashMap::from([(3, vec![LotSelection { lot_id: 2, quantity: d(8) }])]);
+        let book = build_lot_book(&ledger(), CostBasisMethod::Specific, &selections).unwrap();
+
+        assert_eq!(book.closed_lots[0].lot_id, 2);
+        assert_eq!(book.closed_lots[0].quantity, d(8));
+        assert_eq!(book.closed_lots[0].cost_basis, d(960));
+        assert_eq!(book.closed_lots[1].lot_id, 1);
+        assert_eq!(book.closed_lots[1].quantity, d(7));
+        assert_eq!(book.closed_lots[1].cost_basis, d(700));
+        // Lo obtenido se reparte por títulos y el total se conserva
+        let proceeds: Decimal = book.closed_lots.iter().map(|l| l.proceeds).sum();
+        assert_eq!(proceeds, d(1950));
+
+        let remaining: Vec<(i32, Decimal)> = book.open_lots.iter().map(|l| (l.lot_id, l.quantity)).collect();
+        assert_eq!(remaining, vec![(1, d(3)), (2, d(2))]);
+    }
+
+    #[test]
+    fn selling_more_than_held_is_an_error() {
+        let mut entries = ledger();
+        entries.push(trade(4, TransactionKind::Sell, 5, d(6), d(130)));
+        assert!(build_lot_book(&entries, CostBasisMethod::Fifo, &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+// Reglas de redondeo:
+// * Montos en pesos (total_amount, efectivo, costo, P&L): centavos, mitad hacia arriba (NUMERIC(18,2)).
+// * Precios y cantidades: 6 decimales (NUMERIC(18,6)), para fracciones de título y precios del SIC.
+// * Porcentajes: 2 decimales.
+pub const MXN_SCALE: u32 = 2;
+pub const PRICE_SCALE: u32 = 6;
+pub const QUANTITY_SCALE: u32 = 6;
+pub const PERCENT_SCALE: u32 = 2;
+
+pub fn round_mxn(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(MXN_SCALE, RoundingStrategy::MidpointAwayFromZero)
+}
+
+pub fn round_price(price: Decimal) -> Decimal {
+    price.round_dp_with_strategy(PRICE_SCALE, RoundingStrategy::MidpointAwayFromZero)
+}
+
+pub fn round_quantity(quantity: Decimal) -> Decimal {
+    quantity.round_dp_with_strategy(QUANTITY_SCALE, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// `part` como porcentaje de `whole`; 0 si `whole` no es positivo.
+pub fn percent(part: Decimal, whole: Decimal) -> Decimal {
+    if whole > Decimal::ZERO {
+        (part / whole * Decimal::ONE_HUNDRED).round_dp_with_strategy(PERCENT_SCALE, RoundingStrategy::MidpointAwayFromZero)
+    } else {
+        Decimal::ZERO
+    }
+}
+
+/// Convierte un precio que viene como `f64` de la API de DataBursátil.
+pub fn price_from_f64(value: f64) -> Option<Decimal> {
+    Decimal::from_f64(value).map(round_price)
+}
@@ -0,0 +1,318 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::money;
+use crate::performance::{to_f64, to_percent};
+use crate::risk::{self, HoldingReturns};
+
+pub const DEFAULT_PATHS: u32 = 5_000;
+pub const MAX_PATHS: u32 = 20_000;
+pub const MAX_HORIZON_DAYS: u32 = 252 * 30;
+// Semilla fija por omisión: la misma petición da el mismo resultado
+pub const DEFAULT_SEED: u64 = 20240101;
+// Aportaciones mensuales por omisión (días hábiles)
+const DEFAULT_CONTRIBUTION_INTERVAL: u32 = 21;
+// Puntos de la banda que se regresan para graficar
+const MAX_BAND_POINTS: u32 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SimulationMethod {
+    // Días históricos completos elegidos al azar; conserva colas y correlaciones observadas
+    #[default]
+    Bootstrap,
+    // Normal multivariada con la media y covarianza históricas
+    Normal,
+}
+
+/// Parámetros de la simulación. Horizonte e intervalo de aportación en días hábiles;
+/// la aportación en pesos se invierte con los pesos actuales del portafolio.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationParams {
+    pub horizon_days: u32,
+    #[serde(default)]
+    pub paths: Option<u32>,
+    #[serde(default)]
+    pub method: SimulationMethod,
+    #[serde(default)]
+    pub contribution: Option<Decimal>,
+    #[serde(default)]
+    pub contribution_interval_days: Option<u32>,
+    #[serde(default)]
+    pub target_value: Option<Decimal>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Percentiles del valor del portafolio en un día de la simulación, en pesos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationBand {
+    pub day: u32,
+    pub contributed: Decimal,
+    pub p5: Decimal,
+    pub p25: Decimal,
+    pub p50: Decimal,
+    pub p75: Decimal,
+    pub p95: Decimal,
+}
+
+/// Probabilidades en porcentaje, sobre el valor al final del horizonte.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationResult {
+    pub portfolio_id: i32,
+    pub method: SimulationMethod,
+    pub paths: u32,
+    pub horizon_days: u32,
+    pub seed: u64,
+    pub observations: usize,
+    pub initial_value: Decimal,
+    pub total_contributions: Decimal,
+    pub bands: Vec<SimulationBand>,
+    pub final_value: Option<SimulationBand>,
+    pub target_value: Option<Decimal>,
+    pub probability_of_target: Option<Decimal>,
+    pub probability_of_loss: Option<Decimal>,
+    pub warnings: Vec<String>,
+}
+
+// Factor de Cholesky (triangular inferior) de una matriz de covarianzas
+fn cholesky(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut lower = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| lower[i][k] * lower[j][k]).sum();
+            if i == j {
+                let diagonal = matrix[i][i] - sum;
+                if diagonal <= 0.0 {
+                    return None;
+                }
+                lower[i][j] = diagonal.sqrt();
+            } else {
+                lower[i][j] = (matrix[i][j] - sum) / lower[j][j];
+            }
+        }
+    }
+    Some(lower)
+}
+
+// Normal estándar por Box-Muller
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+enum ReturnModel {
+    Bootstrap { returns: Vec<Vec<f64>>, observations: usize },
+    Normal { means: Vec<f64>, lower: Vec<Vec<f64>> },
+}
+
+impl ReturnModel {
+    fn fit(method: SimulationMethod, returns: &[Vec<f64>]) -> Result<Self, String> {
+        let observations = returns.first().map_or(0, |r| r.len());
+        match method {
+            SimulationMethod::Bootstrap => Ok(ReturnModel::Bootstrap { returns: returns.to_vec(), observations }),
+            SimulationMethod::Normal => {
+                let n = returns.len();
+                let means: Vec<f64> = returns.iter().map(|r| r.iter().sum::<f64>() / observations as f64).collect();
+                let mut covariance = vec![vec![0.0; n]; n];
+                for i in 0..n {
+                    for j in 0..n {
+                        covariance[i][j] = returns[i].iter().zip(&returns[j])
+                            .map(|(a, b)| (a - means[i]) * (b - means[j]))
+                            .sum::<f64>() / (observations as f64 - 1.0);
+                    }
+                }
+                // Series casi colineales: se agrega un poco a la diagonal antes de rendirse
+                let lower = cholesky(&covariance).or_else(|| {
+                    let mut jittered = covariance.clone();
+                    for (i, row) in jittered.iter_mut().enumerate() {
+                        row[i] += 1e-10;
+                    }
+                    cholesky(&jittered)
+                }).ok_or("La matriz de covarianzas no es positiva definida; use el método BOOTSTRAP")?;
+                Ok(ReturnModel::Normal { means, lower })
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng, out: &mut [f64]) {
+        match self {
+            ReturnModel::Bootstrap { returns, observations } => {
+                let t = rng.gen_range(0..*observations);
+                for (value, series) in out.iter_mut().zip(returns) {
+                    *value = series[t];
+                }
+            }
+            ReturnModel::Normal { means, lower } => {
+                let z: Vec<f64> = (0..means.len()).map(|_| standard_normal(rng)).collect();
+                for (i, value) in out.iter_mut().enumerate() {
+                    let shock: f64 = lower[i].iter().zip(&z).take(i + 1).map(|(l, z)| l * z).sum();
+                    // Un activo no puede perder más del 100% en un día
+                    *value = (means[i] + shock).max(-1.0);
+                }
+            }
+        }
+    }
+}
+
+fn to_mxn(value: f64) -> Decimal {
+    Decimal::from_f64(value).map(money::round_mxn).unwrap_or_default()
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let low = rank.floor() as usize;
+    let high = rank.ceil() as usize;
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+fn band(day: u32, contributed: f64, values: &mut [f64]) -> SimulationBand {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let at = |p: f64| to_mxn(percentile(values, p));
+    SimulationBand {
+        day,
+        contributed: to_mxn(contributed),
+        p5: at(0.05),
+        p25: at(0.25),
+        p50: at(0.50),
+        p75: at(0.75),
+        p95: at(0.95),
+    }
+}
+
+/// Corre la simulación; es intensiva en CPU, así que se llama desde un hilo bloqueante.
+/// Cada trayectoria lleva el valor de cada posición y del efectivo (que no rinde);
+/// las aportaciones se reparten con los pesos iniciales.
+pub fn simulate(
+    portfolio_id: i32,
+    params: &SimulationParams,
+    inputs: &HoldingReturns,
+    mut warnings: Vec<String>,
+) -> Result<SimulationResult, String> {
+    let paths = params.paths.unwrap_or(DEFAULT_PATHS);
+    let seed = params.seed.unwrap_or(DEFAULT_SEED);
+    let horizon = params.horizon_days;
+    let contribution = params.contribution.map(to_f64).unwrap_or(0.0);
+    let interval = params.contribution_interval_days.unwrap_or(DEFAULT_CONTRIBUTION_INTERVAL).max(1);
+    let observations = inputs.dates.len();
+
+    let initial: Vec<f64> = inputs.values.iter().copied().chain(std::iter::once(inputs.cash)).collect();
+    let initial_value: f64 = initial.iter().sum();
+    if initial_value <= 0.0 && contribution <= 0.0 {
+        return Err("El portafolio no tiene valor ni aportaciones que simular".to_string());
+    }
+    let weights: Vec<f64> = if initial_value > 0.0 {
+        initial.iter().map(|v| v / initial_value).collect()
+    } else {
+        // Sin posiciones, la aportación se queda en efectivo
+        let mut cash_only = vec![0.0; initial.len()];
+        cash_only[initial.len() - 1] = 1.0;
+        cash_only
+    };
+
+    let assets = inputs.tickers.len();
+    let model = if assets > 0 && observations >= risk::MIN_OBSERVATIONS {
+        Some(ReturnModel::fit(params.method, &inputs.returns)?)
+    } else {
+        if assets > 0 {
+            warnings.push(format!(
+                "Se necesitan al menos {} rendimientos diarios comunes; se simula sin variación de precios",
+                risk::MIN_OBSERVATIONS
+            ));
+        }
+        None
+    };
+
+    let step = horizon.div_ceil(MAX_BAND_POINTS).max(1);
+    let checkpoints: Vec<u32> = (0..=horizon).filter(|d| d % step == 0 || *d == horizon).collect();
+    let mut samples: Vec<Vec<f64>> = vec![Vec::with_capacity(paths as usize); checkpoints.len()];
+    let contributed_at = |day: u32| contribution * (day / interval) as f64;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut daily = vec![0.0; assets];
+    for _ in 0..paths {
+        let mut values = initial.clone();
+        let mut next_checkpoint = 0;
+        for day in 0..=horizon {
+            if day > 0 {
+                if let Some(model) = &model {
+                    model.sample(&mut rng, &mut daily);
+                    for (value, r) in values.iter_mut().zip(&daily) {
+                        *value *= 1.0 + r;
+                    }
+                }
+                if contribution > 0.0 && day % interval == 0 {
+                    for (value, w) in values.iter_mut().zip(&weights) {
+                        *value += contribution * w;
+                    }
+                }
+            }
+            if checkpoints.get(next_checkpoint) == Some(&day) {
+                samples[next_checkpoint].push(values.iter().sum());
+                next_checkpoint += 1;
+            }
+        }
+    }
+
+    let total_contributions = contributed_at(horizon);
+    let final_values = samples.last().cloned().unwrap_or_default();
+    let probability = |hit: usize| to_percent(hit as f64 / final_values.len() as f64);
+    let target_value = params.target_value;
+    let probability_of_target = target_value.map(to_f64).and_then(|target| {
+        probability(final_values.iter().filter(|v| **v >= target).count())
+    });
+    let invested = initial_value + total_contributions;
+    let probability_of_loss = probability(final_values.iter().filter(|v| **v < invested).count());
+
+    let bands: Vec<SimulationBand> = checkpoints.iter()
+        .zip(samples.iter_mut())
+        .map(|(day, values)| band(*day, contributed_at(*day), values))
+        .collect();
+
+    Ok(SimulationResult {
+        portfolio_id,
+        method: params.method,
+        paths,
+        horizon_days: horizon,
+        seed,
+        observations,
+        initial_value: to_mxn(initial_value),
+        total_contributions: to_mxn(total_contributions),
+        final_value: bands.last().cloned(),
+        bands,
+        target_value,
+        probability_of_target,
+        probability_of_loss,
+        warnings,
+    })
+}
+
+pub async fn run_monte_carlo_logic(
+    portfolio_id: i32,
+    params: SimulationParams,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<SimulationResult, String> {
+    if params.horizon_days == 0 || params.horizon_days > MAX_HORIZON_DAYS {
+        return Err(format!("El horizonte debe estar entre 1 y {} días hábiles", MAX_HORIZON_DAYS));
+    }
+    if matches!(params.paths, Some(p) if p == 0 || p > MAX_PATHS) {
+        return Err(format!("El número de trayectorias debe estar entre 1 y {}", MAX_PATHS));
+    }
+    if matches!(params.contribution, Some(c) if c < Decimal::ZERO) {
+        return Err("La aportación periódica no puede ser negativa".to_string());
+    }
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let mut warnings = Vec::new();
+    let inputs = risk::holding_returns(&**client, portfolio_id, &mut warnings).await?;
+    drop(client);
+
+    tokio::task::spawn_blocking(move || simulate(portfolio_id, &params, &inputs, warnings))
+        .await
+        .map_err(|e| format!("La simulación se interrumpió: {}", e))?
+}
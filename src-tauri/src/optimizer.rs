@@ -0,0 +1,418 @@
+use chrono::{Months, NaiveDate, Utc};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::allocation::{self, AllocationTarget};
+use crate::money;
+use crate::performance::{to_f64, to_percent};
+use crate::prices;
+use crate::risk;
+use crate::snapshots;
+use crate::yield_curve;
+
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+const DEFAULT_RANGE: &str = "3Y";
+const DEFAULT_FRONTIER_POINTS: u32 = 20;
+const MAX_FRONTIER_POINTS: u32 = 100;
+const MAX_TICKERS: usize = 30;
+const SOLVER_ITERATIONS: usize = 5_000;
+const SOLVER_TOLERANCE: f64 = 1e-12;
+
+/// Universo y restricciones. `max_weight`, `target_return` y `risk_free_rate` en porcentaje
+/// (rendimientos anuales); sin `risk_free_rate` se usa la curva de CETES a un año.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizerParams {
+    pub tickers: Vec<String>,
+    #[serde(default)]
+    pub range: Option<String>,
+    #[serde(default)]
+    pub max_weight: Option<Decimal>,
+    #[serde(default)]
+    pub target_return: Option<Decimal>,
+    #[serde(default)]
+    pub risk_free_rate: Option<Decimal>,
+    #[serde(default)]
+    pub frontier_points: Option<u32>,
+}
+
+/// Estimación anual de un ticker, en porcentaje.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetEstimate {
+    pub ticker: String,
+    pub expected_return: Decimal,
+    pub volatility: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickerWeight {
+    pub ticker: String,
+    pub weight: Decimal,
+}
+
+/// Un portafolio de la frontera: rendimiento esperado y volatilidad anuales en porcentaje.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrontierPoint {
+    pub expected_return: Decimal,
+    pub volatility: Decimal,
+    pub sharpe: Option<Decimal>,
+    pub weights: Vec<TickerWeight>,
+}
+
+/// `shrinkage` es la intensidad de Ledoit-Wolf hacia una matriz diagonal (0 = muestral).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizationResult {
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub observations: usize,
+    pub risk_free_rate: Decimal,
+    pub shrinkage: Decimal,
+    pub assets: Vec<AssetEstimate>,
+    pub min_variance: FrontierPoint,
+    pub max_sharpe: FrontierPoint,
+    pub target: Option<FrontierPoint>,
+    pub frontier: Vec<FrontierPoint>,
+    pub warnings: Vec<String>,
+}
+
+/// Covarianza de Ledoit-Wolf (2004) con objetivo m·I, sobre rendimientos diarios.
+/// Regresa la matriz y la intensidad de encogimiento.
+pub fn shrunk_covariance(returns: &[Vec<f64>]) -> (Vec<Vec<f64>>, f64) {
+    let n = returns.len();
+    let t = returns.first().map_or(0, |r| r.len());
+    let means: Vec<f64> = returns.iter().map(|r| r.iter().sum::<f64>() / t as f64).collect();
+    let centered: Vec<Vec<f64>> = returns.iter().zip(&means).map(|(r, m)| r.iter().map(|x| x - m).collect()).collect();
+
+    let mut sample = vec![vec![0.0; n]; n];
+    for (i, row) in sample.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = centered[i].iter().zip(&centered[j]).map(|(a, b)| a * b).sum::<f64>() / t as f64;
+        }
+    }
+    let mu = (0..n).map(|i| sample[i][i]).sum::<f64>() / n as f64;
+    let d2 = (0..n)
+        .flat_map(|i| (0..n).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let target = if i == j { mu } else { 0.0 };
+            (sample[i][j] - target).powi(2)
+        })
+        .sum::<f64>() / n as f64;
+    let b2_bar = (0..t)
+        .map(|k| {
+            (0..n)
+                .flat_map(|i| (0..n).map(move |j| (i, j)))
+                .map(|(i, j)| (centered[i][k] * centered[j][k] - sample[i][j]).powi(2))
+                .sum::<f64>() / n as f64
+        })
+        .sum::<f64>() / (t as f64 * t as f64);
+    let shrinkage = if d2 > 0.0 { (b2_bar.min(d2) / d2).clamp(0.0, 1.0) } else { 1.0 };
+
+    let mut shrunk = sample;
+    for (i, row) in shrunk.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            let target = if i == j { mu } else { 0.0 };
+            *cell = shrinkage * target + (1.0 - shrinkage) * *cell;
+        }
+    }
+    (shrunk, shrinkage)
+}
+
+struct Problem {
+    expected: Vec<f64>,
+    covariance: Vec<Vec<f64>>,
+    max_weight: f64,
+    risk_free: f64,
+}
+
+impl Problem {
+    fn variance(&self, w: &[f64]) -> f64 {
+        w.iter().enumerate()
+            .map(|(i, wi)| wi * self.covariance[i].iter().zip(w).map(|(c, wj)| c * wj).sum::<f64>())
+            .sum()
+    }
+
+    fn expected_return(&self, w: &[f64]) -> f64 {
+        self.expected.iter().zip(w).map(|(m, wi)| m * wi).sum()
+    }
+
+    fn sharpe(&self, w: &[f64]) -> f64 {
+        let volatility = self.variance(w).max(0.0).sqrt();
+        if volatility > 0.0 { (self.expected_return(w) - self.risk_free) / volatility } else { f64::NAN }
+    }
+
+    // Proyección sobre {Σw = 1, 0 ≤ w ≤ max_weight} buscando el corrimiento τ por bisección
+    fn project(&self, v: &[f64]) -> Vec<f64> {
+        let clamp = |tau: f64| -> Vec<f64> { v.iter().map(|x| (x - tau).clamp(0.0, self.max_weight)).collect() };
+        let (mut low, mut high) = (
+            v.iter().cloned().fold(f64::INFINITY, f64::min) - self.max_weight - 1.0,
+            v.iter().cloned().fold(f64::NEG_INFINITY, f64::max) + 1.0,
+        );
+        for _ in 0..60 {
+            let mid = (low + high) / 2.0;
+            if clamp(mid).iter().sum::<f64>() > 1.0 {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        clamp((low + high) / 2.0)
+    }
+
+    fn solve(&self, lambda: f64) -> Vec<f64> {
+        let n = self.expected.len();
+        self.solve_from(lambda, &vec![1.0 / n as f64; n])
+    }
+
+    /// Mínimo de w'Σw - λ·μ'w con gradiente proyectado, partiendo de `start`.
+    fn solve_from(&self, lambda: f64, start: &[f64]) -> Vec<f64> {
+        let n = self.expected.len();
+        // Paso 1/L con L acotada por la suma absoluta máxima de renglón de 2Σ
+        let lipschitz = self.covariance.iter().map(|row| row.iter().map(|c| c.abs()).sum::<f64>()).fold(0.0, f64::max) * 2.0;
+        let step = if lipschitz > 0.0 { 1.0 / lipschitz } else { 1.0 };
+        let mut w = self.project(start);
+        for _ in 0..SOLVER_ITERATIONS {
+            let gradient: Vec<f64> = (0..n)
+                .map(|i| 2.0 * self.covariance[i].iter().zip(&w).map(|(c, wj)| c * wj).sum::<f64>() - lambda * self.expected[i])
+                .collect();
+            let next = self.project(&w.iter().zip(&gradient).map(|(wi, g)| wi - step * g).collect::<Vec<f64>>());
+            let change: f64 = next.iter().zip(&w).map(|(a, b)| (a - b).powi(2)).sum();
+            w = next;
+            if change < SOLVER_TOLERANCE {
+                break;
+            }
+        }
+        w
+    }
+
+    // Rendimiento máximo alcanzable: llenar primero los de mayor rendimiento esperado
+    fn max_return_weights(&self) -> Vec<f64> {
+        let mut order: Vec<usize> = (0..self.expected.len()).collect();
+        order.sort_by(|a, b| self.expected[*b].partial_cmp(&self.expected[*a]).unwrap_or(std::cmp::Ordering::Equal));
+        let mut w = vec![0.0; self.expected.len()];
+        let mut left: f64 = 1.0;
+        for i in order {
+            w[i] = left.min(self.max_weight);
+            left -= w[i];
+        }
+        w
+    }
+
+    /// Mínima varianza con rendimiento esperado ≥ `target`, buscando λ por bisección.
+    /// Cada paso parte de la solución anterior para converger en pocas iteraciones.
+    fn solve_target(&self, target: f64, min_variance: &[f64]) -> Option<Vec<f64>> {
+        if self.expected_return(min_variance) >= target {
+            return Some(min_variance.to_vec());
+        }
+        if self.expected_return(&self.max_return_weights()) < target - 1e-9 {
+            return None;
+        }
+        let mut high = 1.0;
+        let mut warm = self.solve_from(high, min_variance);
+        while self.expected_return(&warm) < target {
+            high *= 4.0;
+            if high > 1e8 {
+                return Some(self.max_return_weights());
+            }
+            warm = self.solve_from(high, &warm);
+        }
+        let mut low = 0.0;
+        for _ in 0..40 {
+            let mid = (low + high) / 2.0;
+            let w = self.solve_from(mid, &warm);
+            if self.expected_return(&w) < target {
+                low = mid;
+            } else {
+                high = mid;
+                warm = w;
+            }
+        }
+        Some(warm)
+    }
+}
+
+fn point(problem: &Problem, tickers: &[String], w: &[f64]) -> FrontierPoint {
+    let volatility = problem.variance(w).max(0.0).sqrt();
+    FrontierPoint {
+        expected_return: to_percent(problem.expected_return(w)).unwrap_or_default(),
+        volatility: to_percent(volatility).unwrap_or_default(),
+        sharpe: Decimal::from_f64(problem.sharpe(w)).map(|s| s.round_dp(4)),
+        weights: tickers.iter().zip(w)
+            .map(|(ticker, weight)| TickerWeight { ticker: ticker.clone(), weight: to_percent(*weight).unwrap_or_default() })
+            .collect(),
+    }
+}
+
+fn optimize(
+    tickers: &[String],
+    problem: &Problem,
+    target_return: Option<f64>,
+    frontier_points: u32,
+) -> (FrontierPoint, FrontierPoint, Option<FrontierPoint>, Vec<FrontierPoint>) {
+    let min_weights = problem.solve(0.0);
+    let min_return = problem.expected_return(&min_weights);
+    let max_return = problem.expected_return(&problem.max_return_weights());
+
+    let targets: Vec<f64> = (0..frontier_points)
+        .map(|k| min_return + (max_return - min_return) * k as f64 / (frontier_points.max(2) - 1) as f64)
+        .collect();
+    // Cada punto conserva su rendimiento objetivo; los objetivos sin solución se omiten
+    let frontier: Vec<(f64, Vec<f64>)> = targets.iter()
+        .filter_map(|t| problem.solve_target(*t, &min_weights).map(|w| (*t, w)))
+        .collect();
+
+    // Sharpe es unimodal sobre la frontera: se afina alrededor del mejor punto de la rejilla
+    let best = frontier.iter().enumerate()
+        .max_by(|a, b| problem.sharpe(&a.1.1).partial_cmp(&problem.sharpe(&b.1.1)).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let target_at = |i: usize| frontier.get(i).map_or(min_return, |(t, _)| *t);
+    let mut low = target_at(best.saturating_sub(1));
+    let mut high = target_at((best + 1).min(frontier.len().saturating_sub(1)));
+    for _ in 0..15 {
+        let (a, b) = (low + (high - low) / 3.0, high - (high - low) / 3.0);
+        let sharpe_at = |t: f64| problem.solve_target(t, &min_weights).map_or(f64::NEG_INFINITY, |w| problem.sharpe(&w));
+        if sharpe_at(a) < sharpe_at(b) {
+            low = a;
+        } else {
+            high = b;
+        }
+    }
+    let sharpe_weights = problem.solve_target((low + high) / 2.0, &min_weights).unwrap_or_else(|| min_weights.clone());
+
+    let target = target_return.and_then(|t| problem.solve_target(t, &min_weights)).map(|w| point(problem, tickers, &w));
+    (
+        point(problem, tickers, &min_weights),
+        point(problem, tickers, &sharpe_weights),
+        target,
+        frontier.iter().map(|(_, w)| point(problem, tickers, w)).collect(),
+    )
+}
+
+pub async fn optimize_portfolio_logic(
+    params: OptimizerParams,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<OptimizationResult, String> {
+    let mut tickers: Vec<String> = Vec::new();
+    for ticker in &params.tickers {
+        let ticker = ticker.trim().to_uppercase();
+        if !ticker.is_empty() && !tickers.contains(&ticker) {
+            tickers.push(ticker);
+        }
+    }
+    if tickers.len() < 2 || tickers.len() > MAX_TICKERS {
+        return Err(format!("Indique entre 2 y {} emisoras para optimizar", MAX_TICKERS));
+    }
+    let max_weight = params.max_weight.map(|w| to_f64(w) / 100.0).unwrap_or(1.0);
+    if max_weight <= 0.0 || max_weight > 1.0 || max_weight * (tickers.len() as f64) < 1.0 - 1e-9 {
+        return Err(format!("Con {} emisoras el peso máximo debe estar entre {:.2}% y 100%", tickers.len(), 100.0 / tickers.len() as f64));
+    }
+    let frontier_points = params.frontier_points.unwrap_or(DEFAULT_FRONTIER_POINTS).clamp(2, MAX_FRONTIER_POINTS);
+
+    let today = Utc::now().date_naive();
+    let range = params.range.as_deref().unwrap_or(DEFAULT_RANGE);
+    let from = snapshots::range_start(range, today)?.unwrap_or(today - Months::new(120));
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let mut warnings = Vec::new();
+    let mut series = Vec::new();
+    for ticker in &tickers {
+        series.push(prices::daily_closes(&**client, ticker, from).await?);
+    }
+    let risk_free = match params.risk_free_rate {
+        Some(rate) => to_f64(rate) / 100.0,
+        None => match yield_curve::risk_free_rate(&**client, today, yield_curve::ONE_YEAR_DAYS).await? {
+            Some(rate) => rate / 100.0,
+            None => {
+                warnings.push("No hay curva de rendimiento guardada; se usa 0% como tasa libre de riesgo".to_string());
+                0.0
+            }
+        },
+    };
+    drop(client);
+
+    let (dates, returns) = risk::aligned_returns(&series, from, today);
+    if dates.len() < risk::MIN_OBSERVATIONS {
+        return Err(format!(
+            "Se necesitan al menos {} rendimientos diarios comunes (hay {})",
+            risk::MIN_OBSERVATIONS, dates.len()
+        ));
+    }
+
+    let target_return = params.target_return.map(|t| to_f64(t) / 100.0);
+    tokio::task::spawn_blocking(move || {
+        let (daily_covariance, shrinkage) = shrunk_covariance(&returns);
+        let expected: Vec<f64> = returns.iter()
+            .map(|r| r.iter().sum::<f64>() / r.len() as f64 * TRADING_DAYS_PER_YEAR)
+            .collect();
+        let covariance: Vec<Vec<f64>> = daily_covariance.iter()
+            .map(|row| row.iter().map(|c| c * TRADING_DAYS_PER_YEAR).collect())
+            .collect();
+        let problem = Problem { expected, covariance, max_weight, risk_free };
+
+        let (min_variance, max_sharpe, target, frontier) = optimize(&tickers, &problem, target_return, frontier_points);
+        if target_return.is_some() && target.is_none() {
+            warnings.push("El rendimiento objetivo no es alcanzable con estas restricciones".to_string());
+        }
+        let assets = tickers.iter().enumerate()
+            .map(|(i, ticker)| AssetEstimate {
+                ticker: ticker.clone(),
+                expected_return: to_percent(problem.expected[i]).unwrap_or_default(),
+                volatility: to_percent(problem.covariance[i][i].sqrt()).unwrap_or_default(),
+            })
+            .collect();
+
+        OptimizationResult {
+            start_date: dates.first().copied(),
+            end_date: dates.last().copied(),
+            observations: dates.len(),
+            risk_free_rate: to_percent(risk_free).unwrap_or_default(),
+            shrinkage: Decimal::from_f64(shrinkage).map(|s| s.round_dp(4)).unwrap_or_default(),
+            assets,
+            min_variance,
+            max_sharpe,
+            target,
+            frontier,
+            warnings,
+        }
+    })
+    .await
+    .map_err(|e| format!("La optimización se interrumpió: {}", e))
+}
+
+/// Guarda los pesos de un punto de la frontera como asignación objetivo por ticker.
+/// `cash_weight` (porcentaje) se reserva como efectivo y el resto se escala.
+pub async fn apply_frontier_point_logic(
+    portfolio_id: i32,
+    weights: Vec<TickerWeight>,
+    cash_weight: Option<Decimal>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<AllocationTarget>, String> {
+    let cash_weight = cash_weight.unwrap_or_default();
+    if cash_weight < Decimal::ZERO || cash_weight >= Decimal::ONE_HUNDRED {
+        return Err("La reserva de efectivo debe estar entre 0% y 100%".to_string());
+    }
+    let invested = Decimal::ONE_HUNDRED - cash_weight;
+    let total: Decimal = weights.iter().map(|w| w.weight).sum();
+    if total <= Decimal::ZERO {
+        return Err("El punto elegido no tiene pesos".to_string());
+    }
+
+    let mut targets: Vec<AllocationTarget> = weights.iter()
+        .filter(|w| w.weight > Decimal::ZERO)
+        .map(|w| AllocationTarget {
+            bucket: w.ticker.clone(),
+            weight: (w.weight / total * invested).round_dp(money::PERCENT_SCALE),
+        })
+        .filter(|t| t.weight > Decimal::ZERO)
+        .collect();
+    if cash_weight > Decimal::ZERO {
+        targets.push(AllocationTarget { bucket: allocation::CASH_BUCKET.to_string(), weight: cash_weight });
+    }
+    // El redondeo se absorbe en el peso más grande para que sumen exactamente 100
+    let residual = Decimal::ONE_HUNDRED - targets.iter().map(|t| t.weight).sum::<Decimal>();
+    if let Some(largest) = targets.iter_mut().max_by(|a, b| a.weight.cmp(&b.weight)) {
+        largest.weight += residual;
+    }
+
+    allocation::set_allocation_targets_logic(portfolio_id, "TICKER", targets, db_pool).await
+}
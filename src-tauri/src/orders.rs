@@ -0,0 +1,413 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Utc, Weekday};
+use deadpool_postgres::Pool;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres::GenericClient;
+
+use crate::ledger::{self, CashSource, NewLedgerEntry, TransactionKind};
+use crate::money;
+use crate::valuation;
+
+// La BMV opera de 8:30 a 15:00, hora del centro de México (UTC-6 todo el año)
+const MARKET_UTC_OFFSET_SECONDS: i32 = -6 * 3600;
+const MARKET_OPEN: (u32, u32) = (8, 30);
+const MARKET_CLOSE: (u32, u32) = (15, 0);
+const MATCHER_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OrderType {
+    // Se ejecuta con la siguiente cotización
+    Market,
+    // Compra a limit_price o menos; vende a limit_price o más
+    Limit,
+    // Se vuelve orden a mercado cuando el precio toca stop_price
+    Stop,
+}
+
+impl OrderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Market => "MARKET",
+            OrderType::Limit => "LIMIT",
+            OrderType::Stop => "STOP",
+        }
+    }
+}
+
+impl FromStr for OrderType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "MARKET" | "MERCADO" => Ok(OrderType::Market),
+            "LIMIT" | "LIMITADA" => Ok(OrderType::Limit),
+            "STOP" => Ok(OrderType::Stop),
+            _ => Err(format!("Tipo de orden desconocido: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OrderStatus {
+    Open,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Open => "OPEN",
+            OrderStatus::Filled => "FILLED",
+            OrderStatus::Cancelled => "CANCELLED",
+            OrderStatus::Rejected => "REJECTED",
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "OPEN" => Ok(OrderStatus::Open),
+            "FILLED" => Ok(OrderStatus::Filled),
+            "CANCELLED" => Ok(OrderStatus::Cancelled),
+            "REJECTED" => Ok(OrderStatus::Rejected),
+            _ => Err(format!("Estado de orden desconocido: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    pub order_id: i32,
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub side: TransactionKind,
+    pub order_type: OrderType,
+    pub quantity: Decimal,
+    pub limit_price: Option<Decimal>,
+    pub stop_price: Option<Decimal>,
+    pub status: OrderStatus,
+    pub fill_price: Option<Decimal>,
+    pub filled_at: Option<DateTime<Utc>>,
+    pub transaction_id: Option<i32>,
+    pub reject_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Order {
+    /// Precio de ejecución con la cotización `last`, o None si la orden no se dispara.
+    fn fill_price(&self, last: Decimal) -> Option<Decimal> {
+        let buy = self.side == TransactionKind::Buy;
+        let triggered = match self.order_type {
+            OrderType::Market => true,
+            OrderType::Limit => {
+                let limit = self.limit_price?;
+                if buy { last <= limit } else { last >= limit }
+            }
+            OrderType::Stop => {
+                let stop = self.stop_price?;
+                if buy { last >= stop } else { last <= stop }
+            }
+        };
+        triggered.then_some(last)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewOrder {
+    pub portfolio_id: i32,
+    pub ticker: String,
+    pub side: String,
+    pub order_type: String,
+    pub quantity: Decimal,
+    #[serde(default)]
+    pub limit_price: Option<Decimal>,
+    #[serde(default)]
+    pub stop_price: Option<Decimal>,
+}
+
+const ORDER_COLUMNS: &str = "order_id, portfolio_id, ticker, side, order_type, quantity, limit_price, stop_price, status, fill_price, filled_at, transaction_id, reject_reason, created_at";
+
+fn order_from_row(row: &tokio_postgres::Row) -> Result<Order, String> {
+    let side: String = row.get("side");
+    let order_type: String = row.get("order_type");
+    let status: String = row.get("status");
+    Ok(Order {
+        order_id: row.get("order_id"),
+        portfolio_id: row.get("portfolio_id"),
+        ticker: row.get("ticker"),
+        side: side.parse()?,
+        order_type: order_type.parse()?,
+        quantity: row.get("quantity"),
+        limit_price: row.get("limit_price"),
+        stop_price: row.get("stop_price"),
+        status: status.parse()?,
+        fill_price: row.get("fill_price"),
+        filled_at: row.get("filled_at"),
+        transaction_id: row.get("transaction_id"),
+        reject_reason: row.get("reject_reason"),
+        created_at: row.get("created_at"),
+    })
+}
+
+/// Horario de la BMV, de lunes a viernes. No considera días festivos: en ellos la
+/// cotización no cambia y solo se ejecutarían órdenes que ya se cumplían al cierre anterior.
+pub fn is_market_open(now: DateTime<Utc>) -> bool {
+    let offset = FixedOffset::east_opt(MARKET_UTC_OFFSET_SECONDS).unwrap();
+    let local = now.with_timezone(&offset);
+    let open = NaiveTime::from_hms_opt(MARKET_OPEN.0, MARKET_OPEN.1, 0).unwrap();
+    let close = NaiveTime::from_hms_opt(MARKET_CLOSE.0, MARKET_CLOSE.1, 0).unwrap();
+    !matches!(local.weekday(), Weekday::Sat | Weekday::Sun) && local.time() >= open && local.time() < close
+}
+
+pub async fn is_paper_portfolio<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<bool, String> {
+    let row = client.query_opt("SELECT is_paper FROM portafolios WHERE id = $1", &[&portfolio_id])
+        .await.map_err(|e| format!("Error al consultar el portafolio: {}", e))?
+        .ok_or_else(|| format!("No se encontró el portafolio {}", portfolio_id))?;
+    Ok(row.get("is_paper"))
+}
+
+/// Un portafolio de práctica solo opera con órdenes simuladas; las compras y ventas
+/// directas, importaciones y aportaciones programadas se rechazan. Los depósitos y retiros
+/// sí se permiten para fondear la cuenta.
+pub async fn ensure_real_portfolio<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<(), String> {
+    if is_paper_portfolio(client, portfolio_id).await? {
+        return Err("El portafolio es de práctica; sus compras y ventas se registran con órdenes simuladas".to_string());
+    }
+    Ok(())
+}
+
+pub async fn set_paper_portfolio_logic(portfolio_id: i32, is_paper: bool, db_pool: &Pool) -> Result<bool, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    if is_paper && !is_paper_portfolio(&**client, portfolio_id).await? {
+        // Un portafolio con movimientos reales no puede pasar a ser de práctica: las órdenes
+        // simuladas se mezclarían con sus posiciones
+        let real = client.query_one(
+            "SELECT EXISTS (
+                SELECT 1 FROM portfolio_transactions t
+                WHERE t.portfolio_id = $1 AND t.voided_at IS NULL
+                  AND NOT EXISTS (SELECT 1 FROM orders o WHERE o.transaction_id = t.transaction_id)
+             ) AS real",
+            &[&portfolio_id],
+        ).await.map_err(|e| format!("Error al consultar los movimientos del portafolio: {}", e))?;
+        if real.get::<_, bool>("real") {
+            return Err("El portafolio ya tiene movimientos reales; no puede marcarse como de práctica".to_string());
+        }
+        let plans = client.query_one(
+            "SELECT EXISTS (SELECT 1 FROM contribution_plans WHERE portfolio_id = $1 AND status = 'ACTIVE') AS plans",
+            &[&portfolio_id],
+        ).await.map_err(|e| format!("Error al consultar los planes de aportaciones: {}", e))?;
+        if plans.get::<_, bool>("plans") {
+            return Err("El portafolio tiene planes de aportaciones activos; páuselos antes de marcarlo como de práctica".to_string());
+        }
+    }
+    if !is_paper {
+        // Un portafolio con operaciones simuladas no puede pasar a ser real
+        let simulated = client.query_one(
+            "SELECT EXISTS (SELECT 1 FROM orders WHERE portfolio_id = $1 AND status = 'FILLED') AS simulated",
+            &[&portfolio_id],
+        ).await.map_err(|e| format!("Error al consultar las órdenes: {}", e))?;
+        if simulated.get::<_, bool>("simulated") {
+            return Err("El portafolio ya tiene operaciones simuladas; no puede marcarse como real".to_string());
+        }
+        client.execute("UPDATE orders SET status = 'CANCELLED' WHERE portfolio_id = $1 AND status = 'OPEN'", &[&portfolio_id])
+            .await.map_err(|e| format!("No se pudieron cancelar las órdenes: {}", e))?;
+    }
+    let rows_affected = client.execute("UPDATE portafolios SET is_paper = $2 WHERE id = $1", &[&portfolio_id, &is_paper])
+        .await.map_err(|e| format!("No se pudo actualizar el portafolio: {}", e))?;
+    if rows_affected == 0 {
+        return Err(format!("No se encontró el portafolio {}", portfolio_id));
+    }
+    Ok(is_paper)
+}
+
+/// Registra una orden en un portafolio de práctica. Si el mercado está abierto se intenta
+/// ejecutar de inmediato.
+pub async fn place_order_logic(order: NewOrder, db_pool: &Pool) -> Result<Order, String> {
+    let side: TransactionKind = order.side.parse()?;
+    if !side.is_trade() {
+        return Err("El lado de la orden debe ser 'buy' o 'sell'".to_string());
+    }
+    let order_type: OrderType = order.order_type.parse()?;
+    let quantity = money::round_quantity(order.quantity);
+    if quantity <= Decimal::ZERO {
+        return Err("La cantidad debe ser mayor a cero".to_string());
+    }
+    let limit_price = order.limit_price.filter(|_| order_type == OrderType::Limit).map(money::round_price);
+    let stop_price = order.stop_price.filter(|_| order_type == OrderType::Stop).map(money::round_price);
+    match order_type {
+        OrderType::Limit if !matches!(limit_price, Some(p) if p > Decimal::ZERO) => {
+            return Err("Una orden limitada necesita un precio límite mayor a cero".to_string());
+        }
+        OrderType::Stop if !matches!(stop_price, Some(p) if p > Decimal::ZERO) => {
+            return Err("Una orden stop necesita un precio de activación mayor a cero".to_string());
+        }
+        _ => {}
+    }
+    let ticker = order.ticker.trim().to_uppercase();
+    if ticker.is_empty() {
+        return Err("La orden necesita un ticker".to_string());
+    }
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    if !is_paper_portfolio(&**client, order.portfolio_id).await? {
+        return Err("Solo los portafolios de práctica aceptan órdenes simuladas".to_string());
+    }
+    if side == TransactionKind::Sell {
+        let held = ledger::held_quantity(&**client, order.portfolio_id, &ticker).await?;
+        if held < quantity {
+            return Err(format!("No hay suficientes títulos de {} para vender (disponibles: {})", ticker, held));
+        }
+    }
+    let row = client.query_one(
+        &format!(
+            "INSERT INTO orders (portfolio_id, ticker, side, order_type, quantity, limit_price, stop_price)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+            ORDER_COLUMNS
+        ),
+        &[&order.portfolio_id, &ticker, &side.as_str(), &order_type.as_str(), &quantity, &limit_price, &stop_price],
+    ).await.map_err(|e| format!("No se pudo registrar la orden: {}", e))?;
+    let placed = order_from_row(&row)?;
+    drop(client);
+
+    if !is_market_open(Utc::now()) {
+        return Ok(placed);
+    }
+    let Some(last) = valuation::market_price_mxn(&placed.ticker).await else {
+        return Ok(placed);
+    };
+    match try_fill(db_pool, &placed, last).await? {
+        Some(filled) => Ok(filled),
+        None => Ok(placed),
+    }
+}
+
+pub async fn cancel_order_logic(order_id: i32, db_pool: &Pool) -> Result<Order, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let row = client.query_opt(
+        &format!("UPDATE orders SET status = 'CANCELLED' WHERE order_id = $1 AND status = 'OPEN' RETURNING {}", ORDER_COLUMNS),
+        &[&order_id],
+    ).await.map_err(|e| format!("No se pudo cancelar la orden: {}", e))?
+        .ok_or_else(|| "No se encontró una orden abierta con ese número".to_string())?;
+    order_from_row(&row)
+}
+
+pub async fn list_orders_logic(portfolio_id: i32, status: Option<String>, db_pool: &Pool) -> Result<Vec<Order>, String> {
+    let status = status.map(|s| s.parse::<OrderStatus>()).transpose()?.map(|s| s.as_str());
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!(
+            "SELECT {} FROM orders WHERE portfolio_id = $1 AND ($2::text IS NULL OR status = $2)
+             ORDER BY created_at DESC, order_id DESC",
+            ORDER_COLUMNS
+        ),
+        &[&portfolio_id, &status],
+    ).await.map_err(|e| format!("Error al consultar las órdenes: {}", e))?;
+    rows.iter().map(order_from_row).collect()
+}
+
+// La orden se marca como ejecutada, se registra en el ledger y se enlaza en una sola
+// transacción; el marcado bloquea la orden para que dos ciclos no la ejecuten dos veces.
+// Si el ledger la rechaza (sin efectivo o sin títulos) queda REJECTED.
+async fn try_fill(db_pool: &Pool, order: &Order, last: Decimal) -> Result<Option<Order>, String> {
+    let Some(fill_price) = order.fill_price(last) else {
+        return Ok(None);
+    };
+    let mut client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let mut tx = client.transaction().await.map_err(|e| e.to_string())?;
+    let claimed = tx.execute(
+        "UPDATE orders SET status = 'FILLED', fill_price = $2, filled_at = now() WHERE order_id = $1 AND status = 'OPEN'",
+        &[&order.order_id, &fill_price],
+    ).await.map_err(|e| format!("No se pudo actualizar la orden: {}", e))?;
+    if claimed == 0 {
+        return Ok(None);
+    }
+
+    let mut entry = NewLedgerEntry::trade(order.portfolio_id, &order.ticker, order.side, order.quantity, fill_price);
+    entry.notes = Some(format!("Orden simulada #{} ({})", order.order_id, order.order_type.as_str()));
+    // El ledger escribe en un savepoint para poder deshacer solo su parte si rechaza la operación
+    let trade = tx.transaction().await.map_err(|e| e.to_string())?;
+    let recorded = ledger::record_trade_in(&*trade, entry, CashSource::Portfolio).await;
+    let row = match recorded {
+        Ok(recorded) => {
+            trade.commit().await.map_err(|e| e.to_string())?;
+            tx.query_one(
+                &format!("UPDATE orders SET transaction_id = $2 WHERE order_id = $1 RETURNING {}", ORDER_COLUMNS),
+                &[&order.order_id, &recorded.transaction_id],
+            ).await
+        }
+        Err(reason) => {
+            trade.rollback().await.map_err(|e| e.to_string())?;
+            tx.query_one(
+                &format!(
+                    "UPDATE orders SET status = 'REJECTED', fill_price = NULL, filled_at = NULL, reject_reason = $2
+                     WHERE order_id = $1 RETURNING {}",
+                    ORDER_COLUMNS
+                ),
+                &[&order.order_id, &reason],
+            ).await
+        }
+    }.map_err(|e| format!("No se pudo actualizar la orden: {}", e))?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(Some(order_from_row(&row)?))
+}
+
+/// Revisa las órdenes abiertas contra la última cotización de cada ticker. Devuelve las
+/// órdenes que se ejecutaron o rechazaron. Fuera del horario de mercado no hace nada.
+pub async fn process_open_orders_logic(db_pool: &Pool) -> Result<Vec<Order>, String> {
+    if !is_market_open(Utc::now()) {
+        return Ok(Vec::new());
+    }
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM orders WHERE status = 'OPEN' ORDER BY created_at, order_id", ORDER_COLUMNS),
+        &[],
+    ).await.map_err(|e| format!("Error al consultar las órdenes: {}", e))?;
+    drop(client);
+    let open: Vec<Order> = rows.iter().map(order_from_row).collect::<Result<_, _>>()?;
+
+    let mut quotes: HashMap<String, Option<Decimal>> = HashMap::new();
+    let mut processed = Vec::new();
+    for order in open {
+        if !quotes.contains_key(&order.ticker) {
+            quotes.insert(order.ticker.clone(), valuation::market_price_mxn(&order.ticker).await);
+        }
+        let Some(last) = quotes[&order.ticker] else { continue };
+        if let Some(updated) = try_fill(db_pool, &order, last).await? {
+            processed.push(updated);
+        }
+    }
+    Ok(processed)
+}
+
+/// Ciclo en segundo plano que ejecuta las órdenes simuladas mientras la aplicación corre.
+pub async fn run_order_matcher(db_pool: Arc<Pool>) {
+    let mut interval = tokio::time::interval(MATCHER_INTERVAL);
+    loop {
+        interval.tick().await;
+        match process_open_orders_logic(&db_pool).await {
+            Ok(processed) if !processed.is_empty() => {
+                println!("[ORDERS] {} órdenes simuladas procesadas", processed.len());
+            }
+            Ok(_) => {}
+            Err(e) => println!("[ORDERS] Error al procesar órdenes: {}", e),
+        }
+    }
+}
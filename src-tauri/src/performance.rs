@@ -0,0 +1,269 @@
+use chrono::{Datelike, Months, NaiveDate};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::money;
+use crate::snapshots;
+use crate::valuation::DailyValuation;
+
+const DAYS_PER_YEAR: f64 = 365.0;
+
+/// Rendimientos de un periodo, en porcentaje.
+/// TWR elimina el efecto de los depósitos y retiros; MWR (XIRR) lo incluye.
+/// Los anualizados solo se reportan para periodos de un año o más.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeriodReturn {
+    pub period: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub days: i64,
+    pub twr: Option<Decimal>,
+    pub twr_annualized: Option<Decimal>,
+    pub mwr: Option<Decimal>,
+    pub mwr_annualized: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PerformanceReport {
+    pub portfolio_id: i32,
+    pub inception_date: Option<NaiveDate>,
+    pub as_of: Option<NaiveDate>,
+    pub periods: Vec<PeriodReturn>,
+}
+
+pub fn to_f64(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+pub fn to_percent(ratio: f64) -> Option<Decimal> {
+    if !ratio.is_finite() {
+        return None;
+    }
+    Decimal::from_f64(ratio * 100.0).map(|p| p.round_dp(money::PERCENT_SCALE))
+}
+
+fn annualize(cumulative: f64, days: i64) -> f64 {
+    (1.0 + cumulative).powf(DAYS_PER_YEAR / days as f64) - 1.0
+}
+
+/// Rendimiento diario con los flujos al inicio del día: r = V_t / (V_{t-1} + F_t) - 1.
+pub fn daily_returns(history: &[DailyValuation]) -> Vec<(NaiveDate, f64)> {
+    history.windows(2)
+        .map(|pair| {
+            let invested = to_f64(pair[0].total_value + pair[1].net_flow);
+            let r = if invested > 0.0 { to_f64(pair[1].total_value) / invested - 1.0 } else { 0.0 };
+            (pair[1].date, r)
+        })
+        .collect()
+}
+
+/// TWR acumulado entre el cierre de `history[base]` y el cierre de `history[end]`.
+fn twr(history: &[DailyValuation], base: usize, end: usize) -> f64 {
+    daily_returns(&history[base..=end]).iter().fold(1.0, |acc, (_, r)| acc * (1.0 + r)) - 1.0
+}
+
+fn xnpv(rate: f64, flows: &[(NaiveDate, f64)]) -> f64 {
+    let start = flows[0].0;
+    flows.iter()
+        .map(|(date, amount)| amount / (1.0 + rate).powf((*date - start).num_days() as f64 / DAYS_PER_YEAR))
+        .sum()
+}
+
+/// Tasa anual que hace cero el valor presente de `flows` (negativos = aportaciones).
+/// Newton-Raphson y, si no converge, bisección.
+pub fn xirr(flows: &[(NaiveDate, f64)]) -> Option<f64> {
+    let has_positive = flows.iter().any(|(_, a)| *a > 0.0);
+    let has_negative = flows.iter().any(|(_, a)| *a < 0.0);
+    if flows.len() < 2 || !has_positive || !has_negative {
+        return None;
+    }
+
+    let mut rate = 0.1;
+    for _ in 0..100 {
+        let value = xnpv(rate, flows);
+        let step = 1e-6;
+        let derivative = (xnpv(rate + step, flows) - value) / step;
+        if derivative == 0.0 || !derivative.is_finite() {
+            break;
+        }
+        let next = rate - value / derivative;
+        if !next.is_finite() || next <= -1.0 {
+            break;
+        }
+        if (next - rate).abs() < 1e-10 {
+            return Some(next);
+        }
+        rate = next;
+    }
+
+    let (mut low, mut high) = (-0.9999, 10.0);
+    let mut low_value = xnpv(low, flows);
+    if low_value * xnpv(high, flows) > 0.0 {
+        return None;
+    }
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        let mid_value = xnpv(mid, flows);
+        if mid_value.abs() < 1e-9 {
+            return Some(mid);
+        }
+        if mid_value * low_value < 0.0 {
+            high = mid;
+        } else {
+            low = mid;
+            low_value = mid_value;
+        }
+    }
+    Some((low + high) / 2.0)
+}
+
+/// Flujos para XIRR vistos por el inversionista: valor inicial y depósitos como aportación,
+/// retiros y valor final como recuperación.
+fn mwr_flows(history: &[DailyValuation], base: usize, end: usize) -> Vec<(NaiveDate, f64)> {
+    let mut flows = vec![(history[base].date, -to_f64(history[base].total_value))];
+    for day in &history[base + 1..=end] {
+        if !day.net_flow.is_zero() {
+            flows.push((day.date, -to_f64(day.net_flow)));
+        }
+    }
+    flows.push((history[end].date, to_f64(history[end].total_value)));
+    flows
+}
+
+fn period_return(period: &str, history: &[DailyValuation], base: usize) -> PeriodReturn {
+    let end = history.len() - 1;
+    let start_date = history[base].date;
+    let end_date = history[end].date;
+    let days = (end_date - start_date).num_days();
+    let annualizable = days >= DAYS_PER_YEAR as i64;
+
+    let (twr_cumulative, xirr_rate) = if base < end {
+        (Some(twr(history, base, end)), xirr(&mwr_flows(history, base, end)))
+    } else {
+        (None, None)
+    };
+
+    PeriodReturn {
+        period: period.to_string(),
+        start_date,
+        end_date,
+        days,
+        twr: twr_cumulative.and_then(to_percent),
+        twr_annualized: twr_cumulative.filter(|_| annualizable).and_then(|r| to_percent(annualize(r, days))),
+        mwr: xirr_rate.and_then(|r| to_percent((1.0 + r).powf(days as f64 / DAYS_PER_YEAR) - 1.0)),
+        mwr_annualized: xirr_rate.filter(|_| annualizable).and_then(to_percent),
+    }
+}
+
+/// MTD, QTD, YTD, 1Y, 3Y y desde el inicio, a partir del historial diario de valuación.
+pub fn performance_periods(history: &[DailyValuation]) -> Vec<PeriodReturn> {
+    let (Some(first), Some(last)) = (history.first(), history.last()) else {
+        return Vec::new();
+    };
+    let as_of = last.date;
+    let month_start = as_of.with_day(1).unwrap();
+    let quarter_start = NaiveDate::from_ymd_opt(as_of.year(), (as_of.month0() / 3) * 3 + 1, 1).unwrap();
+    let year_start = NaiveDate::from_ymd_opt(as_of.year(), 1, 1).unwrap();
+
+    // (periodo, cierre desde el que se mide, requiere historia completa)
+    let boundaries = [
+        ("MTD", month_start.pred_opt().unwrap(), false),
+        ("QTD", quarter_start.pred_opt().unwrap(), false),
+        ("YTD", year_start.pred_opt().unwrap(), false),
+        ("1Y", as_of - Months::new(12), true),
+        ("3Y", as_of - Months::new(36), true),
+        ("ITD", first.date, false),
+    ];
+
+    boundaries.iter()
+        .filter(|(_, boundary, full_history)| !full_history || *boundary >= first.date)
+        .map(|(period, boundary, _)| {
+            // Último cierre en o antes del límite; si el portafolio es más nuevo, desde su inicio
+            let base = history.iter().rposition(|d| d.date <= *boundary).unwrap_or(0);
+            period_return(period, history, base)
+        })
+        .collect()
+}
+
+pub async fn get_portfolio_performance_logic(
+    portfolio_id: i32,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<PerformanceReport, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let history = snapshots::valuation_series(&**client, portfolio_id).await?;
+    Ok(PerformanceReport {
+        portfolio_id,
+        inception_date: history.first().map(|d| d.date),
+        as_of: history.last().map(|d| d.date),
+        periods: performance_periods(&history),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    fn valuation(date: NaiveDate, total_value: i64, net_flow: i64) -> DailyValuation {
+        DailyValuation {
+            date,
+            market_value: Decimal::from(total_value),
+            cash: Decimal::ZERO,
+            total_value: Decimal::from(total_value),
+            net_flow: Decimal::from(net_flow),
+        }
+    }
+
+    #[test]
+    fn xirr_converges_to_the_annual_rate() {
+        // 1,000 invertidos que valen 1,100 a los 365 días rinden 10% anual
+        let start = date(1, 1);
+        let flows = [(start, -1000.0), (start + chrono::Duration::days(365), 1100.0)];
+        let rate = xirr(&flows).unwrap();
+        assert!((rate - 0.10).abs() < 1e-6, "xirr = {}", rate);
+        assert!(xnpv(rate, &flows).abs() < 1e-6);
+    }
+
+    #[test]
+    fn xirr_with_intermediate_contribution_zeroes_npv() {
+        let flows = [(date(1, 1), -1000.0), (date(4, 1), -500.0), (date(12, 31), 1650.0)];
+        let rate = xirr(&flows).unwrap();
+        assert!(rate > 0.0);
+        assert!(xnpv(rate, &flows).abs() < 1e-6);
+    }
+
+    #[test]
+    fn xirr_without_sign_change_has_no_solution() {
+        assert_eq!(xirr(&[(date(1, 1), -1000.0), (date(6, 1), -500.0)]), None);
+        assert_eq!(xirr(&[(date(1, 1), 1000.0), (date(6, 1), 500.0)]), None);
+        assert_eq!(xirr(&[(date(1, 1), -1000.0)]), None);
+    }
+
+    #[test]
+    fn daily_returns_put_flows_at_the_start_of_the_day() {
+        let history = [
+            valuation(date(1, 1), 1000, 0),
+            valuation(date(1, 2), 1100, 0),
+            // Un depósito de 900 no es rendimiento: 2,000 / (1,100 + 900) - 1 = 0
+            valuation(date(1, 3), 2000, 900),
+            valuation(date(1, 4), 1900, 0),
+        ];
+        let returns = daily_returns(&history);
+        assert_eq!(returns.len(), 3);
+        assert_eq!(returns[0].0, date(1, 2));
+        assert!((returns[0].1 - 0.10).abs() < 1e-12);
+        assert!(returns[1].1.abs() < 1e-12);
+        assert!((returns[2].1 + 0.05).abs() < 1e-12);
+        assert!((twr(&history, 0, 3) - (1.1 * 0.95 - 1.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn daily_returns_are_zero_without_invested_capital() {
+        let history = [valuation(date(1, 1), 0, 0), valuation(date(1, 2), 0, 0)];
+        assert_eq!(daily_returns(&history), vec![(date(1, 2), 0.0)]);
+    }
+}
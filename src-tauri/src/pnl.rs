@@ -0,0 +1,247 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::ledger::{LedgerEntry, TransactionKind};
+use crate::lots::{self, ClosedLot};
+use crate::money;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PnlPeriod {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl PnlPeriod {
+    fn label(&self, date: NaiveDate) -> String {
+        match self {
+            PnlPeriod::Month => format!("{}-{:02}", date.year(), date.month()),
+            PnlPeriod::Quarter => format!("{}-T{}", date.year(), (date.month() - 1) / 3 + 1),
+            PnlPeriod::Year => date.year().to_string(),
+        }
+    }
+}
+
+impl FromStr for PnlPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "month" | "mes" => Ok(PnlPeriod::Month),
+            "quarter" | "trimestre" => Ok(PnlPeriod::Quarter),
+            "year" | "año" | "anio" => Ok(PnlPeriod::Year),
+            _ => Err(format!("Periodo desconocido: {}", s)),
+        }
+    }
+}
+
+// Los montos se reportan en pesos, al tipo de cambio de la compra y de la venta de cada lote
+
+/// Ganancia realizada de una venta, sumando los lotes que consumió.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RealizedTrade {
+    pub sell_transaction_id: i32,
+    pub ticker: String,
+    pub date: DateTime<Utc>,
+    pub quantity: Decimal,
+    pub cost_basis: Decimal,
+    pub proceeds: Decimal,
+    pub realized_gain: Decimal,
+    pub return_percent: Decimal,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RealizedSummary {
+    pub key: String,
+    pub quantity: Decimal,
+    pub cost_basis: Decimal,
+    pub proceeds: Decimal,
+    pub realized_gain: Decimal,
+    pub return_percent: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RealizedPnlReport {
+    pub trades: Vec<RealizedTrade>,
+    pub by_ticker: Vec<RealizedSummary>,
+    pub by_period: Vec<RealizedSummary>,
+    pub total_realized: Decimal,
+}
+
+/// Una posición que se abrió desde cero y se vendió por completo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosedPosition {
+    pub ticker: String,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: DateTime<Utc>,
+    pub holding_days: i64,
+    pub quantity: Decimal,
+    pub average_entry_price: Decimal,
+    pub average_exit_price: Decimal,
+    pub cost_basis: Decimal,
+    pub proceeds: Decimal,
+    pub realized_gain: Decimal,
+    pub return_percent: Decimal,
+}
+
+impl RealizedSummary {
+    fn add(&mut self, lot: &ClosedLot) {
+        self.quantity += lot.quantity;
+        self.cost_basis += lot.cost_basis_mxn();
+        self.proceeds += lot.proceeds_mxn();
+        self.realized_gain += lot.realized_gain_mxn();
+    }
+
+    fn finish(mut self) -> Self {
+        self.return_percent = money::percent(self.realized_gain, self.cost_basis);
+        self
+    }
+}
+
+fn in_range(date: DateTime<Utc>, from: Option<NaiveDate>, to: Option<NaiveDate>) -> bool {
+    let day = date.date_naive();
+    !matches!(from, Some(f) if day < f) && !matches!(to, Some(t) if day > t)
+}
+
+pub fn realized_report(
+    closed_lots: &[ClosedLot],
+    period: PnlPeriod,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> RealizedPnlReport {
+    let lots: Vec<&ClosedLot> = closed_lots.iter().filter(|l| in_range(l.disposed_at, from, to)).collect();
+
+    let mut trades: Vec<RealizedTrade> = Vec::new();
+    for lot in &lots {
+        match trades.iter_mut().find(|t| t.sell_transaction_id == lot.sell_transaction_id) {
+            Some(trade) => {
+                trade.quantity += lot.quantity;
+                trade.cost_basis += lot.cost_basis_mxn();
+                trade.proceeds += lot.proceeds_mxn();
+                trade.realized_gain += lot.realized_gain_mxn();
+            }
+            None => trades.push(RealizedTrade {
+                sell_transaction_id: lot.sell_transaction_id,
+                ticker: lot.ticker.clone(),
+                date: lot.disposed_at,
+                quantity: lot.quantity,
+                cost_basis: lot.cost_basis_mxn(),
+                proceeds: lot.proceeds_mxn(),
+                realized_gain: lot.realized_gain_mxn(),
+                return_percent: Decimal::ZERO,
+            }),
+        }
+    }
+    for trade in trades.iter_mut() {
+        trade.return_percent = money::percent(trade.realized_gain, trade.cost_basis);
+    }
+
+    let mut by_ticker: BTreeMap<String, RealizedSummary> = BTreeMap::new();
+    let mut by_period: BTreeMap<String, RealizedSummary> = BTreeMap::new();
+    for lot in &lots {
+        by_ticker.entry(lot.ticker.clone())
+            .or_insert_with(|| RealizedSummary { key: lot.ticker.clone(), ..Default::default() })
+            .add(lot);
+        let label = period.label(lot.disposed_at.date_naive());
+        by_period.entry(label.clone())
+            .or_insert_with(|| RealizedSummary { key: label, ..Default::default() })
+            .add(lot);
+    }
+
+    RealizedPnlReport {
+        total_realized: lots.iter().map(|l| l.realized_gain_mxn()).sum(),
+        trades,
+        by_ticker: by_ticker.into_values().map(RealizedSummary::finish).collect(),
+        by_period: by_period.into_values().map(RealizedSummary::finish).collect(),
+    }
+}
+
+/// Agrupa las operaciones de cada ticker en ciclos que empiezan con la posición en cero
+/// y terminan cuando vuelve a cero. Los ciclos aún abiertos no se reportan.
+pub fn closed_positions(entries: &[LedgerEntry], closed_lots: &[ClosedLot]) -> Vec<ClosedPosition> {
+    struct Cycle {
+        opened_at: DateTime<Utc>,
+        quantity: Decimal,
+        sells: HashSet<i32>,
+    }
+
+    let mut cycles: HashMap<String, Cycle> = HashMap::new();
+    let mut positions = Vec::new();
+
+    for entry in entries.iter().filter(|e| e.transaction_type.is_trade()) {
+        match entry.transaction_type {
+            TransactionKind::Buy => {
+                cycles.entry(entry.ticker.clone())
+                    .or_insert_with(|| Cycle { opened_at: entry.transaction_date, quantity: Decimal::ZERO, sells: HashSet::new() })
+                    .quantity += entry.quantity;
+            }
+            TransactionKind::Sell => {
+                let Some(cycle) = cycles.get_mut(&entry.ticker) else { continue };
+                cycle.quantity -= entry.quantity;
+                cycle.sells.insert(entry.transaction_id);
+                if cycle.quantity > Decimal::ZERO {
+                    continue;
+                }
+                let cycle = cycles.remove(&entry.ticker).unwrap();
+                let mut summary = RealizedSummary::default();
+                for lot in closed_lots.iter().filter(|l| cycle.sells.contains(&l.sell_transaction_id)) {
+                    summary.add(lot);
+                }
+                let (average_entry_price, average_exit_price) = if summary.quantity > Decimal::ZERO {
+                    (
+                        money::round_price(summary.cost_basis / summary.quantity),
+                        money::round_price(summary.proceeds / summary.quantity),
+                    )
+                } else {
+                    (Decimal::ZERO, Decimal::ZERO)
+                };
+                positions.push(ClosedPosition {
+                    ticker: entry.ticker.clone(),
+                    opened_at: cycle.opened_at,
+                    closed_at: entry.transaction_date,
+                    holding_days: (entry.transaction_date - cycle.opened_at).num_days(),
+                    quantity: summary.quantity,
+                    average_entry_price,
+                    average_exit_price,
+                    cost_basis: summary.cost_basis,
+                    proceeds: summary.proceeds,
+                    realized_gain: summary.realized_gain,
+                    return_percent: money::percent(summary.realized_gain, summary.cost_basis),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    positions.sort_by_key(|p| std::cmp::Reverse(p.closed_at));
+    positions
+}
+
+pub async fn get_realized_pnl_logic(
+    portfolio_id: i32,
+    period: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<RealizedPnlReport, String> {
+    let period = match period {
+        Some(p) => p.parse()?,
+        None => PnlPeriod::Month,
+    };
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let book = lots::load_lot_book(&**client, portfolio_id).await?;
+    Ok(realized_report(&book.closed_lots, period, from, to))
+}
+
+pub async fn get_closed_positions_logic(
+    portfolio_id: i32,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<ClosedPosition>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let (entries, book) = lots::load_trades_with_lots(&**client, portfolio_id).await?;
+    Ok(closed_positions(&entries, &book.closed_lots))
+}
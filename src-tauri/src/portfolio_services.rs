@@ -1,24 +1,48 @@
-use std::sync::Arc;
 use tauri::State;
-use deadpool_postgres::Pool;
 use chrono::NaiveDate;
+use rust_decimal::Decimal;
 
+use crate::AppState;
+use crate::allocation;
+use crate::backtest;
+use crate::cash;
+use crate::contributions;
+use crate::benchmarks;
+use crate::dividends;
+use crate::export;
+use crate::fees;
+use crate::fixed_income;
+use crate::fx;
+use crate::importer;
+use crate::isr;
+use crate::ledger::LedgerEntry;
+use crate::ledger_audit;
+use crate::lots;
+use crate::montecarlo;
+use crate::optimizer;
+use crate::orders;
+use crate::performance;
+use crate::pnl;
 use crate::portfolio;
+use crate::risk;
+use crate::snapshots;
+use crate::stress;
+use crate::yield_curve;
 
 #[tauri::command(async)]
 pub async fn get_portfolio_summary(
     portfolio_id: i32,
-    db_pool: State<'_, Arc<Pool>>,
+    state: State<'_, AppState>,
 ) -> Result<portfolio::PortfolioSummary, String> {
-    portfolio::get_portfolio_summary_logic(portfolio_id, db_pool).await
+    portfolio::get_portfolio_summary_logic(portfolio_id, &state.db_pool).await
 }
 
 #[tauri::command(async)]
 pub async fn add_cash_movement(
     payload: portfolio::AddCashMovementPayload, 
-    db_pool: State<'_, Arc<Pool>>,
-) -> Result<portfolio::CashFlow, String> {
-    portfolio::add_cash_movement_logic(payload, db_pool).await
+    state: State<'_, AppState>,
+) -> Result<LedgerEntry, String> {
+    portfolio::add_cash_movement_logic(payload, &state.db_pool).await
 }
 
 #[tauri::command(async)]
@@ -26,12 +50,13 @@ pub async fn add_asset_transaction(
     portfolio_id: i32,
     ticker: String,
     transaction_type: String,
-    quantity: f64,
-    price: f64,
+    quantity: Decimal,
+    price: Decimal,
     transaction_date: NaiveDate,
     use_cash_from_portfolio: bool,
-    db_pool: State<'_, Arc<Pool>>,
-) -> Result<portfolio::AssetTransaction, String> {
+    currency: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<LedgerEntry, String> {
     portfolio::add_asset_transaction_logic(
         portfolio_id,
         ticker,
@@ -40,7 +65,8 @@ pub async fn add_asset_transaction(
         price,
         transaction_date,
         use_cash_from_portfolio,
-        db_pool,
+        currency,
+        &state.db_pool,
     )
     .await
 }
@@ -48,7 +74,581 @@ pub async fn add_asset_transaction(
 #[tauri::command(async)]
 pub async fn delete_transaction(
     transaction_id: i32,
-    db_pool: State<'_, Arc<Pool>>,
+    state: State<'_, AppState>,
 ) -> Result<String, String> {
-    portfolio::delete_transaction_logic(transaction_id, db_pool).await
-}
\ No newline at end of file
+    portfolio::delete_transaction_logic(transaction_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn update_transaction(
+    transaction_id: i32,
+    update: ledger_audit::EntryUpdate,
+    changed_by: Option<String>,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<LedgerEntry, String> {
+    ledger_audit::update_entry_logic(transaction_id, update, changed_by, reason, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn void_transaction(
+    transaction_id: i32,
+    changed_by: Option<String>,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<LedgerEntry, String> {
+    ledger_audit::void_entry_logic(transaction_id, changed_by, reason, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn undo_transaction_change(
+    transaction_id: i32,
+    changed_by: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<LedgerEntry, String> {
+    ledger_audit::undo_last_change_logic(transaction_id, changed_by, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_ledger_audit(
+    portfolio_id: i32,
+    transaction_id: Option<i32>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ledger_audit::AuditRecord>, String> {
+    ledger_audit::list_ledger_audit_logic(portfolio_id, transaction_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_tax_lots(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<lots::LotBook, String> {
+    let client = state.db_pool.get().await.map_err(|e| e.to_string())?;
+    lots::load_lot_book(&**client, portfolio_id).await
+}
+
+#[tauri::command(async)]
+pub async fn set_cost_basis_method(
+    portfolio_id: i32,
+    method: String,
+    state: State<'_, AppState>,
+) -> Result<lots::CostBasisMethod, String> {
+    lots::set_cost_basis_method_logic(portfolio_id, &method, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn assign_sale_lots(
+    sell_transaction_id: i32,
+    selections: Vec<lots::LotSelection>,
+    state: State<'_, AppState>,
+) -> Result<lots::LotBook, String> {
+    lots::assign_sale_lots_logic(sell_transaction_id, selections, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_realized_pnl(
+    portfolio_id: i32,
+    period: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    state: State<'_, AppState>,
+) -> Result<pnl::RealizedPnlReport, String> {
+    pnl::get_realized_pnl_logic(portfolio_id, period, from, to, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_closed_positions(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<pnl::ClosedPosition>, String> {
+    pnl::get_closed_positions_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_isr_annual_report(
+    portfolio_id: i32,
+    fiscal_year: i32,
+    state: State<'_, AppState>,
+) -> Result<isr::IsrAnnualReport, String> {
+    isr::get_isr_annual_report_logic(portfolio_id, fiscal_year, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn export_isr_annual_report(
+    portfolio_id: i32,
+    fiscal_year: i32,
+    format: String,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    isr::export_isr_annual_report_logic(portfolio_id, fiscal_year, &format, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn save_inpc_values(
+    values: Vec<isr::InpcValue>,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    isr::save_inpc_values_logic(values, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn record_dividend(
+    dividend: dividends::NewDividend,
+    state: State<'_, AppState>,
+) -> Result<dividends::DividendRecord, String> {
+    dividends::record_dividend_logic(dividend, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_dividends(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<dividends::DividendRecord>, String> {
+    let client = state.db_pool.get().await.map_err(|e| e.to_string())?;
+    dividends::list_dividends(&**client, portfolio_id).await
+}
+
+#[tauri::command(async)]
+pub async fn suggest_dividends(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<dividends::DividendSuggestion>, String> {
+    dividends::suggest_dividends_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_dividend_income(
+    portfolio_id: i32,
+    year: Option<i32>,
+    state: State<'_, AppState>,
+) -> Result<dividends::DividendIncomeReport, String> {
+    dividends::get_dividend_income_logic(portfolio_id, year, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_dividend_yield_on_cost(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<dividends::YieldOnCost>, String> {
+    dividends::get_dividend_yield_on_cost_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_fee_schedule(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<fees::FeeSchedule, String> {
+    fees::get_fee_schedule_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_fee_schedule(
+    schedule: fees::FeeSchedule,
+    state: State<'_, AppState>,
+) -> Result<fees::FeeSchedule, String> {
+    fees::set_fee_schedule_logic(schedule, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_fee_report(
+    portfolio_id: i32,
+    year: Option<i32>,
+    state: State<'_, AppState>,
+) -> Result<fees::FeeReport, String> {
+    fees::get_fee_report_logic(portfolio_id, year, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn save_fx_rates(
+    rates: Vec<fx::FxRate>,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    fx::save_fx_rates_logic(rates, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn refresh_fx_rates(
+    state: State<'_, AppState>,
+) -> Result<Vec<fx::FxRate>, String> {
+    fx::refresh_fx_rates_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_fx_rates(
+    currency: String,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    state: State<'_, AppState>,
+) -> Result<Vec<fx::FxRate>, String> {
+    fx::list_fx_rates_logic(currency, from, to, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_portfolio_performance(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<performance::PerformanceReport, String> {
+    performance::get_portfolio_performance_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_portfolio_history(
+    portfolio_id: i32,
+    range: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<snapshots::PortfolioSnapshot>, String> {
+    snapshots::get_portfolio_history_logic(portfolio_id, range, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn compare_to_benchmark(
+    portfolio_id: i32,
+    components: Vec<benchmarks::BenchmarkComponent>,
+    range: String,
+    state: State<'_, AppState>,
+) -> Result<benchmarks::BenchmarkComparison, String> {
+    benchmarks::compare_to_benchmark_logic(portfolio_id, components, range, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn save_benchmark_levels(
+    levels: Vec<benchmarks::BenchmarkLevel>,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    benchmarks::save_benchmark_levels_logic(levels, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn refresh_benchmark_levels(
+    state: State<'_, AppState>,
+) -> Result<Vec<benchmarks::BenchmarkLevel>, String> {
+    benchmarks::refresh_benchmark_levels_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_allocation(
+    portfolio_id: i32,
+    dimension: String,
+    state: State<'_, AppState>,
+) -> Result<allocation::AllocationReport, String> {
+    allocation::get_allocation_logic(portfolio_id, &dimension, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_allocation_targets(
+    portfolio_id: i32,
+    dimension: String,
+    targets: Vec<allocation::AllocationTarget>,
+    state: State<'_, AppState>,
+) -> Result<Vec<allocation::AllocationTarget>, String> {
+    allocation::set_allocation_targets_logic(portfolio_id, &dimension, targets, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_ticker_classification(
+    classification: allocation::TickerClassification,
+    state: State<'_, AppState>,
+) -> Result<allocation::TickerClassification, String> {
+    allocation::set_ticker_classification_logic(classification, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_rebalance_plan(
+    portfolio_id: i32,
+    dimension: String,
+    options: Option<allocation::RebalanceOptions>,
+    state: State<'_, AppState>,
+) -> Result<allocation::RebalancePlan, String> {
+    allocation::get_rebalance_plan_logic(portfolio_id, &dimension, options.unwrap_or_default(), &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_portfolio_risk(
+    portfolio_id: i32,
+    confidence: Option<Decimal>,
+    horizon: Option<u32>,
+    state: State<'_, AppState>,
+) -> Result<risk::RiskReport, String> {
+    risk::get_portfolio_risk_logic(portfolio_id, confidence, horizon, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn run_monte_carlo(
+    portfolio_id: i32,
+    params: montecarlo::SimulationParams,
+    state: State<'_, AppState>,
+) -> Result<montecarlo::SimulationResult, String> {
+    montecarlo::run_monte_carlo_logic(portfolio_id, params, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn optimize_portfolio(
+    params: optimizer::OptimizerParams,
+    state: State<'_, AppState>,
+) -> Result<optimizer::OptimizationResult, String> {
+    optimizer::optimize_portfolio_logic(params, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn apply_frontier_point(
+    portfolio_id: i32,
+    weights: Vec<optimizer::TickerWeight>,
+    cash_weight: Option<Decimal>,
+    state: State<'_, AppState>,
+) -> Result<Vec<allocation::AllocationTarget>, String> {
+    optimizer::apply_frontier_point_logic(portfolio_id, weights, cash_weight, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_stress_scenarios(state: State<'_, AppState>) -> Result<Vec<stress::StressScenario>, String> {
+    stress::list_stress_scenarios_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn save_stress_scenario(
+    scenario: stress::StressScenario,
+    state: State<'_, AppState>,
+) -> Result<stress::StressScenario, String> {
+    stress::save_stress_scenario_logic(scenario, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn delete_stress_scenario(scenario_id: i32, state: State<'_, AppState>) -> Result<String, String> {
+    stress::delete_stress_scenario_logic(scenario_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn run_stress_test(
+    portfolio_id: i32,
+    scenario_id: Option<i32>,
+    scenario: Option<stress::StressScenario>,
+    state: State<'_, AppState>,
+) -> Result<stress::StressResult, String> {
+    stress::run_stress_test_logic(portfolio_id, scenario_id, scenario, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn run_all_stress_tests(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<stress::StressResult>, String> {
+    stress::run_all_stress_tests_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn run_backtest(
+    params: backtest::BacktestParams,
+    state: State<'_, AppState>,
+) -> Result<backtest::BacktestResult, String> {
+    backtest::run_backtest_logic(params, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_paper_portfolio(
+    portfolio_id: i32,
+    is_paper: bool,
+    state: State<'_, AppState>,
+) -> Result<bool, String> {
+    orders::set_paper_portfolio_logic(portfolio_id, is_paper, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn place_order(order: orders::NewOrder, state: State<'_, AppState>) -> Result<orders::Order, String> {
+    orders::place_order_logic(order, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn cancel_order(order_id: i32, state: State<'_, AppState>) -> Result<orders::Order, String> {
+    orders::cancel_order_logic(order_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_orders(
+    portfolio_id: i32,
+    status: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<orders::Order>, String> {
+    orders::list_orders_logic(portfolio_id, status, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn process_open_orders(state: State<'_, AppState>) -> Result<Vec<orders::Order>, String> {
+    orders::process_open_orders_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn create_contribution_plan(
+    plan: contributions::NewContributionPlan,
+    state: State<'_, AppState>,
+) -> Result<contributions::ContributionPlan, String> {
+    contributions::create_contribution_plan_logic(plan, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_contribution_plans(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<contributions::ContributionPlan>, String> {
+    contributions::list_contribution_plans_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_contribution_plan_status(
+    plan_id: i32,
+    status: String,
+    state: State<'_, AppState>,
+) -> Result<contributions::ContributionPlan, String> {
+    contributions::set_contribution_plan_status_logic(plan_id, status, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn delete_contribution_plan(plan_id: i32, state: State<'_, AppState>) -> Result<String, String> {
+    contributions::delete_contribution_plan_logic(plan_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_pending_contributions(
+    portfolio_id: i32,
+    status: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<contributions::PendingContribution>, String> {
+    contributions::list_pending_contributions_logic(portfolio_id, status, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn confirm_contribution(
+    pending_id: i32,
+    price: Option<Decimal>,
+    state: State<'_, AppState>,
+) -> Result<contributions::PendingContribution, String> {
+    contributions::confirm_contribution_logic(pending_id, price, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn skip_contribution(pending_id: i32, state: State<'_, AppState>) -> Result<String, String> {
+    contributions::skip_contribution_logic(pending_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn run_contribution_scheduler(
+    state: State<'_, AppState>,
+) -> Result<Vec<contributions::PendingContribution>, String> {
+    contributions::run_contribution_scheduler_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn buy_fixed_income(
+    purchase: fixed_income::FixedIncomePurchase,
+    state: State<'_, AppState>,
+) -> Result<LedgerEntry, String> {
+    fixed_income::buy_fixed_income_logic(purchase, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn list_fixed_income_instruments(
+    state: State<'_, AppState>,
+) -> Result<Vec<fixed_income::FixedIncomeInstrument>, String> {
+    fixed_income::list_instruments_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_fixed_income_valuation(
+    ticker: String,
+    valuation_method: String,
+    spread: Option<Decimal>,
+    state: State<'_, AppState>,
+) -> Result<fixed_income::FixedIncomeInstrument, String> {
+    fixed_income::set_fixed_income_valuation_logic(ticker, valuation_method, spread, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_fixed_income_positions(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<fixed_income::FixedIncomePosition>, String> {
+    fixed_income::get_fixed_income_positions_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn process_fixed_income(state: State<'_, AppState>) -> Result<Vec<fixed_income::FixedIncomeFlow>, String> {
+    fixed_income::process_fixed_income_logic(&state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_yield_curve(
+    date: Option<NaiveDate>,
+    method: Option<String>,
+    tenors: Option<Vec<i64>>,
+    state: State<'_, AppState>,
+) -> Result<yield_curve::YieldCurveView, String> {
+    yield_curve::get_yield_curve_logic(date, method, tenors, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn build_yield_curves(from: Option<NaiveDate>, state: State<'_, AppState>) -> Result<usize, String> {
+    yield_curve::build_yield_curves_logic(from, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn preview_import(
+    request: importer::ImportRequest,
+    state: State<'_, AppState>,
+) -> Result<importer::ImportPreview, String> {
+    importer::preview_import_logic(request, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn import_transactions(
+    request: importer::ImportRequest,
+    state: State<'_, AppState>,
+) -> Result<importer::ImportResult, String> {
+    importer::import_transactions_logic(request, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn export_portfolio_ledger(
+    portfolio_id: i32,
+    format: String,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    include_snapshots: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    export::export_ledger_logic(portfolio_id, &format, from, to, include_snapshots.unwrap_or(false), &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_cash_accounts(
+    portfolio_id: i32,
+    state: State<'_, AppState>,
+) -> Result<Vec<cash::CashAccountStatus>, String> {
+    cash::list_cash_accounts_logic(portfolio_id, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn set_cash_account(
+    account: cash::CashAccount,
+    state: State<'_, AppState>,
+) -> Result<cash::CashAccount, String> {
+    cash::set_cash_account_logic(account, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn accrue_cash_interest(
+    portfolio_id: Option<i32>,
+    through: Option<NaiveDate>,
+    state: State<'_, AppState>,
+) -> Result<Vec<LedgerEntry>, String> {
+    cash::accrue_interest_logic(portfolio_id, through, &state.db_pool).await
+}
+
+#[tauri::command(async)]
+pub async fn get_cash_statement(
+    portfolio_id: i32,
+    currency: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    state: State<'_, AppState>,
+) -> Result<cash::CashStatement, String> {
+    cash::cash_statement_logic(portfolio_id, currency, from, to, &state.db_pool).await
+}
@@ -0,0 +1,74 @@
+use chrono::{Datelike, Duration, NaiveDate, Utc, Weekday};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use tokio_postgres::GenericClient;
+
+use crate::assets;
+use crate::fixed_income;
+use crate::money;
+
+// Serie de cierres diarios en pesos, por fecha
+pub type PriceSeries = BTreeMap<NaiveDate, Decimal>;
+
+/// Último día hábil (lunes a viernes) en o antes de `date`.
+pub fn last_business_day(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date - Duration::days(2),
+        _ => date,
+    }
+}
+
+pub fn is_business_day(date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+/// Último cierre conocido en o antes de `date`.
+pub fn close_on(series: &PriceSeries, date: NaiveDate) -> Option<Decimal> {
+    series.range(..=date).next_back().map(|(_, close)| *close)
+}
+
+async fn stored_closes<C: GenericClient>(client: &C, ticker: &str, from: NaiveDate) -> Result<PriceSeries, String> {
+    let rows = client.query(
+        "SELECT price_date, close FROM price_history WHERE ticker = $1 AND price_date >= $2 ORDER BY price_date",
+        &[&ticker, &from],
+    ).await.map_err(|e| format!("Error al consultar el histórico de {}: {}", ticker, e))?;
+    Ok(rows.iter().map(|r| (r.get("price_date"), r.get("close"))).collect())
+}
+
+async fn fetch_closes<C: GenericClient>(client: &C, ticker: &str, from: NaiveDate) -> Result<(), String> {
+    let days = (Utc::now().date_naive() - from).num_days().max(1);
+    let months = (days / 30 + 1) as i32;
+    let closes = assets::historical_data_intradia(ticker, months)
+        .await
+        .map_err(|e| format!("Error al descargar el histórico de {}: {}", ticker, e))?;
+    for close in closes {
+        let Some(price) = close.close.and_then(money::price_from_f64) else { continue };
+        client.execute(
+            "INSERT INTO price_history (ticker, price_date, close) VALUES ($1, $2, $3)
+             ON CONFLICT (ticker, price_date) DO UPDATE SET close = EXCLUDED.close",
+            &[&ticker, &close.date, &price],
+        ).await.map_err(|e| format!("No se pudo guardar el histórico de {}: {}", ticker, e))?;
+    }
+    Ok(())
+}
+
+/// Cierres diarios de `ticker` desde `from`. Se leen de price_history y solo se descargan
+/// de DataBursátil cuando faltan el inicio del rango o los días más recientes.
+pub async fn daily_closes<C: GenericClient>(client: &C, ticker: &str, from: NaiveDate) -> Result<PriceSeries, String> {
+    // Unos días antes para tener un cierre con qué arrancar si `from` no fue día hábil
+    let lookback = from - Duration::days(7);
+    let stored = stored_closes(client, ticker, lookback).await?;
+    let latest_expected = last_business_day(Utc::now().date_naive() - Duration::days(1));
+    let covers_start = stored.keys().next().is_some_and(|first| *first <= last_business_day(from));
+    let covers_end = stored.keys().next_back().is_some_and(|last| *last >= latest_expected);
+    if covers_start && covers_end {
+        return Ok(stored);
+    }
+    // Los instrumentos de deuda no cotizan en DataBursátil; sus precios los escribe fixed_income.rs
+    if fixed_income::instrument(client, ticker).await?.is_some() {
+        return Ok(stored);
+    }
+    fetch_closes(client, ticker, lookback).await?;
+    stored_closes(client, ticker, lookback).await
+}
@@ -0,0 +1,305 @@
+use chrono::{Duration, NaiveDate, Utc};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tokio_postgres::GenericClient;
+
+use crate::fx;
+use crate::money;
+use crate::performance::{to_f64, to_percent};
+use crate::prices::{self, PriceSeries};
+use crate::valuation;
+
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+pub const DEFAULT_HORIZON_DAYS: u32 = 10;
+// Un año de cierres para estimar volatilidades y correlaciones
+const LOOKBACK_DAYS: i64 = 365;
+pub const MIN_OBSERVATIONS: usize = 20;
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+/// VaR y CVaR a un horizonte, en pesos (pérdida como monto positivo).
+/// El paramétrico supone rendimientos normales con media cero y escala con la raíz del plazo;
+/// el histórico usa las pérdidas observadas acumuladas en ventanas de `days` días.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskHorizon {
+    pub days: u32,
+    pub parametric_var: Option<Decimal>,
+    pub parametric_cvar: Option<Decimal>,
+    pub historical_var: Option<Decimal>,
+    pub historical_cvar: Option<Decimal>,
+}
+
+/// Contribución de una posición al riesgo, con volatilidades anualizadas en porcentaje.
+/// marginal = ∂σ/∂w; componente = w × marginal (suman la volatilidad del portafolio);
+/// `component_var` es la parte del VaR paramétrico al horizonte pedido, en pesos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositionRisk {
+    pub ticker: String,
+    pub market_value: Decimal,
+    pub weight: Decimal,
+    pub volatility: Option<Decimal>,
+    pub marginal_contribution: Option<Decimal>,
+    pub component_contribution: Option<Decimal>,
+    pub percent_of_risk: Option<Decimal>,
+    pub component_var: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskReport {
+    pub portfolio_id: i32,
+    pub confidence: Decimal,
+    pub portfolio_value: Decimal,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub observations: usize,
+    pub daily_volatility: Option<Decimal>,
+    pub annual_volatility: Option<Decimal>,
+    pub horizons: Vec<RiskHorizon>,
+    pub positions: Vec<PositionRisk>,
+    pub warnings: Vec<String>,
+}
+
+/// Inversa de la normal estándar (aproximación racional de Acklam, error < 1.2e-9).
+#[allow(clippy::excessive_precision)]
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2, 1.383577518672690e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2, 6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    const P_LOW: f64 = 0.02425;
+
+    if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -normal_quantile(1.0 - p)
+    }
+}
+
+fn normal_density(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+fn to_mxn(value: f64) -> Option<Decimal> {
+    if !value.is_finite() {
+        return None;
+    }
+    Decimal::from_f64(value).map(money::round_mxn)
+}
+
+fn covariance(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+    a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum::<f64>() / (n - 1.0)
+}
+
+/// Rendimientos diarios alineados en los días hábiles en que todas las series tienen cierre.
+pub fn aligned_returns(series: &[PriceSeries], from: NaiveDate, to: NaiveDate) -> (Vec<NaiveDate>, Vec<Vec<f64>>) {
+    // Días con al menos un cierre real; el resto se llena con el último conocido
+    let days: BTreeSet<NaiveDate> = series.iter()
+        .flat_map(|s| s.range(from..=to).map(|(d, _)| *d))
+        .filter(|d| prices::is_business_day(*d))
+        .collect();
+    let mut dates = Vec::new();
+    let mut returns = vec![Vec::new(); series.len()];
+    let mut previous: Option<Vec<f64>> = None;
+    for day in days {
+        let closes: Option<Vec<f64>> = series.iter().map(|s| prices::close_on(s, day).map(to_f64)).collect();
+        let Some(closes) = closes else { continue };
+        if let Some(prev) = &previous {
+            for (i, (close, prev_close)) in closes.iter().zip(prev).enumerate() {
+                returns[i].push(if *prev_close > 0.0 { close / prev_close - 1.0 } else { 0.0 });
+            }
+            dates.push(day);
+        }
+        previous = Some(closes);
+    }
+    (dates, returns)
+}
+
+/// VaR y CVaR históricos de una serie de pérdidas y ganancias en pesos.
+fn historical_var(pnl: &[f64], confidence: f64) -> Option<(f64, f64)> {
+    if pnl.len() < MIN_OBSERVATIONS {
+        return None;
+    }
+    let mut sorted = pnl.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let tail = (((1.0 - confidence) * sorted.len() as f64).ceil() as usize).max(1);
+    let var = -sorted[tail - 1];
+    let cvar = -sorted[..tail].iter().sum::<f64>() / tail as f64;
+    Some((var, cvar))
+}
+
+/// Posiciones valuadas en pesos con sus rendimientos diarios alineados del último año.
+/// `returns[i][t]` es el rendimiento de `tickers[i]` en `dates[t]`.
+#[derive(Debug, Clone)]
+pub struct HoldingReturns {
+    pub tickers: Vec<String>,
+    pub values: Vec<f64>,
+    pub cash: f64,
+    pub dates: Vec<NaiveDate>,
+    pub returns: Vec<Vec<f64>>,
+}
+
+fn compute_risk(portfolio_id: i32, confidence: f64, horizon: u32, inputs: HoldingReturns, mut warnings: Vec<String>) -> RiskReport {
+    let HoldingReturns { tickers, values, cash, dates, returns } = inputs;
+    let portfolio_value = values.iter().sum::<f64>() + cash;
+    let weights: Vec<f64> = values.iter().map(|v| if portfolio_value > 0.0 { v / portfolio_value } else { 0.0 }).collect();
+    let observations = dates.len();
+    let enough = observations >= MIN_OBSERVATIONS && !tickers.is_empty();
+    if !enough {
+        warnings.push(format!("Se necesitan al menos {} rendimientos diarios comunes para estimar el riesgo", MIN_OBSERVATIONS));
+    }
+
+    let n = tickers.len();
+    let cov: Vec<Vec<f64>> = if enough {
+        (0..n).map(|i| (0..n).map(|j| covariance(&returns[i], &returns[j])).collect()).collect()
+    } else {
+        Vec::new()
+    };
+    // Σw y σ diaria del portafolio
+    let sigma_w: Vec<f64> = if enough {
+        (0..n).map(|i| (0..n).map(|j| cov[i][j] * weights[j]).sum()).collect()
+    } else {
+        Vec::new()
+    };
+    let sigma = if enough { weights.iter().zip(&sigma_w).map(|(w, s)| w * s).sum::<f64>().max(0.0).sqrt() } else { f64::NAN };
+    let z = normal_quantile(confidence);
+    let tail_factor = normal_density(z) / (1.0 - confidence);
+    let annual = TRADING_DAYS_PER_YEAR.sqrt();
+
+    // Pérdidas y ganancias diarias con las posiciones de hoy
+    let pnl: Vec<f64> = (0..observations)
+        .map(|t| (0..n).map(|i| values[i] * returns[i][t]).sum())
+        .collect();
+
+    let mut horizon_days = vec![1];
+    if horizon > 1 {
+        horizon_days.push(horizon);
+    }
+    let horizons = horizon_days.into_iter()
+        .map(|days| {
+            let scale = (days as f64).sqrt();
+            let windows: Vec<f64> = pnl.windows(days as usize).map(|w| w.iter().sum()).collect();
+            let historical = if enough { historical_var(&windows, confidence) } else { None };
+            RiskHorizon {
+                days,
+                parametric_var: to_mxn(z * sigma * scale * portfolio_value),
+                parametric_cvar: to_mxn(tail_factor * sigma * scale * portfolio_value),
+                historical_var: historical.and_then(|(var, _)| to_mxn(var)),
+                historical_cvar: historical.and_then(|(_, cvar)| to_mxn(cvar)),
+            }
+        })
+        .collect();
+
+    let horizon_scale = (horizon.max(1) as f64).sqrt();
+    let positions = (0..n)
+        .map(|i| {
+            let marginal = if enough && sigma > 0.0 { sigma_w[i] / sigma } else { f64::NAN };
+            let component = weights[i] * marginal;
+            PositionRisk {
+                ticker: tickers[i].clone(),
+                market_value: to_mxn(values[i]).unwrap_or_default(),
+                weight: to_percent(weights[i]).unwrap_or_default(),
+                volatility: if enough { to_percent(cov[i][i].sqrt() * annual) } else { None },
+                marginal_contribution: to_percent(marginal * annual),
+                component_contribution: to_percent(component * annual),
+                percent_of_risk: to_percent(component / sigma),
+                component_var: to_mxn(z * component * horizon_scale * portfolio_value),
+            }
+        })
+        .collect();
+
+    RiskReport {
+        portfolio_id,
+        confidence: Decimal::from_f64(confidence).map(|c| c.round_dp(4)).unwrap_or_default(),
+        portfolio_value: to_mxn(portfolio_value).unwrap_or_default(),
+        start_date: dates.first().copied(),
+        end_date: dates.last().copied(),
+        observations,
+        daily_volatility: to_percent(sigma),
+        annual_volatility: to_percent(sigma * annual),
+        horizons,
+        positions,
+        warnings,
+    }
+}
+
+/// Posiciones actuales con su histórico de rendimientos. Las que no tienen cotización o
+/// histórico suficiente se omiten con un aviso; el efectivo se suma en pesos.
+pub async fn holding_returns<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    warnings: &mut Vec<String>,
+) -> Result<HoldingReturns, String> {
+    let today = Utc::now().date_naive();
+    let from = today - Duration::days(LOOKBACK_DAYS);
+
+    let mut tickers = Vec::new();
+    let mut values = Vec::new();
+    let mut series = Vec::new();
+    for holding in valuation::value_holdings(client, portfolio_id, fx::MXN).await? {
+        let Some(value) = holding.market_value_base else {
+            warnings.push(format!("{} no tiene cotización; se omite", holding.ticker));
+            continue;
+        };
+        let closes = prices::daily_closes(client, &holding.ticker, from).await?;
+        if closes.range(from..).count() < MIN_OBSERVATIONS {
+            warnings.push(format!("{} no tiene suficiente histórico de precios; se omite", holding.ticker));
+            continue;
+        }
+        tickers.push(holding.ticker);
+        values.push(to_f64(value));
+        series.push(closes);
+    }
+    let cash: f64 = valuation::value_cash(client, portfolio_id, fx::MXN).await?
+        .iter()
+        .map(|c| to_f64(c.amount_base))
+        .sum();
+
+    let (dates, returns) = aligned_returns(&series, from, today);
+    Ok(HoldingReturns { tickers, values, cash, dates, returns })
+}
+
+/// Riesgo de las posiciones actuales con el último año de cierres. El efectivo cuenta en el
+/// valor del portafolio pero no aporta riesgo.
+pub async fn portfolio_risk<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    confidence: f64,
+    horizon: u32,
+) -> Result<RiskReport, String> {
+    let mut warnings = Vec::new();
+    let inputs = holding_returns(client, portfolio_id, &mut warnings).await?;
+    Ok(compute_risk(portfolio_id, confidence, horizon, inputs, warnings))
+}
+
+pub async fn get_portfolio_risk_logic(
+    portfolio_id: i32,
+    confidence: Option<Decimal>,
+    horizon: Option<u32>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<RiskReport, String> {
+    // Se acepta 0.95 o 95
+    let confidence = match confidence.map(to_f64) {
+        Some(c) if c > 1.0 => c / 100.0,
+        Some(c) => c,
+        None => DEFAULT_CONFIDENCE,
+    };
+    if !(0.5..1.0).contains(&confidence) {
+        return Err("El nivel de confianza debe estar entre 50% y 100%".to_string());
+    }
+    let horizon = horizon.unwrap_or(DEFAULT_HORIZON_DAYS);
+    if horizon == 0 || horizon > 250 {
+        return Err("El horizonte debe estar entre 1 y 250 días hábiles".to_string());
+    }
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    portfolio_risk(&**client, portfolio_id, confidence, horizon).await
+}
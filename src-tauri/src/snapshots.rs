@@ -0,0 +1,157 @@
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::GenericClient;
+
+use crate::valuation::{self, DailyValuation};
+
+/// Valor del portafolio al cierre de un día, guardado en portfolio_snapshots. Montos en pesos.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioSnapshot {
+    pub snapshot_date: NaiveDate,
+    pub market_value: Decimal,
+    pub cash: Decimal,
+    pub total_value: Decimal,
+    pub net_flow: Decimal,
+    // Depósitos menos retiros acumulados desde el inicio
+    pub contributions: Decimal,
+}
+
+impl From<&PortfolioSnapshot> for DailyValuation {
+    fn from(snapshot: &PortfolioSnapshot) -> Self {
+        DailyValuation {
+            date: snapshot.snapshot_date,
+            market_value: snapshot.market_value,
+            cash: snapshot.cash,
+            total_value: snapshot.total_value,
+            net_flow: snapshot.net_flow,
+        }
+    }
+}
+
+pub fn range_start(range: &str, today: NaiveDate) -> Result<Option<NaiveDate>, String> {
+    let months = |m: u32| Some(today - Months::new(m));
+    Ok(match range.trim().to_uppercase().as_str() {
+        "1M" => months(1),
+        "3M" => months(3),
+        "6M" => months(6),
+        "YTD" => NaiveDate::from_ymd_opt(today.year(), 1, 1),
+        "1Y" => months(12),
+        "3Y" => months(36),
+        "5Y" => months(60),
+        "ALL" | "MAX" => None,
+        _ => return Err(format!("Rango desconocido: {} (usar 1M, 3M, 6M, YTD, 1Y, 3Y, 5Y o ALL)", range)),
+    })
+}
+
+/// Borra los snapshots desde `date`; se reconstruyen en la siguiente actualización.
+pub async fn invalidate_from<C: GenericClient>(client: &C, portfolio_id: i32, date: NaiveDate) -> Result<(), String> {
+    client.execute(
+        "DELETE FROM portfolio_snapshots WHERE portfolio_id = $1 AND snapshot_date >= $2",
+        &[&portfolio_id, &date],
+    ).await.map_err(|e| format!("Error al invalidar el historial del portafolio: {}", e))?;
+    Ok(())
+}
+
+// Primer día que hay que recalcular: el último snapshot (sus precios pueden haber cambiado)
+// o antes, si desde entonces se registró o modificó un movimiento con fecha anterior.
+async fn rebuild_from<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<Option<NaiveDate>, String> {
+    let row = client.query_one(
+        "SELECT MAX(snapshot_date) AS last_date, MAX(computed_at) AS computed_at
+         FROM portfolio_snapshots WHERE portfolio_id = $1",
+        &[&portfolio_id],
+    ).await.map_err(|e| format!("Error al consultar el historial del portafolio: {}", e))?;
+    let last_date: Option<NaiveDate> = row.get("last_date");
+    let computed_at: Option<DateTime<Utc>> = row.get("computed_at");
+    let (Some(last_date), Some(computed_at)) = (last_date, computed_at) else {
+        return Ok(None);
+    };
+
+    let row = client.query_one(
+        "SELECT MIN(transaction_date) AS changed_from
+         FROM portfolio_transactions WHERE portfolio_id = $1 AND updated_at > $2",
+        &[&portfolio_id, &computed_at],
+    ).await.map_err(|e| format!("Error al consultar los cambios del ledger: {}", e))?;
+    let changed_from: Option<DateTime<Utc>> = row.get("changed_from");
+    Ok(Some(match changed_from {
+        Some(changed) => changed.date_naive().min(last_date),
+        None => last_date,
+    }))
+}
+
+/// Calcula y guarda los snapshots que faltan o quedaron desactualizados. Regresa cuántos escribió.
+pub async fn update_snapshots<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<usize, String> {
+    let from = rebuild_from(client, portfolio_id).await?;
+    let mut contributions = Decimal::ZERO;
+    if let Some(from) = from {
+        invalidate_from(client, portfolio_id, from).await?;
+        let row = client.query_opt(
+            "SELECT contributions FROM portfolio_snapshots
+             WHERE portfolio_id = $1 AND snapshot_date < $2
+             ORDER BY snapshot_date DESC LIMIT 1",
+            &[&portfolio_id, &from],
+        ).await.map_err(|e| format!("Error al consultar el historial del portafolio: {}", e))?;
+        contributions = row.map(|r| r.get("contributions")).unwrap_or_default();
+    }
+
+    let history = valuation::valuation_history(client, portfolio_id, from, Utc::now().date_naive()).await?;
+    let mut written = 0;
+    for day in &history {
+        contributions += day.net_flow;
+        client.execute(
+            "INSERT INTO portfolio_snapshots (portfolio_id, snapshot_date, market_value, cash, total_value, net_flow, contributions, computed_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, now())
+             ON CONFLICT (portfolio_id, snapshot_date) DO UPDATE SET
+                market_value = EXCLUDED.market_value,
+                cash = EXCLUDED.cash,
+                total_value = EXCLUDED.total_value,
+                net_flow = EXCLUDED.net_flow,
+                contributions = EXCLUDED.contributions,
+                computed_at = now()",
+            &[&portfolio_id, &day.date, &day.market_value, &day.cash, &day.total_value, &day.net_flow, &contributions],
+        ).await.map_err(|e| format!("No se pudo guardar el historial del {}: {}", day.date, e))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+pub async fn load_snapshots<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    from: Option<NaiveDate>,
+) -> Result<Vec<PortfolioSnapshot>, String> {
+    let rows = client.query(
+        "SELECT snapshot_date, market_value, cash, total_value, net_flow, contributions
+         FROM portfolio_snapshots
+         WHERE portfolio_id = $1 AND ($2::date IS NULL OR snapshot_date >= $2)
+         ORDER BY snapshot_date",
+        &[&portfolio_id, &from],
+    ).await.map_err(|e| format!("Error al consultar el historial del portafolio: {}", e))?;
+    Ok(rows.iter()
+        .map(|row| PortfolioSnapshot {
+            snapshot_date: row.get("snapshot_date"),
+            market_value: row.get("market_value"),
+            cash: row.get("cash"),
+            total_value: row.get("total_value"),
+            net_flow: row.get("net_flow"),
+            contributions: row.get("contributions"),
+        })
+        .collect())
+}
+
+/// Historial diario actualizado, listo para los cálculos de rendimiento y riesgo.
+pub async fn valuation_series<C: GenericClient>(client: &C, portfolio_id: i32) -> Result<Vec<DailyValuation>, String> {
+    update_snapshots(client, portfolio_id).await?;
+    Ok(load_snapshots(client, portfolio_id, None).await?.iter().map(DailyValuation::from).collect())
+}
+
+pub async fn get_portfolio_history_logic(
+    portfolio_id: i32,
+    range: String,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<PortfolioSnapshot>, String> {
+    let from = range_start(&range, Utc::now().date_naive())?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    update_snapshots(&**client, portfolio_id).await?;
+    load_snapshots(&**client, portfolio_id, from).await
+}
@@ -0,0 +1,477 @@
+use chrono::{Duration, NaiveDate, Utc};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use tokio_postgres::GenericClient;
+
+use crate::allocation;
+use crate::benchmarks;
+use crate::fx;
+use crate::money;
+use crate::performance::to_f64;
+use crate::prices::{self, PriceSeries};
+use crate::risk;
+use crate::valuation;
+
+// Índice contra el que se estiman las betas y con el que se aproximan los títulos sin histórico
+pub const MARKET_INDEX: &str = "IPC";
+const BETA_LOOKBACK_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ScenarioKind {
+    // Se repiten los movimientos observados entre start_date y end_date
+    Historical,
+    // Se aplican los choques indicados
+    Hypothetical,
+}
+
+impl ScenarioKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScenarioKind::Historical => "HISTORICAL",
+            ScenarioKind::Hypothetical => "HYPOTHETICAL",
+        }
+    }
+}
+
+impl FromStr for ScenarioKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "HISTORICAL" | "HISTORICO" => Ok(ScenarioKind::Historical),
+            "HYPOTHETICAL" | "HIPOTETICO" => Ok(ScenarioKind::Hypothetical),
+            _ => Err(format!("Tipo de escenario desconocido: {}", s)),
+        }
+    }
+}
+
+/// Factor al que se aplica un choque:
+/// * un índice (IPC, FTSEBIVA, SP500, DJIA), que llega a cada posición a través de su beta;
+/// * un tipo de cambio (USDMXN, EURMXN), para posiciones y efectivo en esa moneda;
+/// * `SECTOR:<sector>`, `TIPO_VALOR:<clase>` o `TICKER:<ticker>`, como movimiento directo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShockFactor {
+    Index(String),
+    Currency(String),
+    Sector(String),
+    AssetClass(String),
+    Ticker(String),
+}
+
+impl fmt::Display for ShockFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShockFactor::Index(symbol) => write!(f, "{}", symbol),
+            ShockFactor::Currency(currency) => write!(f, "{}MXN", currency),
+            ShockFactor::Sector(sector) => write!(f, "SECTOR:{}", sector),
+            ShockFactor::AssetClass(class) => write!(f, "TIPO_VALOR:{}", class),
+            ShockFactor::Ticker(ticker) => write!(f, "TICKER:{}", ticker),
+        }
+    }
+}
+
+impl FromStr for ShockFactor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let factor = s.trim().to_uppercase();
+        if let Some((kind, value)) = factor.split_once(':') {
+            let value = value.trim().to_string();
+            if value.is_empty() {
+                return Err(format!("Factor de choque incompleto: {}", s));
+            }
+            return match kind.trim() {
+                "SECTOR" => Ok(ShockFactor::Sector(value)),
+                "TIPO_VALOR" | "TIPO" => Ok(ShockFactor::AssetClass(value)),
+                "TICKER" | "EMISORA" => Ok(ShockFactor::Ticker(value)),
+                _ => Err(format!("Factor de choque desconocido: {}", s)),
+            };
+        }
+        if benchmarks::INDEX_BENCHMARKS.contains(&factor.as_str()) {
+            return Ok(ShockFactor::Index(factor));
+        }
+        if let Some(currency) = factor.strip_suffix(fx::MXN) {
+            let currency = fx::normalize_currency(currency)?;
+            if currency != fx::MXN {
+                return Ok(ShockFactor::Currency(currency));
+            }
+        }
+        Err(format!(
+            "Factor de choque desconocido: {} (usar {}, USDMXN, EURMXN, SECTOR:, TIPO_VALOR: o TICKER:)",
+            s, benchmarks::INDEX_BENCHMARKS.join(", ")
+        ))
+    }
+}
+
+/// Choque hipotético; `change` en porcentaje (-20 = baja de 20%).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StressShock {
+    pub factor: String,
+    pub change: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StressScenario {
+    #[serde(default)]
+    pub scenario_id: Option<i32>,
+    pub name: String,
+    pub kind: ScenarioKind,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub start_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub end_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub shocks: Vec<StressShock>,
+}
+
+impl StressScenario {
+    fn validate(&self) -> Result<Vec<(ShockFactor, f64)>, String> {
+        if self.name.trim().is_empty() {
+            return Err("El escenario necesita un nombre".to_string());
+        }
+        match self.kind {
+            ScenarioKind::Historical => match (self.start_date, self.end_date) {
+                (Some(start), Some(end)) if start < end => Ok(Vec::new()),
+                _ => Err("Un escenario histórico necesita fecha de inicio anterior a la de fin".to_string()),
+            },
+            ScenarioKind::Hypothetical => {
+                if self.shocks.is_empty() {
+                    return Err("Un escenario hipotético necesita al menos un choque".to_string());
+                }
+                self.shocks.iter()
+                    .map(|shock| {
+                        if shock.change <= -Decimal::ONE_HUNDRED {
+                            return Err(format!("El choque a {} no puede ser de -100% o menos", shock.factor));
+                        }
+                        Ok((shock.factor.parse()?, to_f64(shock.change) / 100.0))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Resultado por posición, en pesos. `shock` es el cambio aplicado en porcentaje y
+/// `method` indica de dónde salió: HISTORICO, BETA (aproximado con la beta contra el
+/// índice; `beta` es la del IPC) o DIRECTO.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StressPosition {
+    pub ticker: String,
+    pub currency: String,
+    pub market_value: Decimal,
+    pub beta: Option<Decimal>,
+    pub shock: Decimal,
+    pub pnl: Decimal,
+    pub value_after: Decimal,
+    pub method: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StressResult {
+    pub portfolio_id: i32,
+    pub scenario: StressScenario,
+    pub positions: Vec<StressPosition>,
+    pub cash_value: Decimal,
+    pub cash_pnl: Decimal,
+    pub total_value: Decimal,
+    pub total_pnl: Decimal,
+    pub total_pnl_percent: Decimal,
+    pub warnings: Vec<String>,
+}
+
+fn to_decimal(value: f64, scale: u32) -> Decimal {
+    Decimal::from_f64(value).map(|d| d.round_dp(scale)).unwrap_or_default()
+}
+
+fn window_return(series: &PriceSeries, start: NaiveDate, end: NaiveDate) -> Option<f64> {
+    // Se exige un cierre cercano al inicio para no medir desde un dato muy anterior
+    let (first_date, _) = series.range(start - Duration::days(7)..=start).next_back()?;
+    let start_close = series[first_date];
+    let end_close = prices::close_on(series, end)?;
+    (start_close > Decimal::ZERO).then(|| to_f64(end_close / start_close) - 1.0)
+}
+
+// Beta contra el índice con el último año de rendimientos diarios
+fn estimate_beta(ticker_closes: &PriceSeries, index_levels: &PriceSeries, today: NaiveDate) -> Option<f64> {
+    let from = today - Duration::days(BETA_LOOKBACK_DAYS);
+    let (dates, returns) = risk::aligned_returns(&[ticker_closes.clone(), index_levels.clone()], from, today);
+    if dates.len() < risk::MIN_OBSERVATIONS {
+        return None;
+    }
+    let n = dates.len() as f64;
+    let mean_t = returns[0].iter().sum::<f64>() / n;
+    let mean_i = returns[1].iter().sum::<f64>() / n;
+    let covariance: f64 = returns[0].iter().zip(&returns[1]).map(|(t, i)| (t - mean_t) * (i - mean_i)).sum();
+    let variance: f64 = returns[1].iter().map(|i| (i - mean_i).powi(2)).sum();
+    (variance > 0.0).then_some(covariance / variance)
+}
+
+fn scenario_from_row(row: &tokio_postgres::Row) -> Result<StressScenario, String> {
+    let kind: String = row.get("kind");
+    let shocks: String = row.get("shocks");
+    Ok(StressScenario {
+        scenario_id: Some(row.get("scenario_id")),
+        name: row.get("name"),
+        kind: kind.parse()?,
+        description: row.get("description"),
+        start_date: row.get("start_date"),
+        end_date: row.get("end_date"),
+        shocks: serde_json::from_str(&shocks).map_err(|e| format!("Choques inválidos en el escenario: {}", e))?,
+    })
+}
+
+const SCENARIO_COLUMNS: &str = "scenario_id, name, kind, description, start_date, end_date, shocks::text AS shocks";
+
+pub async fn list_stress_scenarios_logic(db_pool: &deadpool_postgres::Pool) -> Result<Vec<StressScenario>, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows = client.query(
+        &format!("SELECT {} FROM stress_scenarios ORDER BY kind, start_date NULLS LAST, name", SCENARIO_COLUMNS),
+        &[],
+    ).await.map_err(|e| format!("Error al consultar los escenarios: {}", e))?;
+    rows.iter().map(scenario_from_row).collect()
+}
+
+async fn load_scenario<C: GenericClient>(client: &C, scenario_id: i32) -> Result<StressScenario, String> {
+    let row = client.query_opt(
+        &format!("SELECT {} FROM stress_scenarios WHERE scenario_id = $1", SCENARIO_COLUMNS),
+        &[&scenario_id],
+    ).await.map_err(|e| format!("Error al consultar el escenario: {}", e))?
+        .ok_or_else(|| format!("No se encontró el escenario {}", scenario_id))?;
+    scenario_from_row(&row)
+}
+
+/// Guarda un escenario nuevo o, con `scenario_id`, reemplaza uno existente.
+pub async fn save_stress_scenario_logic(
+    scenario: StressScenario,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<StressScenario, String> {
+    scenario.validate()?;
+    let shocks = serde_json::to_string(&scenario.shocks).map_err(|e| e.to_string())?;
+    let name = scenario.name.trim().to_string();
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let row = match scenario.scenario_id {
+        Some(scenario_id) => client.query_opt(
+            &format!(
+                "UPDATE stress_scenarios SET name = $2, kind = $3, description = $4, start_date = $5, end_date = $6,
+                        shocks = $7::text::jsonb, updated_at = now()
+                 WHERE scenario_id = $1 RETURNING {}",
+                SCENARIO_COLUMNS
+            ),
+            &[&scenario_id, &name, &scenario.kind.as_str(), &scenario.description, &scenario.start_date, &scenario.end_date, &shocks],
+        ).await.map_err(|e| format!("No se pudo guardar el escenario: {}", e))?
+            .ok_or_else(|| format!("No se encontró el escenario {}", scenario_id))?,
+        None => client.query_one(
+            &format!(
+                "INSERT INTO stress_scenarios (name, kind, description, start_date, end_date, shocks)
+                 VALUES ($1, $2, $3, $4, $5, $6::text::jsonb) RETURNING {}",
+                SCENARIO_COLUMNS
+            ),
+            &[&name, &scenario.kind.as_str(), &scenario.description, &scenario.start_date, &scenario.end_date, &shocks],
+        ).await.map_err(|e| format!("No se pudo guardar el escenario: {}", e))?,
+    };
+    scenario_from_row(&row)
+}
+
+pub async fn delete_stress_scenario_logic(scenario_id: i32, db_pool: &deadpool_postgres::Pool) -> Result<String, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let rows_affected = client.execute("DELETE FROM stress_scenarios WHERE scenario_id = $1", &[&scenario_id])
+        .await.map_err(|e| format!("Error al eliminar el escenario: {}", e))?;
+    if rows_affected == 1 {
+        Ok("Escenario eliminado correctamente.".to_string())
+    } else {
+        Err("No se encontró el escenario para eliminar.".to_string())
+    }
+}
+
+/// Aplica el escenario a las posiciones y el efectivo actuales.
+pub async fn stress_test<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    scenario: StressScenario,
+) -> Result<StressResult, String> {
+    let shocks = scenario.validate()?;
+    let today = Utc::now().date_naive();
+    let mut warnings = Vec::new();
+
+    let holdings: Vec<valuation::HoldingValuation> = valuation::value_holdings(client, portfolio_id, fx::MXN).await?
+        .into_iter()
+        .filter(|h| {
+            if h.market_value_base.is_none() {
+                warnings.push(format!("{} no tiene cotización; no se incluye en el escenario", h.ticker));
+            }
+            h.market_value_base.is_some()
+        })
+        .collect();
+    let cash = valuation::value_cash(client, portfolio_id, fx::MXN).await?;
+    let index_levels = benchmarks::stored_levels(client, MARKET_INDEX).await?;
+    // Los choques a otros índices llegan a cada posición con su beta contra ese índice
+    let mut other_index_levels: HashMap<String, PriceSeries> = HashMap::new();
+    for (factor, _) in &shocks {
+        if let ShockFactor::Index(symbol) = factor {
+            if symbol != MARKET_INDEX && !other_index_levels.contains_key(symbol) {
+                other_index_levels.insert(symbol.clone(), benchmarks::stored_levels(client, symbol).await?);
+            }
+        }
+    }
+
+    // Movimiento del tipo de cambio de cada moneda extranjera en el escenario
+    let mut currency_moves: HashMap<String, f64> = HashMap::new();
+    let currencies = holdings.iter().map(|h| h.currency.clone()).chain(cash.iter().map(|c| c.currency.clone()));
+    for currency in currencies.filter(|c| c != fx::MXN) {
+        if currency_moves.contains_key(&currency) {
+            continue;
+        }
+        let change = match scenario.kind {
+            ScenarioKind::Historical => {
+                let (start, end) = (scenario.start_date.unwrap(), scenario.end_date.unwrap());
+                window_return(&fx::rate_series(client, &currency).await?, start, end).unwrap_or_else(|| {
+                    warnings.push(format!("No hay tipos de cambio {}MXN en el periodo; se supone sin cambio", currency));
+                    0.0
+                })
+            }
+            ScenarioKind::Hypothetical => shocks.iter()
+                .filter(|(factor, _)| *factor == ShockFactor::Currency(currency.clone()))
+                .map(|(_, change)| *change)
+                .sum(),
+        };
+        currency_moves.insert(currency, change);
+    }
+
+    let tickers: Vec<String> = holdings.iter().map(|h| h.ticker.clone()).collect();
+    let classes = allocation::classifications(client, &tickers).await?;
+
+    let mut positions = Vec::new();
+    for holding in &holdings {
+        let market_value = holding.market_value_base.unwrap_or_default();
+        let from = match scenario.kind {
+            ScenarioKind::Historical => scenario.start_date.unwrap() - Duration::days(7),
+            ScenarioKind::Hypothetical => today - Duration::days(BETA_LOOKBACK_DAYS),
+        };
+        let closes = prices::daily_closes(client, &holding.ticker, from).await?;
+        let beta = estimate_beta(&closes, &index_levels, today);
+
+        let (price_move, method, fx_move) = match scenario.kind {
+            // Los cierres de DataBursátil están en pesos, así que ya incluyen el tipo de cambio
+            ScenarioKind::Historical => {
+                let (start, end) = (scenario.start_date.unwrap(), scenario.end_date.unwrap());
+                match window_return(&closes, start, end) {
+                    Some(change) => (change, "HISTORICO", 0.0),
+                    None => match window_return(&index_levels, start, end) {
+                        Some(index_change) => {
+                            warnings.push(format!("{} no cotizaba en el periodo; se aproxima con beta al {}", holding.ticker, MARKET_INDEX));
+                            (beta.unwrap_or(1.0) * index_change, "BETA", 0.0)
+                        }
+                        None => {
+                            warnings.push(format!("Sin histórico de {} ni del {} en el periodo; se supone sin cambio", holding.ticker, MARKET_INDEX));
+                            (0.0, "SIN DATOS", 0.0)
+                        }
+                    },
+                }
+            }
+            ScenarioKind::Hypothetical => {
+                let class = classes.get(&holding.ticker);
+                let direct = shocks.iter().find(|(factor, _)| *factor == ShockFactor::Ticker(holding.ticker.clone()));
+                let fx_move = currency_moves.get(&holding.currency).copied().unwrap_or_default();
+                match direct {
+                    Some((_, change)) => (*change, "DIRECTO", fx_move),
+                    None => {
+                        let mut change = 0.0;
+                        for (factor, shock) in &shocks {
+                            change += match factor {
+                                ShockFactor::Index(symbol) if symbol == MARKET_INDEX => {
+                                    if beta.is_none() {
+                                        warnings.push(format!("Sin histórico suficiente para la beta de {}; se usa 1", holding.ticker));
+                                    }
+                                    beta.unwrap_or(1.0) * shock
+                                }
+                                ShockFactor::Index(symbol) => {
+                                    let index_beta = other_index_levels.get(symbol)
+                                        .and_then(|levels| estimate_beta(&closes, levels, today));
+                                    if index_beta.is_none() {
+                                        warnings.push(format!("Sin histórico suficiente para la beta de {} contra el {}; se usa 1", holding.ticker, symbol));
+                                    }
+                                    index_beta.unwrap_or(1.0) * shock
+                                }
+                                ShockFactor::Sector(sector) if class.and_then(|c| c.sector.as_ref()) == Some(sector) => *shock,
+                                ShockFactor::AssetClass(asset_class) if class.map(|c| &c.asset_class) == Some(asset_class) => *shock,
+                                _ => 0.0,
+                            };
+                        }
+                        (change.max(-1.0), "BETA", fx_move)
+                    }
+                }
+            }
+        };
+
+        let total_move = ((1.0 + price_move) * (1.0 + fx_move) - 1.0).max(-1.0);
+        let pnl = money::round_mxn(market_value * to_decimal(total_move, 8));
+        positions.push(StressPosition {
+            ticker: holding.ticker.clone(),
+            currency: holding.currency.clone(),
+            market_value,
+            beta: beta.map(|b| to_decimal(b, 4)),
+            shock: to_decimal(total_move * 100.0, money::PERCENT_SCALE),
+            pnl,
+            value_after: market_value + pnl,
+            method: method.to_string(),
+        });
+    }
+
+    let cash_value: Decimal = cash.iter().map(|c| c.amount_base).sum();
+    let cash_pnl: Decimal = cash.iter()
+        .map(|c| money::round_mxn(c.amount_base * to_decimal(currency_moves.get(&c.currency).copied().unwrap_or_default(), 8)))
+        .sum();
+    let total_value = positions.iter().map(|p| p.market_value).sum::<Decimal>() + cash_value;
+    let total_pnl = positions.iter().map(|p| p.pnl).sum::<Decimal>() + cash_pnl;
+    warnings.dedup();
+
+    Ok(StressResult {
+        portfolio_id,
+        scenario,
+        positions,
+        cash_value,
+        cash_pnl,
+        total_value,
+        total_pnl,
+        total_pnl_percent: money::percent(total_pnl, total_value),
+        warnings,
+    })
+}
+
+/// Corre un escenario guardado (`scenario_id`) o uno ad hoc (`scenario`).
+pub async fn run_stress_test_logic(
+    portfolio_id: i32,
+    scenario_id: Option<i32>,
+    scenario: Option<StressScenario>,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<StressResult, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let scenario = match (scenario_id, scenario) {
+        (Some(scenario_id), _) => load_scenario(&**client, scenario_id).await?,
+        (None, Some(scenario)) => scenario,
+        (None, None) => return Err("Indique un escenario guardado o uno nuevo".to_string()),
+    };
+    stress_test(&**client, portfolio_id, scenario).await
+}
+
+/// Todos los escenarios guardados contra un portafolio, del peor al mejor.
+pub async fn run_all_stress_tests_logic(
+    portfolio_id: i32,
+    db_pool: &deadpool_postgres::Pool,
+) -> Result<Vec<StressResult>, String> {
+    let scenarios = list_stress_scenarios_logic(db_pool).await?;
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let mut results = Vec::new();
+    for scenario in scenarios {
+        results.push(stress_test(&**client, portfolio_id, scenario).await?);
+    }
+    results.sort_by_key(|r| r.total_pnl);
+    Ok(results)
+}
@@ -1,4 +1,5 @@
 use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tauri::State;
@@ -6,6 +7,7 @@ use deadpool_postgres::Pool;
 
 // Import AppState from lib
 use crate::AppState;
+use crate::ledger::{self, CashSource, LedgerEntry, NewLedgerEntry, TransactionKind};
 
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,6 +23,7 @@ pub struct Portfolio {
     pub user_id: i32, // Coincide con 'usuario_id'
     pub name: String, // Coincide con 'nombre'
     pub created_at: Option<NaiveDateTime>,
+    pub is_paper: bool, // Portafolio de práctica (ver orders.rs)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -37,28 +40,11 @@ pub struct PortfolioMovement {
     pub portfolio_id: i32,
     pub ticker: String,
     pub quantity: i32,
-    pub price: f64,
+    pub price: Decimal,
     pub movement_type: String, // "compra" o "venta"
     pub created_at: chrono::NaiveDateTime,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct PortfolioTransaction {
-    pub transaction_id: i32,
-    pub portfolio_id: i32,
-    pub user_id: i32,
-    pub ticker: String,
-    pub transaction_type: String, // "BUY", "SELL", etc.
-    pub quantity: f64,
-    pub price: f64,
-    pub transaction_date: chrono::DateTime<chrono::Utc>,
-    pub total_amount: f64,
-    pub currency: String,
-    pub notes: Option<String>,
-    pub created_at: chrono::DateTime<chrono::Utc>,
-    pub updated_at: chrono::DateTime<chrono::Utc>,
-}
-
 
 
 #[tauri::command(async)]
@@ -88,14 +74,15 @@ pub async fn create_user(
 pub async fn create_portfolio(
     user_id: i32,
     name: String,
+    is_paper: Option<bool>,
     state: State<'_, AppState>,
 ) -> Result<Portfolio, String> {
     let db_pool = &state.db_pool;
     let client = db_pool.get().await
         .map_err(|e| format!("Error de conexión a la base de datos: {}", e))?;
     let row = client.query_one(
-        "INSERT INTO portafolios (usuario_id, nombre) VALUES ($1, $2) RETURNING id, usuario_id, nombre, created_at",
-        &[&user_id, &name],
+        "INSERT INTO portafolios (usuario_id, nombre, is_paper) VALUES ($1, $2, $3) RETURNING id, usuario_id, nombre, created_at, is_paper",
+        &[&user_id, &name, &is_paper.unwrap_or(false)],
     ).await.map_err(|e| format!("No se pudo crear el portafolio. Es posible que el nombre ya exista: {}", e))?;
 
     Ok(Portfolio {
@@ -103,6 +90,7 @@ pub async fn create_portfolio(
         user_id: row.get("usuario_id"),
         name: row.get("nombre"),
         created_at: row.get("created_at"),
+        is_paper: row.get("is_paper"),
     })
 }
 
@@ -210,7 +198,7 @@ pub async fn update_portfolio(
     let set_clause = set_clauses.join(", ");
     params.push(&portfolio_id);
     let query = format!(
-        "UPDATE portafolios SET {} WHERE id = ${} RETURNING id, usuario_id, nombre, created_at",
+        "UPDATE portafolios SET {} WHERE id = ${} RETURNING id, usuario_id, nombre, created_at, is_paper",
         set_clause, idx
     );
     let row = client.query_one(&query, &params).await
@@ -220,6 +208,7 @@ pub async fn update_portfolio(
         user_id: row.get("usuario_id"),
         name: row.get("nombre"),
         created_at: row.get("created_at"),
+        is_paper: row.get("is_paper"),
     })
 }
 
@@ -237,7 +226,7 @@ pub async fn list_users_with_portfolios_internal(
     for user_row in user_rows {
         let user_id: i32 = user_row.get("id");
         let portfolios = client.query(
-            "SELECT id, usuario_id as user_id, nombre as name, created_at FROM portafolios WHERE usuario_id = $1",
+            "SELECT id, usuario_id as user_id, nombre as name, created_at, is_paper FROM portafolios WHERE usuario_id = $1",
             &[&user_id],
         ).await.map_err(|e| format!("Error al consultar portafolios para usuario {}: {}", user_id, e))?;
         let portfolios: Vec<Portfolio> = portfolios.into_iter().map(|row| Portfolio {
@@ -245,6 +234,7 @@ pub async fn list_users_with_portfolios_internal(
             user_id: row.get("user_id"),
             name: row.get("name"),
             created_at: row.get("created_at"),
+            is_paper: row.get("is_paper"),
         }).collect();
         users.push(UserWithPortfolios {
             id: user_row.get("id"),
@@ -269,11 +259,11 @@ pub async fn add_portfolio_movement(
     portfolio_id: i32,
     ticker: &str,
     quantity: i32,
-    price: f64,
+    price: Decimal,
     movement_type: &str, // "BUY" o "SELL"
     db_pool: &Pool,
-) -> Result<PortfolioTransaction, String> {
-    let client = db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
+) -> Result<LedgerEntry, String> {
+    let mut client = db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
     
     // Verificar si el ticker existe en la tabla emisoras
     match ticker_exists_in_emisoras(ticker, &client).await {
@@ -282,218 +272,60 @@ pub async fn add_portfolio_movement(
         Ok(true) => {} // Continue with the transaction
     }
     
-    // NOTA: user_id se debe pasar o deducir, aquí se usa 1 como ejemplo
-    let user_id = 1;
-    let total_amount = price * quantity as f64;
-    let currency = "MXN";
-    let notes: Option<String> = None;
-    
-    // Manejar flujo de caja
-    println!("[DEBUG] Iniciando manejo de cash flow para portfolio {}, tipo: {}, cantidad: {}", 
-             portfolio_id, movement_type, total_amount);
-    
-    if let Err(e) = handle_cash_flow(&client, portfolio_id, movement_type, ticker, total_amount).await {
-        println!("[ERROR] Error en flujo de caja: {}", e);
-        return Err(format!("Error en flujo de caja: {}", e));
-    }
-    
-    println!("[DEBUG] Cash flow completado exitosamente, procediendo con transacción principal");
-    
-    // Convert to f64 for PostgreSQL DECIMAL compatibility
-    let quantity_f64 = quantity as f64;
-    let total_amount_f64 = total_amount;
+    let kind: TransactionKind = movement_type.parse()?;
+    let entry = NewLedgerEntry::trade(portfolio_id, ticker, kind, Decimal::from(quantity), price);
     
-    let row = client.query_one(
-        "INSERT INTO portfolio_transactions (portfolio_id, user_id, ticker, transaction_type, quantity, price, total_amount, currency, notes, transaction_date) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now()) RETURNING transaction_id, portfolio_id, user_id, ticker, transaction_type, quantity, price, transaction_date, total_amount, currency, notes, created_at, updated_at",
-        &[&portfolio_id, &user_id, &ticker.to_string(), &movement_type.to_string(), &quantity_f64, &price, &total_amount_f64, &currency.to_string(), &notes],
-    ).await.map_err(|e| {
-        println!("[ERROR] Error al insertar transacción principal: {}", e);
-        format!("Error al agregar movimiento: {}", e)
-    })?;
+    // La compra usa el efectivo del portafolio y respeta el sobregiro de su cuenta (ver cash.rs)
+    let recorded = ledger::record_trade(&mut client, entry, CashSource::Portfolio).await?;
     
     println!("[DEBUG] Transacción agregada exitosamente: {} {} de {} a {}", 
-             movement_type, quantity, ticker, price);
+             kind, quantity, ticker, price);
     
-    Ok(PortfolioTransaction {
-        transaction_id: row.get("transaction_id"),
-        portfolio_id: row.get("portfolio_id"),
-        user_id: row.get("user_id"),
-        ticker: row.get("ticker"),
-        transaction_type: row.get("transaction_type"),
-        quantity: row.get("quantity"),
-        price: row.get("price"),
-        transaction_date: row.get("transaction_date"),
-        total_amount: row.get("total_amount"),
-        currency: row.get("currency"),
-        notes: row.get("notes"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-    })
+    Ok(recorded)
 }
 
 #[tauri::command(async)]
 pub async fn list_portfolio_movements(
     portfolio_id: i32,
     db_pool: std::sync::Arc<deadpool_postgres::Pool>,
-) -> Result<Vec<PortfolioTransaction>, String> {
+) -> Result<Vec<LedgerEntry>, String> {
     let client = db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
-    let rows = client.query(
-        "SELECT transaction_id, portfolio_id, user_id, ticker, transaction_type, quantity, price, transaction_date, total_amount, currency, notes, created_at, updated_at FROM portfolio_transactions WHERE portfolio_id = $1 AND transaction_type IN ('BUY', 'SELL') ORDER BY transaction_date ASC",
-        &[&portfolio_id],
-    ).await.map_err(|e| format!("Error al listar movimientos: {}", e))?;
-    Ok(rows.into_iter().map(|row| PortfolioTransaction {
-        transaction_id: row.get("transaction_id"),
-        portfolio_id: row.get("portfolio_id"),
-        user_id: row.get("user_id"),
-        ticker: row.get("ticker"),
-        transaction_type: row.get("transaction_type"),
-        quantity: row.get("quantity"),
-        price: row.get("price"),
-        transaction_date: row.get("transaction_date"),
-        total_amount: row.get("total_amount"),
-        currency: row.get("currency"),
-        notes: row.get("notes"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-    }).collect())
+    ledger::list_entries(&**client, portfolio_id, Some(&[TransactionKind::Buy, TransactionKind::Sell])).await
 }
 
 #[tauri::command]
 pub async fn add_portfolio_transaction(
     portfolio_id: i32,
-    user_id: i32,
     ticker: &str,
-    transaction_type: &str, // "BUY" o "SELL"
-    quantity: f64,
-    price: f64,
-    total_amount: f64,
-    currency: &str,
+    transaction_type: &str, // "BUY", "SELL", "DEPOSIT", "WITHDRAWAL" o "DIVIDEND"
+    quantity: Decimal,
+    price: Decimal,
+    currency: Option<String>,
     notes: Option<String>,
     state: tauri::State<'_, AppState>,
-) -> Result<PortfolioTransaction, String> {
-    let client = state.db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
-    let row = client.query_one(
-        "INSERT INTO portfolio_transactions (portfolio_id, user_id, ticker, transaction_type, quantity, price, total_amount, currency, notes, transaction_date) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now()) RETURNING transaction_id, portfolio_id, user_id, ticker, transaction_type, quantity, price, transaction_date, total_amount, currency, notes, created_at, updated_at",
-        &[&portfolio_id, &user_id, &ticker.to_string(), &transaction_type.to_string(), &quantity, &price, &total_amount, &currency.to_string(), &notes],
-    ).await.map_err(|e| format!("Error al agregar transacción: {}", e))?;
-    Ok(PortfolioTransaction {
-        transaction_id: row.get("transaction_id"),
-        portfolio_id: row.get("portfolio_id"),
-        user_id: row.get("user_id"),
-        ticker: row.get("ticker"),
-        transaction_type: row.get("transaction_type"),
-        quantity: row.get("quantity"),
-        price: row.get("price"),
-        transaction_date: row.get("transaction_date"),
-        total_amount: row.get("total_amount"),
-        currency: row.get("currency"),
-        notes: row.get("notes"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-    })
+) -> Result<LedgerEntry, String> {
+    let mut client = state.db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
+    let kind: TransactionKind = transaction_type.parse()?;
+    let mut entry = NewLedgerEntry::trade(portfolio_id, ticker, kind, quantity, price);
+    if let Some(currency) = currency {
+        entry.currency = currency;
+    }
+    entry.notes = notes;
+
+    if kind.is_trade() {
+        ledger::record_trade(&mut client, entry, CashSource::Portfolio).await
+    } else {
+        ledger::record_cash_movement(&**client, entry).await
+    }
 }
 
 #[tauri::command(async)]
 pub async fn list_portfolio_transactions(
     portfolio_id: i32,
     db_pool: std::sync::Arc<deadpool_postgres::Pool>,
-) -> Result<Vec<PortfolioTransaction>, String> {
+) -> Result<Vec<LedgerEntry>, String> {
     let client = db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
-    let rows = client.query(
-        "SELECT transaction_id, portfolio_id, user_id, ticker, transaction_type, quantity, price, transaction_date, total_amount, currency, notes, created_at, updated_at FROM portfolio_transactions WHERE portfolio_id = $1 ORDER BY transaction_date ASC",
-        &[&portfolio_id],
-    ).await.map_err(|e| format!("Error al listar transacciones: {}", e))?;
-    Ok(rows.into_iter().map(|row| PortfolioTransaction {
-        transaction_id: row.get("transaction_id"),
-        portfolio_id: row.get("portfolio_id"),
-        user_id: row.get("user_id"),
-        ticker: row.get("ticker"),
-        transaction_type: row.get("transaction_type"),
-        quantity: row.get("quantity"),
-        price: row.get("price"),
-        transaction_date: row.get("transaction_date"),
-        total_amount: row.get("total_amount"),
-        currency: row.get("currency"),
-        notes: row.get("notes"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-    }).collect())
-}
-
-// Function to handle cash flow for portfolio transactions
-async fn handle_cash_flow(
-    client: &deadpool_postgres::Object,
-    portfolio_id: i32,
-    transaction_type: &str,
-    ticker: &str,
-    total_amount: f64,
-) -> Result<(), String> {
-    // Obtener el cash actual del portafolio
-    let cash_row = client.query_opt(
-        "SELECT SUM(CASE 
-                    WHEN transaction_type = 'DEPOSIT' THEN total_amount 
-                    WHEN transaction_type = 'WITHDRAWAL' THEN -total_amount 
-                    ELSE 0 
-                  END) as current_cash
-         FROM portfolio_transactions 
-         WHERE portfolio_id = $1 AND ticker = 'CASH'",
-        &[&portfolio_id],
-    ).await.map_err(|e| format!("Error al consultar cash: {}", e))?;
-    
-    let current_cash = if let Some(row) = cash_row {
-        row.get::<_, Option<f64>>("current_cash").unwrap_or(0.0)
-    } else {
-        0.0 // Si no hay registro de cash, empezar con 0
-    };
-    
-    let new_cash = match transaction_type {
-        "BUY" => {
-            // Al comprar, reducir cash. Si no hay suficiente, agregar el faltante
-            if current_cash >= total_amount {
-                current_cash - total_amount
-            } else {
-                let faltante = total_amount - current_cash;
-                println!("[DEBUG] Cash insuficiente. Actual: {}, Necesario: {}, Agregando: {}", 
-                         current_cash, total_amount, faltante);
-                
-                // Registrar el cash adicional como depósito
-                client.execute(
-                    "INSERT INTO portfolio_transactions (portfolio_id, user_id, ticker, transaction_type, quantity, price, total_amount, currency, notes, transaction_date) 
-                     VALUES ($1, 1, 'CASH', 'DEPOSIT', 1, $2, $2, 'MXN', 'Auto-deposit for insufficient cash', now())",
-                    &[&portfolio_id, &faltante],
-                ).await.map_err(|e| format!("Error al agregar depósito automático: {}", e))?;
-                
-                // El nuevo cash será el total después del depósito menos la compra
-                let cash_after_deposit = current_cash + faltante;
-                cash_after_deposit - total_amount
-            }
-        },
-        "SELL" => {
-            // Al vender, aumentar cash
-            current_cash + total_amount
-        },
-        _ => return Err(format!("Tipo de transacción desconocido: {}", transaction_type)),
-    };
-    
-    // Registrar el nuevo estado del cash como depósito/retiro según corresponda
-    let (cash_transaction_type, cash_notes) = match transaction_type {
-        "BUY" => ("WITHDRAWAL", format!("Cash withdrawal for {} purchase", ticker)),
-        "SELL" => ("DEPOSIT", format!("Cash deposit from {} sale", ticker)),
-        _ => return Err(format!("Tipo de transacción desconocido: {}", transaction_type)),
-    };
-    
-    // Solo registrar la transacción de cash si el monto es mayor a 0
-    if new_cash.abs() > 0.01 { // Usar tolerancia para flotantes
-        client.execute(
-            "INSERT INTO portfolio_transactions (portfolio_id, user_id, ticker, transaction_type, quantity, price, total_amount, currency, notes, transaction_date) 
-             VALUES ($1, 1, 'CASH', $2, 1, $3, $3, 'MXN', $4, now())",
-            &[&portfolio_id, &cash_transaction_type, &new_cash.abs(), &cash_notes],
-        ).await.map_err(|e| format!("Error al actualizar cash: {}", e))?;
-    }
-    
-    println!("[DEBUG] Cash flow: {} {} -> Cash: {} -> {}", transaction_type, total_amount, current_cash, new_cash);
-    
-    Ok(())
+    ledger::list_entries(&**client, portfolio_id, None).await
 }
 
 // Function to check if a ticker exists in the emisoras table
@@ -535,10 +367,10 @@ pub async fn add_portfolio_movement_command(
     portfolio_id: i32,
     ticker: String,
     quantity: i32,
-    price: f64,
+    price: Decimal,
     movement_type: String,
     state: tauri::State<'_, AppState>,
-) -> Result<PortfolioTransaction, String> {
+) -> Result<LedgerEntry, String> {
     add_portfolio_movement(
         portfolio_id,
         &ticker,
@@ -589,28 +421,10 @@ pub async fn search_valid_tickers(
 #[tauri::command]
 pub async fn get_portfolio_cash(
     portfolio_id: i32,
+    currency: Option<String>,
     state: tauri::State<'_, AppState>,
-) -> Result<f64, String> {
+) -> Result<Decimal, String> {
     let client = state.db_pool.get().await.map_err(|e| format!("Error de conexión: {}", e))?;
-    
-    let cash_row = client.query_opt(
-        "SELECT SUM(CASE 
-                    WHEN transaction_type = 'DEPOSIT' THEN total_amount 
-                    WHEN transaction_type = 'WITHDRAWAL' THEN -total_amount 
-                    ELSE 0 
-                  END) as current_cash
-         FROM portfolio_transactions 
-         WHERE portfolio_id = $1 AND ticker = 'CASH'",
-        &[&portfolio_id],
-    ).await.map_err(|e| format!("Error al consultar cash: {}", e))?;
-    
-    let current_cash = if let Some(row) = cash_row {
-        row.get::<_, Option<f64>>("current_cash").unwrap_or(0.0)
-    } else {
-        0.0
-    };
-    
-    Ok(current_cash)
-}
-
-
+    let currency = crate::fx::normalize_currency(currency.as_deref().unwrap_or(crate::fx::MXN))?;
+    ledger::cash_balance(&**client, portfolio_id, &currency).await
+}
\ No newline at end of file
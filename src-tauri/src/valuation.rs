@@ -0,0 +1,228 @@
+use chrono::{Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tokio_postgres::GenericClient;
+
+use crate::data_bursatil_client;
+use crate::fixed_income;
+use crate::fx;
+use crate::ledger::{self, TransactionKind};
+use crate::lots;
+use crate::money;
+use crate::prices::{self, PriceSeries};
+
+/// Una posición abierta valuada en su moneda y en la moneda base.
+/// La ganancia no realizada en moneda base se separa en precio y tipo de cambio:
+/// precio = (valor - costo) en moneda local al tipo de cambio de hoy;
+/// cambiaria = costo en moneda local al tipo de cambio de hoy menos el costo histórico en moneda base.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HoldingValuation {
+    pub ticker: String,
+    pub currency: String,
+    pub quantity: Decimal,
+    pub average_cost: Decimal,
+    pub total_cost: Decimal,
+    pub current_price: Option<Decimal>,
+    pub market_value: Option<Decimal>,
+    pub base_currency: String,
+    pub fx_rate: Decimal,
+    pub cost_base: Decimal,
+    pub market_value_base: Option<Decimal>,
+    pub price_gain_base: Option<Decimal>,
+    pub fx_gain_base: Decimal,
+    pub unrealized_pnl_base: Option<Decimal>,
+}
+
+/// Valor del portafolio al cierre de un día hábil, en pesos.
+/// `net_flow` son los depósitos menos los retiros desde el día hábil anterior.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyValuation {
+    pub date: NaiveDate,
+    pub market_value: Decimal,
+    pub cash: Decimal,
+    pub total_value: Decimal,
+    pub net_flow: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashValuation {
+    pub currency: String,
+    pub amount: Decimal,
+    pub amount_base: Decimal,
+}
+
+/// Último precio en pesos de DataBursátil; los títulos del SIC también cotizan en pesos.
+/// Un precio en cero o negativo cuenta como sin cotización.
+pub async fn market_price_mxn(ticker: &str) -> Option<Decimal> {
+    match data_bursatil_client::get_cotizaciones_async(ticker).await {
+        Ok(Some(cotizacion)) => cotizacion.ultimo_precio.and_then(money::price_from_f64).filter(|p| *p > Decimal::ZERO),
+        _ => None,
+    }
+}
+
+pub async fn value_holdings<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    base_currency: &str,
+) -> Result<Vec<HoldingValuation>, String> {
+    let base_currency = fx::normalize_currency(base_currency)?;
+    let today = Utc::now().date_naive();
+    let book = lots::load_lot_book(client, portfolio_id).await?;
+
+    // Costo histórico en moneda base: cada lote a su tipo de cambio de compra
+    let mut cost_base: BTreeMap<String, Decimal> = BTreeMap::new();
+    for lot in &book.open_lots {
+        let base_rate = fx::rate_to_mxn(client, &base_currency, lot.acquired_at.date_naive()).await?;
+        *cost_base.entry(lot.ticker.clone()).or_default() += lot.cost_basis * lot.fx_rate / base_rate;
+    }
+
+    let mut positions: Vec<(String, lots::Position)> = book.positions().into_iter().collect();
+    positions.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut holdings = Vec::new();
+    for (ticker, position) in positions {
+        let fx_rate = fx::conversion_rate(client, &position.currency, &base_currency, today).await?;
+        let local_per_mxn = fx::rate_to_mxn(client, &position.currency, today).await?;
+        let current_price = match fixed_income::instrument(client, &ticker).await? {
+            Some(instrument) => Some(fixed_income::position_price(client, &instrument, &book.open_lots, today).await?),
+            None => market_price_mxn(&ticker).await.map(|p| money::round_price(p / local_per_mxn)),
+        };
+        let market_value = current_price.map(|price| money::round_mxn(price * position.quantity));
+
+        let cost_base = money::round_mxn(cost_base.get(&ticker).copied().unwrap_or_default());
+        let cost_at_today_rate = money::round_mxn(position.total_cost * fx_rate);
+        let market_value_base = market_value.map(|mv| money::round_mxn(mv * fx_rate));
+        let price_gain_base = market_value_base.map(|mv| mv - cost_at_today_rate);
+
+        holdings.push(HoldingValuation {
+            average_cost: position.average_cost(),
+            total_cost: position.total_cost,
+            quantity: position.quantity,
+            currency: position.currency,
+            ticker,
+            current_price,
+            market_value,
+            base_currency: base_currency.clone(),
+            fx_rate,
+            cost_base,
+            market_value_base,
+            price_gain_base,
+            fx_gain_base: cost_at_today_rate - cost_base,
+            unrealized_pnl_base: market_value_base.map(|mv| mv - cost_base),
+        });
+    }
+    Ok(holdings)
+}
+
+pub async fn value_cash<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    base_currency: &str,
+) -> Result<Vec<CashValuation>, String> {
+    let base_currency = fx::normalize_currency(base_currency)?;
+    let today = Utc::now().date_naive();
+    let mut cash = Vec::new();
+    for (currency, amount) in ledger::cash_balances(client, portfolio_id).await? {
+        let rate = fx::conversion_rate(client, &currency, &base_currency, today).await?;
+        cash.push(CashValuation {
+            amount_base: money::round_mxn(amount * rate),
+            currency,
+            amount,
+        });
+    }
+    Ok(cash)
+}
+
+/// Reconstruye el valor diario del portafolio a partir del ledger y los cierres históricos.
+/// Los títulos se valúan al último cierre conocido (o al último precio operado si no hay
+/// histórico); el efectivo en otra moneda, al último tipo de cambio conocido a ese día.
+pub async fn valuation_history<C: GenericClient>(
+    client: &C,
+    portfolio_id: i32,
+    from: Option<NaiveDate>,
+    to: NaiveDate,
+) -> Result<Vec<DailyValuation>, String> {
+    let entries = ledger::list_entries(client, portfolio_id, None).await?;
+    let Some(inception) = entries.first().map(|e| e.transaction_date.date_naive()) else {
+        return Ok(Vec::new());
+    };
+    let start = from.map_or(inception, |f| f.max(inception));
+
+    let mut closes: HashMap<String, PriceSeries> = HashMap::new();
+    let mut fx_rates: HashMap<String, BTreeMap<NaiveDate, Decimal>> = HashMap::new();
+    for entry in &entries {
+        if entry.transaction_type.is_trade() && !closes.contains_key(&entry.ticker) {
+            closes.insert(entry.ticker.clone(), prices::daily_closes(client, &entry.ticker, inception).await?);
+        }
+        if entry.currency != fx::MXN && !fx_rates.contains_key(&entry.currency) {
+            fx_rates.insert(entry.currency.clone(), fx::rate_series(client, &entry.currency).await?);
+        }
+    }
+
+    let mut quantities: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut last_trade_price: HashMap<String, Decimal> = HashMap::new();
+    let mut last_entry_fx_rate: HashMap<String, Decimal> = HashMap::new();
+    let mut cash: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut pending_flow = Decimal::ZERO;
+    let mut history = Vec::new();
+    let mut next_entry = 0;
+    let mut day = inception;
+
+    while day <= to {
+        while next_entry < entries.len() && entries[next_entry].transaction_date.date_naive() <= day {
+            let entry = &entries[next_entry];
+            next_entry += 1;
+            let signed = entry.cash_effect();
+            *cash.entry(entry.currency.clone()).or_default() += signed;
+            match entry.transaction_type {
+                TransactionKind::Buy => *quantities.entry(entry.ticker.clone()).or_default() += entry.quantity,
+                TransactionKind::Sell => *quantities.entry(entry.ticker.clone()).or_default() -= entry.quantity,
+                TransactionKind::Deposit | TransactionKind::Withdrawal => pending_flow += signed * entry.fx_rate,
+                TransactionKind::Dividend | TransactionKind::Interest => {}
+            }
+            if entry.transaction_type.is_trade() {
+                last_trade_price.insert(entry.ticker.clone(), entry.price * entry.fx_rate);
+            }
+            last_entry_fx_rate.insert(entry.currency.clone(), entry.fx_rate);
+        }
+
+        if day < start && prices::is_business_day(day) {
+            pending_flow = Decimal::ZERO;
+        } else if prices::is_business_day(day) {
+            let mut market_value = Decimal::ZERO;
+            for (ticker, quantity) in quantities.iter().filter(|(_, q)| **q > Decimal::ZERO) {
+                let price = closes.get(ticker)
+                    .and_then(|series| prices::close_on(series, day))
+                    .or_else(|| last_trade_price.get(ticker).copied())
+                    .unwrap_or_default();
+                market_value += *quantity * price;
+            }
+            let mut cash_mxn = Decimal::ZERO;
+            for (currency, amount) in &cash {
+                // Sin tipo de cambio guardado a esa fecha se usa el del último movimiento en la moneda
+                let rate = if currency == fx::MXN {
+                    Decimal::ONE
+                } else {
+                    fx_rates.get(currency)
+                        .and_then(|series| prices::close_on(series, day))
+                        .or_else(|| last_entry_fx_rate.get(currency).copied())
+                        .ok_or_else(|| format!("No hay tipo de cambio de {} al {}", currency, day))?
+                };
+                cash_mxn += *amount * rate;
+            }
+            let market_value = money::round_mxn(market_value);
+            let cash_mxn = money::round_mxn(cash_mxn);
+            history.push(DailyValuation {
+                date: day,
+                market_value,
+                cash: cash_mxn,
+                total_value: market_value + cash_mxn,
+                net_flow: money::round_mxn(pending_flow),
+            });
+            pending_flow = Decimal::ZERO;
+        }
+        day += Duration::days(1);
+    }
+    Ok(history)
+}
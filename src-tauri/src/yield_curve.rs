@@ -0,0 +1,398 @@
+use chrono::{Duration, NaiveDate, Utc};
+use deadpool_postgres::Pool;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use tokio_postgres::GenericClient;
+
+use crate::benchmarks;
+use crate::performance::to_f64;
+use crate::prices;
+
+// Plazos en días de los CETES con tasa publicada
+pub const CETE_TENORS: [(i64, &str); 4] = [(28, "CETE28"), (91, "CETE91"), (182, "CETE182"), (364, "CETE364")];
+// Plazo de la tasa libre de riesgo anual (Sharpe y similares)
+pub const ONE_YEAR_DAYS: i64 = 364;
+const DAY_COUNT: f64 = 360.0;
+// Una tasa guardada hace más de una semana no entra a la curva del día
+const MAX_POINT_AGE_DAYS: i64 = 7;
+// Plazos que se devuelven al graficar la curva
+const DISPLAY_TENORS: [i64; 10] = [1, 28, 63, 91, 182, 273, 364, 546, 728, 1092];
+const RATE_SCALE: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Interpolation {
+    Linear,
+    // Spline cúbico natural
+    #[default]
+    CubicSpline,
+    NelsonSiegel,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_uppercase().replace([' ', '-'], "_").as_str() {
+            "LINEAR" | "LINEAL" => Ok(Interpolation::Linear),
+            "CUBIC_SPLINE" | "SPLINE" => Ok(Interpolation::CubicSpline),
+            "NELSON_SIEGEL" => Ok(Interpolation::NelsonSiegel),
+            _ => Err(format!("Interpolación desconocida: {}", s)),
+        }
+    }
+}
+
+/// Parámetros de Nelson-Siegel; el plazo en años y la tasa en porcentaje.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NelsonSiegel {
+    pub beta0: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub tau: f64,
+}
+
+fn ns_loadings(years: f64, tau: f64) -> (f64, f64) {
+    let x = years / tau;
+    if x < 1e-8 {
+        return (1.0, 0.0);
+    }
+    let slope = (1.0 - (-x).exp()) / x;
+    (slope, slope - (-x).exp())
+}
+
+impl NelsonSiegel {
+    pub fn rate(&self, years: f64) -> f64 {
+        let (slope, curvature) = ns_loadings(years, self.tau);
+        self.beta0 + self.beta1 * slope + self.beta2 * curvature
+    }
+
+    /// Mínimos cuadrados sobre las betas para cada tau de una malla logarítmica.
+    pub fn fit(points: &[(f64, f64)]) -> Option<NelsonSiegel> {
+        if points.len() < 3 {
+            return None;
+        }
+        let mut best: Option<(f64, NelsonSiegel)> = None;
+        for step in 0..=120 {
+            let tau = 0.05 * (100.0f64).powf(step as f64 / 120.0);
+            let rows: Vec<[f64; 3]> = points.iter()
+                .map(|(years, _)| {
+                    let (slope, curvature) = ns_loadings(*years, tau);
+                    [1.0, slope, curvature]
+                })
+                .collect();
+            let Some(betas) = least_squares(&rows, &points.iter().map(|(_, r)| *r).collect::<Vec<_>>()) else {
+                continue;
+            };
+            let fitted = NelsonSiegel { beta0: betas[0], beta1: betas[1], beta2: betas[2], tau };
+            let error: f64 = points.iter().map(|(years, rate)| (fitted.rate(*years) - rate).powi(2)).sum();
+            if !matches!(best, Some((best_error, _)) if best_error <= error) {
+                best = Some((error, fitted));
+            }
+        }
+        best.map(|(_, fitted)| fitted)
+    }
+}
+
+// Ecuaciones normales de 3x3 por eliminación gaussiana
+fn least_squares(rows: &[[f64; 3]], values: &[f64]) -> Option<[f64; 3]> {
+    let mut a = [[0.0; 4]; 3];
+    for (row, value) in rows.iter().zip(values) {
+        for i in 0..3 {
+            for j in 0..3 {
+                a[i][j] += row[i] * row[j];
+            }
+            a[i][3] += row[i] * value;
+        }
+    }
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        let pivot_row = a[col];
+        for (i, row) in a.iter_mut().enumerate() {
+            if i != col {
+                let factor = row[col] / pivot_row[col];
+                for (value, pivot_value) in row.iter_mut().zip(pivot_row).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    Some([a[0][3] / a[0][0], a[1][3] / a[1][1], a[2][3] / a[2][2]])
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub tenor_days: i64,
+    pub rate: Decimal,
+}
+
+/// Curva de un día. Las tasas son anuales en porcentaje, simples con base 360 hasta un
+/// año y capitalizables anualmente después.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YieldCurve {
+    pub curve_date: NaiveDate,
+    pub points: Vec<CurvePoint>,
+    pub nelson_siegel: Option<NelsonSiegel>,
+}
+
+impl YieldCurve {
+    fn knots(&self) -> Vec<(f64, f64)> {
+        self.points.iter().map(|p| (p.tenor_days as f64, to_f64(p.rate))).collect()
+    }
+
+    /// Tasa en porcentaje al plazo `tenor_days`. Fuera de los plazos observados, lineal y
+    /// spline se extienden planas.
+    pub fn rate(&self, tenor_days: i64, method: Interpolation) -> f64 {
+        let knots = self.knots();
+        let t = tenor_days.max(1) as f64;
+        match (method, self.nelson_siegel) {
+            (Interpolation::NelsonSiegel, Some(ns)) => ns.rate(t / DAY_COUNT),
+            (Interpolation::CubicSpline, _) if knots.len() >= 3 => natural_spline(&knots, t),
+            _ => linear(&knots, t),
+        }
+    }
+
+    pub fn discount_factor(&self, tenor_days: i64, method: Interpolation) -> f64 {
+        let r = self.rate(tenor_days, method) / 100.0;
+        let t = tenor_days.max(0) as f64 / DAY_COUNT;
+        if t <= 1.0 {
+            1.0 / (1.0 + r * t)
+        } else {
+            1.0 / (1.0 + r).powf(t)
+        }
+    }
+
+    /// Tasa forward simple anual (porcentaje, base 360) entre dos plazos.
+    pub fn forward_rate(&self, from_days: i64, to_days: i64, method: Interpolation) -> Option<f64> {
+        if to_days <= from_days {
+            return None;
+        }
+        let ratio = self.discount_factor(from_days, method) / self.discount_factor(to_days, method);
+        Some((ratio - 1.0) * DAY_COUNT / (to_days - from_days) as f64 * 100.0)
+    }
+}
+
+fn linear(knots: &[(f64, f64)], t: f64) -> f64 {
+    let (first, last) = (knots[0], knots[knots.len() - 1]);
+    if t <= first.0 {
+        return first.1;
+    }
+    if t >= last.0 {
+        return last.1;
+    }
+    let i = knots.iter().position(|(x, _)| *x >= t).unwrap();
+    let ((x0, y0), (x1, y1)) = (knots[i - 1], knots[i]);
+    y0 + (y1 - y0) * (t - x0) / (x1 - x0)
+}
+
+fn natural_spline(knots: &[(f64, f64)], t: f64) -> f64 {
+    let n = knots.len();
+    let (first, last) = (knots[0], knots[n - 1]);
+    if t <= first.0 {
+        return first.1;
+    }
+    if t >= last.0 {
+        return last.1;
+    }
+    // Segundas derivadas con extremos naturales (algoritmo de Thomas)
+    let h: Vec<f64> = knots.windows(2).map(|w| w[1].0 - w[0].0).collect();
+    let mut m = vec![0.0; n];
+    let mut diag = vec![0.0; n];
+    let mut rhs = vec![0.0; n];
+    for i in 1..n - 1 {
+        diag[i] = 2.0 * (h[i - 1] + h[i]);
+        rhs[i] = 6.0 * ((knots[i + 1].1 - knots[i].1) / h[i] - (knots[i].1 - knots[i - 1].1) / h[i - 1]);
+        if i > 1 {
+            let factor = h[i - 1] / diag[i - 1];
+            diag[i] -= factor * h[i - 1];
+            rhs[i] -= factor * rhs[i - 1];
+        }
+    }
+    for i in (1..n - 1).rev() {
+        let next = if i + 1 < n - 1 { h[i] * m[i + 1] } else { 0.0 };
+        m[i] = (rhs[i] - next) / diag[i];
+    }
+    let i = knots.iter().position(|(x, _)| *x >= t).unwrap() - 1;
+    let (x0, y0) = knots[i];
+    let (x1, y1) = knots[i + 1];
+    let a = (x1 - t) / h[i];
+    let b = (t - x0) / h[i];
+    a * y0 + b * y1 + ((a.powi(3) - a) * m[i] + (b.powi(3) - b) * m[i + 1]) * h[i].powi(2) / 6.0
+}
+
+fn round_rate(rate: f64) -> Decimal {
+    Decimal::from_f64(rate).map(|r| r.round_dp(RATE_SCALE)).unwrap_or_default()
+}
+
+/// Construye y guarda la curva de `date` con las tasas de CETES guardadas en
+/// benchmark_levels. Devuelve None si hay menos de dos plazos recientes.
+pub async fn store_curve<C: GenericClient>(client: &C, date: NaiveDate) -> Result<Option<YieldCurve>, String> {
+    let mut points = Vec::new();
+    for (tenor_days, symbol) in CETE_TENORS {
+        let levels = benchmarks::stored_levels(client, symbol).await?;
+        let recent = levels.range(date - Duration::days(MAX_POINT_AGE_DAYS)..=date).next_back();
+        if let Some((_, rate)) = recent {
+            points.push(CurvePoint { tenor_days, rate: *rate });
+        }
+    }
+    if points.len() < 2 {
+        return Ok(None);
+    }
+    let knots: Vec<(f64, f64)> = points.iter().map(|p| (p.tenor_days as f64 / DAY_COUNT, to_f64(p.rate))).collect();
+    let curve = YieldCurve { curve_date: date, nelson_siegel: NelsonSiegel::fit(&knots), points };
+    let ns = curve.nelson_siegel;
+
+    client.execute(
+        "INSERT INTO yield_curves (curve_date, ns_beta0, ns_beta1, ns_beta2, ns_tau, computed_at)
+         VALUES ($1, $2, $3, $4, $5, now())
+         ON CONFLICT (curve_date) DO UPDATE SET ns_beta0 = EXCLUDED.ns_beta0, ns_beta1 = EXCLUDED.ns_beta1,
+            ns_beta2 = EXCLUDED.ns_beta2, ns_tau = EXCLUDED.ns_tau, computed_at = now()",
+        &[&date, &ns.map(|n| n.beta0), &ns.map(|n| n.beta1), &ns.map(|n| n.beta2), &ns.map(|n| n.tau)],
+    ).await.map_err(|e| format!("No se pudo guardar la curva del {}: {}", date, e))?;
+    client.execute("DELETE FROM yield_curve_points WHERE curve_date = $1", &[&date])
+        .await.map_err(|e| format!("No se pudo guardar la curva del {}: {}", date, e))?;
+    for point in &curve.points {
+        client.execute(
+            "INSERT INTO yield_curve_points (curve_date, tenor_days, rate) VALUES ($1, $2, $3)",
+            &[&date, &(point.tenor_days as i32), &point.rate],
+        ).await.map_err(|e| format!("No se pudo guardar la curva del {}: {}", date, e))?;
+    }
+    Ok(Some(curve))
+}
+
+/// Curvas guardadas entre dos fechas, por fecha.
+pub async fn curves_between<C: GenericClient>(
+    client: &C,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, YieldCurve>, String> {
+    let rows = client.query(
+        "SELECT c.curve_date, c.ns_beta0, c.ns_beta1, c.ns_beta2, c.ns_tau, p.tenor_days, p.rate
+         FROM yield_curves c JOIN yield_curve_points p ON p.curve_date = c.curve_date
+         WHERE c.curve_date BETWEEN $1 AND $2
+         ORDER BY c.curve_date, p.tenor_days",
+        &[&from, &to],
+    ).await.map_err(|e| format!("Error al consultar las curvas de rendimiento: {}", e))?;
+    let mut curves: BTreeMap<NaiveDate, YieldCurve> = BTreeMap::new();
+    for row in &rows {
+        let curve_date: NaiveDate = row.get("curve_date");
+        let curve = curves.entry(curve_date).or_insert_with(|| {
+            let params = (row.get("ns_beta0"), row.get("ns_beta1"), row.get("ns_beta2"), row.get("ns_tau"));
+            YieldCurve {
+                curve_date,
+                points: Vec::new(),
+                nelson_siegel: match params {
+                    (Some(beta0), Some(beta1), Some(beta2), Some(tau)) => Some(NelsonSiegel { beta0, beta1, beta2, tau }),
+                    _ => None,
+                },
+            }
+        });
+        let tenor_days: i32 = row.get("tenor_days");
+        curve.points.push(CurvePoint { tenor_days: tenor_days as i64, rate: row.get("rate") });
+    }
+    Ok(curves)
+}
+
+/// Última curva guardada en o antes de `date`.
+pub async fn curve_on<C: GenericClient>(client: &C, date: NaiveDate) -> Result<Option<YieldCurve>, String> {
+    let row = client.query_opt(
+        "SELECT MAX(curve_date) AS curve_date FROM yield_curves WHERE curve_date <= $1",
+        &[&date],
+    ).await.map_err(|e| format!("Error al consultar la curva de rendimiento: {}", e))?;
+    let Some(curve_date) = row.and_then(|r| r.get::<_, Option<NaiveDate>>("curve_date")) else {
+        return Ok(None);
+    };
+    Ok(curves_between(client, curve_date, curve_date).await?.remove(&curve_date))
+}
+
+/// Tasa libre de riesgo en porcentaje al plazo indicado según la curva vigente en `date`.
+pub async fn risk_free_rate<C: GenericClient>(client: &C, date: NaiveDate, tenor_days: i64) -> Result<Option<f64>, String> {
+    Ok(curve_on(client, date).await?.map(|curve| curve.rate(tenor_days, Interpolation::default())))
+}
+
+/// Construye las curvas de todos los días con tasas de CETES guardadas desde `from`
+/// (por omisión, desde la última curva guardada).
+pub async fn build_yield_curves_logic(from: Option<NaiveDate>, db_pool: &Pool) -> Result<usize, String> {
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let from = match from {
+        Some(from) => Some(from),
+        None => client.query_one("SELECT MAX(curve_date) AS last_curve FROM yield_curves", &[])
+            .await.map_err(|e| format!("Error al consultar las curvas de rendimiento: {}", e))?
+            .get::<_, Option<NaiveDate>>("last_curve")
+            .map(|d| d + Duration::days(1)),
+    };
+    let rows = client.query(
+        "SELECT DISTINCT value_date FROM benchmark_levels
+         WHERE symbol = ANY($1) AND ($2::date IS NULL OR value_date >= $2)
+         ORDER BY value_date",
+        &[&CETE_TENORS.iter().map(|(_, s)| s.to_string()).collect::<Vec<_>>(), &from],
+    ).await.map_err(|e| format!("Error al consultar las tasas de CETES: {}", e))?;
+    let mut built = 0;
+    for row in &rows {
+        let date: NaiveDate = row.get("value_date");
+        if prices::is_business_day(date) && store_curve(&**client, date).await?.is_some() {
+            built += 1;
+        }
+    }
+    Ok(built)
+}
+
+/// Tasa, factor de descuento y forward desde el plazo anterior, por plazo consultado.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurveQuery {
+    pub tenor_days: i64,
+    pub rate: Decimal,
+    pub discount_factor: Decimal,
+    pub forward_rate: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct YieldCurveView {
+    pub curve: YieldCurve,
+    pub method: Interpolation,
+    pub tenors: Vec<CurveQuery>,
+}
+
+/// Curva vigente en `date` (hoy por omisión) evaluada en `tenors` o en plazos estándar.
+pub async fn get_yield_curve_logic(
+    date: Option<NaiveDate>,
+    method: Option<String>,
+    tenors: Option<Vec<i64>>,
+    db_pool: &Pool,
+) -> Result<YieldCurveView, String> {
+    let method = method.map(|m| m.parse()).transpose()?.unwrap_or_default();
+    let date = date.unwrap_or_else(|| Utc::now().date_naive());
+    let mut tenors = tenors.unwrap_or_else(|| DISPLAY_TENORS.to_vec());
+    if tenors.iter().any(|t| *t <= 0) {
+        return Err("Los plazos deben ser mayores a cero días".to_string());
+    }
+    tenors.sort_unstable();
+    tenors.dedup();
+
+    let client = db_pool.get().await.map_err(|e| e.to_string())?;
+    let curve = curve_on(&**client, date).await?
+        .ok_or_else(|| format!("No hay curva de rendimiento al {}; actualice las tasas de CETES", date))?;
+    if method == Interpolation::NelsonSiegel && curve.nelson_siegel.is_none() {
+        return Err("La curva de esa fecha tiene menos de tres plazos; no hay ajuste Nelson-Siegel".to_string());
+    }
+    let mut previous = 0;
+    let tenors = tenors.into_iter()
+        .map(|tenor_days| {
+            let forward = (previous > 0).then(|| curve.forward_rate(previous, tenor_days, method)).flatten();
+            previous = tenor_days;
+            CurveQuery {
+                tenor_days,
+                rate: round_rate(curve.rate(tenor_days, method)),
+                discount_factor: round_rate(curve.discount_factor(tenor_days, method)),
+                forward_rate: forward.map(round_rate),
+            }
+        })
+        .collect();
+    Ok(YieldCurveView { curve, method, tenors })
+}
@@ -5,24 +5,33 @@ CREATE TABLE IF NOT EXISTS public.portfolio_transactions
     user_id integer NOT NULL,
     ticker character varying(20) COLLATE pg_catalog."default" NOT NULL,
     transaction_type character varying(12) COLLATE pg_catalog."default" NOT NULL,
-    quantity double precision NOT NULL,
-    price double precision,
+    quantity numeric(18,6) NOT NULL,
+    price numeric(18,6),
     transaction_date timestamp with time zone NOT NULL,
-    total_amount double precision NOT NULL,
+    total_amount numeric(18,2) NOT NULL,
+    commission numeric(18,2) NOT NULL DEFAULT 0,
+    commission_iva numeric(18,2) NOT NULL DEFAULT 0,
     currency character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'MXN'::character varying,
+    fx_rate numeric(18,6) NOT NULL DEFAULT 1,
     notes text COLLATE pg_catalog."default",
     created_at timestamp with time zone NOT NULL DEFAULT now(),
     updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    linked_transaction_id integer,
+    voided_at timestamp with time zone,
     CONSTRAINT portfolio_transactions_pkey PRIMARY KEY (transaction_id),
     CONSTRAINT fk_portfolio FOREIGN KEY (portfolio_id)
         REFERENCES public.portafolios (id) MATCH SIMPLE
         ON UPDATE NO ACTION
         ON DELETE CASCADE,
+    CONSTRAINT portfolio_transactions_linked_transaction_id_fkey FOREIGN KEY (linked_transaction_id)
+        REFERENCES public.portfolio_transactions (transaction_id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE CASCADE,
     CONSTRAINT fk_user FOREIGN KEY (user_id)
         REFERENCES public.usuarios (id) MATCH SIMPLE
         ON UPDATE NO ACTION
         ON DELETE CASCADE,
-    CONSTRAINT portfolio_transactions_transaction_type_check CHECK (transaction_type::text = ANY (ARRAY['BUY'::character varying, 'SELL'::character varying, 'DIVIDEND'::character varying, 'DEPOSIT'::character varying, 'WITHDRAWAL'::character varying]::text[]))
+    CONSTRAINT portfolio_transactions_transaction_type_check CHECK (transaction_type::text = ANY (ARRAY['BUY'::character varying, 'SELL'::character varying, 'DIVIDEND'::character varying, 'DEPOSIT'::character varying, 'WITHDRAWAL'::character varying, 'INTEREST'::character varying]::text[]))
 )
 
 TABLESPACE pg_default;
@@ -39,4 +48,56 @@ CREATE INDEX IF NOT EXISTS idx_transactions_portfolio_ticker
 CREATE INDEX IF NOT EXISTS idx_transactions_user_date
     ON public.portfolio_transactions USING btree
     (user_id ASC NULLS LAST, transaction_date ASC NULLS LAST)
-    TABLESPACE pg_default;
\ No newline at end of file
+    TABLESPACE pg_default;
+-- Index: public.idx_transactions_linked
+CREATE INDEX IF NOT EXISTS idx_transactions_linked
+    ON public.portfolio_transactions USING btree
+    (linked_transaction_id ASC NULLS LAST)
+    TABLESPACE pg_default
+    WHERE linked_transaction_id IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS public.ledger_audit
+(
+    audit_id integer NOT NULL DEFAULT nextval('ledger_audit_audit_id_seq'::regclass),
+    transaction_id integer NOT NULL,
+    portfolio_id integer NOT NULL,
+    action character varying(10) COLLATE pg_catalog."default" NOT NULL,
+    changed_by character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    changed_at timestamp with time zone NOT NULL DEFAULT now(),
+    reason text COLLATE pg_catalog."default",
+    before_state jsonb NOT NULL,
+    after_state jsonb NOT NULL,
+    CONSTRAINT ledger_audit_pkey PRIMARY KEY (audit_id),
+    CONSTRAINT ledger_audit_action_check CHECK (action::text = ANY (ARRAY['UPDATE'::character varying, 'VOID'::character varying, 'UNDO'::character varying]::text[]))
+)
+
+TABLESPACE pg_default;
+
+ALTER TABLE public.ledger_audit
+    OWNER to garden_admin;
+
+-- Index: public.idx_ledger_audit_transaction
+CREATE INDEX IF NOT EXISTS idx_ledger_audit_transaction
+    ON public.ledger_audit USING btree
+    (transaction_id ASC NULLS LAST, audit_id ASC NULLS LAST)
+    TABLESPACE pg_default;
+
+-- FUNCTION: public.ledger_audit_append_only()
+CREATE OR REPLACE FUNCTION public.ledger_audit_append_only()
+    RETURNS trigger
+    LANGUAGE 'plpgsql'
+AS $BODY$
+BEGIN
+    RAISE EXCEPTION 'ledger_audit es de solo inserción';
+END;
+$BODY$;
+
+ALTER FUNCTION public.ledger_audit_append_only()
+    OWNER TO garden_admin;
+
+-- Trigger: ledger_audit_no_change
+CREATE OR REPLACE TRIGGER ledger_audit_no_change
+    BEFORE UPDATE OR DELETE
+    ON public.ledger_audit
+    FOR EACH ROW
+    EXECUTE FUNCTION public.ledger_audit_append_only();